@@ -176,10 +176,31 @@ CREATE INDEX idx_player_server_session_started_at
 
 CREATE SCHEMA website;
 
+-- A Discord account linked to a website account through OAuth2. The tokens are kept, encrypted
+-- with DISCORD_TOKEN_SECRET, so guild roles can be synced again later.
 CREATE TABLE website.discord_user(
     user_id BIGINT PRIMARY KEY REFERENCES discord_user(user_id) ON DELETE CASCADE NOT NULL,
-    refresh_token TEXT
-);
+    refresh_token TEXT,
+    steam_user_id BIGINT UNIQUE,
+    username TEXT,
+    access_token TEXT,
+    token_expires_at TIMESTAMP WITH TIME ZONE,
+    linked_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    roles_synced_at TIMESTAMP WITH TIME ZONE,
+    -- Failed syncs in a row since the last good one, the background sync backs off on them
+    roles_sync_attempted_at TIMESTAMP WITH TIME ZONE,
+    roles_sync_failures INTEGER NOT NULL DEFAULT 0
+);
+-- Existing installs:
+-- ALTER TABLE website.discord_user
+--     ADD COLUMN steam_user_id BIGINT UNIQUE,
+--     ADD COLUMN username TEXT,
+--     ADD COLUMN access_token TEXT,
+--     ADD COLUMN token_expires_at TIMESTAMP WITH TIME ZONE,
+--     ADD COLUMN linked_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT CURRENT_TIMESTAMP,
+--     ADD COLUMN roles_synced_at TIMESTAMP WITH TIME ZONE;
+ALTER TABLE website.discord_user ADD COLUMN IF NOT EXISTS roles_sync_attempted_at TIMESTAMP WITH TIME ZONE;
+ALTER TABLE website.discord_user ADD COLUMN IF NOT EXISTS roles_sync_failures INTEGER NOT NULL DEFAULT 0;
 
 CREATE TYPE community_visibility_state_enum AS ENUM (
     'Private',
@@ -212,8 +233,24 @@ CREATE TABLE website.steam_user (
     primary_clan_id TEXT NOT NULL,
     time_created BIGINT NOT NULL,
     persona_state_flags INTEGER NOT NULL,
-    comment_permission BOOLEAN NOT NULL
+    comment_permission BOOLEAN NOT NULL,
+    -- Set once the account deletion job has scrubbed the profile, signing up again clears it
+    deleted_at TIMESTAMP WITH TIME ZONE
 );
+-- Existing installs:
+-- ALTER TABLE website.steam_user ADD COLUMN deleted_at TIMESTAMP WITH TIME ZONE;
+
+-- Guides, comments and reports of deleted accounts are handed to this placeholder
+INSERT INTO website.steam_user (
+    user_id, community_visibility_state, profile_state, persona_name, profile_url, avatar, avatar_medium,
+    avatar_full, avatar_hash, last_log_off, persona_state, primary_clan_id, time_created,
+    persona_state_flags, comment_permission
+) VALUES (0, 'Private', 0, 'Deleted user', '', '', '', '', '', 0, 'Offline', '', 0, 0, FALSE)
+ON CONFLICT (user_id) DO NOTHING;
+
+ALTER TABLE website.discord_user
+    ADD CONSTRAINT discord_user_steam_user_fkey
+    FOREIGN KEY (steam_user_id) REFERENCES website.steam_user(user_id) ON DELETE CASCADE;
 
 CREATE TABLE website.user_favorite_maps (
     user_id BIGINT REFERENCES website.steam_user(user_id) ON DELETE CASCADE,
@@ -237,8 +274,18 @@ CREATE TABLE IF NOT EXISTS website.map_3d_model (
     file_size BIGINT NOT NULL,
     created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT CURRENT_TIMESTAMP,
     updated_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    content_hash VARCHAR(64),
+    glb_info JSONB,
+    thumbnail_path TEXT,
+    generated BOOLEAN NOT NULL DEFAULT FALSE,
     UNIQUE(map_name, res_type)
 );
+-- Existing installs:
+-- ALTER TABLE website.map_3d_model
+--     ADD COLUMN content_hash VARCHAR(64),
+--     ADD COLUMN glb_info JSONB,
+--     ADD COLUMN thumbnail_path TEXT;
+ALTER TABLE website.map_3d_model ADD COLUMN IF NOT EXISTS generated BOOLEAN NOT NULL DEFAULT FALSE;
 
 CREATE INDEX idx_map_3d_model_map_name ON website.map_3d_model(map_name);
 CREATE INDEX idx_map_3d_model_uploaded_by ON website.map_3d_model(uploaded_by);
@@ -246,8 +293,45 @@ CREATE INDEX idx_map_3d_model_uploaded_by ON website.map_3d_model(uploaded_by);
 COMMENT ON TABLE website.map_3d_model IS 'Stores metadata for uploaded 3D models (.glb files) for maps';
 COMMENT ON COLUMN website.map_3d_model.res_type IS 'Resolution type: low or high';
 COMMENT ON COLUMN website.map_3d_model.credit IS 'Optional credit/attribution for the model author';
-COMMENT ON COLUMN website.map_3d_model.link_path IS 'File path on disk (e.g., maps/{map_name}/{map_name}_d_c_{res_type}.glb)';
+COMMENT ON COLUMN website.map_3d_model.link_path IS 'Public path of the current version (e.g., maps/{map_name}/{res_type}/{sha256}.glb)';
 COMMENT ON COLUMN website.map_3d_model.file_size IS 'File size in bytes';
+COMMENT ON COLUMN website.map_3d_model.glb_info IS 'Mesh/texture/triangle counts, bounds and compression read from the GLB on upload';
+COMMENT ON COLUMN website.map_3d_model.content_hash IS 'SHA-256 of the current version, NULL for models uploaded before content addressing';
+COMMENT ON COLUMN website.map_3d_model.thumbnail_path IS 'Thumbnail rendered by the model job, relative to CACHE_THUMBNAIL/map-models';
+COMMENT ON COLUMN website.map_3d_model.generated IS 'Low model the model job optimized from the high one, replaced whenever the high one changes';
+
+CREATE TABLE IF NOT EXISTS website.map_3d_model_version (
+    id SERIAL PRIMARY KEY,
+    map_name VARCHAR(255) NOT NULL,
+    res_type VARCHAR(10) NOT NULL CHECK (res_type IN ('low', 'high')),
+    content_hash VARCHAR(64),
+    link_path TEXT NOT NULL,
+    credit TEXT,
+    uploaded_by BIGINT REFERENCES website.steam_user(user_id) ON DELETE SET NULL,
+    file_size BIGINT NOT NULL,
+    glb_info JSONB,
+    generated BOOLEAN NOT NULL DEFAULT FALSE,
+    created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    UNIQUE(map_name, res_type, content_hash)
+);
+ALTER TABLE website.map_3d_model_version ADD COLUMN IF NOT EXISTS generated BOOLEAN NOT NULL DEFAULT FALSE;
+
+CREATE INDEX IF NOT EXISTS idx_map_3d_model_version_map
+    ON website.map_3d_model_version(map_name, res_type, created_at DESC);
+
+COMMENT ON TABLE website.map_3d_model_version IS 'Every distinct upload of a map 3D model, map_3d_model points at the current one';
+COMMENT ON COLUMN website.map_3d_model_version.content_hash IS 'NULL for the model that was current before content addressing, stored under its legacy key';
+
+-- Models uploaded before content addressing become their first version, so they can be rolled back to
+INSERT INTO website.map_3d_model_version
+    (map_name, res_type, content_hash, link_path, credit, uploaded_by, file_size, glb_info, created_at)
+SELECT m.map_name, m.res_type, NULL, m.link_path, m.credit, m.uploaded_by, m.file_size, m.glb_info, m.updated_at
+FROM website.map_3d_model m
+WHERE m.content_hash IS NULL
+  AND NOT EXISTS (
+    SELECT 1 FROM website.map_3d_model_version v
+    WHERE v.map_name = m.map_name AND v.res_type = m.res_type AND v.content_hash IS NULL
+  );
 
 
 CREATE TABLE IF NOT EXISTS website.character_3d_model (
@@ -261,8 +345,14 @@ CREATE TABLE IF NOT EXISTS website.character_3d_model (
     file_size BIGINT NOT NULL,
     created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT CURRENT_TIMESTAMP,
     updated_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    thumbnail_path TEXT,
+    glb_info JSONB,
     UNIQUE(server_id, model_id)
 );
+-- Existing installs:
+-- ALTER TABLE website.character_3d_model
+--     ADD COLUMN thumbnail_path TEXT,
+--     ADD COLUMN glb_info JSONB;
 
 CREATE INDEX IF NOT EXISTS idx_character_3d_model_model_id ON website.character_3d_model(model_id);
 CREATE INDEX IF NOT EXISTS idx_character_3d_model_uploaded_by ON website.character_3d_model(uploaded_by);
@@ -274,26 +364,129 @@ COMMENT ON COLUMN website.character_3d_model.server_id IS 'Server this character
 COMMENT ON COLUMN website.character_3d_model.credit IS 'Optional credit/attribution for the model author';
 COMMENT ON COLUMN website.character_3d_model.link_path IS 'File path or URL for the model';
 COMMENT ON COLUMN website.character_3d_model.file_size IS 'File size in bytes';
+COMMENT ON COLUMN website.character_3d_model.glb_info IS 'Mesh/texture/triangle counts, bounds and compression read from the GLB on upload';
+COMMENT ON COLUMN website.character_3d_model.thumbnail_path IS 'Thumbnail filename under CACHE_THUMBNAIL/characters, uploaded or generated by the model job';
+
+CREATE TABLE IF NOT EXISTS website.model_job (
+    id SERIAL PRIMARY KEY,
+    target JSONB NOT NULL,
+    status VARCHAR(16) NOT NULL DEFAULT 'queued' CHECK (status IN ('queued', 'running', 'done', 'failed')),
+    error TEXT,
+    attempts INTEGER NOT NULL DEFAULT 0,
+    created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    started_at TIMESTAMP WITH TIME ZONE,
+    finished_at TIMESTAMP WITH TIME ZONE
+);
+
+CREATE INDEX IF NOT EXISTS idx_model_job_status ON website.model_job(status, created_at);
+
+COMMENT ON TABLE website.model_job IS 'Background processing of uploaded 3D models: thumbnails and generated low-res map variants';
+COMMENT ON COLUMN website.model_job.target IS 'Which model to process, {"kind": "map_model" | "character_model", ...}';
+
+CREATE TABLE IF NOT EXISTS website.data_export (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    user_id BIGINT REFERENCES website.steam_user(user_id) ON DELETE CASCADE NOT NULL,
+    status VARCHAR(16) NOT NULL DEFAULT 'queued' CHECK (status IN ('queued', 'running', 'done', 'failed', 'expired')),
+    progress SMALLINT NOT NULL DEFAULT 0 CHECK (progress BETWEEN 0 AND 100),
+    step TEXT,
+    error TEXT,
+    file_path TEXT,
+    file_size BIGINT,
+    attempts INTEGER NOT NULL DEFAULT 0,
+    created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    started_at TIMESTAMP WITH TIME ZONE,
+    finished_at TIMESTAMP WITH TIME ZONE,
+    expires_at TIMESTAMP WITH TIME ZONE
+);
+
+CREATE INDEX IF NOT EXISTS idx_data_export_status ON website.data_export(status, created_at);
+CREATE INDEX IF NOT EXISTS idx_data_export_user ON website.data_export(user_id, created_at DESC);
 
+-- Requests racing each other used to queue duplicates, only the newest one is kept going
+UPDATE website.data_export e
+SET status = 'failed', error = 'Superseded by a newer request', finished_at = NOW()
+WHERE e.status IN ('queued', 'running')
+  AND EXISTS (
+      SELECT 1 FROM website.data_export n
+      WHERE n.user_id = e.user_id AND n.status IN ('queued', 'running') AND n.created_at > e.created_at
+  );
+CREATE UNIQUE INDEX IF NOT EXISTS idx_data_export_pending
+    ON website.data_export(user_id) WHERE status IN ('queued', 'running');
 
+COMMENT ON TABLE website.data_export IS 'Personal data archives requested from /accounts/me/export, built in the background';
+COMMENT ON COLUMN website.data_export.step IS 'Section of the archive being written while running';
+COMMENT ON COLUMN website.data_export.file_path IS 'Object key of the archive in export storage, removed once expires_at passes';
+
+CREATE TABLE IF NOT EXISTS website.account_deletion (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    user_id BIGINT REFERENCES website.steam_user(user_id) ON DELETE CASCADE NOT NULL,
+    status VARCHAR(16) NOT NULL DEFAULT 'scheduled' CHECK (status IN ('scheduled', 'cancelled', 'running', 'done', 'failed')),
+    requested_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    scheduled_for TIMESTAMP WITH TIME ZONE NOT NULL,
+    cancelled_at TIMESTAMP WITH TIME ZONE,
+    started_at TIMESTAMP WITH TIME ZONE,
+    completed_at TIMESTAMP WITH TIME ZONE,
+    attempts INTEGER NOT NULL DEFAULT 0,
+    error TEXT,
+    summary JSONB
+);
+
+CREATE UNIQUE INDEX IF NOT EXISTS idx_account_deletion_pending
+    ON website.account_deletion(user_id) WHERE status IN ('scheduled', 'running');
+CREATE INDEX IF NOT EXISTS idx_account_deletion_due ON website.account_deletion(status, scheduled_for);
+
+COMMENT ON TABLE website.account_deletion IS 'DELETE /accounts/me requests, carried out by the deletion job once scheduled_for passes. Done rows are the confirmation kept for admins';
+COMMENT ON COLUMN website.account_deletion.summary IS 'What the job changed, row counts per table';
+
+
+-- One row per signed in device. The refresh token rotates on every use, and the hashes it
+-- rotated away from are kept in used_refresh_tokens until the session expires: seeing one of
+-- those again means the token leaked, so the whole session is revoked.
 CREATE TABLE IF NOT EXISTS website.user_refresh_tokens (
-    user_id BIGINT PRIMARY KEY REFERENCES discord_user(user_id) ON DELETE CASCADE NOT NULL,
-    refresh_token_hash VARCHAR(64) NOT NULL,
+    session_id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    user_id BIGINT REFERENCES website.steam_user(user_id) ON DELETE CASCADE NOT NULL,
+    refresh_token_hash VARCHAR(64) NOT NULL UNIQUE,
     expires_at TIMESTAMP WITH TIME ZONE NOT NULL,
-    created_at TIMESTAMP WITH TIME ZONE DEFAULT current_timestamp,
-    updated_at TIMESTAMP WITH TIME ZONE DEFAULT current_timestamp,
+    created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT current_timestamp,
+    updated_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT current_timestamp,
     device_id TEXT NOT NULL,
+    device_name TEXT,
+    user_agent TEXT,
+    ip_address TEXT,
+    revoked_at TIMESTAMP WITH TIME ZONE,
     UNIQUE (user_id, device_id)
 );
+-- Migrating from the single-session table:
+-- ALTER TABLE website.user_refresh_tokens DROP CONSTRAINT user_refresh_tokens_pkey;
+-- ALTER TABLE website.user_refresh_tokens DROP CONSTRAINT user_refresh_tokens_user_id_fkey;
+-- ALTER TABLE website.user_refresh_tokens
+--     ADD COLUMN session_id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+--     ADD COLUMN device_name TEXT,
+--     ADD COLUMN user_agent TEXT,
+--     ADD COLUMN ip_address TEXT,
+--     ADD COLUMN revoked_at TIMESTAMP WITH TIME ZONE,
+--     ADD CONSTRAINT user_refresh_tokens_user_id_fkey
+--         FOREIGN KEY (user_id) REFERENCES website.steam_user(user_id) ON DELETE CASCADE,
+--     ADD CONSTRAINT user_refresh_tokens_refresh_token_hash_key UNIQUE (refresh_token_hash);
 
 CREATE INDEX IF NOT EXISTS idx_refresh_tokens_expires_at
     ON website.user_refresh_tokens(expires_at);
 
+CREATE TABLE IF NOT EXISTS website.used_refresh_tokens (
+    refresh_token_hash VARCHAR(64) PRIMARY KEY,
+    session_id UUID REFERENCES website.user_refresh_tokens(session_id) ON DELETE CASCADE NOT NULL,
+    used_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT current_timestamp
+);
+
+CREATE INDEX IF NOT EXISTS idx_used_refresh_tokens_session
+    ON website.used_refresh_tokens(session_id);
+
 CREATE OR REPLACE FUNCTION cleanup_expired_refresh_tokens()
     RETURNS INTEGER AS $$
     DECLARE
     deleted_count INTEGER;
     BEGIN
+        -- Revoked sessions stay until they would have expired, so reuse is still detected
         DELETE FROM website.user_refresh_tokens
         WHERE expires_at < NOW();
 
@@ -331,6 +524,16 @@ CREATE TABLE website.player_playtime(
 );
 
 
+CREATE TABLE website.player_achievements(
+    player_id VARCHAR(100) REFERENCES player(player_id) ON DELETE CASCADE NOT NULL,
+    server_id VARCHAR(100) REFERENCES server(server_id) ON DELETE CASCADE NOT NULL,
+    achievement_key VARCHAR(50) NOT NULL,
+    unlocked_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    PRIMARY KEY(player_id, server_id, achievement_key)
+);
+CREATE INDEX idx_player_achievements_player ON website.player_achievements(player_id, unlocked_at DESC);
+
+
 CREATE TABLE website.kofi_donors (
     id          UUID PRIMARY KEY DEFAULT gen_random_uuid(),
     display_name TEXT NOT NULL,
@@ -403,6 +606,8 @@ CREATE UNIQUE INDEX CONCURRENTLY player_playtime_ranks_idx
     ON website.player_playtime_ranks (player_id, server_id);
 
 
+-- Nightly snapshot for external consumers only, the API ranks players live off
+-- idx_player_map_time_rank below, see PlayerBasicQuery<Vec<DbMapRank>>
 CREATE MATERIALIZED VIEW website.player_map_rank AS
 SELECT
     server_id,
@@ -416,20 +621,72 @@ FROM website.player_map_time;
 CREATE UNIQUE INDEX CONCURRENTLY player_map_rank_idx
     ON website.player_map_rank (server_id, map, player_id);
 
-CREATE TABLE website.user_roles (
-    user_id BIGINT REFERENCES website.steam_user(user_id) ON DELETE CASCADE,
-    role VARCHAR(20) NOT NULL CHECK (role IN ('superuser', 'community_admin', 'regular')),
+-- One map's leaderboard page, see get_map_leaderboard
+CREATE INDEX idx_player_map_time_rank
+    ON website.player_map_time (server_id, map, total_playtime DESC);
+
+-- Roles bundle named permissions ('*' grants all of them). A grant gives a user a role
+-- globally, for one community (and all of its servers) or for one server.
+CREATE TABLE website.role (
+    id SERIAL PRIMARY KEY,
+    name VARCHAR(64) NOT NULL UNIQUE,
+    description TEXT,
+    permissions TEXT[] NOT NULL DEFAULT '{}',
+    created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+INSERT INTO website.role (name, description, permissions) VALUES
+    ('superuser', 'Everything, everywhere', '{*}'),
+    ('community_admin', 'Runs a community and its servers', '{models.upload,players.anonymize,players.view_anonymized,roles.manage}'),
+    ('moderator', 'Guide and music moderation', '{guides.moderate,music.moderate}');
+
+-- Members holding a Discord guild role get a website role, in the same scopes a grant can have.
+CREATE TABLE website.discord_role_mapping (
+    id SERIAL PRIMARY KEY,
+    guild_id BIGINT NOT NULL,
+    discord_role_id BIGINT NOT NULL,
+    role_id INTEGER NOT NULL REFERENCES website.role(id) ON DELETE CASCADE,
     community_id UUID REFERENCES community(community_id) ON DELETE CASCADE,
-    created_at TIMESTAMP WITH TIME ZONE DEFAULT CURRENT_TIMESTAMP,
-    UNIQUE(user_id, community_id),
-    CONSTRAINT role_community_check CHECK (
-        (role = 'community_admin' AND community_id IS NOT NULL) OR
-        (role IN ('superuser', 'regular') AND community_id IS NULL)
-        )
+    server_id VARCHAR(100) REFERENCES server(server_id) ON DELETE CASCADE,
+    created_by BIGINT REFERENCES website.steam_user(user_id) ON DELETE SET NULL,
+    created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    CONSTRAINT discord_role_mapping_scope_check CHECK (community_id IS NULL OR server_id IS NULL)
+);
+
+CREATE UNIQUE INDEX idx_discord_role_mapping_unique
+    ON website.discord_role_mapping(guild_id, discord_role_id, role_id, COALESCE(community_id::TEXT, ''), COALESCE(server_id, ''));
+
+CREATE TABLE website.role_grant (
+    id BIGSERIAL PRIMARY KEY,
+    user_id BIGINT NOT NULL REFERENCES website.steam_user(user_id) ON DELETE CASCADE,
+    role_id INTEGER NOT NULL REFERENCES website.role(id) ON DELETE CASCADE,
+    community_id UUID REFERENCES community(community_id) ON DELETE CASCADE,
+    server_id VARCHAR(100) REFERENCES server(server_id) ON DELETE CASCADE,
+    granted_by BIGINT REFERENCES website.steam_user(user_id) ON DELETE SET NULL,
+    -- Set on grants made by Discord role sync, which removes them again when the role goes away
+    discord_mapping_id INTEGER REFERENCES website.discord_role_mapping(id) ON DELETE CASCADE,
+    created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    CONSTRAINT role_grant_scope_check CHECK (community_id IS NULL OR server_id IS NULL)
 );
 
-CREATE INDEX idx_user_roles_user_id ON website.user_roles(user_id);
-CREATE INDEX idx_user_roles_community ON website.user_roles(community_id) WHERE community_id IS NOT NULL;
+CREATE UNIQUE INDEX idx_role_grant_unique
+    ON website.role_grant(user_id, role_id, COALESCE(community_id::TEXT, ''), COALESCE(server_id, ''));
+CREATE INDEX idx_role_grant_user ON website.role_grant(user_id);
+CREATE INDEX idx_role_grant_community ON website.role_grant(community_id) WHERE community_id IS NOT NULL;
+
+-- Existing installs carry their website.user_roles over, 'regular' had no role to begin with
+DO $$
+BEGIN
+    IF to_regclass('website.user_roles') IS NOT NULL THEN
+        INSERT INTO website.role_grant (user_id, role_id, community_id)
+        SELECT ur.user_id, r.id, ur.community_id
+        FROM website.user_roles ur
+        JOIN website.role r ON r.name = ur.role
+        ON CONFLICT DO NOTHING;
+        DROP TABLE website.user_roles;
+    END IF;
+END
+$$;
 
 CREATE TABLE website.user_anonymization (
     user_id BIGINT REFERENCES website.steam_user(user_id) ON DELETE CASCADE,
@@ -456,28 +713,113 @@ CREATE TRIGGER update_user_anonymization_timestamp
     FOR EACH ROW
     EXECUTE FUNCTION update_anonymization_timestamp();
 
-CREATE OR REPLACE FUNCTION website.is_superuser(check_user_id BIGINT)
+-- Global grants apply everywhere, community grants to the community's servers too.
+-- Leave both scope arguments NULL to ask for a global permission.
+CREATE OR REPLACE FUNCTION website.has_permission(
+    check_user_id BIGINT,
+    check_permission TEXT,
+    check_community_id UUID DEFAULT NULL,
+    check_server_id TEXT DEFAULT NULL
+)
 RETURNS BOOLEAN AS $$
 BEGIN
 RETURN EXISTS (
-    SELECT 1 FROM website.user_roles
-    WHERE user_id = check_user_id AND role = 'superuser'
+    SELECT 1 FROM website.role_grant g
+    JOIN website.role r ON r.id = g.role_id
+    WHERE g.user_id = check_user_id
+      AND (check_permission = ANY(r.permissions) OR '*' = ANY(r.permissions))
+      AND (
+          (g.community_id IS NULL AND g.server_id IS NULL)
+          OR g.community_id = COALESCE(
+              check_community_id,
+              (SELECT s.community_id FROM server s WHERE s.server_id = check_server_id)
+          )
+          OR g.server_id = check_server_id
+      )
 );
 END;
 $$ LANGUAGE plpgsql;
 
-CREATE OR REPLACE FUNCTION website.is_community_admin(check_user_id BIGINT, check_community_id UUID)
+CREATE OR REPLACE FUNCTION website.is_superuser(check_user_id BIGINT)
 RETURNS BOOLEAN AS $$
 BEGIN
 RETURN EXISTS (
-    SELECT 1 FROM website.user_roles
-    WHERE user_id = check_user_id
-      AND role = 'community_admin'
-      AND community_id = check_community_id
+    SELECT 1 FROM website.role_grant g
+    JOIN website.role r ON r.id = g.role_id
+    WHERE g.user_id = check_user_id
+      AND g.community_id IS NULL AND g.server_id IS NULL
+      AND '*' = ANY(r.permissions)
 );
 END;
 $$ LANGUAGE plpgsql;
 
+-- Whoever may see through anonymization in this community
+CREATE OR REPLACE FUNCTION website.is_community_admin(check_user_id BIGINT, check_community_id UUID)
+RETURNS BOOLEAN AS $$
+BEGIN
+RETURN website.has_permission(check_user_id, 'players.view_anonymized', check_community_id);
+END;
+$$ LANGUAGE plpgsql;
+
+
+CREATE TABLE IF NOT EXISTS website.audit_log (
+    id BIGSERIAL PRIMARY KEY,
+    actor_id BIGINT REFERENCES website.steam_user(user_id) ON DELETE SET NULL,
+    action VARCHAR(64) NOT NULL,
+    target_type VARCHAR(64) NOT NULL,
+    target_id TEXT NOT NULL,
+    before JSONB,
+    after JSONB,
+    created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE INDEX IF NOT EXISTS idx_audit_log_target ON website.audit_log(target_type, target_id, created_at DESC);
+CREATE INDEX IF NOT EXISTS idx_audit_log_actor ON website.audit_log(actor_id, created_at DESC);
+
+-- Low models generated before map_3d_model.generated existed, known by the audit entry the model job left
+UPDATE website.map_3d_model_version v SET generated = TRUE
+FROM website.audit_log a
+WHERE a.action = 'generate' AND a.target_type = 'map_model'
+  AND a.target_id = v.map_name || '/low' AND v.res_type = 'low'
+  AND a.after->>'content_hash' = v.content_hash
+  AND NOT v.generated;
+UPDATE website.map_3d_model m SET generated = TRUE
+FROM website.map_3d_model_version v
+WHERE v.map_name = m.map_name AND v.res_type = m.res_type AND v.content_hash = m.content_hash
+  AND v.generated AND NOT m.generated;
+CREATE INDEX IF NOT EXISTS idx_audit_log_created ON website.audit_log(created_at DESC);
+
+COMMENT ON TABLE website.audit_log IS 'Who changed what, actor_id is NULL for background jobs';
+COMMENT ON COLUMN website.audit_log.target_id IS 'e.g. map_name/res_type for map_model, server_id/model_id for character_model';
+COMMENT ON COLUMN website.audit_log.before IS 'Fields the action changed, as they were. The whole target for deletes';
+COMMENT ON COLUMN website.audit_log.after IS 'Fields the action changed, as they are now. The whole target for creates';
+
+
+-- Self-service keys for bots and third-party sites. Only the SHA-256 of the key is kept,
+-- prefix is the part shown back to the owner. NULL limits fall back to API_KEY_* env defaults.
+CREATE TABLE IF NOT EXISTS website.api_key (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    user_id BIGINT NOT NULL REFERENCES website.steam_user(user_id) ON DELETE CASCADE,
+    name VARCHAR(64) NOT NULL,
+    prefix VARCHAR(16) NOT NULL,
+    key_hash CHAR(64) NOT NULL UNIQUE,
+    scopes TEXT[] NOT NULL DEFAULT '{}',
+    rate_per_minute INTEGER,
+    burst INTEGER,
+    daily_quota INTEGER,
+    monthly_quota INTEGER,
+    created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    last_used_at TIMESTAMP WITH TIME ZONE,
+    revoked_at TIMESTAMP WITH TIME ZONE
+);
+ALTER TABLE website.api_key ADD COLUMN IF NOT EXISTS daily_quota INTEGER;
+ALTER TABLE website.api_key ADD COLUMN IF NOT EXISTS monthly_quota INTEGER;
+
+CREATE INDEX IF NOT EXISTS idx_api_key_user ON website.api_key(user_id);
+
+COMMENT ON COLUMN website.api_key.daily_quota IS 'Requests per UTC day, NULL falls back to API_KEY_DAILY_QUOTA';
+COMMENT ON COLUMN website.api_key.monthly_quota IS 'Requests per calendar month, NULL falls back to API_KEY_MONTHLY_QUOTA';
+
 
 CREATE TYPE data_vote_type_enum AS ENUM ('UpVote', 'DownVote');
 
@@ -487,6 +829,11 @@ CREATE TABLE website.guides (
     map_name TEXT NOT NULL,
     title TEXT NOT NULL,
     content TEXT NOT NULL,
+    -- Rendered and sanitized from content on every write, cleared when a mentioned player's
+    -- anonymization changes so the markdown render job renders it again
+    content_html TEXT,
+    -- Plain text opening for link previews and the sitemap
+    excerpt TEXT,
     category TEXT NOT NULL,
     created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT current_timestamp,
     updated_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT current_timestamp,
@@ -495,7 +842,10 @@ CREATE TABLE website.guides (
     comment_count BIGINT NOT NULL DEFAULT 0,
     slug VARCHAR(120) NOT NULL UNIQUE,
     author_id BIGINT NOT NULL REFERENCES website.steam_user(user_id) ON DELETE CASCADE,
-    server_id VARCHAR(100) REFERENCES server(server_id)
+    server_id VARCHAR(100) REFERENCES server(server_id),
+    search_vector TSVECTOR GENERATED ALWAYS AS (
+        setweight(to_tsvector('english', title), 'A') || setweight(to_tsvector('english', content), 'B')
+    ) STORED
 );
 
 -- Create the guide_comments table
@@ -504,10 +854,12 @@ CREATE TABLE website.guide_comments (
     guide_id UUID NOT NULL REFERENCES website.guides(id) ON DELETE CASCADE,
     author_id BIGINT NOT NULL REFERENCES website.steam_user(user_id) ON DELETE CASCADE,
     content TEXT NOT NULL,
+    content_html TEXT,
     created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
     updated_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
     upvotes BIGINT NOT NULL DEFAULT 0,
-    downvotes BIGINT NOT NULL DEFAULT 0
+    downvotes BIGINT NOT NULL DEFAULT 0,
+    search_vector TSVECTOR GENERATED ALWAYS AS (to_tsvector('english', content)) STORED
 );
 
 -- Create indexes for common queries
@@ -521,6 +873,43 @@ CREATE INDEX idx_guide_comments_guide_id ON website.guide_comments(guide_id);
 CREATE INDEX idx_guide_comments_author_id ON website.guide_comments(author_id);
 CREATE INDEX idx_guide_comments_created_at ON website.guide_comments(created_at DESC);
 
+-- Used by /search, trigram indexes catch typos full-text search can't
+CREATE INDEX idx_guides_search ON website.guides USING gin (search_vector);
+CREATE INDEX idx_guides_title_trgm ON website.guides USING gin (title gin_trgm_ops);
+CREATE INDEX idx_guide_comments_search ON website.guide_comments USING gin (search_vector);
+-- Existing installs:
+-- ALTER TABLE website.guides ADD COLUMN search_vector TSVECTOR GENERATED ALWAYS AS (
+--     setweight(to_tsvector('english', title), 'A') || setweight(to_tsvector('english', content), 'B')
+-- ) STORED;
+-- ALTER TABLE website.guide_comments ADD COLUMN search_vector TSVECTOR
+--     GENERATED ALWAYS AS (to_tsvector('english', content)) STORED;
+-- ALTER TABLE website.guides ADD COLUMN content_html TEXT, ADD COLUMN excerpt TEXT;
+-- ALTER TABLE website.guide_comments ADD COLUMN content_html TEXT;
+-- Rows written before stay NULL until the markdown render job gets to them, clients fall back to content.
+
+-- Every state a guide has been in, revision 1 being the one it was created with
+CREATE TABLE IF NOT EXISTS website.guide_revision (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    guide_id UUID NOT NULL REFERENCES website.guides(id) ON DELETE CASCADE,
+    revision INTEGER NOT NULL,
+    title TEXT NOT NULL,
+    content TEXT NOT NULL,
+    category TEXT NOT NULL,
+    server_id VARCHAR(100),
+    -- Who made the edit, not necessarily the guide author
+    editor_id BIGINT NOT NULL REFERENCES website.steam_user(user_id) ON DELETE CASCADE,
+    -- Set when a moderator brought back an older revision
+    restored_from INTEGER,
+    created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    UNIQUE (guide_id, revision)
+);
+-- Guides without any revision, from before revisions were kept, start out with their current state
+INSERT INTO website.guide_revision (guide_id, revision, title, content, category, server_id, editor_id, created_at)
+SELECT g.id, 1, g.title, g.content, g.category, g.server_id, g.author_id, g.updated_at
+FROM website.guides g
+WHERE NOT EXISTS (SELECT 1 FROM website.guide_revision r WHERE r.guide_id = g.id)
+ON CONFLICT (guide_id, revision) DO NOTHING;
+
 
 CREATE TABLE website.guide_votes (
     guide_id UUID NOT NULL,
@@ -550,8 +939,12 @@ CREATE TABLE website.report_guide (
     status VARCHAR(20) NOT NULL DEFAULT 'pending' CHECK (status IN ('pending', 'resolved', 'dismissed')),
     resolved_by BIGINT REFERENCES website.steam_user(user_id) ON DELETE SET NULL,
     resolved_at TIMESTAMP WITH TIME ZONE,
-    timestamp TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT CURRENT_TIMESTAMP
+    timestamp TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    -- What the guide said when it was reported
+    revision INTEGER
 );
+-- Existing installs:
+-- ALTER TABLE website.report_guide ADD COLUMN revision INTEGER;
 CREATE INDEX idx_report_guide_status ON website.report_guide(status);
 
 CREATE TABLE website.report_guide_comment (
@@ -811,8 +1204,15 @@ CREATE TABLE map_metadata(
     is_tryhard BOOLEAN,
     is_casual BOOLEAN,
     has_lasers BOOLEAN,
-    resolved_workshop_id BIGINT
+    resolved_workshop_id BIGINT,
+    -- Map names split on underscores so ze_mako_reactor matches "mako"
+    search_vector TSVECTOR GENERATED ALWAYS AS (
+        setweight(to_tsvector('simple', replace(name, '_', ' ')), 'A')
+            || setweight(to_tsvector('simple', COALESCE(creators, '')), 'B')
+    ) STORED
 );
+CREATE INDEX idx_map_metadata_search ON map_metadata USING gin (search_vector);
+CREATE INDEX idx_map_metadata_name_trgm ON map_metadata USING gin (name gin_trgm_ops);
 
 CREATE TABLE map_music (
     id UUID PRIMARY KEY DEFAULT uuid_generate_v4(),
@@ -821,8 +1221,18 @@ CREATE TABLE map_music (
     youtube_music TEXT,
     source TEXT NOT NULL,
     tried_searching BOOLEAN NOT NULL DEFAULT false,
-    yt_source BIGINT NOT NULL DEFAULT 0
-);
+    yt_source BIGINT NOT NULL DEFAULT 0,
+    search_vector TSVECTOR GENERATED ALWAYS AS (to_tsvector('simple', music_name)) STORED
+);
+CREATE INDEX idx_map_music_search ON map_music USING gin (search_vector);
+CREATE INDEX idx_map_music_name_trgm ON map_music USING gin (music_name gin_trgm_ops);
+-- Existing installs:
+-- ALTER TABLE map_metadata ADD COLUMN search_vector TSVECTOR GENERATED ALWAYS AS (
+--     setweight(to_tsvector('simple', replace(name, '_', ' ')), 'A')
+--         || setweight(to_tsvector('simple', COALESCE(creators, '')), 'B')
+-- ) STORED;
+-- ALTER TABLE map_music ADD COLUMN search_vector TSVECTOR
+--     GENERATED ALWAYS AS (to_tsvector('simple', music_name)) STORED;
 
 CREATE TABLE associated_map_music (
     id SERIAL PRIMARY KEY,
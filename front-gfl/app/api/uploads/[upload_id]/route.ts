@@ -0,0 +1,18 @@
+import { proxyTus } from "lib/tusProxy";
+
+type Params = { params: Promise<{ upload_id: string }> };
+
+export async function HEAD(req: Request, { params }: Params) {
+    const { upload_id } = await params;
+    return proxyTus(`/uploads/${upload_id}`, req, 'HEAD');
+}
+
+export async function PATCH(req: Request, { params }: Params) {
+    const { upload_id } = await params;
+    return proxyTus(`/uploads/${upload_id}`, req, 'PATCH');
+}
+
+export async function DELETE(req: Request, { params }: Params) {
+    const { upload_id } = await params;
+    return proxyTus(`/uploads/${upload_id}`, req, 'DELETE');
+}
@@ -0,0 +1,5 @@
+import { proxyTus } from "lib/tusProxy";
+
+export async function POST(req: Request) {
+    return proxyTus('/uploads', req, 'POST');
+}
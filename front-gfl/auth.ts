@@ -107,21 +107,26 @@ export function getAuthOptions(req?: NextRequest): AuthOptions {
             : [],
         callbacks: {
             jwt({ token, account, profile }) {
+                const now = Math.floor(Date.now() / 1000);
                 if (account?.provider === STEAM_PROVIDER_ID) {
                     token.steam = profile
+                    token.signedInAt = now
                 }
-                const now = Math.floor(Date.now() / 1000);
+                // Backend tokens keep the sign in time as `iat`, so revoking every token issued
+                // before a moment also ends sessions that keep re-signing them
+                token.signedInAt ??= token.iat ?? now
                 // @ts-expect-error
-                const expiresIn = Math.max(token.exp ?? 0 - now, 60);
+                const expiresIn = Math.max((token.exp ?? 0) - now, 60);
                 token.backendJwt = jwt.sign(
                     {
                         sub: token.sub,
                         type: 'access',
                         name: token.name,
                         iss: "ze-graph",
+                        iat: token.signedInAt,
+                        exp: now + expiresIn,
                     },
                     process.env.NEXTAUTH_SECRET,
-                    { expiresIn }
                 );
 
                 return token
@@ -1,6 +1,7 @@
 import {NextRequest, NextResponse} from "next/server";
 import { auth } from "../auth";
-import { BACKEND_DOMAIN } from "utils/generalUtils";
+import { BACKEND_DOMAIN, forwardedHeaders } from "utils/generalUtils";
+import { headers as requestHeaders } from "next/headers";
 import { CACHE_HEADERS, withCacheHeaders } from './cacheHeaders';
 
 export async function proxyToBackend(
@@ -26,7 +27,10 @@ export async function proxyToBackend(
         }
     }
 
-    const headers: HeadersInit = { "Content-Type": "application/json" };
+    const headers: HeadersInit = {
+        "Content-Type": "application/json",
+        ...forwardedHeaders(req?.headers ?? await requestHeaders()),
+    };
     // @ts-ignore
     if (session?.backendJwt) {
         // @ts-ignore
@@ -83,7 +87,7 @@ export async function proxyToBackendChange<T>(
     const session = await auth()
     const addition = searchParams? `?${new URLSearchParams(searchParams).toString()}`: ''
     const backendUrl = new URL(BACKEND_DOMAIN + endpoint + addition)
-    const headers = {"Content-Type": "application/json"}
+    const headers = {"Content-Type": "application/json", ...forwardedHeaders(await requestHeaders())}
     if(session){
         // @ts-ignore
         headers['Authorization'] = `Bearer ${session?.backendJwt}`
@@ -0,0 +1,48 @@
+import { auth } from "../auth";
+import { BACKEND_DOMAIN, forwardedHeaders } from "utils/generalUtils";
+
+// tus headers that have to reach the backend, and the ones the client needs back
+const REQUEST_HEADERS = [
+    'Tus-Resumable', 'Upload-Length', 'Upload-Metadata', 'Upload-Offset', 'Upload-Checksum', 'Content-Type',
+];
+const RESPONSE_HEADERS = [
+    'Tus-Resumable', 'Tus-Version', 'Tus-Extension', 'Tus-Max-Size', 'Tus-Checksum-Algorithm',
+    'Upload-Offset', 'Upload-Length', 'Upload-Expires', 'Location', 'Cache-Control',
+];
+
+export async function proxyTus(endpoint: string, req: Request, method: 'POST' | 'HEAD' | 'PATCH' | 'DELETE') {
+    const session = await auth();
+
+    const headers: Record<string, string> = forwardedHeaders(req.headers);
+    for (const name of REQUEST_HEADERS) {
+        const value = req.headers.get(name);
+        if (value !== null) headers[name] = value;
+    }
+    // @ts-ignore
+    if (session?.backendJwt) {
+        // @ts-ignore
+        headers["Authorization"] = `Bearer ${session.backendJwt}`;
+    }
+
+    const backendResponse = await fetch(BACKEND_DOMAIN + endpoint, {
+        method,
+        headers,
+        cache: "no-store",
+        body: method === 'PATCH' ? await req.arrayBuffer() : undefined,
+    });
+
+    const responseHeaders = new Headers();
+    for (const name of RESPONSE_HEADERS) {
+        const value = backendResponse.headers.get(name);
+        if (value !== null) responseHeaders.set(name, value);
+    }
+    // The backend hands out its own path, the browser reaches it through /api
+    const location = responseHeaders.get('Location');
+    if (location) responseHeaders.set('Location', `/api${location}`);
+
+    const hasBody = method !== 'HEAD' && backendResponse.status !== 204;
+    return new Response(hasBody ? await backendResponse.text() : null, {
+        status: backendResponse.status,
+        headers: responseHeaders,
+    });
+}
@@ -1,6 +1,7 @@
 import dayjs from "dayjs";
 import {MapImage} from "types/maps";
 import {cookies} from "next/dist/server/request/cookies";
+import {headers as requestHeaders} from "next/dist/server/request/headers";
 
 const API_ROOT = "/data/api"
 const NEXTAPI_ROOT = "/api"
@@ -10,6 +11,16 @@ export const DOMAIN = process.env.NEXT_PUBLIC_DOMAIN ?? "https://zegraph.xyz";
 export const CONTACT_EMAIL = "contact@queeniemella.cc";
 export const ICE_FILE_ENDPOINT = "https://bans.gflclan.com/file/uploads/{}/avatar.webp"
 
+// The backend rate limits per client address, so calls made on a visitor's behalf pass theirs along
+export function forwardedHeaders(source: Headers): Record<string, string> {
+    const forwarded: Record<string, string> = {}
+    for (const name of ["X-Forwarded-For", "X-Real-IP"]) {
+        const value = source.get(name)
+        if (value !== null) forwarded[name] = value
+    }
+    return forwarded
+}
+
 
 export const REGION_COLORS = {
     "Asia + EU": "rgba(255, 99, 132, 0.3)",
@@ -140,6 +151,18 @@ export async function fetchUrl(endpoint: string, options: any = {}, errorOnStill
             Cookie: cookieStore.toString()
         };
     }
+    // Cached fetches are shared between visitors, and headers are part of their cache key
+    const isCached = options?.next?.revalidate !== undefined || options?.cache === "force-cache"
+    if (isOnServer && !isCached) {
+        try {
+            options.headers = {
+                ...options.headers,
+                ...forwardedHeaders(await requestHeaders())
+            };
+        } catch {
+            // Outside of a request, e.g. revalidating in the background
+        }
+    }
 
     const rawOutput = options?.raw_output ?? false
     const method = URI(endpoint, options?.backend)
@@ -1,9 +1,10 @@
-import { fetchApiUrl } from './generalUtils';
-import { Map3DModel, Character3DModel } from '../types/maps';
+import { URI } from './generalUtils';
 
 export const CHUNK_SIZE = 10 * 1024 * 1024; // 10MB
 export const CHUNKED_UPLOAD_THRESHOLD = 50 * 1024 * 1024; // 50MB
 
+const TUS_VERSION = '1.0.0';
+
 export interface UploadProgress {
   totalChunks: number;
   uploadedChunks: number;
@@ -34,28 +35,55 @@ export interface CharacterChunkedUploadOptions {
   signal?: AbortSignal;
 }
 
-interface InitiateUploadResponse {
-  session_id: string;
-  chunk_size: number;
-  total_chunks: number;
+class UploadRequestError extends Error {
+  public status: number;
+  constructor(message: string, status: number) {
+    super(message);
+    this.status = status;
+  }
 }
 
-interface ChunkUploadResponse {
-  chunk_index: number;
-  received: boolean;
-  chunks_remaining: number;
+const sleep = (ms: number) => new Promise(resolve => setTimeout(resolve, ms));
+
+// Upload-Metadata: comma separated "key base64(value)" pairs
+function encodeMetadata(metadata: Record<string, string | undefined>): string {
+  return Object.entries(metadata)
+    .filter(([, value]) => value)
+    .map(([key, value]) => {
+      const bytes = new TextEncoder().encode(value);
+      return `${key} ${btoa(String.fromCharCode(...bytes))}`;
+    })
+    .join(',');
 }
 
-const sleep = (ms: number) => new Promise(resolve => setTimeout(resolve, ms));
+async function tusRequest(url: string, init: RequestInit): Promise<Response> {
+  const response = await fetch(url, {
+    ...init,
+    headers: { 'Tus-Resumable': TUS_VERSION, ...init.headers },
+  });
+  if (!response.ok) {
+    const message = init.method === 'HEAD' ? '' : await response.text();
+    throw new UploadRequestError(message || response.statusText, response.status);
+  }
+  return response;
+}
 
-async function uploadChunkWithRetry(
-  mapName: string,
-  sessionId: string,
-  chunkIndex: number,
-  chunkData: Blob,
-  maxRetries: number = 3,
-  signal?: AbortSignal
-): Promise<ChunkUploadResponse> {
+async function currentOffset(url: string, signal?: AbortSignal): Promise<number> {
+  const response = await tusRequest(url, { method: 'HEAD', signal });
+  return Number(response.headers.get('Upload-Offset'));
+}
+
+// Sends file[start..end) and returns the new offset. The final PATCH also stores the model,
+// when that fails the server keeps the bytes and an empty PATCH at the end retries it.
+async function patchWithRetry(
+  url: string,
+  file: File,
+  start: number,
+  end: number,
+  signal?: AbortSignal,
+  maxRetries: number = 3
+): Promise<number> {
+  let offset = start;
   let lastError: Error | null = null;
 
   for (let attempt = 0; attempt < maxRetries; attempt++) {
@@ -64,20 +92,16 @@ async function uploadChunkWithRetry(
     }
 
     try {
-      const formData = new FormData();
-      formData.append('chunk_index', chunkIndex.toString());
-      formData.append('chunk_data', chunkData);
-
-      const response = await fetchApiUrl(
-        `/maps/${mapName}/3d/upload/chunk/${sessionId}`,
-        {
-          method: 'POST',
-          body: formData,
-          signal,
-        }
-      );
-
-      return response as ChunkUploadResponse;
+      const response = await tusRequest(url, {
+        method: 'PATCH',
+        headers: {
+          'Content-Type': 'application/offset+octet-stream',
+          'Upload-Offset': offset.toString(),
+        },
+        body: file.slice(offset, end),
+        signal,
+      });
+      return Number(response.headers.get('Upload-Offset'));
     } catch (error) {
       lastError = error as Error;
 
@@ -86,130 +110,91 @@ async function uploadChunkWithRetry(
         throw error;
       }
 
-      // Don't retry on client errors (400-499)
-      if (error instanceof Error && 'status' in error) {
-        const status = (error as any).status;
-        if (status >= 400 && status < 500) {
+      // Don't retry on client errors, except a stale offset (409) or a PATCH still being written (423)
+      if (error instanceof UploadRequestError) {
+        const { status } = error;
+        if (status >= 400 && status < 500 && status !== 409 && status !== 423) {
           throw error;
         }
       }
 
-      // Exponential backoff: 1s, 2s, 3s
+      // Backoff: 1s, 2s, 3s, then continue from whatever the server has
       if (attempt < maxRetries - 1) {
         const delay = (attempt + 1) * 1000;
         console.warn(
-          `Chunk ${chunkIndex} upload failed (attempt ${attempt + 1}/${maxRetries}), retrying in ${delay}ms...`,
+          `Upload at offset ${offset} failed (attempt ${attempt + 1}/${maxRetries}), retrying in ${delay}ms...`,
           error
         );
         await sleep(delay);
+        offset = Math.min(await currentOffset(url, signal), end);
       }
     }
   }
 
-  throw lastError || new Error(`Failed to upload chunk ${chunkIndex} after ${maxRetries} attempts`);
+  throw lastError || new Error(`Failed to upload bytes ${start}-${end} after ${maxRetries} attempts`);
 }
 
-export async function uploadFileChunked(
-  options: ChunkedUploadOptions
-): Promise<Map3DModel> {
-  const { mapName, file, resType, credit, onProgress, onError, signal } = options;
+async function uploadResumable(
+  metadata: Record<string, string | undefined>,
+  file: File,
+  onProgress?: (progress: UploadProgress) => void,
+  signal?: AbortSignal
+): Promise<void> {
+  const created = await tusRequest(URI('/uploads', true), {
+    method: 'POST',
+    headers: {
+      'Upload-Length': file.size.toString(),
+      'Upload-Metadata': encodeMetadata(metadata),
+    },
+    signal,
+  });
+  const uploadUrl = created.headers.get('Location');
+  if (!uploadUrl) {
+    throw new Error('Upload was created without a location');
+  }
 
+  const totalChunks = Math.ceil(file.size / CHUNK_SIZE);
+  let offset = 0;
   try {
-    // Step 1: Initiate upload session
-    const initiateResponse = await fetchApiUrl(
-      `/maps/${mapName}/3d/upload/initiate`,
-      {
-        method: 'POST',
-        headers: {
-          'Content-Type': 'application/json',
-        },
-        body: JSON.stringify({
-          res_type: resType,
-          credit: credit || undefined,
-          file_size: file.size,
-          file_name: file.name,
-        }),
-        signal,
-      }
-    ) as InitiateUploadResponse;
-
-    const { session_id, total_chunks } = initiateResponse;
-
-    // Step 2: Upload chunks sequentially
-    for (let chunkIndex = 0; chunkIndex < total_chunks; chunkIndex++) {
+    while (offset < file.size) {
       if (signal?.aborted) {
-        // Cancel the upload session
-        try {
-          await fetchApiUrl(
-            `/maps/${mapName}/3d/upload/cancel/${session_id}`,
-            {
-              method: 'DELETE',
-              signal: undefined, // Don't pass abort signal to cancel request
-            }
-          );
-        } catch (cancelError) {
-          console.error('Failed to cancel upload session:', cancelError);
-        }
         throw new DOMException('Upload cancelled', 'AbortError');
       }
 
-      const start = chunkIndex * CHUNK_SIZE;
-      const end = Math.min(start + CHUNK_SIZE, file.size);
-      const chunkData = file.slice(start, end);
-
-      try {
-        await uploadChunkWithRetry(
-          mapName,
-          session_id,
-          chunkIndex,
-          chunkData,
-          3,
-          signal
-        );
-
-        // Update progress
-        const bytesUploaded = end;
-        const progress: UploadProgress = {
-          totalChunks: total_chunks,
-          uploadedChunks: chunkIndex + 1,
-          percentage: (bytesUploaded / file.size) * 100,
-          currentChunk: chunkIndex,
-          bytesUploaded,
-          totalBytes: file.size,
-        };
-
-        onProgress?.(progress);
-      } catch (error) {
-        const err = error as Error;
-        onError?.(err, chunkIndex);
-
-        // Try to cancel the session on error
-        try {
-          await fetchApiUrl(
-            `/maps/${mapName}/3d/upload/cancel/${session_id}`,
-            {
-              method: 'DELETE',
-              signal: undefined,
-            }
-          );
-        } catch (cancelError) {
-          console.error('Failed to cancel upload session after error:', cancelError);
-        }
-
-        throw err;
-      }
+      const end = Math.min(offset + CHUNK_SIZE, file.size);
+      offset = await patchWithRetry(uploadUrl, file, offset, end, signal);
+
+      const uploadedChunks = Math.ceil(offset / CHUNK_SIZE);
+      onProgress?.({
+        totalChunks,
+        uploadedChunks,
+        percentage: (offset / file.size) * 100,
+        currentChunk: uploadedChunks - 1,
+        bytesUploaded: offset,
+        totalBytes: file.size,
+      });
     }
+  } catch (error) {
+    // Drop what the server has so far, without the abort signal so this still goes out
+    try {
+      await tusRequest(uploadUrl, { method: 'DELETE' });
+    } catch (terminateError) {
+      console.error('Failed to terminate upload:', terminateError);
+    }
+    throw error;
+  }
+}
 
-    // Step 3: Complete upload
-    const result = await fetchApiUrl(
-      `/maps/${mapName}/3d/upload/complete/${session_id}`,
-      {
-        method: 'POST',
-        signal,
-      }
-    ) as Map3DModel;
+export async function uploadFileChunked(options: ChunkedUploadOptions): Promise<void> {
+  const { mapName, file, resType, credit, onProgress, onError, signal } = options;
 
-    return result;
+  try {
+    await uploadResumable(
+      { kind: 'map_model', map_name: mapName, res_type: resType, credit },
+      file,
+      onProgress,
+      signal
+    );
   } catch (error) {
     if (error instanceof DOMException && error.name === 'AbortError') {
       throw error;
@@ -221,116 +206,21 @@ export async function uploadFileChunked(
   }
 }
 
-async function uploadCharacterChunkWithRetry(
-  serverId: string,
-  modelId: string,
-  sessionId: string,
-  chunkIndex: number,
-  chunkData: Blob,
-  maxRetries: number = 3,
-  signal?: AbortSignal
-): Promise<ChunkUploadResponse> {
-  let lastError: Error | null = null;
-
-  for (let attempt = 0; attempt < maxRetries; attempt++) {
-    if (signal?.aborted) {
-      throw new DOMException('Upload cancelled', 'AbortError');
-    }
-
-    try {
-      const formData = new FormData();
-      formData.append('chunk_index', chunkIndex.toString());
-      formData.append('chunk_data', chunkData);
-
-      const response = await fetchApiUrl(
-        `/servers/${serverId}/characters/${modelId}/3d/upload/chunk/${sessionId}`,
-        { method: 'POST', body: formData, signal }
-      );
-
-      return response as ChunkUploadResponse;
-    } catch (error) {
-      lastError = error as Error;
-
-      if (error instanceof DOMException && error.name === 'AbortError') {
-        throw error;
-      }
-
-      if (error instanceof Error && 'status' in error) {
-        const status = (error as any).status;
-        if (status >= 400 && status < 500) throw error;
-      }
-
-      if (attempt < maxRetries - 1) {
-        await sleep((attempt + 1) * 1000);
-      }
-    }
-  }
-
-  throw lastError || new Error(`Failed to upload chunk ${chunkIndex} after ${maxRetries} attempts`);
-}
-
-export async function uploadCharacterFileChunked(
-  options: CharacterChunkedUploadOptions
-): Promise<Character3DModel> {
+export async function uploadCharacterFileChunked(options: CharacterChunkedUploadOptions): Promise<void> {
   const { modelId, name, serverId, file, credit, onProgress, onError, signal } = options;
 
   try {
-    const initiateResponse = await fetchApiUrl(
-      `/servers/${serverId}/characters/${modelId}/3d/upload/initiate`,
-      {
-        method: 'POST',
-        headers: { 'Content-Type': 'application/json' },
-        body: JSON.stringify({ name: name || undefined, credit: credit || undefined, file_size: file.size, file_name: file.name }),
-        signal,
-      }
-    ) as InitiateUploadResponse;
-
-    const { session_id, total_chunks } = initiateResponse;
-
-    for (let chunkIndex = 0; chunkIndex < total_chunks; chunkIndex++) {
-      if (signal?.aborted) {
-        try {
-          await fetchApiUrl(`/servers/${serverId}/characters/${modelId}/3d/upload/cancel/${session_id}`, { method: 'DELETE' });
-        } catch {}
-        throw new DOMException('Upload cancelled', 'AbortError');
-      }
-
-      const start = chunkIndex * CHUNK_SIZE;
-      const end = Math.min(start + CHUNK_SIZE, file.size);
-      const chunkData = file.slice(start, end);
-
-      try {
-        await uploadCharacterChunkWithRetry(serverId, modelId, session_id, chunkIndex, chunkData, 3, signal);
-
-        const bytesUploaded = end;
-        onProgress?.({
-          totalChunks: total_chunks,
-          uploadedChunks: chunkIndex + 1,
-          percentage: (bytesUploaded / file.size) * 100,
-          currentChunk: chunkIndex,
-          bytesUploaded,
-          totalBytes: file.size,
-        });
-      } catch (error) {
-        const err = error as Error;
-        onError?.(err, chunkIndex);
-        try {
-          await fetchApiUrl(`/servers/${serverId}/characters/${modelId}/3d/upload/cancel/${session_id}`, { method: 'DELETE' });
-        } catch {}
-        throw err;
-      }
-    }
-
-    const result = await fetchApiUrl(
-      `/servers/${serverId}/characters/${modelId}/3d/upload/complete/${session_id}`,
-      { method: 'POST', signal }
-    ) as Character3DModel;
-
-    return result;
+    await uploadResumable(
+      { kind: 'character_model', server_id: serverId, model_id: modelId, name, credit },
+      file,
+      onProgress,
+      signal
+    );
   } catch (error) {
     if (error instanceof DOMException && error.name === 'AbortError') {
       throw error;
     }
+
     const err = error as Error;
     onError?.(err);
     throw err;
@@ -4,4 +4,19 @@ pub mod model;
 pub mod utils;
 pub mod workers;
 pub mod push_service;
-pub mod map_storage;
+pub mod asset_storage;
+pub mod achievements;
+pub mod glb;
+pub mod uploads;
+pub mod model_processing;
+pub mod audit;
+pub mod permissions;
+pub mod api_keys;
+pub mod rate_limit;
+pub mod sessions;
+pub mod discord;
+pub mod data_export;
+pub mod account_deletion;
+pub mod anonymization;
+pub mod guide_revisions;
+pub mod markdown;
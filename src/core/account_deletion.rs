@@ -0,0 +1,219 @@
+use std::time::Duration;
+
+use serde_json::json;
+use sqlx::{Pool, Postgres};
+use uuid::Uuid;
+
+use crate::AppData;
+use crate::core::anonymization::forget_privacy;
+use crate::core::audit::record_audit;
+use crate::core::model::DbAccountDeletion;
+use crate::core::sessions::deny_tokens_before_now;
+use crate::core::utils::{get_env_bool, get_env_default};
+
+const POLL_INTERVAL: Duration = Duration::from_secs(60);
+/// Running deletions older than this were lost to a restart and get picked up again.
+const STUCK_AFTER_SECS: f64 = 3600.0;
+const MAX_ATTEMPTS: i32 = 3;
+const DEFAULT_GRACE_DAYS: i32 = 14;
+/// `website.steam_user` row that authored content is handed to
+pub const DELETED_USER_ID: i64 = 0;
+
+/// `ACCOUNT_DELETION_GRACE_DAYS`, how long a deletion can still be undone.
+pub fn grace_days() -> i32 {
+    get_env_default("ACCOUNT_DELETION_GRACE_DAYS")
+        .and_then(|s| s.parse().ok())
+        .filter(|d| *d >= 0)
+        .unwrap_or(DEFAULT_GRACE_DAYS)
+}
+
+async fn claim_deletion(pool: &Pool<Postgres>) -> Result<Option<DbAccountDeletion>, sqlx::Error> {
+    sqlx::query!(
+        "UPDATE website.account_deletion
+         SET status = CASE WHEN attempts < $1 THEN 'scheduled' ELSE 'failed' END,
+             error = CASE WHEN attempts < $1 THEN NULL ELSE 'Interrupted too many times' END,
+             completed_at = CASE WHEN attempts < $1 THEN NULL ELSE NOW() END
+         WHERE status = 'running' AND started_at < NOW() - make_interval(secs => $2)",
+        MAX_ATTEMPTS, STUCK_AFTER_SECS
+    )
+    .execute(pool)
+    .await?;
+
+    sqlx::query_as!(
+        DbAccountDeletion,
+        "UPDATE website.account_deletion
+         SET status = 'running', started_at = NOW(), attempts = attempts + 1
+         WHERE id = (
+             SELECT id FROM website.account_deletion
+             WHERE status = 'scheduled' AND scheduled_for <= NOW()
+             ORDER BY scheduled_for
+             LIMIT 1
+             FOR UPDATE SKIP LOCKED
+         )
+         RETURNING *"
+    )
+    .fetch_optional(pool)
+    .await
+}
+
+async fn finish_deletion(pool: &Pool<Postgres>, deletion_id: Uuid, result: &Result<serde_json::Value, String>) {
+    let (status, summary, error) = match result {
+        Ok(summary) => ("done", Some(summary.clone()), None),
+        Err(e) => ("failed", None, Some(e.clone())),
+    };
+    let result = sqlx::query!(
+        "UPDATE website.account_deletion SET status = $2, summary = $3, error = $4, completed_at = NOW()
+         WHERE id = $1",
+        deletion_id, status, summary, error
+    )
+    .execute(pool)
+    .await;
+    if let Err(e) = result {
+        tracing::error!("Failed to update account deletion {deletion_id}: {e}");
+    }
+}
+
+/// Carries out deletions whose grace period is over. Claimed with `SKIP LOCKED` like model
+/// jobs, so several instances can run it. Disable with `ACCOUNT_DELETIONS=false`.
+pub async fn run_account_deletions(app: AppData) {
+    if !get_env_bool("ACCOUNT_DELETIONS", true) {
+        tracing::info!("Account deletions are disabled");
+        return
+    }
+    loop {
+        let deletion = match claim_deletion(&app.pool).await {
+            Ok(Some(deletion)) => deletion,
+            Ok(None) => {
+                tokio::time::sleep(POLL_INTERVAL).await;
+                continue
+            }
+            Err(e) => {
+                tracing::error!("Failed to claim account deletion: {e}");
+                tokio::time::sleep(POLL_INTERVAL).await;
+                continue
+            }
+        };
+
+        let result = delete_account(&app, deletion.user_id).await;
+        match &result {
+            Ok(summary) => {
+                tracing::info!("Deleted account {}", deletion.user_id);
+                record_audit(
+                    &app.pool, None, "delete_account", "user", &deletion.user_id.to_string(),
+                    None, Some(json!({ "deletion_id": deletion.id, "summary": summary })),
+                ).await;
+            }
+            Err(e) => tracing::warn!("Deleting account {} failed: {e}", deletion.user_id),
+        }
+        finish_deletion(&app.pool, deletion.id, &result).await;
+    }
+}
+
+/// Removes what only mattered to the user and detaches what other people still read. Guides,
+/// comments and guide reports stay, owned by [`DELETED_USER_ID`], music reports go. The `steam_user` row stays
+/// too, scrubbed, so the anonymization it now has in every community keeps applying to the
+/// player records game servers hold under the same Steam ID.
+async fn delete_account(app: &AppData, user_id: i64) -> Result<serde_json::Value, String> {
+    let err = |e: sqlx::Error| format!("Failed to delete account data: {e}");
+    let mut tx = app.pool.begin().await.map_err(err)?;
+
+    let guides = sqlx::query!(
+        "UPDATE website.guides SET author_id = $2 WHERE author_id = $1", user_id, DELETED_USER_ID
+    ).execute(&mut *tx).await.map_err(err)?.rows_affected();
+    sqlx::query!(
+        "UPDATE website.guide_revision SET editor_id = $2 WHERE editor_id = $1", user_id, DELETED_USER_ID
+    ).execute(&mut *tx).await.map_err(err)?;
+    let comments = sqlx::query!(
+        "UPDATE website.guide_comments SET author_id = $2 WHERE author_id = $1", user_id, DELETED_USER_ID
+    ).execute(&mut *tx).await.map_err(err)?.rows_affected();
+    let reports = sqlx::query!(
+        "UPDATE website.report_guide SET user_id = $2 WHERE user_id = $1", user_id, DELETED_USER_ID
+    ).execute(&mut *tx).await.map_err(err)?.rows_affected()
+        + sqlx::query!(
+            "UPDATE website.report_guide_comment SET user_id = $2 WHERE user_id = $1", user_id, DELETED_USER_ID
+        ).execute(&mut *tx).await.map_err(err)?.rows_affected();
+    // One report per track, reporter and status is allowed, so these can't all move to the
+    // placeholder user the way guide reports do
+    let music_reports = sqlx::query!("DELETE FROM website.report_map_music WHERE user_id = $1", user_id)
+        .execute(&mut *tx).await.map_err(err)?.rows_affected();
+    // Reports the user handled as a moderator stay handled, without saying by whom
+    sqlx::query!("UPDATE website.report_guide SET resolved_by = NULL WHERE resolved_by = $1", user_id)
+        .execute(&mut *tx).await.map_err(err)?;
+    sqlx::query!("UPDATE website.report_guide_comment SET resolved_by = NULL WHERE resolved_by = $1", user_id)
+        .execute(&mut *tx).await.map_err(err)?;
+    sqlx::query!("UPDATE website.report_map_music SET resolved_by = NULL WHERE resolved_by = $1", user_id)
+        .execute(&mut *tx).await.map_err(err)?;
+
+    // Vote counts on guides and comments follow through their triggers
+    let votes = sqlx::query!("DELETE FROM website.guide_votes WHERE user_id = $1", user_id)
+        .execute(&mut *tx).await.map_err(err)?.rows_affected()
+        + sqlx::query!("DELETE FROM website.guide_comment_votes WHERE user_id = $1", user_id)
+            .execute(&mut *tx).await.map_err(err)?.rows_affected();
+
+    let subscriptions = sqlx::query!("DELETE FROM website.map_change_subscriptions WHERE user_id = $1", user_id)
+        .execute(&mut *tx).await.map_err(err)?.rows_affected()
+        + sqlx::query!("DELETE FROM website.map_notify_subscriptions WHERE user_id = $1", user_id)
+            .execute(&mut *tx).await.map_err(err)?.rows_affected()
+        + sqlx::query!("DELETE FROM website.push_subscriptions WHERE user_id = $1", user_id)
+            .execute(&mut *tx).await.map_err(err)?.rows_affected();
+    sqlx::query!("DELETE FROM website.push_notification_log WHERE user_id = $1", user_id)
+        .execute(&mut *tx).await.map_err(err)?;
+    sqlx::query!("DELETE FROM website.notification_preferences WHERE user_id = $1", user_id)
+        .execute(&mut *tx).await.map_err(err)?;
+    let favorites = sqlx::query!("DELETE FROM website.user_favorite_maps WHERE user_id = $1", user_id)
+        .execute(&mut *tx).await.map_err(err)?.rows_affected();
+
+    let sessions = sqlx::query!("DELETE FROM website.user_refresh_tokens WHERE user_id = $1", user_id)
+        .execute(&mut *tx).await.map_err(err)?.rows_affected();
+    let api_keys = sqlx::query!("DELETE FROM website.api_key WHERE user_id = $1", user_id)
+        .execute(&mut *tx).await.map_err(err)?.rows_affected();
+    let discord = sqlx::query!("DELETE FROM website.discord_user WHERE steam_user_id = $1", user_id)
+        .execute(&mut *tx).await.map_err(err)?.rows_affected();
+    let role_grants = sqlx::query!("DELETE FROM website.role_grant WHERE user_id = $1", user_id)
+        .execute(&mut *tx).await.map_err(err)?.rows_affected();
+    let exports = sqlx::query_scalar!(
+        "DELETE FROM website.data_export WHERE user_id = $1 RETURNING id", user_id
+    ).fetch_all(&mut *tx).await.map_err(err)?;
+
+    let communities = sqlx::query_scalar!(
+        "INSERT INTO website.user_anonymization (user_id, community_id, anonymized, hide_location)
+         SELECT $1, community_id, TRUE, TRUE FROM community
+         ON CONFLICT (user_id, community_id)
+         DO UPDATE SET anonymized = TRUE, hide_location = TRUE, updated_at = CURRENT_TIMESTAMP
+         RETURNING community_id AS \"community_id!\"",
+        user_id
+    ).fetch_all(&mut *tx).await.map_err(err)?;
+
+    sqlx::query!(
+        "UPDATE website.steam_user
+         SET persona_name = 'Deleted user', profile_url = '', avatar = '', avatar_medium = '',
+             avatar_full = '', avatar_hash = '', primary_clan_id = '', deleted_at = NOW()
+         WHERE user_id = $1",
+        user_id
+    ).execute(&mut *tx).await.map_err(err)?;
+
+    tx.commit().await.map_err(err)?;
+
+    deny_tokens_before_now(app, user_id).await;
+    for community_id in &communities {
+        forget_privacy(app, *community_id).await;
+    }
+    for export_id in exports {
+        let _ = app.export_storage.delete(export_id).await;
+    }
+
+    Ok(json!({
+        "guides_anonymized": guides,
+        "comments_anonymized": comments,
+        "reports_anonymized": reports,
+        "music_reports_removed": music_reports,
+        "votes_removed": votes,
+        "subscriptions_removed": subscriptions,
+        "favorites_removed": favorites,
+        "sessions_removed": sessions,
+        "api_keys_removed": api_keys,
+        "discord_unlinked": discord > 0,
+        "role_grants_removed": role_grants,
+        "communities_anonymized": communities.len(),
+    }))
+}
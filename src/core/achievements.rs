@@ -0,0 +1,303 @@
+use std::sync::Arc;
+use std::time::Duration;
+use chrono::{DateTime, Utc};
+use serde::Deserialize;
+use sqlx::postgres::PgListener;
+use sqlx::{Pool, Postgres};
+use crate::AppData;
+use crate::core::api_models::*;
+use crate::core::model::*;
+use crate::core::push_service::{NotificationType, PushNotificationService};
+use crate::core::utils::*;
+
+const RECONNECT_DELAY: Duration = Duration::from_secs(30);
+
+pub enum AchievementCriteria {
+    /// Total playtime on a single server, in hours.
+    ServerPlaytimeHours(f64),
+    /// Played every enabled map currently in the server's rotation.
+    FullRotation,
+    /// Best rank on any map of the server is at or above this.
+    MapRankAtMost(i64),
+    /// Consecutive days with at least one session on the server.
+    DailyStreak(i64),
+    /// Sessions started between midnight and 5 AM UTC.
+    NightSessions(i64),
+}
+
+pub struct AchievementDefinition {
+    pub key: &'static str,
+    pub name: &'static str,
+    pub description: &'static str,
+    pub criteria: AchievementCriteria,
+}
+
+pub const ACHIEVEMENTS: &[AchievementDefinition] = &[
+    AchievementDefinition {
+        key: "playtime_10h",
+        name: "Regular",
+        description: "Play 10 hours on a server",
+        criteria: AchievementCriteria::ServerPlaytimeHours(10.),
+    },
+    AchievementDefinition {
+        key: "playtime_100h",
+        name: "Veteran",
+        description: "Play 100 hours on a server",
+        criteria: AchievementCriteria::ServerPlaytimeHours(100.),
+    },
+    AchievementDefinition {
+        key: "playtime_1000h",
+        name: "No Life",
+        description: "Play 1000 hours on a server",
+        criteria: AchievementCriteria::ServerPlaytimeHours(1000.),
+    },
+    AchievementDefinition {
+        key: "full_rotation",
+        name: "Map Collector",
+        description: "Play every map in the server's rotation",
+        criteria: AchievementCriteria::FullRotation,
+    },
+    AchievementDefinition {
+        key: "map_top_10",
+        name: "Top 10",
+        description: "Reach top 10 playtime on any map",
+        criteria: AchievementCriteria::MapRankAtMost(10),
+    },
+    AchievementDefinition {
+        key: "map_top_1",
+        name: "Map Owner",
+        description: "Reach the highest playtime on any map",
+        criteria: AchievementCriteria::MapRankAtMost(1),
+    },
+    AchievementDefinition {
+        key: "streak_7d",
+        name: "Weekly Habit",
+        description: "Play 7 days in a row",
+        criteria: AchievementCriteria::DailyStreak(7),
+    },
+    AchievementDefinition {
+        key: "streak_30d",
+        name: "Dedicated",
+        description: "Play 30 days in a row",
+        criteria: AchievementCriteria::DailyStreak(30),
+    },
+    AchievementDefinition {
+        key: "night_owl",
+        name: "Night Owl",
+        description: "Start 50 sessions between midnight and 5 AM (UTC)",
+        criteria: AchievementCriteria::NightSessions(50),
+    },
+];
+
+impl AchievementDefinition {
+    pub fn is_met(&self, stats: &DbAchievementStats) -> bool {
+        match self.criteria {
+            AchievementCriteria::ServerPlaytimeHours(hours) => {
+                stats.total_playtime.clone().map(pg_interval_to_f64).unwrap_or(0.) >= hours * 3600.
+            }
+            AchievementCriteria::FullRotation => {
+                let rotation = stats.rotation_maps.unwrap_or_default();
+                rotation > 0 && stats.rotation_maps_played.unwrap_or_default() >= rotation
+            }
+            AchievementCriteria::MapRankAtMost(rank) => {
+                stats.best_map_rank.is_some_and(|best| best <= rank)
+            }
+            AchievementCriteria::DailyStreak(days) => stats.longest_streak.unwrap_or_default() >= days,
+            AchievementCriteria::NightSessions(count) => stats.night_sessions.unwrap_or_default() >= count,
+        }
+    }
+}
+
+pub fn find_achievement(key: &str) -> Option<&'static AchievementDefinition> {
+    ACHIEVEMENTS.iter().find(|a| a.key == key)
+}
+
+async fn get_achievement_stats(
+    pool: &Pool<Postgres>, player_id: &str, server_id: &str
+) -> Result<DbAchievementStats, sqlx::Error> {
+    sqlx::query_as!(DbAchievementStats, "
+        WITH days AS (
+            SELECT DISTINCT (started_at AT TIME ZONE 'UTC')::date AS day
+            FROM player_server_session
+            WHERE player_id = $1 AND server_id = $2
+        ),
+        islands AS (
+            SELECT day - (ROW_NUMBER() OVER (ORDER BY day))::int AS grp
+            FROM days
+        ),
+        rotation AS (
+            SELECT map FROM server_map
+            WHERE server_id = $2 AND enabled = TRUE AND removed = FALSE
+        )
+        SELECT
+            (SELECT total_playtime FROM website.player_playtime
+             WHERE player_id = $1 AND server_id = $2) AS total_playtime,
+            (SELECT COUNT(*) FROM rotation) AS rotation_maps,
+            (SELECT COUNT(*) FROM rotation r
+             JOIN website.player_map_time pmt
+                ON pmt.map = r.map AND pmt.server_id = $2 AND pmt.player_id = $1
+             WHERE pmt.total_playtime > INTERVAL '0') AS rotation_maps_played,
+            (SELECT MIN(map_rank) FROM (
+                SELECT (
+                    SELECT COUNT(*) + 1
+                    FROM website.player_map_time other
+                    WHERE other.server_id = pmt.server_id
+                        AND other.map = pmt.map
+                        AND other.total_playtime > pmt.total_playtime
+                ) AS map_rank
+                FROM website.player_map_time pmt
+                WHERE pmt.server_id = $2 AND pmt.player_id = $1
+                    AND pmt.total_playtime > INTERVAL '1 hour'
+             ) ranks) AS best_map_rank,
+            (SELECT MAX(c) FROM (SELECT COUNT(*) AS c FROM islands GROUP BY grp) s) AS longest_streak,
+            (SELECT COUNT(*) FROM player_server_session
+             WHERE player_id = $1 AND server_id = $2
+                AND EXTRACT(HOUR FROM started_at AT TIME ZONE 'UTC') < 5) AS night_sessions
+    ", player_id, server_id).fetch_one(pool).await
+}
+
+/// Evaluates every achievement for a player on one server, storing new unlocks as of
+/// `earned_at`. Returns the keys newly unlocked by this evaluation.
+pub async fn evaluate_player_achievements(
+    pool: &Pool<Postgres>, push_service: Option<Arc<PushNotificationService>>,
+    player_id: &str, server_id: &str, earned_at: DateTime<Utc>,
+) -> Result<Vec<String>, sqlx::Error> {
+    let stats = get_achievement_stats(pool, player_id, server_id).await?;
+    let met: Vec<String> = ACHIEVEMENTS.iter()
+        .filter(|a| a.is_met(&stats))
+        .map(|a| a.key.to_string())
+        .collect();
+    if met.is_empty() {
+        return Ok(vec![])
+    }
+    // Several instances may evaluate the same leave, only the one inserting the row notifies.
+    let inserted = sqlx::query_scalar!("
+        INSERT INTO website.player_achievements(player_id, server_id, achievement_key, unlocked_at)
+        SELECT $1, $2, key, $4 FROM UNNEST($3::text[]) AS key
+        ON CONFLICT (player_id, server_id, achievement_key) DO NOTHING
+        RETURNING achievement_key
+    ", player_id, server_id, &met[..], earned_at.to_db_time()).fetch_all(pool).await?;
+
+    let unlocked: Vec<(String, &'static AchievementDefinition)> = inserted.iter()
+        .filter_map(|key| find_achievement(key))
+        .map(|definition| (server_id.to_string(), definition))
+        .collect();
+    if let Some(push) = push_service {
+        notify_unlocks(pool, push, player_id, &unlocked).await;
+    }
+    Ok(inserted)
+}
+
+#[derive(Deserialize)]
+struct PlayerActivityEvent {
+    player_id: String,
+    server_id: String,
+    event_name: String,
+    created_at: String,
+}
+
+/// Evaluates achievements whenever a player leaves a server, which is when their session
+/// gets accounted for. Missed events are caught up on the player's next leave.
+pub async fn run_achievement_evaluations(app: AppData) {
+    if !get_env_bool("ACHIEVEMENTS", true) {
+        tracing::info!("Achievement evaluation is disabled");
+        return
+    }
+    let db_url = get_env("DATABASE_URL");
+    let push_service = get_env_bool("ACHIEVEMENT_NOTIFICATIONS", false)
+        .then(|| app.push_service.clone());
+    loop {
+        let mut listener = match PgListener::connect(&db_url).await {
+            Ok(listener) => listener,
+            Err(e) => {
+                tracing::error!("Achievements failed to connect to PostgreSQL: {e}");
+                tokio::time::sleep(RECONNECT_DELAY).await;
+                continue
+            }
+        };
+        if let Err(e) = listener.listen("player_activity").await {
+            tracing::error!("Achievements failed to LISTEN on player_activity: {e}");
+            tokio::time::sleep(RECONNECT_DELAY).await;
+            continue
+        }
+        loop {
+            let notification = match listener.recv().await {
+                Ok(notification) => notification,
+                Err(e) => {
+                    tracing::error!("Achievements lost player_activity: {e}");
+                    break
+                }
+            };
+            let Ok(event) = serde_json::from_str::<PlayerActivityEvent>(notification.payload()) else {
+                tracing::warn!("Unreadable player_activity payload: {}", notification.payload());
+                continue
+            };
+            if event.event_name != "leave" {
+                continue
+            }
+            let earned_at = DateTime::parse_from_rfc3339(&event.created_at)
+                .map(|d| d.with_timezone(&Utc))
+                .unwrap_or_else(|_| Utc::now());
+            let pool = app.pool.clone();
+            let push_service = push_service.clone();
+            tokio::spawn(async move {
+                let result = evaluate_player_achievements(
+                    &pool, push_service, &event.player_id, &event.server_id, earned_at
+                ).await;
+                if let Err(e) = result {
+                    tracing::warn!("Failed to evaluate achievements for {}: {e}", event.player_id);
+                }
+            });
+        }
+        tokio::time::sleep(RECONNECT_DELAY).await;
+    }
+}
+
+async fn notify_unlocks(
+    pool: &Pool<Postgres>, push: Arc<PushNotificationService>,
+    player_id: &str, unlocked: &[(String, &'static AchievementDefinition)]
+) {
+    if unlocked.is_empty() {
+        return
+    }
+    // Only players that have logged in to the website can have subscriptions.
+    let Ok(user_id) = player_id.parse::<i64>() else {
+        return
+    };
+    let Ok(Some(_)) = sqlx::query_scalar!(
+        "SELECT user_id FROM website.steam_user WHERE user_id = $1", user_id
+    ).fetch_optional(pool).await else {
+        return
+    };
+    for (_, definition) in unlocked {
+        let title = format!("Achievement unlocked: {}", definition.name);
+        let body = definition.description.to_string();
+        if let Err(e) = push.send_notification(user_id, title, body, NotificationType::System).await {
+            tracing::warn!("Failed to send achievement notification to {user_id}: {e}");
+        }
+    }
+}
+
+pub async fn get_player_achievements(
+    pool: &Pool<Postgres>, player_id: &str
+) -> Result<Vec<PlayerAchievement>, sqlx::Error> {
+    let rows = sqlx::query_as!(DbPlayerAchievement, "
+        SELECT pa.server_id, pa.achievement_key, pa.unlocked_at
+        FROM website.player_achievements pa
+        WHERE pa.player_id = $1
+        ORDER BY pa.unlocked_at DESC
+    ", player_id).fetch_all(pool).await?;
+
+    Ok(rows.into_iter()
+        .filter_map(|row| {
+            let definition = find_achievement(&row.achievement_key)?;
+            Some(PlayerAchievement {
+                key: definition.key.to_string(),
+                name: definition.name.to_string(),
+                description: definition.description.to_string(),
+                server_id: row.server_id,
+                unlocked_at: db_to_utc(row.unlocked_at),
+            })
+        })
+        .collect())
+}
@@ -0,0 +1,239 @@
+use std::collections::HashMap;
+
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::AppData;
+use crate::core::api_models::{
+    CountryPlayer, LeaderboardEntry, PlayerBrief, PlayerDetailSession, PlayerSeen, PlayerTableRank, SearchKind,
+    SearchPlayer, SearchResult, TimelinePlayer,
+};
+use crate::core::permissions::{has_permission, Permission, Scope};
+use crate::core::utils::UserToken;
+
+pub const ANONYMOUS_NAME: &str = "Anonymous";
+
+/// A player's `website.user_anonymization` in one community.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub struct PlayerPrivacy {
+    pub anonymized: bool,
+    pub hide_location: bool,
+}
+
+fn privacy_key(community_id: Uuid) -> String {
+    format!("anonymization:{community_id}")
+}
+
+const EVERYWHERE_KEY: &str = "anonymization:everywhere";
+
+fn server_community_key(server_id: &str) -> String {
+    format!("anonymization-community:{server_id}")
+}
+
+/// Players of the community with anything hidden, by player id. Kept in the memory cache,
+/// so other instances pick up a change within its TTL.
+async fn community_privacy(app: &AppData, community_id: Uuid) -> Result<HashMap<String, PlayerPrivacy>, sqlx::Error> {
+    let key = privacy_key(community_id);
+    if let Some(cached) = app.cache.memory.get(&key).await {
+        if let Ok(players) = serde_json::from_str(&cached) {
+            return Ok(players)
+        }
+    }
+    let rows = sqlx::query!(
+        "SELECT user_id, anonymized, hide_location FROM website.user_anonymization
+         WHERE community_id = $1 AND (anonymized OR hide_location)",
+        community_id
+    )
+    .fetch_all(&*app.pool)
+    .await?;
+    let players: HashMap<String, PlayerPrivacy> = rows
+        .into_iter()
+        .map(|r| (r.user_id.to_string(), PlayerPrivacy { anonymized: r.anonymized, hide_location: r.hide_location }))
+        .collect();
+    if let Ok(serialized) = serde_json::to_string(&players) {
+        app.cache.memory.insert(key, serialized).await;
+    }
+    Ok(players)
+}
+
+/// Like [`community_privacy`] across all communities, hiding whatever any of them hides.
+async fn privacy_everywhere(app: &AppData) -> Result<HashMap<String, PlayerPrivacy>, sqlx::Error> {
+    if let Some(cached) = app.cache.memory.get(EVERYWHERE_KEY).await {
+        if let Ok(players) = serde_json::from_str(&cached) {
+            return Ok(players)
+        }
+    }
+    let rows = sqlx::query!(
+        r#"SELECT user_id, bool_or(anonymized) AS "anonymized!", bool_or(hide_location) AS "hide_location!"
+           FROM website.user_anonymization
+           WHERE anonymized OR hide_location
+           GROUP BY user_id"#
+    )
+    .fetch_all(&*app.pool)
+    .await?;
+    let players: HashMap<String, PlayerPrivacy> = rows
+        .into_iter()
+        .map(|r| (r.user_id.to_string(), PlayerPrivacy { anonymized: r.anonymized, hide_location: r.hide_location }))
+        .collect();
+    if let Ok(serialized) = serde_json::to_string(&players) {
+        app.cache.memory.insert(EVERYWHERE_KEY.to_string(), serialized).await;
+    }
+    Ok(players)
+}
+
+async fn server_community(app: &AppData, server_id: &str) -> Result<Option<Uuid>, sqlx::Error> {
+    let key = server_community_key(server_id);
+    if let Some(cached) = app.cache.memory.get(&key).await {
+        if let Ok(community_id) = serde_json::from_str(&cached) {
+            return Ok(community_id)
+        }
+    }
+    let community_id = sqlx::query_scalar!("SELECT community_id FROM server WHERE server_id = $1", server_id)
+        .fetch_optional(&*app.pool)
+        .await?
+        .flatten();
+    if let Ok(serialized) = serde_json::to_string(&community_id) {
+        app.cache.memory.insert(key, serialized).await;
+    }
+    Ok(community_id)
+}
+
+/// Call after changing anonymization in a community so this instance stops serving the old one.
+pub async fn forget_privacy(app: &AppData, community_id: Uuid) {
+    app.cache.memory.invalidate(&privacy_key(community_id)).await;
+    app.cache.memory.invalidate(EVERYWHERE_KEY).await;
+}
+
+/// What one viewer may see of the players of one community. Every response carrying another
+/// player's identity or location goes through [`Anonymizer::apply`].
+pub struct Anonymizer {
+    viewer_id: Option<i64>,
+    /// Holds `players.view_anonymized` for the community
+    sees_through: bool,
+    players: HashMap<String, PlayerPrivacy>,
+}
+
+impl Anonymizer {
+    /// For a server's community. Servers outside any community hide nobody.
+    pub async fn for_server(app: &AppData, server_id: &str, viewer: Option<&UserToken>) -> Result<Self, sqlx::Error> {
+        let community_id = server_community(app, server_id).await?;
+        Self::for_community(app, community_id, viewer).await
+    }
+
+    pub async fn for_community(app: &AppData, community_id: Option<Uuid>, viewer: Option<&UserToken>) -> Result<Self, sqlx::Error> {
+        let viewer_id = viewer.map(|v| v.id);
+        let Some(community_id) = community_id else {
+            return Ok(Self { viewer_id, sees_through: false, players: HashMap::new() })
+        };
+        let players = community_privacy(app, community_id).await?;
+        let sees_through = match viewer_id {
+            Some(id) if !players.is_empty() => {
+                has_permission(app, id, Permission::PlayersViewAnonymized, Scope::Community(community_id)).await
+            }
+            _ => false,
+        };
+        Ok(Self { viewer_id, sees_through, players })
+    }
+
+    /// For responses not tied to a server, where a player hidden in any community stays hidden.
+    pub async fn everywhere(app: &AppData, viewer: Option<&UserToken>) -> Result<Self, sqlx::Error> {
+        let viewer_id = viewer.map(|v| v.id);
+        let players = privacy_everywhere(app).await?;
+        let sees_through = match viewer_id {
+            Some(id) if !players.is_empty() => {
+                has_permission(app, id, Permission::PlayersViewAnonymized, Scope::Global).await
+            }
+            _ => false,
+        };
+        Ok(Self { viewer_id, sees_through, players })
+    }
+
+    /// What has to be hidden of the player from this viewer. Players always see themselves.
+    pub fn privacy(&self, player_id: &str) -> PlayerPrivacy {
+        if self.sees_through || self.viewer_id.is_some_and(|id| id.to_string() == player_id) {
+            return PlayerPrivacy::default()
+        }
+        self.players.get(player_id).copied().unwrap_or_default()
+    }
+
+    pub fn apply<T: Anonymize>(&self, value: T) -> Option<T> {
+        value.anonymize(self)
+    }
+
+    pub fn apply_all<T: Anonymize>(&self, values: Vec<T>) -> Vec<T> {
+        values.into_iter().filter_map(|v| v.anonymize(self)).collect()
+    }
+}
+
+/// A response type carrying a player's identity or location.
+pub trait Anonymize: Sized {
+    /// Hides what the player's privacy asks for, `None` drops the item altogether.
+    fn anonymize(self, anonymizer: &Anonymizer) -> Option<Self>;
+}
+
+/// Anonymized players keep their row with a throwaway id, so totals still add up but the
+/// row can't be followed back to them.
+fn anonymous_id() -> String {
+    Uuid::new_v4().to_string()
+}
+
+impl Anonymize for SearchPlayer {
+    /// Searching by name or id would confirm who the anonymized player is, so they're left out.
+    fn anonymize(self, anonymizer: &Anonymizer) -> Option<Self> {
+        match anonymizer.privacy(&self.id).anonymized {
+            true => None,
+            false => Some(self),
+        }
+    }
+}
+
+/// For rows that show a player by `id` and `name`, both are swapped out when anonymized.
+macro_rules! anonymize_player_rows {
+    ($($row:ty),+ $(,)?) => {$(
+        impl Anonymize for $row {
+            fn anonymize(mut self, anonymizer: &Anonymizer) -> Option<Self> {
+                if anonymizer.privacy(&self.id).anonymized {
+                    self.id = anonymous_id();
+                    self.name = ANONYMOUS_NAME.to_string();
+                    self.is_anonymous = true;
+                }
+                Some(self)
+            }
+        }
+    )+};
+}
+
+anonymize_player_rows!(
+    PlayerSeen,
+    PlayerBrief,
+    LeaderboardEntry,
+    TimelinePlayer,
+    PlayerTableRank,
+    PlayerDetailSession,
+);
+
+impl Anonymize for CountryPlayer {
+    /// The row exists to place the player in a country, so hiding the location drops it.
+    fn anonymize(mut self, anonymizer: &Anonymizer) -> Option<Self> {
+        let privacy = anonymizer.privacy(&self.id);
+        if privacy.hide_location {
+            return None
+        }
+        if privacy.anonymized {
+            self.id = anonymous_id();
+            self.name = ANONYMOUS_NAME.to_string();
+            self.is_anonymous = true;
+        }
+        Some(self)
+    }
+}
+
+impl Anonymize for SearchResult {
+    /// Same as [`SearchPlayer`], other kinds of results don't carry a player.
+    fn anonymize(self, anonymizer: &Anonymizer) -> Option<Self> {
+        match self.kind == SearchKind::Player && anonymizer.privacy(&self.id).anonymized {
+            true => None,
+            false => Some(self),
+        }
+    }
+}
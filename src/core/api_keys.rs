@@ -0,0 +1,503 @@
+use std::collections::BTreeMap;
+use std::net::IpAddr;
+use std::sync::{Arc, LazyLock};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use chrono::{DateTime, Datelike, Duration, NaiveDate, Utc};
+use moka::future::Cache;
+use poem::http::{HeaderValue, Method, StatusCode};
+use poem::{Endpoint, IntoResponse, Middleware, Request};
+use rand::distr::Alphanumeric;
+use rand::Rng;
+use sha2::{Digest, Sha256};
+use uuid::Uuid;
+
+use crate::AppData;
+use crate::core::api_models::{route_pattern, UriExtension};
+use crate::core::rate_limit::client_ip;
+use crate::core::utils::get_env_default;
+
+pub const API_KEY_HEADER: &str = "X-API-Key";
+const KEY_PREFIX: &str = "zeg_";
+const SECRET_LEN: usize = 40;
+/// Long enough to find the key in a list, far too short to guess the rest.
+const SHOWN_LEN: usize = 12;
+const DEFAULT_RATE_PER_MINUTE: i32 = 120;
+const DEFAULT_BURST: i32 = 60;
+const USAGE_RETENTION_DAYS: i64 = 35;
+/// Month totals outlive the month they count, the quota only ever reads the current one.
+const MONTHLY_USAGE_RETENTION_DAYS: i64 = 62;
+const LAST_USED_RESOLUTION_SECS: u64 = 60;
+/// Wrong keys an address may send per window before it is turned away without a lookup.
+const DEFAULT_FAILED_LOOKUPS: i64 = 20;
+const FAILED_LOOKUP_WINDOW_SECS: i64 = 60;
+
+/// Keys only ever read, and only the route groups their scopes list.
+pub const API_KEY_SCOPES: &[(&str, &[&str])] = &[
+    ("servers.read", &["/servers", "/communities", "/graph", "/fetch-status-truncated"]),
+    ("players.read", &["/players"]),
+    ("maps.read", &["/maps", "/music", "/thumbnails", "/meta_thumbnails", "/assets"]),
+    ("radars.read", &["/radars"]),
+    ("events.read", &["/events", "/announcements"]),
+    ("search.read", &["/search"]),
+];
+
+pub fn is_valid_scope(scope: &str) -> bool {
+    API_KEY_SCOPES.iter().any(|(name, _)| *name == scope)
+}
+
+fn scope_for_route(pattern: &str) -> Option<&'static str> {
+    API_KEY_SCOPES.iter()
+        .find(|(_, prefixes)| prefixes.iter().any(|prefix| {
+            pattern == *prefix || pattern.starts_with(&format!("{prefix}/"))
+        }))
+        .map(|(name, _)| *name)
+}
+
+/// A fresh key and the part of it that is shown back to the owner later.
+pub fn generate_api_key() -> (String, String) {
+    let secret: String = rand::rng()
+        .sample_iter(&Alphanumeric)
+        .take(SECRET_LEN)
+        .map(char::from)
+        .collect();
+    let key = format!("{KEY_PREFIX}{secret}");
+    let shown = key[..SHOWN_LEN].to_string();
+    (key, shown)
+}
+
+pub fn hash_api_key(key: &str) -> String {
+    hex::encode(Sha256::digest(key.as_bytes()))
+}
+
+/// Live keys by hash, kept apart from the response cache so lookups can't evict it or each other.
+static KEY_CACHE: LazyLock<Cache<String, ApiKeyInfo>> = LazyLock::new(|| {
+    Cache::builder()
+        .time_to_live(std::time::Duration::from_secs(60))
+        .max_capacity(1_000)
+        .build()
+});
+
+/// Drops this instance's cached lookup, others catch up within the key cache TTL.
+pub async fn forget_api_key(key_hash: &str) {
+    KEY_CACHE.invalidate(key_hash).await;
+}
+
+#[derive(Clone)]
+struct ApiKeyInfo {
+    id: Uuid,
+    scopes: Vec<String>,
+    rate_per_minute: i32,
+    burst: i32,
+    /// Requests per UTC day and calendar month, `None` is unlimited
+    daily_quota: Option<i64>,
+    monthly_quota: Option<i64>,
+}
+
+/// `API_KEY_DAILY_QUOTA` or `API_KEY_MONTHLY_QUOTA` for keys without their own, unset or 0 is unlimited.
+fn default_quota(name: &str) -> Option<i64> {
+    get_env_default(name).and_then(|s| s.parse().ok())
+}
+
+/// Only live keys are cached, unknown ones are held off by [`failed_lookup_wait`] instead.
+async fn lookup_api_key(app: &AppData, key: &str) -> Result<Option<ApiKeyInfo>, sqlx::Error> {
+    let key_hash = hash_api_key(key);
+    if let Some(info) = KEY_CACHE.get(&key_hash).await {
+        return Ok(Some(info))
+    }
+
+    let row = sqlx::query!(
+        "SELECT id, scopes, rate_per_minute, burst, daily_quota, monthly_quota FROM website.api_key
+         WHERE key_hash = $1 AND revoked_at IS NULL",
+        key_hash
+    )
+    .fetch_optional(&*app.pool)
+    .await?;
+
+    let default_rate = get_env_default("API_KEY_RATE_PER_MINUTE")
+        .and_then(|s| s.parse().ok())
+        .unwrap_or(DEFAULT_RATE_PER_MINUTE);
+    let default_burst = get_env_default("API_KEY_BURST")
+        .and_then(|s| s.parse().ok())
+        .unwrap_or(DEFAULT_BURST);
+    let info = row.map(|r| ApiKeyInfo {
+        id: r.id,
+        scopes: r.scopes,
+        rate_per_minute: r.rate_per_minute.unwrap_or(default_rate).max(1),
+        burst: r.burst.unwrap_or(default_burst).max(1),
+        daily_quota: r.daily_quota.map(i64::from)
+            .or_else(|| default_quota("API_KEY_DAILY_QUOTA"))
+            .filter(|q| *q > 0),
+        monthly_quota: r.monthly_quota.map(i64::from)
+            .or_else(|| default_quota("API_KEY_MONTHLY_QUOTA"))
+            .filter(|q| *q > 0),
+    });
+    if let Some(info) = &info {
+        KEY_CACHE.insert(key_hash, info.clone()).await;
+    }
+    Ok(info)
+}
+
+fn failed_lookup_key(ip: IpAddr) -> String {
+    format!("api_key_failures:{ip}")
+}
+
+/// Seconds until `ip` may try keys again once it sent `API_KEY_FAILED_LOOKUPS` wrong ones
+/// within the window. Fails open when Redis is down.
+async fn failed_lookup_wait(app: &AppData, ip: IpAddr) -> Option<u64> {
+    let limit = get_env_default("API_KEY_FAILED_LOOKUPS")
+        .and_then(|s| s.parse().ok())
+        .unwrap_or(DEFAULT_FAILED_LOOKUPS);
+    let mut conn = app.cache.redis_pool.get().await.ok()?;
+    let key = failed_lookup_key(ip);
+    let (count, ttl): (Option<i64>, i64) = redis::pipe()
+        .get(&key)
+        .ttl(&key)
+        .query_async(&mut conn)
+        .await
+        .ok()?;
+    (count.unwrap_or_default() >= limit).then(|| ttl.max(1) as u64)
+}
+
+async fn record_failed_lookup(app: &AppData, ip: IpAddr) {
+    let Ok(mut conn) = app.cache.redis_pool.get().await else {
+        return
+    };
+    let key = failed_lookup_key(ip);
+    let count: redis::RedisResult<i64> = redis::cmd("INCR").arg(&key).query_async(&mut conn).await;
+    if let Ok(1) = count {
+        let _: redis::RedisResult<()> = redis::cmd("EXPIRE")
+            .arg(&key)
+            .arg(FAILED_LOOKUP_WINDOW_SECS)
+            .query_async(&mut conn)
+            .await;
+    }
+}
+
+static TOKEN_BUCKET: LazyLock<redis::Script> = LazyLock::new(|| redis::Script::new(r#"
+local rate = tonumber(ARGV[1])
+local burst = tonumber(ARGV[2])
+local now = tonumber(ARGV[3])
+local state = redis.call('HMGET', KEYS[1], 'tokens', 'ts')
+local tokens = tonumber(state[1]) or burst
+local ts = tonumber(state[2]) or now
+tokens = math.min(burst, tokens + (now - ts) * rate)
+local allowed = 0
+if tokens >= 1 then
+    tokens = tokens - 1
+    allowed = 1
+end
+redis.call('HSET', KEYS[1], 'tokens', tostring(tokens), 'ts', tostring(now))
+redis.call('PEXPIRE', KEYS[1], math.ceil(burst / rate) + 1000)
+return {allowed, math.floor(tokens), math.ceil((1 - tokens) / rate)}
+"#));
+
+enum Bucket {
+    Allowed { remaining: i64 },
+    /// Milliseconds until the next token
+    Empty { retry_after_ms: i64 },
+}
+
+/// Refills at `rate_per_minute`, holds at most `burst` tokens. Fails open when Redis is down.
+async fn take_token(app: &AppData, info: &ApiKeyInfo) -> Bucket {
+    let Ok(mut conn) = app.cache.redis_pool.get().await else {
+        return Bucket::Allowed { remaining: info.burst as i64 }
+    };
+    let now_ms = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_millis() as i64;
+    let per_ms = info.rate_per_minute as f64 / 60_000.0;
+    let result: redis::RedisResult<(i64, i64, i64)> = TOKEN_BUCKET
+        .key(format!("api_key_bucket:{}", info.id))
+        .arg(per_ms)
+        .arg(info.burst)
+        .arg(now_ms)
+        .invoke_async(&mut conn)
+        .await;
+    match result {
+        Ok((1, remaining, _)) => Bucket::Allowed { remaining },
+        Ok((_, _, retry_after_ms)) => Bucket::Empty { retry_after_ms },
+        Err(e) => {
+            tracing::warn!("API key rate limiter unavailable: {e}");
+            Bucket::Allowed { remaining: info.burst as i64 }
+        }
+    }
+}
+
+fn usage_key(key_id: Uuid, day: NaiveDate) -> String {
+    format!("api_key_usage:{key_id}:{day}")
+}
+
+/// Requests of the whole month, next to the per route counts of each day
+fn monthly_usage_key(key_id: Uuid, day: NaiveDate) -> String {
+    format!("api_key_usage:{key_id}:{}", day.format("%Y-%m"))
+}
+
+fn next_day(now: DateTime<Utc>) -> DateTime<Utc> {
+    (now.date_naive() + Duration::days(1)).and_time(Default::default()).and_utc()
+}
+
+fn next_month(now: DateTime<Utc>) -> DateTime<Utc> {
+    let today = now.date_naive();
+    let (year, month) = match today.month() {
+        12 => (today.year() + 1, 1),
+        month => (today.year(), month + 1),
+    };
+    NaiveDate::from_ymd_opt(year, month, 1)
+        .unwrap_or(today)
+        .and_time(Default::default())
+        .and_utc()
+}
+
+/// When the first quota in the way resets, `None` while both leave room. A full month
+/// outlasts a full day so it wins.
+fn quota_reset(info: &ApiKeyInfo, used_today: i64, used_this_month: i64, now: DateTime<Utc>) -> Option<DateTime<Utc>> {
+    if info.monthly_quota.is_some_and(|quota| used_this_month >= quota) {
+        return Some(next_month(now))
+    }
+    info.daily_quota
+        .is_some_and(|quota| used_today >= quota)
+        .then(|| next_day(now))
+}
+
+static QUOTA_USAGE: LazyLock<redis::Script> = LazyLock::new(|| redis::Script::new(r#"
+local today = 0
+for _, count in ipairs(redis.call('HVALS', KEYS[1])) do
+    today = today + tonumber(count)
+end
+return {today, tonumber(redis.call('GET', KEYS[2])) or 0}
+"#));
+
+/// Reads the usage counters [`count_usage`] keeps. Fails open when Redis is down.
+async fn quota_exceeded(app: &AppData, info: &ApiKeyInfo) -> Option<DateTime<Utc>> {
+    if info.daily_quota.is_none() && info.monthly_quota.is_none() {
+        return None
+    }
+    let mut conn = app.cache.redis_pool.get().await.ok()?;
+    let now = Utc::now();
+    let today = now.date_naive();
+    let result: redis::RedisResult<(i64, i64)> = QUOTA_USAGE
+        .key(usage_key(info.id, today))
+        .key(monthly_usage_key(info.id, today))
+        .invoke_async(&mut conn)
+        .await;
+    match result {
+        Ok((used_today, used_this_month)) => quota_reset(info, used_today, used_this_month, now),
+        Err(e) => {
+            tracing::warn!("API key quota unavailable: {e}");
+            None
+        }
+    }
+}
+
+async fn count_usage(app: &AppData, key_id: Uuid, route: &str) {
+    let Ok(mut conn) = app.cache.redis_pool.get().await else {
+        return
+    };
+    let today = Utc::now().date_naive();
+    let key = usage_key(key_id, today);
+    let month_key = monthly_usage_key(key_id, today);
+    let counted: redis::RedisResult<()> = redis::pipe()
+        .hincr(&key, route, 1)
+        .ignore()
+        .expire(&key, USAGE_RETENTION_DAYS * 24 * 60 * 60)
+        .ignore()
+        .incr(&month_key, 1)
+        .ignore()
+        .expire(&month_key, MONTHLY_USAGE_RETENTION_DAYS * 24 * 60 * 60)
+        .ignore()
+        .query_async(&mut conn)
+        .await;
+    if let Err(e) = counted {
+        tracing::warn!("Failed to count API key usage: {e}");
+    }
+
+    let seen: redis::RedisResult<Option<String>> = redis::cmd("SET")
+        .arg(format!("api_key_seen:{key_id}"))
+        .arg(1)
+        .arg("NX")
+        .arg("EX")
+        .arg(LAST_USED_RESOLUTION_SECS)
+        .query_async(&mut conn)
+        .await;
+    if let Ok(Some(_)) = seen {
+        let _ = sqlx::query!("UPDATE website.api_key SET last_used_at = NOW() WHERE id = $1", key_id)
+            .execute(&*app.pool)
+            .await;
+    }
+}
+
+/// Requests per day and per route pattern over the last `days` days, oldest day first.
+pub async fn api_key_usage(
+    app: &AppData,
+    key_id: Uuid,
+    days: i64,
+) -> Result<(Vec<(NaiveDate, i64)>, BTreeMap<String, i64>), String> {
+    let mut conn = app.cache.redis_pool.get().await.map_err(|e| e.to_string())?;
+    let today = Utc::now().date_naive();
+    let mut by_day = vec![];
+    let mut by_route = BTreeMap::new();
+    for offset in (0..days).rev() {
+        let day = today - Duration::days(offset);
+        let counts: BTreeMap<String, i64> = redis::cmd("HGETALL")
+            .arg(usage_key(key_id, day))
+            .query_async(&mut conn)
+            .await
+            .map_err(|e| e.to_string())?;
+        by_day.push((day, counts.values().sum()));
+        for (route, count) in counts {
+            *by_route.entry(route).or_insert(0) += count;
+        }
+    }
+    Ok((by_day, by_route))
+}
+
+/// Checks `X-API-Key` when present: the key must be live, the request a read on a route its
+/// scopes cover, its daily and monthly quotas not used up and its token bucket not empty. Addresses sending too many unknown keys are
+/// turned away before the lookup. Requests without a key pass untouched.
+pub struct ApiKeyGate {
+    routers: Vec<Arc<UriExtension>>,
+}
+
+impl ApiKeyGate {
+    pub fn new(apis: Vec<Arc<UriExtension>>) -> ApiKeyGate {
+        ApiKeyGate { routers: apis }
+    }
+}
+
+impl<E: Endpoint<Output = poem::Response>> Middleware<E> for ApiKeyGate {
+    type Output = ApiKeyGateEndpoint<E>;
+
+    fn transform(&self, ep: E) -> Self::Output {
+        ApiKeyGateEndpoint { ep, apis: self.routers.clone() }
+    }
+}
+
+pub struct ApiKeyGateEndpoint<E> {
+    ep: E,
+    apis: Vec<Arc<UriExtension>>,
+}
+
+fn reject(status: StatusCode, message: &str) -> poem::Response {
+    message.to_string().with_status(status).into_response()
+}
+
+impl<E> Endpoint for ApiKeyGateEndpoint<E>
+where
+    E: Endpoint<Output = poem::Response>,
+{
+    type Output = poem::Response;
+
+    async fn call(&self, req: Request) -> poem::Result<Self::Output> {
+        let Some(key) = req.header(API_KEY_HEADER).map(str::to_string) else {
+            return self.ep.call(req).await
+        };
+        let Some(app) = req.data::<AppData>().cloned() else {
+            return self.ep.call(req).await
+        };
+
+        let ip = client_ip(&req);
+        if let Some(retry_after) = match ip {
+            Some(ip) => failed_lookup_wait(&app, ip).await,
+            None => None,
+        } {
+            let mut resp = reject(StatusCode::TOO_MANY_REQUESTS, "Too many unknown API keys");
+            resp.headers_mut().insert("Retry-After", retry_after.into());
+            return Ok(resp)
+        }
+
+        let info = match lookup_api_key(&app, &key).await {
+            Ok(Some(info)) => info,
+            Ok(None) => {
+                if let Some(ip) = ip {
+                    record_failed_lookup(&app, ip).await;
+                }
+                return Ok(reject(StatusCode::UNAUTHORIZED, "Unknown or revoked API key"))
+            }
+            Err(e) => {
+                tracing::error!("Failed to look up API key: {e}");
+                return Ok(reject(StatusCode::INTERNAL_SERVER_ERROR, "Something went wrong"))
+            }
+        };
+
+        if req.method() != Method::GET && req.method() != Method::HEAD {
+            return Ok(reject(StatusCode::FORBIDDEN, "API keys are read-only"))
+        }
+        let route = route_pattern(&self.apis, req.uri().path())
+            .unwrap_or_else(|| "unknown_pattern".to_string());
+        match scope_for_route(&route) {
+            Some(scope) if info.scopes.iter().any(|s| s == scope) => {}
+            Some(scope) => return Ok(reject(StatusCode::FORBIDDEN, &format!("API key lacks the {scope} scope"))),
+            None => return Ok(reject(StatusCode::FORBIDDEN, "API keys can't be used on this route")),
+        }
+
+        if let Some(reset) = quota_exceeded(&app, &info).await {
+            let retry_after = (reset - Utc::now()).num_seconds().max(1);
+            let mut resp = reject(StatusCode::TOO_MANY_REQUESTS, "Quota exceeded");
+            resp.headers_mut().insert("Retry-After", retry_after.into());
+            resp.headers_mut().insert("X-Quota-Reset", reset.timestamp().into());
+            return Ok(resp)
+        }
+
+        let remaining = match take_token(&app, &info).await {
+            Bucket::Allowed { remaining } => remaining,
+            Bucket::Empty { retry_after_ms } => {
+                let retry_after = (retry_after_ms.max(0) as u64).div_ceil(1000).max(1);
+                let mut resp = reject(StatusCode::TOO_MANY_REQUESTS, "Rate limit exceeded");
+                resp.headers_mut().insert("Retry-After", retry_after.into());
+                resp.headers_mut().insert("X-RateLimit-Limit", info.rate_per_minute.into());
+                resp.headers_mut().insert("X-RateLimit-Remaining", HeaderValue::from_static("0"));
+                return Ok(resp)
+            }
+        };
+
+        count_usage(&app, info.id, &route).await;
+        let mut resp = self.ep.call(req).await?;
+        resp.headers_mut().insert("X-RateLimit-Limit", info.rate_per_minute.into());
+        resp.headers_mut().insert("X-RateLimit-Remaining", remaining.into());
+        Ok(resp)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    fn info(daily_quota: Option<i64>, monthly_quota: Option<i64>) -> ApiKeyInfo {
+        ApiKeyInfo {
+            id: Uuid::nil(),
+            scopes: vec![],
+            rate_per_minute: DEFAULT_RATE_PER_MINUTE,
+            burst: DEFAULT_BURST,
+            daily_quota,
+            monthly_quota,
+        }
+    }
+
+    #[test]
+    fn quotas_stop_keys_once_used_up() {
+        let now = Utc.with_ymd_and_hms(2026, 10, 19, 15, 30, 0).unwrap();
+        let tomorrow = Utc.with_ymd_and_hms(2026, 10, 20, 0, 0, 0).unwrap();
+        let next_month = Utc.with_ymd_and_hms(2026, 11, 1, 0, 0, 0).unwrap();
+
+        assert_eq!(quota_reset(&info(None, None), 1_000_000, 1_000_000, now), None);
+        assert_eq!(quota_reset(&info(Some(100), None), 99, 99, now), None);
+        assert_eq!(quota_reset(&info(Some(100), None), 100, 100, now), Some(tomorrow));
+        assert_eq!(quota_reset(&info(None, Some(1_000)), 5, 999, now), None);
+        assert_eq!(quota_reset(&info(None, Some(1_000)), 5, 1_000, now), Some(next_month));
+        assert_eq!(quota_reset(&info(Some(100), Some(1_000)), 100, 1_000, now), Some(next_month));
+    }
+
+    #[test]
+    fn monthly_quota_resets_into_the_next_year() {
+        let now = Utc.with_ymd_and_hms(2026, 12, 31, 23, 59, 59).unwrap();
+        assert_eq!(next_month(now), Utc.with_ymd_and_hms(2027, 1, 1, 0, 0, 0).unwrap());
+        assert_eq!(next_day(now), Utc.with_ymd_and_hms(2027, 1, 1, 0, 0, 0).unwrap());
+    }
+
+    #[test]
+    fn month_totals_stay_apart_from_day_counts() {
+        let day = NaiveDate::from_ymd_opt(2026, 10, 19).unwrap();
+        assert_ne!(usage_key(Uuid::nil(), day), monthly_usage_key(Uuid::nil(), day));
+        assert_eq!(monthly_usage_key(Uuid::nil(), day), format!("api_key_usage:{}:2026-10", Uuid::nil()));
+    }
+}
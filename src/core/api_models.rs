@@ -23,6 +23,25 @@ pub struct PlayerSessionTime{
     pub bucket_time: DateTime<Utc>,
     pub hours: f64,
 }
+#[derive(Object, Serialize, Deserialize, Clone)]
+pub struct PlayerCalendarDay{
+    pub date: String,
+    pub hours: f64,
+}
+#[derive(Object, Serialize, Deserialize, Clone)]
+pub struct PlayerWeekdayAverage{
+    /// 0 is Monday, 6 is Sunday
+    pub weekday: u8,
+    pub average_hours: f64,
+}
+#[derive(Object, Serialize, Deserialize, Clone)]
+pub struct PlayerCalendar{
+    pub year: i32,
+    pub days: Vec<PlayerCalendarDay>,
+    pub current_streak: i64,
+    pub longest_streak: i64,
+    pub weekday_averages: Vec<PlayerWeekdayAverage>,
+}
 #[derive(Object)]
 pub struct PlayerHourDay{
     pub event_type: EventType,
@@ -91,6 +110,38 @@ pub struct SearchPlayer{
     pub(crate) is_anonymous: bool
 }
 
+#[derive(Enum, PartialEq, Clone, Copy)]
+#[oai(rename_all = "lowercase")]
+pub enum SearchKind {
+    Guide,
+    Comment,
+    Map,
+    Music,
+    Player,
+    Server,
+}
+
+#[derive(Object)]
+pub struct SearchResult {
+    pub kind: SearchKind,
+    /// Guide, comment or music id, map name, player or server id
+    pub id: String,
+    pub title: String,
+    /// Matching words wrapped in `<mark>`, when the match is in a longer text
+    pub snippet: Option<String>,
+    pub map_name: Option<String>,
+    pub guide_slug: Option<String>,
+    pub server_id: Option<String>,
+    /// Higher is better, comparable across kinds
+    pub score: f64,
+}
+
+#[derive(Object)]
+pub struct SearchResults {
+    pub query: String,
+    pub results: Vec<SearchResult>,
+}
+
 #[derive(Object)]
 pub struct Server{
     pub id: String,
@@ -316,6 +367,45 @@ pub struct BriefPlayers {
     pub total_players: i64,
     pub players: Vec<PlayerBrief>
 }
+#[derive(Object, Serialize, Deserialize)]
+pub struct LeaderboardEntry{
+    pub rank: i64,
+    pub id: String,
+    pub name: String,
+    pub value: f64,
+    pub is_anonymous: bool,
+}
+#[derive(Object, Serialize, Deserialize)]
+pub struct LeaderboardPage{
+    pub total_players: i64,
+    pub entries: Vec<LeaderboardEntry>,
+    pub next_cursor: Option<String>,
+}
+#[derive(Object, Serialize, Deserialize)]
+pub struct MapLeaderboard{
+    pub total_players: i64,
+    pub players: Vec<LeaderboardEntry>,
+    pub me: Option<LeaderboardEntry>,
+}
+#[derive(Object, Serialize, Deserialize)]
+pub struct PlayerAchievement{
+    pub key: String,
+    pub name: String,
+    pub description: String,
+    pub server_id: String,
+    pub unlocked_at: DateTime<Utc>,
+}
+#[derive(Object)]
+pub struct AchievementInfo{
+    pub key: String,
+    pub name: String,
+    pub description: String,
+}
+#[derive(Object)]
+pub struct PlayerAchievements{
+    pub unlocked: Vec<PlayerAchievement>,
+    pub available: Vec<AchievementInfo>,
+}
 #[derive(Object)]
 pub struct PlayerTableRank{
     pub rank: i64,
@@ -341,6 +431,7 @@ pub struct PlayerBrief{
     pub online_since: Option<DateTime<Utc>>,
     pub last_played: DateTime<Utc>,
     pub last_played_duration: f64,
+    pub is_anonymous: bool,
 }
 #[derive(Object)]
 pub struct MapInfo{
@@ -409,6 +500,36 @@ pub struct MatchData {
 }
 
 
+#[derive(Object, Serialize, Deserialize)]
+pub struct TimelineInterval {
+    pub joined_at: DateTime<Utc>,
+    pub left_at: Option<DateTime<Utc>>,
+}
+#[derive(Object, Serialize, Deserialize)]
+pub struct TimelinePlayer {
+    pub id: String,
+    pub name: String,
+    pub is_anonymous: bool,
+    pub intervals: Vec<TimelineInterval>,
+}
+#[derive(Object, Serialize, Deserialize)]
+pub struct TimelineRound {
+    pub round: i32,
+    pub started_at: DateTime<Utc>,
+    pub ended_at: DateTime<Utc>,
+    pub zombie_score: i16,
+    pub human_score: i16,
+    pub extend_count: i16,
+}
+#[derive(Object, Serialize, Deserialize)]
+pub struct SessionTimeline {
+    pub time_id: i32,
+    pub map: String,
+    pub started_at: DateTime<Utc>,
+    pub ended_at: Option<DateTime<Utc>>,
+    pub players: Vec<TimelinePlayer>,
+    pub rounds: Vec<TimelineRound>,
+}
 #[derive(Object)]
 pub struct MapEventAverage{
     pub event_name: String,
@@ -544,6 +665,7 @@ pub struct PlayerSeen{
     pub name: String,
     pub total_time_together: f64,
     pub last_seen: DateTime<Utc>,
+    pub is_anonymous: bool,
 }
 #[derive(Object)]
 pub struct PlayerSessionPage{
@@ -660,6 +782,7 @@ pub struct CountryPlayer{
     pub total_playtime: f64,
     pub total_player_count: i64,
     pub session_count: i64,
+    pub is_anonymous: bool,
 }
 #[derive(Object)]
 pub struct CountryPlayers{
@@ -735,7 +858,22 @@ impl<'a> poem::FromRequest<'a> for ServerExtractor {
         Ok(ServerExtractor(server))
     }
 }
-type UriExtension = dyn UriPatternExt + Send + Sync;
+pub type UriExtension = dyn UriPatternExt + Send + Sync;
+
+/// The most specific registered pattern matching `uri_path`, e.g. `/servers/{server_id}/maps`.
+pub fn route_pattern(apis: &[Arc<UriExtension>], uri_path: &str) -> Option<String> {
+    let mut a = vec![];
+    for api in apis {
+        for pattern in api.get_all_patterns() {
+            a.push(pattern);
+        }
+    }
+    a.iter()
+        .filter(|pat| pat.is_match(uri_path))
+        .max()
+        .map(|e| e.uri.to_string())
+}
+
 pub struct PatternLogger {
     pub routers: Vec<Arc<UriExtension>>
 }
@@ -760,24 +898,6 @@ pub struct PatternLoggerEndpoint<E> {
     apis: Vec<Arc<UriExtension>>,
 }
 
-impl<E> PatternLoggerEndpoint<E>
-where
-    E: Endpoint<Output = poem::Response>,
-{
-    #[allow(mismatched_lifetime_syntaxes)]
-    fn find_pattern(&self, uri_path: &str) -> Option<RoutePattern> {
-        let mut a = vec![];
-        for api in &self.apis {
-            for pattern in api.get_all_patterns() {
-                a.push(pattern);
-            }
-        }
-        a.iter()
-            .filter(|pat| pat.is_match(uri_path))
-            .max()
-            .map(|e| e.clone())
-    }
-}
 impl<E> Endpoint for PatternLoggerEndpoint<E>
 where
     E: Endpoint<Output = poem::Response>,
@@ -792,8 +912,8 @@ where
             }
         }
         let uri_path = String::from(uri.path());
-        let transaction_name = match self.find_pattern(&uri_path) {
-            Some(pattern) => pattern.uri.to_string(),
+        let transaction_name = match route_pattern(&self.apis, &uri_path) {
+            Some(pattern) => pattern,
             None => {
                 tracing::warn!("Unregistered pattern: {uri_path}");
                 "unknown_pattern".to_string()
@@ -890,6 +1010,10 @@ impl<'a> RoutePattern<'a> {
         }
     }
 
+    pub fn uri(&self) -> &'a str {
+        self.uri
+    }
+
     pub fn is_match(&self, path: &str) -> bool {
         suppress_panic_logs(|| self.pattern.is_match(path)).unwrap_or(false)
     }
@@ -931,6 +1055,11 @@ pub struct Claims {
     pub name: String,
     pub exp: usize,
     pub iss: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub iat: Option<usize>,
+    /// Session the token was issued for, absent on tokens NextAuth signs itself
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sid: Option<String>,
 }
 
 
@@ -959,6 +1088,31 @@ pub struct SteamProfile {
     pub locstatecode: Option<String>,
     pub loccityid: Option<i64>,
     pub is_superuser: Option<bool>,
+    /// Linked Discord account, only filled in on `/accounts/me`
+    #[serde(default)]
+    pub discord: Option<DiscordLink>,
+}
+
+#[derive(Object, Serialize, Deserialize, Clone)]
+pub struct DiscordLink {
+    pub discord_id: String,
+    pub username: Option<String>,
+    pub display_name: Option<String>,
+    pub avatar: Option<String>,
+    pub linked_at: DateTime<Utc>,
+    pub roles_synced_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Object, Serialize, Deserialize)]
+pub struct DiscordCallbackDto {
+    pub code: String,
+    pub state: String,
+}
+
+#[derive(Object, Serialize)]
+pub struct DiscordRoleSync {
+    pub granted: i64,
+    pub revoked: i64,
 }
 
 #[derive(Deserialize)]
@@ -1018,7 +1172,12 @@ pub struct Guide {
     pub map_name: String,
     pub server_id: Option<String>,
     pub title: String,
+    /// Markdown source
     pub content: String,
+    /// `content` rendered and sanitized, safe to insert as is
+    pub content_html: Option<String>,
+    /// Plain text opening of the guide
+    pub excerpt: Option<String>,
     pub category: String,
     pub author: GuideAuthor,
     pub created_at: DateTime<Utc>,
@@ -1030,12 +1189,73 @@ pub struct Guide {
     pub user_vote: Option<VoteType>,
 }
 
+#[derive(Object)]
+pub struct GuideRevisionBrief {
+    pub revision: i32,
+    pub title: String,
+    pub category: String,
+    pub server_id: Option<String>,
+    pub editor: GuideAuthor,
+    /// Revision a moderator brought back with this one
+    pub restored_from: Option<i32>,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Object)]
+pub struct GuideRevision {
+    pub guide_id: String,
+    pub revision: i32,
+    pub title: String,
+    pub content: String,
+    pub category: String,
+    pub server_id: Option<String>,
+    pub editor: GuideAuthor,
+    pub restored_from: Option<i32>,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Enum)]
+#[oai(rename_all = "lowercase")]
+pub enum DiffChange {
+    Equal,
+    Insert,
+    Delete,
+}
+
+#[derive(Object)]
+pub struct DiffLine {
+    pub change: DiffChange,
+    pub text: String,
+}
+
+#[derive(Object)]
+pub struct FieldChange {
+    pub before: Option<String>,
+    pub after: Option<String>,
+}
+
+#[derive(Object)]
+pub struct GuideRevisionDiff {
+    pub guide_id: String,
+    pub from: i32,
+    pub to: i32,
+    /// Only set for the fields that differ
+    pub title: Option<FieldChange>,
+    pub category: Option<FieldChange>,
+    pub server_id: Option<FieldChange>,
+    /// Line by line, unchanged lines included
+    pub content: Vec<DiffLine>,
+    pub lines_added: i64,
+    pub lines_removed: i64,
+}
+
 #[derive(Object)]
 pub struct GuideComment {
     pub id: String,
     pub guide_id: String,
     pub author: GuideAuthor,
     pub content: String,
+    pub content_html: Option<String>,
     pub created_at: DateTime<Utc>,
     pub updated_at: DateTime<Utc>,
     pub upvotes: i64,
@@ -1099,6 +1319,10 @@ pub struct GuideReportAdmin {
     pub resolver_name: Option<String>,
     pub resolved_at: Option<DateTime<Utc>>,
     pub created_at: DateTime<Utc>,
+    /// Guide revision current when the report was filed
+    pub reported_revision: Option<i32>,
+    /// What the guide said at that revision
+    pub reported_content: Option<String>,
 }
 
 #[derive(Object)]
@@ -1321,6 +1545,19 @@ pub enum ResType {  // TODO: Actually use this
     High,
 }
 
+/// What we could read from a GLB without loading its buffers.
+#[derive(Object, Serialize, Deserialize, Clone)]
+pub struct GlbInfo {
+    pub mesh_count: i64,
+    pub texture_count: i64,
+    pub triangle_count: i64,
+    /// Union of the mesh POSITION bounds, node transforms are not applied.
+    pub bbox_min: Option<Vec<f64>>,
+    pub bbox_max: Option<Vec<f64>>,
+    pub uses_draco: bool,
+    pub uses_meshopt: bool,
+}
+
 #[derive(Object, Serialize)]
 pub struct Map3DModel {
     pub id: i32,
@@ -1333,34 +1570,25 @@ pub struct Map3DModel {
     pub file_size: i64,
     pub created_at: DateTime<Utc>,
     pub updated_at: DateTime<Utc>,
+    pub content_hash: Option<String>,
+    pub glb_info: Option<GlbInfo>,
+    /// Generated after upload, served from `/thumbnails/map-models/{thumbnail_path}`
+    pub thumbnail_path: Option<String>,
+    /// A low model optimized from the high one rather than uploaded
+    pub generated: bool,
 }
 
-#[derive(Object, Serialize, Deserialize, Clone)]
-pub struct UploadSession {
-    pub session_id: String,
-    pub map_name: String,
-    pub res_type: String,
+#[derive(Object, Serialize)]
+pub struct Map3DModelVersion {
+    pub id: i32,
+    /// None for the model uploaded before content addressing
+    pub content_hash: Option<String>,
+    pub link_path: String,
     pub credit: Option<String>,
-    pub total_chunks: u32,
-    pub chunk_size: usize,
-    pub total_size: u64,
-    pub uploaded_by: i64,
-    pub created_at: String,
-    pub chunks_received: Vec<u32>,
-}
-
-#[derive(Object, Serialize, Deserialize)]
-pub struct InitiateUploadResponse {
-    pub session_id: String,
-    pub chunk_size: usize,
-    pub total_chunks: u32,
-}
-
-#[derive(Object, Serialize, Deserialize)]
-pub struct ChunkUploadResponse {
-    pub chunk_index: u32,
-    pub received: bool,
-    pub chunks_remaining: u32,
+    pub uploaded_by: Option<i64>,
+    pub file_size: i64,
+    pub created_at: DateTime<Utc>,
+    pub is_current: bool,
 }
 
 #[derive(Object, Serialize)]
@@ -1384,21 +1612,7 @@ pub struct Character3DModel {
     pub file_size: i64,
     pub created_at: DateTime<Utc>,
     pub updated_at: DateTime<Utc>,
-}
-
-#[derive(Object, Serialize, Deserialize, Clone)]
-pub struct CharacterUploadSession {
-    pub session_id: String,
-    pub model_id: String,
-    pub name: Option<String>,
-    pub server_id: String,
-    pub credit: Option<String>,
-    pub total_chunks: u32,
-    pub chunk_size: usize,
-    pub total_size: u64,
-    pub uploaded_by: i64,
-    pub created_at: String,
-    pub chunks_received: Vec<u32>,
+    pub glb_info: Option<GlbInfo>,
 }
 
 #[derive(Object, Serialize, Deserialize, Clone)]
@@ -1430,3 +1644,43 @@ pub struct ServerRequestsPaginated {
     pub total: i64,
     pub requests: Vec<ServerRequestAdmin>,
 }
+
+// ============================================================================
+// SESSION MODELS
+// ============================================================================
+
+#[derive(Object, Serialize, Deserialize)]
+pub struct CreateSessionDto {
+    /// Stable per browser or app install, signing in again on it replaces its session
+    pub device_id: String,
+    pub device_name: Option<String>,
+}
+
+#[derive(Object, Serialize, Deserialize)]
+pub struct RefreshSessionDto {
+    pub refresh_token: String,
+}
+
+#[derive(Object, Serialize)]
+pub struct SessionTokens {
+    pub session_id: String,
+    pub access_token: String,
+    pub access_token_expires_at: DateTime<Utc>,
+    /// Single use, the response to refreshing carries the next one
+    pub refresh_token: String,
+    pub refresh_token_expires_at: DateTime<Utc>,
+}
+
+#[derive(Object, Serialize)]
+pub struct UserSession {
+    pub id: String,
+    pub device_id: String,
+    pub device_name: Option<String>,
+    pub user_agent: Option<String>,
+    pub ip_address: Option<String>,
+    pub created_at: DateTime<Utc>,
+    pub last_refreshed_at: DateTime<Utc>,
+    pub expires_at: DateTime<Utc>,
+    /// The session the request was made with
+    pub current: bool,
+}
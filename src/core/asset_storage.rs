@@ -0,0 +1,905 @@
+use std::collections::HashMap;
+use std::marker::PhantomData;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use hmac::{Hmac, Mac};
+use sha2::{Digest, Sha256};
+use aws_credential_types::Credentials;
+use aws_sdk_s3::config::BehaviorVersion;
+use aws_sdk_s3::presigning::PresigningConfig;
+use aws_sdk_s3::primitives::ByteStream;
+use aws_sdk_s3::{Client, config::Region};
+use tokio::io::AsyncReadExt;
+use tokio::sync::RwLock;
+
+use crate::core::utils::get_env_default;
+
+type HmacSha256 = Hmac<Sha256>;
+
+const DEFAULT_URL_TTL_SECS: u64 = 3600;
+const DEFAULT_DOWNLOAD_BASE_URL: &str = "/data/api";
+/// Presigned URLs are only handed out by the rate limited download route, one per request.
+const PRESIGNED_DOWNLOAD_TTL: Duration = Duration::from_secs(60);
+
+/// An object as reported by [`StorageBackend::list`].
+#[derive(Clone)]
+pub struct StoredObject {
+    pub key: String,
+    pub size: u64,
+    pub modified_at: Option<DateTime<Utc>>,
+}
+
+/// Where asset bytes end up. Keys are always relative to the backend root / bucket.
+#[async_trait]
+pub trait StorageBackend: Send + Sync {
+    async fn put_bytes(&self, key: &str, bytes: &[u8], content_type: &str) -> Result<(), String>;
+    /// Moves (or uploads) a file that already sits on local disk.
+    async fn put_file(&self, key: &str, file_path: &Path, content_type: &str) -> Result<(), String>;
+    async fn delete(&self, key: &str) -> Result<(), String>;
+    /// Copies an object to a local file, for jobs that need to read it back.
+    async fn download(&self, key: &str, target: &Path) -> Result<(), String>;
+    /// Every object whose key starts with `prefix`.
+    async fn list(&self, prefix: &str) -> Result<Vec<StoredObject>, String>;
+    /// A temporary GET URL handed out by the backend itself, `None` when it cannot presign.
+    async fn presigned_url(&self, _key: &str, _expires_in: Duration) -> Option<Result<String, String>> {
+        None
+    }
+    fn local_root(&self) -> Option<&str> {
+        None
+    }
+}
+
+pub struct LocalBackend {
+    root: String,
+}
+
+impl LocalBackend {
+    pub fn new(root: String) -> Self {
+        Self { root }
+    }
+}
+
+#[async_trait]
+impl StorageBackend for LocalBackend {
+    async fn put_bytes(&self, key: &str, bytes: &[u8], _content_type: &str) -> Result<(), String> {
+        let path = Path::new(&self.root).join(key);
+        if let Some(parent) = path.parent() {
+            tokio::fs::create_dir_all(parent)
+                .await
+                .map_err(|e| format!("Failed to create directory: {e}"))?;
+        }
+        tokio::fs::write(&path, bytes)
+            .await
+            .map_err(|e| format!("Failed to write file: {e}"))
+    }
+
+    async fn put_file(&self, key: &str, file_path: &Path, _content_type: &str) -> Result<(), String> {
+        let target_path = Path::new(&self.root).join(key);
+        if file_path == target_path {
+            return Ok(())
+        }
+        if let Some(parent) = target_path.parent() {
+            tokio::fs::create_dir_all(parent)
+                .await
+                .map_err(|e| format!("Failed to create directory: {e}"))?;
+        }
+        tokio::fs::rename(file_path, &target_path)
+            .await
+            .map_err(|e| format!("Failed to move file: {e}"))
+    }
+
+    async fn delete(&self, key: &str) -> Result<(), String> {
+        let path = Path::new(&self.root).join(key);
+        tokio::fs::remove_file(&path)
+            .await
+            .map_err(|e| format!("Failed to delete file {path:?}: {e}"))
+    }
+
+    async fn download(&self, key: &str, target: &Path) -> Result<(), String> {
+        let path = Path::new(&self.root).join(key);
+        tokio::fs::copy(&path, target)
+            .await
+            .map_err(|e| format!("Failed to copy file {path:?}: {e}"))?;
+        Ok(())
+    }
+
+    /// Walks the root directory, hidden entries such as the `.tmp` upload area are skipped.
+    async fn list(&self, prefix: &str) -> Result<Vec<StoredObject>, String> {
+        let mut objects = vec![];
+        let mut pending = vec![PathBuf::from(&self.root)];
+        while let Some(dir) = pending.pop() {
+            let mut entries = match tokio::fs::read_dir(&dir).await {
+                Ok(entries) => entries,
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => continue,
+                Err(e) => return Err(format!("Failed to read directory {dir:?}: {e}")),
+            };
+            while let Some(entry) = entries.next_entry()
+                .await
+                .map_err(|e| format!("Failed to read directory {dir:?}: {e}"))? {
+                if entry.file_name().to_string_lossy().starts_with('.') {
+                    continue
+                }
+                let path = entry.path();
+                let Ok(metadata) = entry.metadata().await else {
+                    continue
+                };
+                if metadata.is_dir() {
+                    pending.push(path);
+                    continue
+                }
+                let Ok(relative) = path.strip_prefix(&self.root) else {
+                    continue
+                };
+                let key = relative.components()
+                    .map(|c| c.as_os_str().to_string_lossy())
+                    .collect::<Vec<_>>()
+                    .join("/");
+                if key.starts_with(prefix) {
+                    objects.push(StoredObject {
+                        key,
+                        size: metadata.len(),
+                        modified_at: metadata.modified().ok().map(DateTime::<Utc>::from),
+                    });
+                }
+            }
+        }
+        Ok(objects)
+    }
+
+    fn local_root(&self) -> Option<&str> {
+        Some(&self.root)
+    }
+}
+
+/// Any S3-compatible endpoint, Cloudflare R2 and MinIO included.
+pub struct S3Backend {
+    client: Client,
+    bucket: String,
+}
+
+impl S3Backend {
+    pub fn new(
+        endpoint: String, region: String, access_key: String, secret_key: String,
+        bucket: String, force_path_style: bool,
+    ) -> Self {
+        let credentials = Credentials::new(access_key, secret_key, None, None, "s3");
+        let config = aws_sdk_s3::Config::builder()
+            .behavior_version(BehaviorVersion::v2026_01_12())
+            .region(Region::new(region))
+            .endpoint_url(endpoint)
+            .credentials_provider(credentials)
+            .force_path_style(force_path_style)
+            .build();
+        Self { client: Client::from_conf(config), bucket }
+    }
+}
+
+#[async_trait]
+impl StorageBackend for S3Backend {
+    async fn put_bytes(&self, key: &str, bytes: &[u8], content_type: &str) -> Result<(), String> {
+        self.client
+            .put_object()
+            .bucket(&self.bucket)
+            .key(key)
+            .content_type(content_type)
+            .body(ByteStream::from(bytes.to_vec()))
+            .send()
+            .await
+            .map_err(|e| format!("S3 upload failed: {e}"))?;
+        Ok(())
+    }
+
+    async fn put_file(&self, key: &str, file_path: &Path, content_type: &str) -> Result<(), String> {
+        let body = ByteStream::from_path(file_path)
+            .await
+            .map_err(|e| format!("Failed to read file for upload: {e}"))?;
+        self.client
+            .put_object()
+            .bucket(&self.bucket)
+            .key(key)
+            .content_type(content_type)
+            .body(body)
+            .send()
+            .await
+            .map_err(|e| format!("S3 upload failed: {e}"))?;
+        Ok(())
+    }
+
+    async fn delete(&self, key: &str) -> Result<(), String> {
+        self.client
+            .delete_object()
+            .bucket(&self.bucket)
+            .key(key)
+            .send()
+            .await
+            .map_err(|e| format!("S3 delete failed: {e}"))?;
+        Ok(())
+    }
+
+    async fn download(&self, key: &str, target: &Path) -> Result<(), String> {
+        let object = self.client
+            .get_object()
+            .bucket(&self.bucket)
+            .key(key)
+            .send()
+            .await
+            .map_err(|e| format!("S3 download failed: {e}"))?;
+        let mut reader = object.body.into_async_read();
+        let mut file = tokio::fs::File::create(target)
+            .await
+            .map_err(|e| format!("Failed to create file: {e}"))?;
+        tokio::io::copy(&mut reader, &mut file)
+            .await
+            .map_err(|e| format!("S3 download failed: {e}"))?;
+        Ok(())
+    }
+
+    async fn list(&self, prefix: &str) -> Result<Vec<StoredObject>, String> {
+        let mut objects = vec![];
+        let mut continuation_token = None;
+        loop {
+            let page = self.client
+                .list_objects_v2()
+                .bucket(&self.bucket)
+                .prefix(prefix)
+                .set_continuation_token(continuation_token)
+                .send()
+                .await
+                .map_err(|e| format!("S3 list failed: {e}"))?;
+            for object in page.contents() {
+                let Some(key) = object.key() else {
+                    continue
+                };
+                objects.push(StoredObject {
+                    key: key.to_string(),
+                    size: object.size().unwrap_or_default().max(0) as u64,
+                    modified_at: object.last_modified()
+                        .and_then(|t| DateTime::from_timestamp(t.secs(), 0)),
+                });
+            }
+            continuation_token = page.next_continuation_token().map(str::to_string);
+            if continuation_token.is_none() {
+                break
+            }
+        }
+        Ok(objects)
+    }
+
+    async fn presigned_url(&self, key: &str, expires_in: Duration) -> Option<Result<String, String>> {
+        let config = match PresigningConfig::expires_in(expires_in) {
+            Ok(config) => config,
+            Err(e) => return Some(Err(format!("Invalid presigning config: {e}"))),
+        };
+        let result = self.client
+            .get_object()
+            .bucket(&self.bucket)
+            .key(key)
+            .presigned(config)
+            .await
+            .map(|request| request.uri().to_string())
+            .map_err(|e| format!("S3 presign failed: {e}"));
+        Some(result)
+    }
+}
+
+/// Keeps objects in process memory. Nothing survives a restart, meant for development and the
+/// tests below.
+#[derive(Default)]
+pub struct MemoryBackend {
+    objects: RwLock<HashMap<String, Vec<u8>>>,
+}
+
+impl MemoryBackend {
+    pub async fn get(&self, key: &str) -> Option<Vec<u8>> {
+        self.objects.read().await.get(key).cloned()
+    }
+}
+
+#[async_trait]
+impl StorageBackend for MemoryBackend {
+    async fn put_bytes(&self, key: &str, bytes: &[u8], _content_type: &str) -> Result<(), String> {
+        self.objects.write().await.insert(key.to_string(), bytes.to_vec());
+        Ok(())
+    }
+
+    async fn put_file(&self, key: &str, file_path: &Path, _content_type: &str) -> Result<(), String> {
+        let bytes = tokio::fs::read(file_path)
+            .await
+            .map_err(|e| format!("Failed to read file: {e}"))?;
+        self.objects.write().await.insert(key.to_string(), bytes);
+        let _ = tokio::fs::remove_file(file_path).await;
+        Ok(())
+    }
+
+    async fn delete(&self, key: &str) -> Result<(), String> {
+        match self.objects.write().await.remove(key) {
+            Some(_) => Ok(()),
+            None => Err(format!("Object {key} does not exist")),
+        }
+    }
+
+    async fn download(&self, key: &str, target: &Path) -> Result<(), String> {
+        let bytes = self.get(key).await.ok_or(format!("Object {key} does not exist"))?;
+        tokio::fs::write(target, bytes)
+            .await
+            .map_err(|e| format!("Failed to write file: {e}"))
+    }
+
+    async fn list(&self, prefix: &str) -> Result<Vec<StoredObject>, String> {
+        Ok(self.objects.read().await
+            .iter()
+            .filter(|(key, _)| key.starts_with(prefix))
+            .map(|(key, bytes)| StoredObject { key: key.clone(), size: bytes.len() as u64, modified_at: None })
+            .collect())
+    }
+}
+
+/// Env variable names used to configure one asset kind.
+pub struct AssetEnv {
+    pub backend: &'static str,
+    pub object_prefix: &'static str,
+    pub public_base_url: &'static str,
+    pub local_root: &'static str,
+    /// `public` (default) or `signed`.
+    pub url_mode: &'static str,
+}
+
+/// How links to stored objects are handed out.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum UrlMode {
+    /// Permanent URLs under the public base URL.
+    Public,
+    /// Expiring HMAC signed URLs served by `/assets/{kind}/download`, which redirects
+    /// to a short lived presigned URL on S3 compatible backends.
+    Signed,
+}
+
+/// Settings for [`UrlMode::Signed`], shared by every asset kind.
+/// `ASSET_SIGNING_SECRET`, `ASSET_URL_TTL_SECS` and `ASSET_DOWNLOAD_BASE_URL`.
+#[derive(Clone)]
+pub struct UrlSigning {
+    secret: Option<Vec<u8>>,
+    ttl: Duration,
+    download_base_url: String,
+}
+
+impl UrlSigning {
+    pub fn from_env() -> Self {
+        Self {
+            secret: get_env_default("ASSET_SIGNING_SECRET")
+                .filter(|s| !s.is_empty())
+                .map(String::into_bytes),
+            ttl: Duration::from_secs(
+                get_env_default("ASSET_URL_TTL_SECS")
+                    .and_then(|s| s.parse().ok())
+                    .unwrap_or(DEFAULT_URL_TTL_SECS)
+            ),
+            download_base_url: get_env_default("ASSET_DOWNLOAD_BASE_URL")
+                .unwrap_or_else(|| DEFAULT_DOWNLOAD_BASE_URL.to_string()),
+        }
+    }
+
+    fn mac(&self, kind: &str, key: &str, expires: i64) -> Option<HmacSha256> {
+        let mut mac = HmacSha256::new_from_slice(self.secret.as_deref()?).ok()?;
+        mac.update(format!("{kind}\n{key}\n{expires}").as_bytes());
+        Some(mac)
+    }
+}
+
+/// A kind of asset we host. Each kind owns its configuration and key layout,
+/// the storage itself is shared through [`AssetStorage`].
+pub trait AssetKind: Send + Sync + 'static {
+    /// Whatever identifies a single object of this kind.
+    type Id<'a>: Copy;
+    const NAME: &'static str;
+    const ENV: AssetEnv;
+    /// Backend to use when `ENV.backend` is unset.
+    const FALLBACK_BACKEND_ENV: Option<&'static str> = None;
+    const DEFAULT_LOCAL_ROOT: &'static str;
+    const DEFAULT_PUBLIC_BASE_URL: &'static str;
+    const CONTENT_TYPE: &'static str;
+    /// Objects only leave through routes that check who is asking, so no public URL is needed.
+    const PRIVATE: bool = false;
+
+    /// Object key without the configured prefix.
+    fn key(id: Self::Id<'_>) -> String;
+
+    /// Whether an unprefixed key follows this kind's layout. Kinds can share a bucket,
+    /// so anything else found while listing belongs to someone else.
+    fn owns_key(key: &str) -> bool;
+}
+
+pub struct MapAsset;
+impl AssetKind for MapAsset {
+    /// `(map_name, res_type, content_hash)`
+    type Id<'a> = (&'a str, &'a str, Option<&'a str>);
+    const NAME: &'static str = "map";
+    const ENV: AssetEnv = AssetEnv {
+        backend: "MAP_STORAGE_BACKEND",
+        object_prefix: "MAPS_OBJECT_PREFIX",
+        public_base_url: "MAPS_PUBLIC_BASE_URL",
+        local_root: "STORE_UPLOAD",
+        url_mode: "MAPS_URL_MODE",
+    };
+    const DEFAULT_LOCAL_ROOT: &'static str = "./maps";
+    const DEFAULT_PUBLIC_BASE_URL: &'static str = "/models/maps";
+    const CONTENT_TYPE: &'static str = "model/gltf-binary";
+
+    /// Uploads are addressed by their SHA-256 so every version gets its own URL.
+    /// Models uploaded before hashing still live under the old fixed name.
+    fn key((map_name, res_type, content_hash): Self::Id<'_>) -> String {
+        match content_hash {
+            Some(hash) => format!("{map_name}/{res_type}/{hash}.glb"),
+            None => format!("{map_name}/{map_name}_d_c_{res_type}.glb"),
+        }
+    }
+
+    fn owns_key(key: &str) -> bool {
+        let is_res = |res: &str| res == "low" || res == "high";
+        match key.split('/').collect::<Vec<_>>().as_slice() {
+            [_, res_type, file] => is_res(*res_type) && file.strip_suffix(".glb")
+                .is_some_and(|hash| hash.len() == 64 && hash.bytes().all(|b| b.is_ascii_hexdigit())),
+            [map_name, file] => file.strip_prefix(&format!("{map_name}_d_c_"))
+                .and_then(|rest| rest.strip_suffix(".glb"))
+                .is_some_and(is_res),
+            _ => false,
+        }
+    }
+}
+
+pub struct CharacterAsset;
+impl AssetKind for CharacterAsset {
+    /// `model_id`
+    type Id<'a> = &'a str;
+    const NAME: &'static str = "character";
+    const ENV: AssetEnv = AssetEnv {
+        backend: "CHARACTER_STORAGE_BACKEND",
+        object_prefix: "CHARACTER_OBJECT_PREFIX",
+        public_base_url: "CHARACTER_PUBLIC_BASE_URL",
+        local_root: "CHARACTER_STORE_UPLOAD",
+        url_mode: "CHARACTER_URL_MODE",
+    };
+    const FALLBACK_BACKEND_ENV: Option<&'static str> = Some("MAP_STORAGE_BACKEND");
+    const DEFAULT_LOCAL_ROOT: &'static str = "./characters";
+    const DEFAULT_PUBLIC_BASE_URL: &'static str = "/models/characters";
+    const CONTENT_TYPE: &'static str = "model/gltf-binary";
+
+    fn key(model_id: Self::Id<'_>) -> String {
+        format!("{model_id}/{model_id}_char.glb")
+    }
+
+    fn owns_key(key: &str) -> bool {
+        match key.split_once('/') {
+            Some((model_id, file)) => file == format!("{model_id}_char.glb"),
+            None => false,
+        }
+    }
+}
+
+/// Personal data archives from `/accounts/me/export`, `EXPORT_DIR` is their local root.
+pub struct ExportAsset;
+impl AssetKind for ExportAsset {
+    /// Export id
+    type Id<'a> = uuid::Uuid;
+    const NAME: &'static str = "export";
+    const ENV: AssetEnv = AssetEnv {
+        backend: "EXPORT_STORAGE_BACKEND",
+        object_prefix: "EXPORT_OBJECT_PREFIX",
+        public_base_url: "EXPORT_PUBLIC_BASE_URL",
+        local_root: "EXPORT_DIR",
+        url_mode: "EXPORT_URL_MODE",
+    };
+    const FALLBACK_BACKEND_ENV: Option<&'static str> = Some("MAP_STORAGE_BACKEND");
+    const DEFAULT_LOCAL_ROOT: &'static str = "./exports";
+    const DEFAULT_PUBLIC_BASE_URL: &'static str = "";
+    const CONTENT_TYPE: &'static str = "application/json";
+    const PRIVATE: bool = true;
+
+    fn key(export_id: Self::Id<'_>) -> String {
+        format!("exports/{export_id}.json")
+    }
+
+    fn owns_key(key: &str) -> bool {
+        key.strip_prefix("exports/")
+            .and_then(|file| file.strip_suffix(".json"))
+            .is_some_and(|id| uuid::Uuid::parse_str(id).is_ok())
+    }
+}
+
+pub type MapStorage = AssetStorage<MapAsset>;
+pub type CharacterStorage = AssetStorage<CharacterAsset>;
+pub type ExportStorage = AssetStorage<ExportAsset>;
+
+pub struct AssetStorage<K: AssetKind> {
+    backend: Arc<dyn StorageBackend>,
+    object_prefix: String,
+    public_base_url: String,
+    url_mode: UrlMode,
+    signing: UrlSigning,
+    kind: PhantomData<K>,
+}
+
+impl<K: AssetKind> Clone for AssetStorage<K> {
+    fn clone(&self) -> Self {
+        Self {
+            backend: self.backend.clone(),
+            object_prefix: self.object_prefix.clone(),
+            public_base_url: self.public_base_url.clone(),
+            url_mode: self.url_mode,
+            signing: self.signing.clone(),
+            kind: PhantomData,
+        }
+    }
+}
+
+impl<K: AssetKind> AssetStorage<K> {
+    pub fn new(
+        backend: Arc<dyn StorageBackend>, object_prefix: &str, public_base_url: String,
+        url_mode: UrlMode, signing: UrlSigning,
+    ) -> Self {
+        Self {
+            backend,
+            object_prefix: object_prefix.trim_matches('/').to_string(),
+            public_base_url,
+            url_mode,
+            signing,
+            kind: PhantomData,
+        }
+    }
+
+    pub async fn from_env() -> Result<Self, String> {
+        let backend_name = get_env_default(K::ENV.backend)
+            .or_else(|| K::FALLBACK_BACKEND_ENV.and_then(get_env_default))
+            .unwrap_or_else(|| "local".to_string())
+            .to_lowercase();
+
+        let object_prefix = get_env_default(K::ENV.object_prefix).unwrap_or_default();
+
+        let mut public_base_url = get_env_default(K::ENV.public_base_url)
+            .filter(|s| !s.is_empty());
+        if backend_name == "r2" || backend_name == "cloudflare" {
+            public_base_url = public_base_url
+                .or_else(|| get_env_default("R2_PUBLIC_BASE_URL").filter(|s| !s.is_empty()));
+        }
+        let public_base_url = public_base_url.unwrap_or_else(|| {
+            if backend_name == "local" || backend_name == "memory" {
+                K::DEFAULT_PUBLIC_BASE_URL.to_string()
+            } else {
+                String::new()
+            }
+        });
+        if public_base_url.is_empty() && !K::PRIVATE {
+            return Err(format!("{} is required for {} storage", K::ENV.public_base_url, K::NAME));
+        }
+
+        let backend: Arc<dyn StorageBackend> = match backend_name.as_str() {
+            "local" => {
+                let root = get_env_default(K::ENV.local_root)
+                    .unwrap_or_else(|| K::DEFAULT_LOCAL_ROOT.to_string());
+                Arc::new(LocalBackend::new(root))
+            }
+            "memory" => Arc::new(MemoryBackend::default()),
+            "r2" | "cloudflare" => Arc::new(s3_from_env("R2", Some("auto"), false)?),
+            "s3" | "minio" => Arc::new(s3_from_env("S3", None, true)?),
+            other => return Err(format!("Unsupported {}: {other}", K::ENV.backend)),
+        };
+
+        let url_mode = match get_env_default(K::ENV.url_mode).unwrap_or_default().to_lowercase().as_str() {
+            "" | "public" => UrlMode::Public,
+            "signed" => UrlMode::Signed,
+            other => return Err(format!("Unsupported {}: {other}", K::ENV.url_mode)),
+        };
+        let signing = UrlSigning::from_env();
+        // Every backend hands out our HMAC signed links first, presigning only happens on download
+        if url_mode == UrlMode::Signed && signing.secret.is_none() {
+            return Err(format!("ASSET_SIGNING_SECRET is required for signed {} URLs", K::NAME));
+        }
+
+        Ok(Self::new(backend, &object_prefix, public_base_url, url_mode, signing))
+    }
+
+    pub fn is_local(&self) -> bool {
+        self.backend.local_root().is_some()
+    }
+
+    pub fn local_root(&self) -> Option<&str> {
+        self.backend.local_root()
+    }
+
+    pub fn object_prefix(&self) -> &str {
+        &self.object_prefix
+    }
+
+    pub fn object_key(&self, id: K::Id<'_>) -> String {
+        let key = K::key(id);
+        if self.object_prefix.is_empty() {
+            key
+        } else {
+            format!("{}/{key}", self.object_prefix)
+        }
+    }
+
+    pub fn public_url(&self, id: K::Id<'_>) -> String {
+        join_url(&self.public_base_url, &self.object_key(id))
+    }
+
+    /// The URL clients should use for a stored row. External links are kept as they are,
+    /// our own objects get a public or, in signed mode, an expiring URL.
+    pub async fn normalize_link_path(&self, existing: &str, id: K::Id<'_>) -> String {
+        let existing = existing.trim();
+        let external = (existing.starts_with("http://") || existing.starts_with("https://"))
+            && !existing.starts_with(&self.public_base_url);
+        if external || (self.url_mode == UrlMode::Public && existing.starts_with("http")) {
+            return existing.to_string();
+        }
+        if self.url_mode == UrlMode::Signed {
+            match self.signed_url(&self.object_key(id)).await {
+                Ok(url) => return url,
+                Err(e) => tracing::error!("Failed to sign {} URL: {e}", K::NAME),
+            }
+        }
+        self.public_url(id)
+    }
+
+    async fn signed_url(&self, key: &str) -> Result<String, String> {
+        let expires = Utc::now().timestamp() + self.signing.ttl.as_secs() as i64;
+        let mac = self.signing.mac(K::NAME, key, expires).ok_or("ASSET_SIGNING_SECRET is not set")?;
+        let signature = hex::encode(mac.finalize().into_bytes());
+        let encoded_key: String = url::form_urlencoded::byte_serialize(key.as_bytes()).collect();
+        Ok(format!(
+            "{}/assets/{}/download?key={encoded_key}&expires={expires}&signature={signature}",
+            self.signing.download_base_url.trim_end_matches('/'), K::NAME,
+        ))
+    }
+
+    /// Checks a URL produced by [`Self::signed_url`]: not expired, signed by us and
+    /// pointing at an object of this kind.
+    pub fn verify_signed(&self, key: &str, expires: i64, signature: &str) -> bool {
+        if expires < Utc::now().timestamp() {
+            return false
+        }
+        let unprefixed = match self.object_prefix.is_empty() {
+            true => Some(key),
+            false => key.strip_prefix(&format!("{}/", self.object_prefix)),
+        };
+        let safe = key.split('/').all(|segment| !segment.is_empty() && segment != "." && segment != "..");
+        if !safe || !unprefixed.is_some_and(K::owns_key) {
+            return false
+        }
+        let (Some(mac), Ok(signature)) = (self.signing.mac(K::NAME, key, expires), hex::decode(signature)) else {
+            return false
+        };
+        mac.verify_slice(&signature).is_ok()
+    }
+
+    /// Where a verified download is redirected to, `None` when the backend cannot presign
+    /// and the object is streamed by us instead.
+    pub async fn presigned_download(&self, key: &str) -> Option<Result<String, String>> {
+        self.backend.presigned_url(key, PRESIGNED_DOWNLOAD_TTL).await
+    }
+
+    /// Opens a local object for streaming, `None` when the backend is not on disk.
+    pub async fn open_local(&self, key: &str) -> Option<Result<tokio::fs::File, String>> {
+        let path = Path::new(self.local_root()?).join(key);
+        Some(tokio::fs::File::open(&path).await.map_err(|e| format!("Failed to open {path:?}: {e}")))
+    }
+
+    pub fn local_path(&self, id: K::Id<'_>) -> Option<PathBuf> {
+        let root = self.local_root()?;
+        Some(Path::new(root).join(self.object_key(id)))
+    }
+
+    pub async fn store_bytes(&self, id: K::Id<'_>, bytes: &[u8]) -> Result<String, String> {
+        let key = self.object_key(id);
+        self.backend.put_bytes(&key, bytes, K::CONTENT_TYPE).await?;
+        Ok(join_url(&self.public_base_url, &key))
+    }
+
+    pub async fn store_file(&self, id: K::Id<'_>, file_path: &Path) -> Result<String, String> {
+        let key = self.object_key(id);
+        self.backend.put_file(&key, file_path, K::CONTENT_TYPE).await?;
+        Ok(join_url(&self.public_base_url, &key))
+    }
+
+    pub async fn delete(&self, id: K::Id<'_>) -> Result<(), String> {
+        self.backend.delete(&self.object_key(id)).await
+    }
+
+    pub async fn download(&self, id: K::Id<'_>, target: &Path) -> Result<(), String> {
+        self.backend.download(&self.object_key(id), target).await
+    }
+
+    /// Everything under this kind's prefix that follows its key layout, keys include the prefix.
+    pub async fn list(&self) -> Result<Vec<StoredObject>, String> {
+        let prefix = if self.object_prefix.is_empty() {
+            String::new()
+        } else {
+            format!("{}/", self.object_prefix)
+        };
+        let objects = self.backend.list(&prefix).await?;
+        Ok(objects.into_iter()
+            .filter(|object| K::owns_key(&object.key[prefix.len()..]))
+            .collect())
+    }
+
+    /// Deletes by raw key, for objects found through [`Self::list`] that no row points at.
+    pub async fn delete_key(&self, key: &str) -> Result<(), String> {
+        self.backend.delete(key).await
+    }
+}
+
+/// Reads `{prefix}_ENDPOINT`, `{prefix}_ACCESS_KEY_ID`, `{prefix}_SECRET_ACCESS_KEY`,
+/// `{prefix}_BUCKET` and optionally `{prefix}_REGION`.
+fn s3_from_env(prefix: &str, region: Option<&str>, force_path_style: bool) -> Result<S3Backend, String> {
+    let required = |name: &str| {
+        let var = format!("{prefix}_{name}");
+        get_env_default(&var).filter(|s| !s.is_empty()).ok_or(format!("{var} is required"))
+    };
+    let endpoint = required("ENDPOINT")?;
+    let access_key = required("ACCESS_KEY_ID")?;
+    let secret_key = required("SECRET_ACCESS_KEY")?;
+    let bucket = required("BUCKET")?;
+    let region = region.map(str::to_string)
+        .or_else(|| get_env_default(&format!("{prefix}_REGION")))
+        .unwrap_or_else(|| "us-east-1".to_string());
+
+    Ok(S3Backend::new(endpoint, region, access_key, secret_key, bucket, force_path_style))
+}
+
+/// Hex SHA-256 used to address asset versions.
+pub fn content_hash(bytes: &[u8]) -> String {
+    hex::encode(Sha256::digest(bytes))
+}
+
+pub async fn content_hash_file(path: &Path) -> Result<String, String> {
+    let mut file = tokio::fs::File::open(path)
+        .await
+        .map_err(|e| format!("Failed to open file: {e}"))?;
+    let mut hasher = Sha256::new();
+    let mut buffer = vec![0u8; 1024 * 1024];
+    loop {
+        let read = file.read(&mut buffer)
+            .await
+            .map_err(|e| format!("Failed to read file: {e}"))?;
+        if read == 0 {
+            break
+        }
+        hasher.update(&buffer[..read]);
+    }
+    Ok(hex::encode(hasher.finalize()))
+}
+
+fn join_url(base: &str, key: &str) -> String {
+    let base = base.trim_end_matches('/');
+    let key = key.trim_start_matches('/');
+    format!("{base}/{key}")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn block_on<F: std::future::Future>(future: F) -> F::Output {
+        tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap()
+            .block_on(future)
+    }
+
+    fn storage(prefix: &str, url_mode: UrlMode) -> (MapStorage, Arc<MemoryBackend>) {
+        let backend = Arc::new(MemoryBackend::default());
+        let signing = UrlSigning {
+            secret: Some(b"test-secret".to_vec()),
+            ttl: Duration::from_secs(60),
+            download_base_url: "/data/api".to_string(),
+        };
+        let storage = MapStorage::new(backend.clone(), prefix, "/models/maps".to_string(), url_mode, signing);
+        (storage, backend)
+    }
+
+    const HASH: &str = "0123456789abcdef0123456789abcdef0123456789abcdef0123456789abcdef";
+
+    /// `(key, expires, signature)` out of a URL from `signed_url`
+    fn signed_parts(url: &str) -> (String, i64, String) {
+        let parsed = url::Url::parse(&format!("http://localhost{url}")).unwrap();
+        let query: HashMap<String, String> = parsed.query_pairs().into_owned().collect();
+        (query["key"].clone(), query["expires"].parse().unwrap(), query["signature"].clone())
+    }
+
+    #[test]
+    fn stores_under_prefix_and_deletes() {
+        block_on(async {
+            let (storage, backend) = storage("/assets/", UrlMode::Public);
+            let url = storage.store_bytes(("ze_test", "high", Some(HASH)), b"glb").await.unwrap();
+            let key = format!("assets/ze_test/high/{HASH}.glb");
+            assert_eq!(url, format!("/models/maps/{key}"));
+            assert_eq!(backend.get(&key).await.as_deref(), Some(&b"glb"[..]));
+
+            storage.delete(("ze_test", "high", Some(HASH))).await.unwrap();
+            assert!(backend.get(&key).await.is_none());
+            assert!(storage.delete(("ze_test", "high", Some(HASH))).await.is_err());
+        })
+    }
+
+    #[test]
+    fn list_skips_other_kinds_and_prefixes() {
+        block_on(async {
+            let (storage, backend) = storage("maps", UrlMode::Public);
+            storage.store_bytes(("ze_a", "low", Some(HASH)), b"a").await.unwrap();
+            storage.store_bytes(("ze_b", "high", None), b"b").await.unwrap();
+            backend.put_bytes("maps/ze_a/ze_a_char.glb", b"c", "").await.unwrap();
+            backend.put_bytes("maps/ze_a/low/not-a-hash.glb", b"d", "").await.unwrap();
+            backend.put_bytes("other/ze_a/low/x.glb", b"e", "").await.unwrap();
+
+            let mut keys: Vec<String> = storage.list().await.unwrap().into_iter().map(|o| o.key).collect();
+            keys.sort();
+            assert_eq!(keys, vec![
+                format!("maps/ze_a/low/{HASH}.glb"),
+                "maps/ze_b/ze_b_d_c_high.glb".to_string(),
+            ]);
+        })
+    }
+
+    #[test]
+    fn export_keys_stay_apart_from_models() {
+        let id = uuid::Uuid::new_v4();
+        let key = ExportAsset::key(id);
+        assert!(ExportAsset::owns_key(&key));
+        assert!(!MapAsset::owns_key(&key));
+        assert!(!CharacterAsset::owns_key(&key));
+        assert!(!ExportAsset::owns_key("exports/not-a-uuid.json"));
+        assert!(!ExportAsset::owns_key(&format!("{id}.json")));
+    }
+
+    #[test]
+    fn signed_urls_verify_and_reject_tampering() {
+        block_on(async {
+            let (storage, _) = storage("", UrlMode::Signed);
+            let url = storage.normalize_link_path("", ("ze_test", "low", Some(HASH))).await;
+            assert!(url.starts_with("/data/api/assets/map/download?"));
+            let (key, expires, signature) = signed_parts(&url);
+            assert_eq!(key, format!("ze_test/low/{HASH}.glb"));
+            assert!(storage.verify_signed(&key, expires, &signature));
+
+            assert!(!storage.verify_signed(&key, expires + 1, &signature));
+            assert!(!storage.verify_signed(&format!("ze_other/low/{HASH}.glb"), expires, &signature));
+            assert!(!storage.verify_signed(&key, expires, "00"));
+            assert!(!storage.verify_signed(&key, expires, "not hex"));
+        })
+    }
+
+    #[test]
+    fn signed_urls_expire_and_stay_inside_the_kind() {
+        let (storage, _) = storage("", UrlMode::Signed);
+        let past = Utc::now().timestamp() - 1;
+        let sign = |key: &str, expires: i64| {
+            let mac = storage.signing.mac(MapAsset::NAME, key, expires).unwrap();
+            hex::encode(mac.finalize().into_bytes())
+        };
+        let key = format!("ze_test/low/{HASH}.glb");
+        assert!(!storage.verify_signed(&key, past, &sign(&key, past)));
+
+        let future = Utc::now().timestamp() + 60;
+        for key in ["../secrets/low/x.glb", "ze_test/../ze_test_d_c_low.glb", "model/model_char.glb", ""] {
+            assert!(!storage.verify_signed(key, future, &sign(key, future)), "{key}");
+        }
+    }
+
+    #[test]
+    fn public_links_keep_external_urls() {
+        block_on(async {
+            let (storage, _) = storage("", UrlMode::Public);
+            let external = "https://cdn.example.com/ze_test.glb";
+            assert_eq!(storage.normalize_link_path(external, ("ze_test", "low", None)).await, external);
+            assert_eq!(
+                storage.normalize_link_path("", ("ze_test", "low", None)).await,
+                "/models/maps/ze_test/ze_test_d_c_low.glb"
+            );
+        })
+    }
+}
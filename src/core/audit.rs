@@ -0,0 +1,49 @@
+use serde_json::{Map, Value};
+use sqlx::{Pool, Postgres};
+
+/// Appends to `website.audit_log`. Auditing never fails the action itself, errors are only logged.
+/// `actor_id` is `None` for changes made by background jobs.
+///
+/// When both sides are objects only the keys that changed are kept, so an entry reads as a diff.
+pub async fn record_audit(
+    pool: &Pool<Postgres>,
+    actor_id: Option<i64>,
+    action: &str,
+    target_type: &str,
+    target_id: &str,
+    before: Option<Value>,
+    after: Option<Value>,
+) {
+    let (before, after) = diff(before, after);
+    let result = sqlx::query!(
+        "INSERT INTO website.audit_log (actor_id, action, target_type, target_id, before, after)
+         VALUES ($1, $2, $3, $4, $5, $6)",
+        actor_id, action, target_type, target_id, before, after
+    )
+    .execute(pool)
+    .await;
+    if let Err(e) = result {
+        tracing::error!("Failed to record audit {action} on {target_type} {target_id}: {e}");
+    }
+}
+
+fn diff(before: Option<Value>, after: Option<Value>) -> (Option<Value>, Option<Value>) {
+    let (Some(Value::Object(before)), Some(Value::Object(after))) = (&before, &after) else {
+        return (before, after)
+    };
+
+    let mut removed = Map::new();
+    let mut added = Map::new();
+    for (key, old) in before {
+        match after.get(key) {
+            Some(new) if new == old => {}
+            _ => { removed.insert(key.clone(), old.clone()); }
+        }
+    }
+    for (key, new) in after {
+        if before.get(key) != Some(new) {
+            added.insert(key.clone(), new.clone());
+        }
+    }
+    (Some(Value::Object(removed)), Some(Value::Object(added)))
+}
@@ -0,0 +1,458 @@
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use chrono::Utc;
+use futures::stream::BoxStream;
+use futures::TryStreamExt;
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use sqlx::{Pool, Postgres};
+use tokio::io::{AsyncWriteExt, BufWriter};
+use uuid::Uuid;
+
+use crate::AppData;
+use crate::core::model::DbDataExport;
+use crate::core::utils::{get_env_bool, get_env_default};
+
+type HmacSha256 = Hmac<Sha256>;
+
+const POLL_INTERVAL: Duration = Duration::from_secs(10);
+/// Running exports older than this were lost to a restart and get picked up again.
+const STUCK_AFTER_SECS: f64 = 3600.0;
+const MAX_ATTEMPTS: i32 = 3;
+const DEFAULT_RETENTION_HOURS: i32 = 72;
+const DEFAULT_LINK_TTL_SECS: i64 = 3600;
+const DEFAULT_DOWNLOAD_BASE_URL: &str = "/data/api";
+const FORMAT_VERSION: u32 = 1;
+
+#[derive(Clone, Copy)]
+enum Section {
+    Profile,
+    Player,
+    Aliases,
+    Sessions,
+    Infractions,
+    Guides,
+    GuideRevisions,
+    GuideComments,
+    GuideVotes,
+    GuideCommentVotes,
+    Reports,
+    PushSubscriptions,
+    NotificationPreferences,
+    NotificationSubscriptions,
+    FavoriteMaps,
+    Anonymization,
+    Discord,
+    ApiKeys,
+    LoginSessions,
+}
+
+impl Section {
+    /// Written in this order, progress advances by one section at a time.
+    const ALL: [Section; 19] = [
+        Section::Profile,
+        Section::Player,
+        Section::Aliases,
+        Section::Sessions,
+        Section::Infractions,
+        Section::Guides,
+        Section::GuideRevisions,
+        Section::GuideComments,
+        Section::GuideVotes,
+        Section::GuideCommentVotes,
+        Section::Reports,
+        Section::PushSubscriptions,
+        Section::NotificationPreferences,
+        Section::NotificationSubscriptions,
+        Section::FavoriteMaps,
+        Section::Anonymization,
+        Section::Discord,
+        Section::ApiKeys,
+        Section::LoginSessions,
+    ];
+
+    fn name(self) -> &'static str {
+        match self {
+            Section::Profile => "profile",
+            Section::Player => "player",
+            Section::Aliases => "aliases",
+            Section::Sessions => "sessions",
+            Section::Infractions => "infractions",
+            Section::Guides => "guides",
+            Section::GuideRevisions => "guide_revisions",
+            Section::GuideComments => "guide_comments",
+            Section::GuideVotes => "guide_votes",
+            Section::GuideCommentVotes => "guide_comment_votes",
+            Section::Reports => "reports",
+            Section::PushSubscriptions => "push_subscriptions",
+            Section::NotificationPreferences => "notification_preferences",
+            Section::NotificationSubscriptions => "notification_subscriptions",
+            Section::FavoriteMaps => "favorite_maps",
+            Section::Anonymization => "anonymization",
+            Section::Discord => "discord",
+            Section::ApiKeys => "api_keys",
+            Section::LoginSessions => "login_sessions",
+        }
+    }
+}
+
+/// `EXPORT_RETENTION_HOURS`, how long an archive is kept after it's built.
+fn retention_hours() -> i32 {
+    get_env_default("EXPORT_RETENTION_HOURS")
+        .and_then(|s| s.parse().ok())
+        .filter(|h| *h > 0)
+        .unwrap_or(DEFAULT_RETENTION_HOURS)
+}
+
+/// `EXPORT_LINK_TTL_SECS`, how long a single download link works. Asking for the export
+/// status again hands out a new one until the archive itself expires.
+fn link_ttl_secs() -> i64 {
+    get_env_default("EXPORT_LINK_TTL_SECS")
+        .and_then(|s| s.parse().ok())
+        .filter(|s| *s > 0)
+        .unwrap_or(DEFAULT_LINK_TTL_SECS)
+}
+
+/// `EXPORT_SIGNING_SECRET`, without it no download links are handed out.
+fn link_mac(export_id: Uuid, expires: i64) -> Option<HmacSha256> {
+    let secret = get_env_default("EXPORT_SIGNING_SECRET").filter(|s| !s.is_empty())?;
+    let mut mac = HmacSha256::new_from_slice(secret.as_bytes()).ok()?;
+    mac.update(format!("data_export\n{export_id}\n{expires}").as_bytes());
+    Some(mac)
+}
+
+/// Download link for a finished export, valid for `EXPORT_LINK_TTL_SECS` but never past the
+/// archive's own expiry. Served under `ASSET_DOWNLOAD_BASE_URL` like signed asset links.
+pub fn download_url(export_id: Uuid, archive_expires_at: i64) -> Option<String> {
+    let expires = (Utc::now().timestamp() + link_ttl_secs()).min(archive_expires_at);
+    let signature = hex::encode(link_mac(export_id, expires)?.finalize().into_bytes());
+    let base = get_env_default("ASSET_DOWNLOAD_BASE_URL")
+        .unwrap_or_else(|| DEFAULT_DOWNLOAD_BASE_URL.to_string());
+    Some(format!(
+        "{}/accounts/me/export/{export_id}/download?expires={expires}&signature={signature}",
+        base.trim_end_matches('/'),
+    ))
+}
+
+/// Checks a link produced by [`download_url`]
+pub fn verify_download(export_id: Uuid, expires: i64, signature: &str) -> bool {
+    if expires < Utc::now().timestamp() {
+        return false
+    }
+    let (Some(mac), Ok(signature)) = (link_mac(export_id, expires), hex::decode(signature)) else {
+        return false
+    };
+    mac.verify_slice(&signature).is_ok()
+}
+
+/// Where an archive is written before it goes to storage. Local storage gets it under its
+/// hidden `.tmp` area so the move stays on one filesystem.
+fn part_path(app: &AppData, export_id: Uuid) -> PathBuf {
+    let dir = app.export_storage.local_root()
+        .map(|root| Path::new(root).join(".tmp"))
+        .unwrap_or_else(std::env::temp_dir);
+    dir.join(format!("export-{export_id}.part"))
+}
+
+async fn claim_export(pool: &Pool<Postgres>) -> Result<Option<DbDataExport>, sqlx::Error> {
+    sqlx::query!(
+        "UPDATE website.data_export
+         SET status = CASE WHEN attempts < $1 THEN 'queued' ELSE 'failed' END,
+             error = CASE WHEN attempts < $1 THEN NULL ELSE 'Interrupted too many times' END,
+             finished_at = CASE WHEN attempts < $1 THEN NULL ELSE NOW() END
+         WHERE status = 'running' AND started_at < NOW() - make_interval(secs => $2)",
+        MAX_ATTEMPTS, STUCK_AFTER_SECS
+    )
+    .execute(pool)
+    .await?;
+
+    sqlx::query_as!(
+        DbDataExport,
+        "UPDATE website.data_export
+         SET status = 'running', progress = 0, step = NULL, started_at = NOW(), finished_at = NULL,
+             attempts = attempts + 1
+         WHERE id = (
+             SELECT id FROM website.data_export
+             WHERE status = 'queued'
+             ORDER BY created_at
+             LIMIT 1
+             FOR UPDATE SKIP LOCKED
+         )
+         RETURNING *"
+    )
+    .fetch_optional(pool)
+    .await
+}
+
+async fn set_progress(pool: &Pool<Postgres>, export_id: Uuid, progress: i16, step: &str) {
+    let result = sqlx::query!(
+        "UPDATE website.data_export SET progress = $2, step = $3 WHERE id = $1",
+        export_id, progress, step
+    )
+    .execute(pool)
+    .await;
+    if let Err(e) = result {
+        tracing::warn!("Failed to update progress of export {export_id}: {e}");
+    }
+}
+
+async fn finish_export(app: &AppData, export_id: Uuid, result: Result<i64, String>) {
+    let pool = &*app.pool;
+    let result = match result {
+        Ok(file_size) => sqlx::query!(
+            "UPDATE website.data_export
+             SET status = 'done', progress = 100, step = NULL, error = NULL,
+                 file_path = $2, file_size = $3, finished_at = NOW(),
+                 expires_at = NOW() + make_interval(hours => $4)
+             WHERE id = $1",
+            export_id,
+            app.export_storage.object_key(export_id),
+            file_size,
+            retention_hours()
+        )
+        .execute(pool)
+        .await,
+        Err(error) => sqlx::query!(
+            "UPDATE website.data_export SET status = 'failed', error = $2, finished_at = NOW() WHERE id = $1",
+            export_id, error
+        )
+        .execute(pool)
+        .await,
+    };
+    if let Err(e) = result {
+        tracing::error!("Failed to update export {export_id}: {e}");
+    }
+}
+
+/// Removes archives past their expiry, the rows stay behind as `expired`.
+async fn expire_exports(app: &AppData) {
+    let expired = sqlx::query_scalar!(
+        "UPDATE website.data_export
+         SET status = 'expired', file_path = NULL
+         WHERE status = 'done' AND expires_at < NOW()
+         RETURNING id"
+    )
+    .fetch_all(&*app.pool)
+    .await;
+    match expired {
+        Ok(ids) => {
+            for id in ids {
+                if let Err(e) = app.export_storage.delete(id).await {
+                    tracing::warn!("Failed to remove expired export {id}: {e}");
+                }
+            }
+        }
+        Err(e) => tracing::error!("Failed to expire data exports: {e}"),
+    }
+}
+
+/// Builds archives requested through `/accounts/me/export` one at a time and removes them
+/// once they expire. Claimed with `SKIP LOCKED` like model jobs, so several instances can
+/// run it. Disable with `DATA_EXPORTS=false`.
+pub async fn run_data_exports(app: AppData) {
+    if !get_env_bool("DATA_EXPORTS", true) {
+        tracing::info!("Data exports are disabled");
+        return
+    }
+    loop {
+        expire_exports(&app).await;
+        let export = match claim_export(&app.pool).await {
+            Ok(Some(export)) => export,
+            Ok(None) => {
+                tokio::time::sleep(POLL_INTERVAL).await;
+                continue
+            }
+            Err(e) => {
+                tracing::error!("Failed to claim data export: {e}");
+                tokio::time::sleep(POLL_INTERVAL).await;
+                continue
+            }
+        };
+
+        let result = build_archive(&app, &export).await;
+        match &result {
+            Ok(size) => tracing::info!("Data export {} of {} done, {size} bytes", export.id, export.user_id),
+            Err(e) => tracing::warn!("Data export {} of {} failed: {e}", export.id, export.user_id),
+        }
+        let _ = tokio::fs::remove_file(part_path(&app, export.id)).await;
+        finish_export(&app, export.id, result).await;
+    }
+}
+
+/// Rows of a section, each one JSON object. `player_id` is the Steam ID the game servers
+/// record the user under.
+fn section_rows<'a>(
+    pool: &'a Pool<Postgres>, section: Section, user_id: i64, player_id: &'a str,
+) -> BoxStream<'a, Result<Option<serde_json::Value>, sqlx::Error>> {
+    match section {
+        Section::Profile => sqlx::query_scalar!(
+            r#"SELECT to_jsonb(u) AS "row?" FROM website.steam_user u WHERE u.user_id = $1"#,
+            user_id
+        ).fetch(pool),
+        // The geometry is derived from location_code, which is kept
+        Section::Player => sqlx::query_scalar!(
+            r#"SELECT to_jsonb(p) - 'location' AS "row?" FROM player p WHERE p.player_id = $1"#,
+            player_id
+        ).fetch(pool),
+        Section::Aliases => sqlx::query_scalar!(
+            r#"SELECT jsonb_build_object('name', a.event_value, 'server_id', a.server_id, 'seen_at', a.created_at) AS "row?"
+               FROM (
+                   SELECT event_value, NULL::VARCHAR AS server_id, created_at
+                   FROM player_activity WHERE player_id = $1 AND event_name = 'name'
+                   UNION ALL
+                   SELECT event_value, server_id, created_at
+                   FROM player_server_activity WHERE player_id = $1 AND event_name = 'name'
+               ) a
+               ORDER BY a.created_at"#,
+            player_id
+        ).fetch(pool),
+        Section::Sessions => sqlx::query_scalar!(
+            r#"SELECT to_jsonb(s) AS "row?" FROM player_server_session s
+               WHERE s.player_id = $1 ORDER BY s.started_at"#,
+            player_id
+        ).fetch(pool),
+        Section::Infractions => sqlx::query_scalar!(
+            r#"SELECT jsonb_build_object(
+                   'infraction_id', i.infraction_id, 'source', i.source, 'created', i.created, 'payload', i.payload
+               ) AS "row?"
+               FROM server_infractions i
+               WHERE i.payload->'player' ? 'gs_id' AND i.payload->'player'->>'gs_id' = $1
+               ORDER BY i.created"#,
+            player_id
+        ).fetch(pool),
+        Section::Guides => sqlx::query_scalar!(
+            r#"SELECT to_jsonb(g) - 'search_vector' AS "row?" FROM website.guides g WHERE g.author_id = $1 ORDER BY g.created_at"#,
+            user_id
+        ).fetch(pool),
+        Section::GuideRevisions => sqlx::query_scalar!(
+            r#"SELECT to_jsonb(r) AS "row?" FROM website.guide_revision r WHERE r.editor_id = $1 ORDER BY r.created_at"#,
+            user_id
+        ).fetch(pool),
+        Section::GuideComments => sqlx::query_scalar!(
+            r#"SELECT to_jsonb(c) - 'search_vector' AS "row?" FROM website.guide_comments c WHERE c.author_id = $1 ORDER BY c.created_at"#,
+            user_id
+        ).fetch(pool),
+        Section::GuideVotes => sqlx::query_scalar!(
+            r#"SELECT to_jsonb(v) AS "row?" FROM website.guide_votes v WHERE v.user_id = $1"#,
+            user_id
+        ).fetch(pool),
+        Section::GuideCommentVotes => sqlx::query_scalar!(
+            r#"SELECT to_jsonb(v) AS "row?" FROM website.guide_comment_votes v WHERE v.user_id = $1"#,
+            user_id
+        ).fetch(pool),
+        Section::Reports => sqlx::query_scalar!(
+            r#"SELECT to_jsonb(r) || jsonb_build_object('kind', 'guide') AS "row?"
+               FROM website.report_guide r WHERE r.user_id = $1
+               UNION ALL
+               SELECT to_jsonb(r) || jsonb_build_object('kind', 'guide_comment')
+               FROM website.report_guide_comment r WHERE r.user_id = $1
+               UNION ALL
+               SELECT to_jsonb(r) || jsonb_build_object('kind', 'map_music')
+               FROM website.report_map_music r WHERE r.user_id = $1"#,
+            user_id
+        ).fetch(pool),
+        // The keys only let us deliver to the browser, they aren't the user's to keep
+        Section::PushSubscriptions => sqlx::query_scalar!(
+            r#"SELECT to_jsonb(s) - 'p256dh_key' - 'auth_key' AS "row?"
+               FROM website.push_subscriptions s WHERE s.user_id = $1 ORDER BY s.created_at"#,
+            user_id
+        ).fetch(pool),
+        Section::NotificationPreferences => sqlx::query_scalar!(
+            r#"SELECT to_jsonb(p) AS "row?" FROM website.notification_preferences p WHERE p.user_id = $1"#,
+            user_id
+        ).fetch(pool),
+        Section::NotificationSubscriptions => sqlx::query_scalar!(
+            r#"SELECT to_jsonb(s) || jsonb_build_object('kind', 'map_change') AS "row?"
+               FROM website.map_change_subscriptions s WHERE s.user_id = $1
+               UNION ALL
+               SELECT to_jsonb(s) || jsonb_build_object('kind', 'map_notify')
+               FROM website.map_notify_subscriptions s WHERE s.user_id = $1"#,
+            user_id
+        ).fetch(pool),
+        Section::FavoriteMaps => sqlx::query_scalar!(
+            r#"SELECT to_jsonb(f) AS "row?" FROM website.user_favorite_maps f WHERE f.user_id = $1"#,
+            user_id
+        ).fetch(pool),
+        Section::Anonymization => sqlx::query_scalar!(
+            r#"SELECT to_jsonb(a) AS "row?" FROM website.user_anonymization a WHERE a.user_id = $1"#,
+            user_id
+        ).fetch(pool),
+        Section::Discord => sqlx::query_scalar!(
+            r#"SELECT to_jsonb(d) - 'access_token' - 'refresh_token' AS "row?"
+               FROM website.discord_user d WHERE d.steam_user_id = $1"#,
+            user_id
+        ).fetch(pool),
+        Section::ApiKeys => sqlx::query_scalar!(
+            r#"SELECT to_jsonb(k) - 'key_hash' AS "row?" FROM website.api_key k WHERE k.user_id = $1 ORDER BY k.created_at"#,
+            user_id
+        ).fetch(pool),
+        Section::LoginSessions => sqlx::query_scalar!(
+            r#"SELECT to_jsonb(t) - 'refresh_token_hash' AS "row?"
+               FROM website.user_refresh_tokens t WHERE t.user_id = $1 ORDER BY t.created_at"#,
+            user_id
+        ).fetch(pool),
+    }
+}
+
+/// Streams every section into one JSON document, `{"sections": {"<name>": [rows]}}`, written
+/// to a part file first and moved to storage once complete, so a half written archive is
+/// never served. Returns its size.
+async fn build_archive(app: &AppData, export: &DbDataExport) -> Result<i64, String> {
+    let part_path = part_path(app, export.id);
+    if let Some(dir) = part_path.parent() {
+        tokio::fs::create_dir_all(dir)
+            .await
+            .map_err(|e| format!("Failed to create {}: {e}", dir.display()))?;
+    }
+    let file = tokio::fs::File::create(&part_path)
+        .await
+        .map_err(|e| format!("Failed to create archive: {e}"))?;
+    let mut out = BufWriter::new(file);
+    let write_err = |e: std::io::Error| format!("Failed to write archive: {e}");
+
+    let player_id = export.user_id.to_string();
+    let header = serde_json::json!({
+        "format_version": FORMAT_VERSION,
+        "generated_at": Utc::now(),
+        "user_id": player_id,
+    });
+    let header = serde_json::to_string(&header).map_err(|e| e.to_string())?;
+    // Reopen the header object to append the sections to it
+    out.write_all(header.trim_end_matches('}').as_bytes()).await.map_err(write_err)?;
+    out.write_all(b",\"sections\":{").await.map_err(write_err)?;
+
+    for (index, section) in Section::ALL.into_iter().enumerate() {
+        let name = section.name();
+        set_progress(&app.pool, export.id, (index * 100 / Section::ALL.len()) as i16, name).await;
+        if index > 0 {
+            out.write_all(b",").await.map_err(write_err)?;
+        }
+        out.write_all(format!("\"{name}\":[").as_bytes()).await.map_err(write_err)?;
+
+        let mut rows = section_rows(&app.pool, section, export.user_id, &player_id);
+        let mut first = true;
+        while let Some(row) = rows.try_next().await.map_err(|e| format!("Failed to read {name}: {e}"))? {
+            let Some(row) = row else { continue };
+            if !first {
+                out.write_all(b",").await.map_err(write_err)?;
+            }
+            first = false;
+            let row = serde_json::to_vec(&row).map_err(|e| e.to_string())?;
+            out.write_all(&row).await.map_err(write_err)?;
+        }
+        out.write_all(b"]").await.map_err(write_err)?;
+    }
+    out.write_all(b"}}").await.map_err(write_err)?;
+    out.flush().await.map_err(write_err)?;
+    drop(out);
+
+    let size = tokio::fs::metadata(&part_path)
+        .await
+        .map_err(|e| format!("Failed to stat archive: {e}"))?
+        .len();
+    app.export_storage.store_file(export.id, &part_path)
+        .await
+        .map_err(|e| format!("Failed to store archive: {e}"))?;
+    Ok(size as i64)
+}
@@ -0,0 +1,500 @@
+use std::collections::{HashMap, HashSet};
+use std::time::Duration;
+
+use base64::Engine;
+use chacha20poly1305::aead::{Aead, KeyInit};
+use chacha20poly1305::{XChaCha20Poly1305, XNonce};
+use rand::Rng;
+use serde::Deserialize;
+use serde_json::json;
+use sha2::{Digest, Sha256};
+
+use crate::AppData;
+use crate::core::api_models::DiscordLink;
+use crate::core::audit::record_audit;
+use crate::core::model::DbDiscordLink;
+use crate::core::utils::{get_env_bool, get_env_default};
+
+const DEFAULT_API_BASE: &str = "https://discord.com/api/v10";
+const DEFAULT_AUTHORIZE_URL: &str = "https://discord.com/oauth2/authorize";
+const OAUTH_SCOPES: &str = "identify guilds.members.read";
+/// Tokens are refreshed this long before Discord would reject them
+const EXPIRY_MARGIN_SECS: i64 = 60;
+/// Marks tokens sealed with `DISCORD_TOKEN_SECRET`, anything else is from before they were
+const SEALED_PREFIX: &str = "v1:";
+const NONCE_LEN: usize = 24;
+const SYNC_POLL_INTERVAL: Duration = Duration::from_secs(5 * 60);
+/// Pause between users so a batch doesn't run into Discord's rate limits
+const SYNC_PAUSE: Duration = Duration::from_secs(1);
+const SYNC_BATCH: i64 = 50;
+const DEFAULT_SYNC_HOURS: i32 = 6;
+const DEFAULT_MAX_UNVERIFIED_HOURS: i32 = 72;
+/// Failed syncs in a row after which the background sync leaves a link alone until it's
+/// relinked or synced by hand. Each failure doubles the wait before the next try.
+const MAX_SYNC_FAILURES: i32 = 6;
+
+/// `DISCORD_AUTH2_CLIENT_ID`, `DISCORD_AUTH2_CLIENT_SECRET`, `DISCORD_AUTH2_REDIRECT_URI` and
+/// `DISCORD_TOKEN_SECRET`, which the stored tokens are encrypted with, are required.
+/// `DISCORD_API_BASE` and `DISCORD_AUTHORIZE_URL` default to discord.com and exist so a local
+/// stub can stand in for it.
+pub struct DiscordConfig {
+    client_id: String,
+    client_secret: String,
+    redirect_uri: String,
+    api_base: String,
+    authorize_url: String,
+    token_key: [u8; 32],
+}
+
+impl DiscordConfig {
+    pub fn from_env() -> Option<DiscordConfig> {
+        let non_empty = |name: &str| get_env_default(name).filter(|v| !v.trim().is_empty());
+        Some(DiscordConfig {
+            client_id: non_empty("DISCORD_AUTH2_CLIENT_ID")?,
+            client_secret: non_empty("DISCORD_AUTH2_CLIENT_SECRET")?,
+            redirect_uri: non_empty("DISCORD_AUTH2_REDIRECT_URI")?,
+            api_base: non_empty("DISCORD_API_BASE")
+                .unwrap_or_else(|| DEFAULT_API_BASE.to_string())
+                .trim_end_matches('/')
+                .to_string(),
+            authorize_url: non_empty("DISCORD_AUTHORIZE_URL")
+                .unwrap_or_else(|| DEFAULT_AUTHORIZE_URL.to_string()),
+            token_key: Sha256::digest(non_empty("DISCORD_TOKEN_SECRET")?.as_bytes()).into(),
+        })
+    }
+
+    fn seal_token(&self, token: &str) -> Result<String, String> {
+        let mut nonce = [0u8; NONCE_LEN];
+        rand::rng().fill(&mut nonce);
+        let sealed = XChaCha20Poly1305::new(&self.token_key.into())
+            .encrypt(XNonce::from_slice(&nonce), token.as_bytes())
+            .map_err(|_| "Couldn't encrypt the Discord token".to_string())?;
+        let mut out = nonce.to_vec();
+        out.extend(sealed);
+        Ok(format!("{SEALED_PREFIX}{}", base64::engine::general_purpose::STANDARD.encode(out)))
+    }
+
+    /// Tokens stored before they were sealed come back as they are, [`reseal_plain_tokens`]
+    /// replaces them.
+    fn open_token(&self, stored: &str) -> Result<String, String> {
+        let Some(sealed) = stored.strip_prefix(SEALED_PREFIX) else {
+            return Ok(stored.to_string())
+        };
+        let bytes = base64::engine::general_purpose::STANDARD
+            .decode(sealed)
+            .map_err(|_| "Stored Discord token is malformed".to_string())?;
+        if bytes.len() < NONCE_LEN {
+            return Err("Stored Discord token is malformed".to_string())
+        }
+        let (nonce, sealed) = bytes.split_at(NONCE_LEN);
+        let token = XChaCha20Poly1305::new(&self.token_key.into())
+            .decrypt(XNonce::from_slice(nonce), sealed)
+            .map_err(|_| "Stored Discord token doesn't decrypt, was DISCORD_TOKEN_SECRET changed?".to_string())?;
+        String::from_utf8(token).map_err(|_| "Stored Discord token is malformed".to_string())
+    }
+
+    pub fn authorize_link(&self, state: &str) -> Result<String, String> {
+        url::Url::parse_with_params(&self.authorize_url, &[
+            ("client_id", self.client_id.as_str()),
+            ("redirect_uri", self.redirect_uri.as_str()),
+            ("response_type", "code"),
+            ("scope", OAUTH_SCOPES),
+            ("state", state),
+            ("prompt", "consent"),
+        ])
+        .map(|url| url.to_string())
+        .map_err(|e| format!("Invalid DISCORD_AUTHORIZE_URL: {e}"))
+    }
+
+    async fn request_tokens(&self, form: &[(&str, &str)]) -> Result<DiscordTokens, String> {
+        let mut body = vec![
+            ("client_id", self.client_id.as_str()),
+            ("client_secret", self.client_secret.as_str()),
+        ];
+        body.extend_from_slice(form);
+        let resp = reqwest::Client::new()
+            .post(format!("{}/oauth2/token", self.api_base))
+            .form(&body)
+            .send()
+            .await
+            .map_err(|e| e.to_string())?;
+        if !resp.status().is_success() {
+            return Err(format!("Discord token request failed with {}", resp.status()))
+        }
+        resp.json::<DiscordTokens>().await.map_err(|e| e.to_string())
+    }
+
+    pub async fn exchange_code(&self, code: &str) -> Result<DiscordTokens, String> {
+        self.request_tokens(&[
+            ("grant_type", "authorization_code"),
+            ("code", code),
+            ("redirect_uri", self.redirect_uri.as_str()),
+        ]).await
+    }
+
+    async fn refresh(&self, refresh_token: &str) -> Result<DiscordTokens, String> {
+        self.request_tokens(&[
+            ("grant_type", "refresh_token"),
+            ("refresh_token", refresh_token),
+        ]).await
+    }
+
+    pub async fn fetch_user(&self, access_token: &str) -> Result<DiscordIdentity, String> {
+        let resp = reqwest::Client::new()
+            .get(format!("{}/users/@me", self.api_base))
+            .bearer_auth(access_token)
+            .send()
+            .await
+            .map_err(|e| e.to_string())?;
+        if !resp.status().is_success() {
+            return Err(format!("Discord user request failed with {}", resp.status()))
+        }
+        resp.json::<DiscordIdentity>().await.map_err(|e| e.to_string())
+    }
+
+    /// Role ids the user holds in `guild_id`, empty when they aren't a member.
+    async fn fetch_member_roles(&self, access_token: &str, guild_id: i64) -> Result<Vec<String>, String> {
+        let resp = reqwest::Client::new()
+            .get(format!("{}/users/@me/guilds/{guild_id}/member", self.api_base))
+            .bearer_auth(access_token)
+            .send()
+            .await
+            .map_err(|e| e.to_string())?;
+        if resp.status() == reqwest::StatusCode::NOT_FOUND {
+            return Ok(vec![])
+        }
+        if !resp.status().is_success() {
+            return Err(format!("Discord member request for guild {guild_id} failed with {}", resp.status()))
+        }
+        #[derive(Deserialize)]
+        struct Member {
+            roles: Vec<String>,
+        }
+        resp.json::<Member>().await.map(|m| m.roles).map_err(|e| e.to_string())
+    }
+}
+
+#[derive(Deserialize)]
+pub struct DiscordTokens {
+    pub access_token: String,
+    pub refresh_token: Option<String>,
+    pub expires_in: i64,
+}
+
+#[derive(Deserialize)]
+pub struct DiscordIdentity {
+    pub id: String,
+    pub username: String,
+    pub global_name: Option<String>,
+    pub avatar: Option<String>,
+}
+
+impl DiscordIdentity {
+    pub fn avatar_url(&self) -> Option<String> {
+        self.avatar.as_ref().map(|hash| format!("https://cdn.discordapp.com/avatars/{}/{hash}.png", self.id))
+    }
+}
+
+pub async fn fetch_discord_link(app: &AppData, steam_user_id: i64) -> Result<Option<DiscordLink>, sqlx::Error> {
+    let link = sqlx::query_as!(
+        DbDiscordLink,
+        r#"
+        SELECT w.user_id, w.username, d.display_name AS "display_name?", d.avatar AS "avatar?",
+               w.linked_at, w.roles_synced_at
+        FROM website.discord_user w
+        JOIN discord_user d ON d.user_id = w.user_id
+        WHERE w.steam_user_id = $1
+        "#,
+        steam_user_id
+    )
+    .fetch_optional(&*app.pool)
+    .await?;
+    Ok(link.map(Into::into))
+}
+
+/// Stores the tokens against the link encrypted, the refresh token only when Discord sent a new one.
+pub async fn save_tokens(app: &AppData, config: &DiscordConfig, discord_id: i64, tokens: &DiscordTokens) -> Result<(), String> {
+    let access_token = config.seal_token(&tokens.access_token)?;
+    let refresh_token = tokens.refresh_token
+        .as_deref()
+        .map(|token| config.seal_token(token))
+        .transpose()?;
+    sqlx::query!(
+        "UPDATE website.discord_user SET
+            access_token = $2,
+            refresh_token = COALESCE($3, refresh_token),
+            token_expires_at = NOW() + make_interval(secs => $4)
+         WHERE user_id = $1",
+        discord_id,
+        access_token,
+        refresh_token,
+        tokens.expires_in as f64
+    )
+    .execute(&*app.pool)
+    .await
+    .map_err(|e| e.to_string())?;
+    Ok(())
+}
+
+/// An access token for the link, refreshed first when it is about to expire.
+async fn access_token(app: &AppData, config: &DiscordConfig, discord_id: i64) -> Result<String, String> {
+    let link = sqlx::query!(
+        "SELECT access_token, refresh_token,
+            COALESCE(token_expires_at > NOW() + make_interval(secs => $2), FALSE) AS \"fresh!\"
+         FROM website.discord_user WHERE user_id = $1",
+        discord_id,
+        EXPIRY_MARGIN_SECS as f64
+    )
+    .fetch_optional(&*app.pool)
+    .await
+    .map_err(|e| e.to_string())?
+    .ok_or_else(|| "Discord account isn't linked".to_string())?;
+
+    if let (Some(token), true) = (link.access_token, link.fresh) {
+        return config.open_token(&token)
+    }
+    let refresh_token = link.refresh_token.ok_or_else(|| "No Discord refresh token, link again".to_string())?;
+    let tokens = config.refresh(&config.open_token(&refresh_token)?).await?;
+    save_tokens(app, config, discord_id, &tokens).await?;
+    Ok(tokens.access_token)
+}
+
+pub struct RoleSync {
+    pub granted: usize,
+    pub revoked: usize,
+}
+
+/// Brings the user's Discord-sourced role grants in line with the guild roles they hold.
+/// Grants for a guild that couldn't be checked are left alone rather than revoked.
+pub async fn sync_discord_roles(app: &AppData, config: &DiscordConfig, steam_user_id: i64) -> Result<RoleSync, String> {
+    let discord_id = sqlx::query_scalar!(
+        "SELECT user_id FROM website.discord_user WHERE steam_user_id = $1",
+        steam_user_id
+    )
+    .fetch_optional(&*app.pool)
+    .await
+    .map_err(|e| e.to_string())?;
+    let Some(discord_id) = discord_id else {
+        return revoke_discord_grants(app, steam_user_id).await.map(|revoked| RoleSync { granted: 0, revoked })
+    };
+
+    let mappings = sqlx::query!("SELECT id, guild_id, discord_role_id FROM website.discord_role_mapping")
+        .fetch_all(&*app.pool)
+        .await
+        .map_err(|e| e.to_string())?;
+    let guilds: HashSet<i64> = mappings.iter().map(|m| m.guild_id).collect();
+
+    let token = access_token(app, config, discord_id).await?;
+    let mut held: HashMap<i64, Vec<String>> = HashMap::new();
+    for guild_id in guilds {
+        match config.fetch_member_roles(&token, guild_id).await {
+            Ok(roles) => { held.insert(guild_id, roles); }
+            Err(e) => tracing::warn!("Skipping Discord role sync for guild {guild_id}: {e}"),
+        }
+    }
+
+    let checked: Vec<i32> = mappings.iter()
+        .filter(|m| held.contains_key(&m.guild_id))
+        .map(|m| m.id)
+        .collect();
+    let wanted: Vec<i32> = mappings.iter()
+        .filter(|m| held.get(&m.guild_id).is_some_and(|roles| roles.contains(&m.discord_role_id.to_string())))
+        .map(|m| m.id)
+        .collect();
+
+    let granted = sqlx::query!(
+        "INSERT INTO website.role_grant (user_id, role_id, community_id, server_id, discord_mapping_id)
+         SELECT $1, m.role_id, m.community_id, m.server_id, m.id
+         FROM website.discord_role_mapping m
+         WHERE m.id = ANY($2)
+         ON CONFLICT DO NOTHING
+         RETURNING id, role_id, community_id, server_id",
+        steam_user_id,
+        &wanted
+    )
+    .fetch_all(&*app.pool)
+    .await
+    .map_err(|e| e.to_string())?;
+
+    let revoked = sqlx::query!(
+        "DELETE FROM website.role_grant
+         WHERE user_id = $1 AND discord_mapping_id = ANY($2) AND NOT discord_mapping_id = ANY($3)
+         RETURNING id, role_id, community_id, server_id",
+        steam_user_id,
+        &checked,
+        &wanted
+    )
+    .fetch_all(&*app.pool)
+    .await
+    .map_err(|e| e.to_string())?;
+
+    let _ = sqlx::query!(
+        "UPDATE website.discord_user
+         SET roles_synced_at = NOW(), roles_sync_attempted_at = NOW(), roles_sync_failures = 0
+         WHERE user_id = $1",
+        discord_id
+    )
+        .execute(&*app.pool)
+        .await;
+
+    for g in &granted {
+        record_audit(
+            &app.pool, None, "discord_grant_role", "user", &steam_user_id.to_string(), None,
+            Some(json!({ "grant_id": g.id, "role_id": g.role_id, "community_id": g.community_id, "server_id": g.server_id })),
+        ).await;
+    }
+    for g in &revoked {
+        record_audit(
+            &app.pool, None, "discord_revoke_role", "user", &steam_user_id.to_string(),
+            Some(json!({ "grant_id": g.id, "role_id": g.role_id, "community_id": g.community_id, "server_id": g.server_id })),
+            None,
+        ).await;
+    }
+    Ok(RoleSync { granted: granted.len(), revoked: revoked.len() })
+}
+
+/// Drops every grant that came from Discord, used when the account is unlinked.
+pub async fn revoke_discord_grants(app: &AppData, steam_user_id: i64) -> Result<usize, String> {
+    let revoked = sqlx::query!(
+        "DELETE FROM website.role_grant
+         WHERE user_id = $1 AND discord_mapping_id IS NOT NULL
+         RETURNING id, role_id, community_id, server_id",
+        steam_user_id
+    )
+    .fetch_all(&*app.pool)
+    .await
+    .map_err(|e| e.to_string())?;
+
+    for g in &revoked {
+        record_audit(
+            &app.pool, None, "discord_revoke_role", "user", &steam_user_id.to_string(),
+            Some(json!({ "grant_id": g.id, "role_id": g.role_id, "community_id": g.community_id, "server_id": g.server_id })),
+            None,
+        ).await;
+    }
+    Ok(revoked.len())
+}
+
+/// Encrypts tokens that were stored in plain text before `DISCORD_TOKEN_SECRET` existed.
+async fn reseal_plain_tokens(app: &AppData, config: &DiscordConfig) -> Result<usize, String> {
+    let rows = sqlx::query!(
+        "SELECT user_id, access_token, refresh_token FROM website.discord_user
+         WHERE NOT starts_with(access_token, $1) OR NOT starts_with(refresh_token, $1)",
+        SEALED_PREFIX
+    )
+    .fetch_all(&*app.pool)
+    .await
+    .map_err(|e| e.to_string())?;
+
+    let reseal = |token: Option<String>| match token {
+        Some(token) if !token.starts_with(SEALED_PREFIX) => config.seal_token(&token).map(Some),
+        other => Ok(other),
+    };
+    for row in &rows {
+        sqlx::query!(
+            "UPDATE website.discord_user SET access_token = $2, refresh_token = $3 WHERE user_id = $1",
+            row.user_id,
+            reseal(row.access_token.clone())?,
+            reseal(row.refresh_token.clone())?
+        )
+        .execute(&*app.pool)
+        .await
+        .map_err(|e| e.to_string())?;
+    }
+    Ok(rows.len())
+}
+
+/// Re-reads guild roles of every link every `DISCORD_ROLE_SYNC_HOURS`, so grants follow roles
+/// given or taken away on Discord. Links that couldn't be checked for
+/// `DISCORD_ROLE_MAX_UNVERIFIED_HOURS`, e.g. because the user removed the app, lose their
+/// Discord grants. Failing links are retried less and less often and dropped from the sync
+/// after [`MAX_SYNC_FAILURES`], so they can't hold up the others. Disable with
+/// `DISCORD_ROLE_SYNC=false`.
+pub async fn run_discord_role_syncs(app: AppData) {
+    if !get_env_bool("DISCORD_ROLE_SYNC", true) {
+        tracing::info!("Discord role sync is disabled");
+        return
+    }
+    let Some(config) = DiscordConfig::from_env() else {
+        tracing::info!("Discord linking isn't configured, role sync is off");
+        return
+    };
+    let hours = |name: &str, default: i32| get_env_default(name)
+        .and_then(|s| s.parse().ok())
+        .filter(|h: &i32| *h > 0)
+        .unwrap_or(default);
+    let sync_hours = hours("DISCORD_ROLE_SYNC_HOURS", DEFAULT_SYNC_HOURS);
+    let max_unverified_hours = hours("DISCORD_ROLE_MAX_UNVERIFIED_HOURS", DEFAULT_MAX_UNVERIFIED_HOURS);
+
+    match reseal_plain_tokens(&app, &config).await {
+        Ok(0) => {}
+        Ok(count) => tracing::info!("Encrypted the Discord tokens of {count} links"),
+        Err(e) => tracing::error!("Failed to encrypt stored Discord tokens: {e}"),
+    }
+
+    loop {
+        let due = sqlx::query!(
+            r#"SELECT steam_user_id AS "steam_user_id!", roles_sync_failures,
+                COALESCE(roles_synced_at, linked_at) < NOW() - make_interval(hours => $2) AS "lapsed!"
+             FROM website.discord_user
+             WHERE steam_user_id IS NOT NULL
+               AND roles_sync_failures < $4
+               AND (roles_synced_at IS NULL OR roles_synced_at < NOW() - make_interval(hours => $1))
+               AND (roles_sync_failures = 0
+                    OR roles_sync_attempted_at < NOW() - make_interval(hours => $1) * power(2::FLOAT8, roles_sync_failures - 1))
+             ORDER BY COALESCE(roles_sync_attempted_at, roles_synced_at) NULLS FIRST
+             LIMIT $3"#,
+            sync_hours,
+            max_unverified_hours,
+            SYNC_BATCH,
+            MAX_SYNC_FAILURES
+        )
+        .fetch_all(&*app.pool)
+        .await;
+        let due = match due {
+            Ok(due) => due,
+            Err(e) => {
+                tracing::error!("Failed to list Discord links to sync: {e}");
+                tokio::time::sleep(SYNC_POLL_INTERVAL).await;
+                continue
+            }
+        };
+
+        let mut failed = 0;
+        for link in &due {
+            if let Err(e) = sync_discord_roles(&app, &config, link.steam_user_id).await {
+                failed += 1;
+                tracing::warn!("Discord role sync for {} failed: {e}", link.steam_user_id);
+                let recorded = sqlx::query!(
+                    "UPDATE website.discord_user
+                     SET roles_sync_attempted_at = NOW(), roles_sync_failures = roles_sync_failures + 1
+                     WHERE steam_user_id = $1",
+                    link.steam_user_id
+                )
+                .execute(&*app.pool)
+                .await;
+                if let Err(e) = recorded {
+                    tracing::error!("Failed to record the failed Discord role sync: {e}");
+                }
+                let giving_up = link.roles_sync_failures + 1 >= MAX_SYNC_FAILURES;
+                if giving_up {
+                    tracing::info!("Giving up on Discord role sync for {} until they relink", link.steam_user_id);
+                }
+                if link.lapsed || giving_up {
+                    match revoke_discord_grants(&app, link.steam_user_id).await {
+                        Ok(0) => {}
+                        Ok(count) => tracing::info!(
+                            "Revoked {count} Discord grants of {}, their roles couldn't be checked", link.steam_user_id
+                        ),
+                        Err(e) => tracing::error!("Failed to revoke lapsed Discord grants: {e}"),
+                    }
+                }
+            }
+            tokio::time::sleep(SYNC_PAUSE).await;
+        }
+        // A batch of nothing but failures waits like an empty one
+        if (due.len() as i64) < SYNC_BATCH || failed == due.len() {
+            tokio::time::sleep(SYNC_POLL_INTERVAL).await;
+        }
+    }
+}
@@ -0,0 +1,436 @@
+use std::io::{BufReader, Read, Seek};
+use std::path::Path;
+
+use serde_json::Value;
+use tokio::io::AsyncReadExt;
+
+use crate::core::api_models::GlbInfo;
+
+const GLB_MAGIC: &[u8; 4] = b"glTF";
+const GLB_HEADER_LEN: usize = 12;
+const CHUNK_HEADER_LEN: usize = 8;
+const CHUNK_JSON: u32 = 0x4E4F534A;
+const CHUNK_BIN: u32 = 0x004E4942;
+/// Anything bigger than this is not a model we want to serve anyway.
+const MAX_JSON_CHUNK: u32 = 64 * 1024 * 1024;
+
+const MODE_TRIANGLES: u64 = 4;
+const MODE_TRIANGLE_STRIP: u64 = 5;
+const MODE_TRIANGLE_FAN: u64 = 6;
+
+fn read_u32(bytes: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes([bytes[offset], bytes[offset + 1], bytes[offset + 2], bytes[offset + 3]])
+}
+
+/// Validates the 12 byte GLB header plus the first chunk header, returns the JSON chunk length.
+fn parse_header(header: &[u8], actual_len: u64) -> Result<u32, String> {
+    if header.len() < GLB_HEADER_LEN + CHUNK_HEADER_LEN {
+        return Err("File is too small to be a GLB".to_string())
+    }
+    if &header[0..4] != GLB_MAGIC {
+        return Err("Missing glTF magic, not a binary glTF file".to_string())
+    }
+    let version = read_u32(header, 4);
+    if version != 2 {
+        return Err(format!("Unsupported glTF container version {version}"))
+    }
+    let declared_len = read_u32(header, 8) as u64;
+    if declared_len != actual_len {
+        return Err(format!("Header declares {declared_len} bytes but file has {actual_len}"))
+    }
+    let json_len = read_u32(header, 12);
+    if read_u32(header, 16) != CHUNK_JSON {
+        return Err("First chunk is not JSON".to_string())
+    }
+    if json_len > MAX_JSON_CHUNK || GLB_HEADER_LEN as u64 + CHUNK_HEADER_LEN as u64 + json_len as u64 > actual_len {
+        return Err("JSON chunk length is out of bounds".to_string())
+    }
+    Ok(json_len)
+}
+
+fn accessor_count(doc: &Value, index: &Value) -> Result<Option<u64>, String> {
+    let Some(index) = index.as_u64() else {
+        return Ok(None)
+    };
+    doc["accessors"].get(index as usize)
+        .map(|accessor| accessor["count"].as_u64())
+        .ok_or(format!("Accessor {index} does not exist"))
+}
+
+fn vec3(value: &Value) -> Option<[f64; 3]> {
+    let values = value.as_array()?;
+    if values.len() != 3 {
+        return None
+    }
+    Some([values[0].as_f64()?, values[1].as_f64()?, values[2].as_f64()?])
+}
+
+/// Reads what we care about from the glTF JSON document.
+/// `bin_len` is the size of the embedded BIN chunk, if there is one.
+fn inspect_document(json: &[u8], bin_len: Option<u64>) -> Result<GlbInfo, String> {
+    let doc: Value = serde_json::from_slice(json)
+        .map_err(|e| format!("Invalid glTF JSON: {e}"))?;
+
+    let asset_version = doc["asset"]["version"].as_str().unwrap_or_default();
+    if !asset_version.starts_with('2') {
+        return Err(format!("Unsupported glTF asset version '{asset_version}'"))
+    }
+
+    // Buffer 0 without an uri refers to the BIN chunk, it has to actually be there.
+    if let Some(buffer) = doc["buffers"].get(0) {
+        if buffer.get("uri").is_none() {
+            let needed = buffer["byteLength"].as_u64().unwrap_or_default();
+            if bin_len.unwrap_or_default() < needed {
+                return Err("BIN chunk is missing or smaller than the declared buffer".to_string())
+            }
+        }
+    }
+
+    let meshes = doc["meshes"].as_array().map(Vec::as_slice).unwrap_or_default();
+    let mut triangle_count: i64 = 0;
+    let mut bbox: Option<([f64; 3], [f64; 3])> = None;
+    for primitive in meshes.iter().flat_map(|m| m["primitives"].as_array().into_iter().flatten()) {
+        let position = &primitive["attributes"]["POSITION"];
+        let vertices = accessor_count(&doc, position)?;
+        let indices = accessor_count(&doc, &primitive["indices"])?;
+        let count = indices.or(vertices).unwrap_or_default();
+        triangle_count += match primitive["mode"].as_u64().unwrap_or(MODE_TRIANGLES) {
+            MODE_TRIANGLES => count / 3,
+            MODE_TRIANGLE_STRIP | MODE_TRIANGLE_FAN => count.saturating_sub(2),
+            _ => 0,
+        } as i64;
+
+        // POSITION accessors are required to carry min/max, we take the union in mesh space.
+        let Some(accessor) = position.as_u64().and_then(|i| doc["accessors"].get(i as usize)) else {
+            continue
+        };
+        let (Some(min), Some(max)) = (vec3(&accessor["min"]), vec3(&accessor["max"])) else {
+            continue
+        };
+        bbox = Some(match bbox {
+            None => (min, max),
+            Some((lo, hi)) => (
+                [lo[0].min(min[0]), lo[1].min(min[1]), lo[2].min(min[2])],
+                [hi[0].max(max[0]), hi[1].max(max[1]), hi[2].max(max[2])],
+            ),
+        });
+    }
+
+    let extensions: Vec<&str> = doc["extensionsUsed"].as_array()
+        .map(|e| e.iter().filter_map(Value::as_str).collect())
+        .unwrap_or_default();
+
+    Ok(GlbInfo {
+        mesh_count: meshes.len() as i64,
+        texture_count: doc["textures"].as_array().map(Vec::len).unwrap_or_default() as i64,
+        triangle_count,
+        bbox_min: bbox.map(|(min, _)| min.to_vec()),
+        bbox_max: bbox.map(|(_, max)| max.to_vec()),
+        uses_draco: extensions.contains(&"KHR_draco_mesh_compression"),
+        uses_meshopt: extensions.iter().any(|e| *e == "EXT_meshopt_compression" || *e == "KHR_meshopt_compression"),
+    })
+}
+
+/// Length of the BIN chunk following the JSON chunk, if there is one.
+/// `remaining` is the number of file bytes from the start of that chunk header.
+fn bin_chunk_len(chunk_header: &[u8], remaining: u64) -> Result<Option<u64>, String> {
+    if chunk_header.len() < CHUNK_HEADER_LEN || read_u32(chunk_header, 4) != CHUNK_BIN {
+        return Ok(None)
+    }
+    let bin_len = read_u32(chunk_header, 0) as u64;
+    if CHUNK_HEADER_LEN as u64 + bin_len > remaining {
+        return Err("BIN chunk length is out of bounds".to_string())
+    }
+    Ok(Some(bin_len))
+}
+
+pub fn inspect_glb(bytes: &[u8]) -> Result<GlbInfo, String> {
+    let json_len = parse_header(bytes, bytes.len() as u64)? as usize;
+    let json_start = GLB_HEADER_LEN + CHUNK_HEADER_LEN;
+    let json_end = json_start + json_len;
+    let bin_len = bin_chunk_len(&bytes[json_end..], (bytes.len() - json_end) as u64)?;
+    inspect_document(&bytes[json_start..json_end], bin_len)
+}
+
+const COMPONENT_FLOAT: u64 = 5126;
+/// One float VEC3 position.
+const VERTEX_LEN: u64 = 12;
+
+/// `(byte offset into the BIN chunk, stride, count)` of every plain float POSITION accessor.
+/// Counts are cut down to what fits inside the `bin_len` byte BIN chunk, accessors starting
+/// past its end are skipped.
+fn position_sources(doc: &Value, bin_len: u64) -> Vec<(u64, u64, u64)> {
+    let mut sources = vec![];
+    let meshes = doc["meshes"].as_array().map(Vec::as_slice).unwrap_or_default();
+    for primitive in meshes.iter().flat_map(|m| m["primitives"].as_array().into_iter().flatten()) {
+        if primitive["extensions"].get("KHR_draco_mesh_compression").is_some() {
+            continue
+        }
+        let Some(accessor) = primitive["attributes"]["POSITION"].as_u64()
+            .and_then(|i| doc["accessors"].get(i as usize)) else {
+            continue
+        };
+        if accessor["componentType"].as_u64() != Some(COMPONENT_FLOAT) || accessor["type"].as_str() != Some("VEC3") {
+            continue
+        }
+        let Some(view) = accessor["bufferView"].as_u64().and_then(|i| doc["bufferViews"].get(i as usize)) else {
+            continue
+        };
+        if view["buffer"].as_u64().unwrap_or_default() != 0 || view["extensions"].is_object() {
+            continue
+        }
+        let Some(offset) = view["byteOffset"].as_u64().unwrap_or_default()
+            .checked_add(accessor["byteOffset"].as_u64().unwrap_or_default()) else {
+            continue
+        };
+        let Some(room) = bin_len.checked_sub(offset).and_then(|r| r.checked_sub(VERTEX_LEN)) else {
+            continue
+        };
+        let stride = view["byteStride"].as_u64().unwrap_or(VERTEX_LEN).max(VERTEX_LEN);
+        let count = accessor["count"].as_u64().unwrap_or_default().min(room / stride + 1);
+        sources.push((offset, stride, count));
+    }
+    sources
+}
+
+/// Up to `max_points` vertex positions in mesh space, spread evenly over all meshes.
+/// Only plain float positions stored in the BIN chunk are read, Draco/meshopt
+/// compressed primitives are skipped. Blocking, the file is read piecewise instead of whole.
+pub fn sample_positions_file(path: &Path, max_points: usize) -> Result<Vec<[f32; 3]>, String> {
+    let file = std::fs::File::open(path).map_err(|e| format!("Failed to open file: {e}"))?;
+    let file_len = file.metadata()
+        .map_err(|e| format!("Failed to read metadata: {e}"))?
+        .len();
+    sample_positions(file, file_len, max_points)
+}
+
+fn sample_positions<R: Read + Seek>(reader: R, file_len: u64, max_points: usize) -> Result<Vec<[f32; 3]>, String> {
+    let io_error = |e: std::io::Error| format!("Failed to read model: {e}");
+    let mut reader = BufReader::with_capacity(64 * 1024, reader);
+
+    let mut header = [0u8; GLB_HEADER_LEN + CHUNK_HEADER_LEN];
+    reader.read_exact(&mut header)
+        .map_err(|_| "File is too small to be a GLB".to_string())?;
+    let json_len = parse_header(&header, file_len)?;
+    let mut json = vec![0u8; json_len as usize];
+    reader.read_exact(&mut json).map_err(io_error)?;
+    let doc: Value = serde_json::from_slice(&json)
+        .map_err(|e| format!("Invalid glTF JSON: {e}"))?;
+
+    let json_end = header.len() as u64 + json_len as u64;
+    let mut chunk_header = [0u8; CHUNK_HEADER_LEN];
+    if reader.read_exact(&mut chunk_header).is_err() {
+        return Ok(vec![])
+    }
+    let Some(bin_len) = bin_chunk_len(&chunk_header, file_len - json_end)? else {
+        return Ok(vec![])
+    };
+    let bin_start = json_end + CHUNK_HEADER_LEN as u64;
+
+    let sources = position_sources(&doc, bin_len);
+    let total = sources.iter().fold(0u64, |sum, (_, _, count)| sum.saturating_add(*count));
+    let step = (total / max_points.max(1) as u64).max(1);
+
+    let mut points = Vec::with_capacity(total.min(max_points as u64) as usize);
+    let mut position = bin_start;
+    let mut vertex = [0u8; VERTEX_LEN as usize];
+    let read_f32 = |v: &[u8], at: usize| f32::from_le_bytes([v[at], v[at + 1], v[at + 2], v[at + 3]]);
+    for (offset, stride, count) in sources {
+        for i in (0..count).step_by(step as usize) {
+            // Stays inside the BIN chunk, position_sources capped the count
+            let at = bin_start + offset + i * stride;
+            reader.seek_relative(at as i64 - position as i64).map_err(io_error)?;
+            reader.read_exact(&mut vertex).map_err(io_error)?;
+            position = at + VERTEX_LEN;
+            points.push([read_f32(&vertex, 0), read_f32(&vertex, 4), read_f32(&vertex, 8)]);
+        }
+    }
+    Ok(points)
+}
+
+/// Same as [`inspect_glb`] but only reads the header and JSON chunk from disk.
+pub async fn inspect_glb_file(path: &Path) -> Result<GlbInfo, String> {
+    let mut file = tokio::fs::File::open(path)
+        .await
+        .map_err(|e| format!("Failed to open file: {e}"))?;
+    let actual_len = file.metadata()
+        .await
+        .map_err(|e| format!("Failed to read metadata: {e}"))?
+        .len();
+
+    let mut header = [0u8; GLB_HEADER_LEN + CHUNK_HEADER_LEN];
+    file.read_exact(&mut header)
+        .await
+        .map_err(|_| "File is too small to be a GLB".to_string())?;
+    let json_len = parse_header(&header, actual_len)?;
+
+    let mut json = vec![0u8; json_len as usize];
+    file.read_exact(&mut json)
+        .await
+        .map_err(|e| format!("Failed to read JSON chunk: {e}"))?;
+
+    let mut chunk_header = [0u8; CHUNK_HEADER_LEN];
+    let json_end = (GLB_HEADER_LEN + CHUNK_HEADER_LEN) as u64 + json_len as u64;
+    let bin_len = match file.read_exact(&mut chunk_header).await {
+        Ok(_) => bin_chunk_len(&chunk_header, actual_len - json_end)?,
+        Err(_) => None,
+    };
+    inspect_document(&json, bin_len)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+
+    fn block_on<F: std::future::Future>(future: F) -> F::Output {
+        tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap()
+            .block_on(future)
+    }
+
+    /// A GLB container around `json`, with an optional BIN chunk.
+    fn glb(json: &str, bin: Option<&[u8]>) -> Vec<u8> {
+        let mut json = json.as_bytes().to_vec();
+        while json.len() % 4 != 0 {
+            json.push(b' ');
+        }
+        let mut bytes = GLB_MAGIC.to_vec();
+        bytes.extend_from_slice(&2u32.to_le_bytes());
+        bytes.extend_from_slice(&0u32.to_le_bytes());
+        bytes.extend_from_slice(&(json.len() as u32).to_le_bytes());
+        bytes.extend_from_slice(&CHUNK_JSON.to_le_bytes());
+        bytes.extend_from_slice(&json);
+        if let Some(bin) = bin {
+            bytes.extend_from_slice(&(bin.len() as u32).to_le_bytes());
+            bytes.extend_from_slice(&CHUNK_BIN.to_le_bytes());
+            bytes.extend_from_slice(bin);
+        }
+        let total = bytes.len() as u32;
+        bytes[8..12].copy_from_slice(&total.to_le_bytes());
+        bytes
+    }
+
+    fn set_u32(bytes: &mut [u8], offset: usize, value: u32) {
+        bytes[offset..offset + 4].copy_from_slice(&value.to_le_bytes());
+    }
+
+    const TRIANGLE: &str = r#"{
+        "asset": {"version": "2.0"},
+        "buffers": [{"byteLength": 36}],
+        "bufferViews": [{"buffer": 0, "byteLength": 36}],
+        "accessors": [{"bufferView": 0, "componentType": 5126, "type": "VEC3", "count": 3,
+                       "min": [0, 0, 0], "max": [1, 1, 0]}],
+        "meshes": [{"primitives": [{"attributes": {"POSITION": 0}}]}]
+    }"#;
+
+    fn triangle_bin() -> Vec<u8> {
+        [[0f32, 0., 0.], [1., 0., 0.], [0., 1., 0.]]
+            .iter()
+            .flatten()
+            .flat_map(|v| v.to_le_bytes())
+            .collect()
+    }
+
+    #[test]
+    fn inspects_a_valid_model() {
+        let info = inspect_glb(&glb(TRIANGLE, Some(&triangle_bin()))).unwrap();
+        assert_eq!(info.mesh_count, 1);
+        assert_eq!(info.triangle_count, 1);
+        assert_eq!(info.bbox_min, Some(vec![0.0, 0.0, 0.0]));
+        assert_eq!(info.bbox_max, Some(vec![1.0, 1.0, 0.0]));
+        assert!(!info.uses_draco);
+    }
+
+    #[test]
+    fn rejects_truncated_files() {
+        let bytes = glb(TRIANGLE, Some(&triangle_bin()));
+        assert!(inspect_glb(&bytes[..GLB_HEADER_LEN]).is_err());
+        assert!(inspect_glb(&[]).is_err());
+
+        // The declared total no longer matches what is left
+        assert!(inspect_glb(&bytes[..bytes.len() - 4]).is_err());
+
+        // Missing BIN chunk while buffer 0 points at it
+        assert!(inspect_glb(&glb(TRIANGLE, None)).is_err());
+    }
+
+    #[test]
+    fn rejects_oversized_chunk_lengths() {
+        let mut bytes = glb(TRIANGLE, Some(&triangle_bin()));
+        set_u32(&mut bytes, 12, u32::MAX);
+        assert!(inspect_glb(&bytes).is_err());
+
+        let mut bytes = glb(TRIANGLE, Some(&triangle_bin()));
+        let json_len = read_u32(&bytes, 12) as usize;
+        let bin_header = GLB_HEADER_LEN + CHUNK_HEADER_LEN + json_len;
+        set_u32(&mut bytes, bin_header, u32::MAX);
+        assert_eq!(inspect_glb(&bytes).unwrap_err(), "BIN chunk length is out of bounds");
+        assert!(sample_positions(Cursor::new(&bytes), bytes.len() as u64, 10).is_err());
+    }
+
+    #[test]
+    fn rejects_malformed_headers() {
+        let valid = glb(TRIANGLE, Some(&triangle_bin()));
+
+        let mut bytes = valid.clone();
+        bytes[0..4].copy_from_slice(b"gltf");
+        assert!(inspect_glb(&bytes).is_err());
+
+        let mut bytes = valid.clone();
+        set_u32(&mut bytes, 4, 1);
+        assert!(inspect_glb(&bytes).is_err());
+
+        let mut bytes = valid.clone();
+        set_u32(&mut bytes, 16, CHUNK_BIN);
+        assert!(inspect_glb(&bytes).is_err());
+
+        assert!(inspect_glb(&glb("{not json", None)).is_err());
+        assert!(inspect_glb(&glb(r#"{"asset": {"version": "1.0"}}"#, None)).is_err());
+    }
+
+    #[test]
+    fn file_inspection_matches_in_memory() {
+        let path = std::env::temp_dir().join(format!("glb-test-{}.glb", std::process::id()));
+        let mut bytes = glb(TRIANGLE, Some(&triangle_bin()));
+        std::fs::write(&path, &bytes).unwrap();
+        let info = block_on(inspect_glb_file(&path)).unwrap();
+        assert_eq!(info.triangle_count, 1);
+
+        let json_len = read_u32(&bytes, 12) as usize;
+        set_u32(&mut bytes, GLB_HEADER_LEN + CHUNK_HEADER_LEN + json_len, 1 << 20);
+        std::fs::write(&path, &bytes).unwrap();
+        assert!(block_on(inspect_glb_file(&path)).is_err());
+        let _ = std::fs::remove_file(&path);
+    }
+
+    fn sample(bytes: &[u8], max_points: usize) -> Vec<[f32; 3]> {
+        sample_positions(Cursor::new(bytes), bytes.len() as u64, max_points).unwrap()
+    }
+
+    #[test]
+    fn samples_positions() {
+        let points = sample(&glb(TRIANGLE, Some(&triangle_bin())), 10);
+        assert_eq!(points, vec![[0.0, 0.0, 0.0], [1.0, 0.0, 0.0], [0.0, 1.0, 0.0]]);
+
+        // Spread out once there are more vertices than asked for
+        let points = sample(&glb(TRIANGLE, Some(&triangle_bin())), 1);
+        assert_eq!(points, vec![[0.0, 0.0, 0.0]]);
+    }
+
+    #[test]
+    fn sampling_stays_inside_the_bin_chunk() {
+        let bin = triangle_bin();
+        let overrun = TRIANGLE.replace(r#""count": 3"#, r#""count": 4000000000"#);
+        assert_eq!(sample(&glb(&overrun, Some(&bin)), 10).len(), 3);
+
+        let past_end = TRIANGLE.replace(r#""bufferView": 0,"#, r#""bufferView": 0, "byteOffset": 18446744073709551615,"#);
+        assert!(sample(&glb(&past_end, Some(&bin)), 10).is_empty());
+
+        let huge_stride = TRIANGLE.replace(r#""byteLength": 36}]"#, r#""byteLength": 36, "byteStride": 18446744073709551615}]"#);
+        assert_eq!(sample(&glb(&huge_stride, Some(&bin)), 10).len(), 1);
+    }
+}
@@ -0,0 +1,77 @@
+use similar::{ChangeTag, TextDiff};
+use sqlx::{Postgres, Transaction};
+use uuid::Uuid;
+
+use crate::core::api_models::{DiffChange, DiffLine, FieldChange, GuideRevisionDiff};
+use crate::core::model::DbGuideRevision;
+
+/// Stores the guide's current state as its next revision. Call it in the transaction that
+/// changed the guide, after the change, so the row lock keeps revision numbers in order.
+pub async fn record_revision(
+    tx: &mut Transaction<'_, Postgres>,
+    guide_id: Uuid,
+    editor_id: i64,
+    restored_from: Option<i32>,
+) -> Result<i32, sqlx::Error> {
+    sqlx::query_scalar!(
+        "INSERT INTO website.guide_revision (guide_id, revision, title, content, category, server_id, editor_id, restored_from)
+         SELECT g.id,
+                COALESCE((SELECT MAX(revision) FROM website.guide_revision WHERE guide_id = g.id), 0) + 1,
+                g.title, g.content, g.category, g.server_id, $2, $3
+         FROM website.guides g
+         WHERE g.id = $1
+         RETURNING revision",
+        guide_id, editor_id, restored_from
+    )
+    .fetch_one(&mut **tx)
+    .await
+}
+
+fn field_change<T: ToString + PartialEq>(before: Option<T>, after: Option<T>) -> Option<FieldChange> {
+    if before == after {
+        return None
+    }
+    Some(FieldChange {
+        before: before.map(|v| v.to_string()),
+        after: after.map(|v| v.to_string()),
+    })
+}
+
+/// What changed going from `from` to `to`, content compared line by line.
+pub fn diff_revisions(from: &DbGuideRevision, to: &DbGuideRevision) -> GuideRevisionDiff {
+    let text_diff = TextDiff::from_lines(&from.content, &to.content);
+    let mut lines_added = 0;
+    let mut lines_removed = 0;
+    let content = text_diff
+        .iter_all_changes()
+        .map(|change| {
+            let change_type = match change.tag() {
+                ChangeTag::Equal => DiffChange::Equal,
+                ChangeTag::Insert => {
+                    lines_added += 1;
+                    DiffChange::Insert
+                }
+                ChangeTag::Delete => {
+                    lines_removed += 1;
+                    DiffChange::Delete
+                }
+            };
+            DiffLine {
+                change: change_type,
+                text: change.value().trim_end_matches(['\r', '\n']).to_string(),
+            }
+        })
+        .collect();
+
+    GuideRevisionDiff {
+        guide_id: to.guide_id.to_string(),
+        from: from.revision,
+        to: to.revision,
+        title: field_change(Some(&from.title), Some(&to.title)),
+        category: field_change(Some(&from.category), Some(&to.category)),
+        server_id: field_change(from.server_id.as_ref(), to.server_id.as_ref()),
+        content,
+        lines_added,
+        lines_removed,
+    }
+}
@@ -0,0 +1,667 @@
+use std::collections::{HashMap, HashSet};
+use std::fmt::{Display, Formatter};
+use std::time::Duration;
+
+use pulldown_cmark::{html, CowStr, Event, Options, Parser, Tag, TagEnd, TextMergeStream};
+use sqlx::types::time::OffsetDateTime;
+use uuid::Uuid;
+
+use crate::AppData;
+use crate::core::anonymization::{Anonymizer, ANONYMOUS_NAME};
+use crate::core::utils::{get_env_bool, get_server};
+
+pub const MAX_GUIDE_LEN: usize = 50_000;
+pub const MAX_COMMENT_LEN: usize = 5_000;
+const EXCERPT_LEN: usize = 200;
+/// Keeps one post from fanning out into hundreds of lookups
+const MAX_REFERENCES: usize = 50;
+const LINK_REL: &str = "noopener noreferrer nofollow ugc";
+const RENDER_BATCH: i64 = 50;
+const RENDER_POLL_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Markdown rendered to HTML that is safe to insert as is, with a plain text opening.
+pub struct RenderedMarkdown {
+    pub html: String,
+    pub excerpt: String,
+}
+
+pub enum MarkdownError {
+    TooLong(usize),
+    TooManyReferences,
+    InvalidReference(String),
+    UnknownReference(String),
+    Database(sqlx::Error),
+}
+
+impl Display for MarkdownError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            MarkdownError::TooLong(max) => write!(f, "Content can't be longer than {max} characters"),
+            MarkdownError::TooManyReferences => write!(f, "Content can't reference more than {MAX_REFERENCES} maps, players, matches or music"),
+            MarkdownError::InvalidReference(raw) => write!(f, "Invalid reference {raw}"),
+            MarkdownError::UnknownReference(raw) => write!(f, "{raw} doesn't exist"),
+            MarkdownError::Database(e) => write!(f, "Database error: {e}"),
+        }
+    }
+}
+
+impl From<sqlx::Error> for MarkdownError {
+    fn from(e: sqlx::Error) -> Self {
+        MarkdownError::Database(e)
+    }
+}
+
+/// `[[map:ze_example]]`, `[[player:76561198000000000]]`, `[[match:1234]]` or
+/// `[[match:1234@12:30]]` for a point in the match, `[[music:<id>]]`. `|label` before the
+/// closing brackets replaces the text shown.
+#[derive(Clone, PartialEq, Eq, Hash)]
+enum Reference {
+    Map(String),
+    Player(String),
+    Match { time_id: i32, offset_secs: Option<i64> },
+    Music(Uuid),
+}
+
+struct Mention {
+    raw: String,
+    reference: Reference,
+    label: Option<String>,
+}
+
+enum Segment {
+    Text(String),
+    Mention(Mention),
+}
+
+/// `12:30` or `1:02:03` into seconds
+fn parse_offset(value: &str) -> Option<i64> {
+    let parts: Vec<i64> = value.split(':').map(|p| p.parse().ok()).collect::<Option<_>>()?;
+    if parts.iter().any(|p| *p < 0) || parts.iter().skip(1).any(|p| *p >= 60) {
+        return None
+    }
+    match parts.as_slice() {
+        [m, s] => Some(m * 60 + s),
+        [h, m, s] => Some(h * 3600 + m * 60 + s),
+        _ => None,
+    }
+}
+
+/// `None` when it isn't one of ours, so `[[anything else]]` stays text.
+fn parse_mention(inner: &str) -> Option<Result<Mention, MarkdownError>> {
+    let raw = format!("[[{inner}]]");
+    let (body, label) = match inner.split_once('|') {
+        Some((body, label)) => (body, Some(label.trim().to_string()).filter(|l| !l.is_empty())),
+        None => (inner, None),
+    };
+    let (kind, value) = body.split_once(':')?;
+    let value = value.trim();
+    let reference = match kind.trim() {
+        "map" if !value.is_empty() => Reference::Map(value.to_string()),
+        "player" if !value.is_empty() => Reference::Player(value.to_string()),
+        "match" => {
+            let (time_id, offset) = match value.split_once('@') {
+                Some((time_id, offset)) => (time_id, Some(offset)),
+                None => (value, None),
+            };
+            let Ok(time_id) = time_id.trim().parse() else {
+                return Some(Err(MarkdownError::InvalidReference(raw)))
+            };
+            let offset_secs = match offset.map(|o| parse_offset(o.trim())) {
+                Some(None) => return Some(Err(MarkdownError::InvalidReference(raw))),
+                Some(secs) => secs,
+                None => None,
+            };
+            Reference::Match { time_id, offset_secs }
+        }
+        "music" => match Uuid::parse_str(value) {
+            Ok(id) => Reference::Music(id),
+            Err(_) => return Some(Err(MarkdownError::InvalidReference(raw))),
+        },
+        "map" | "player" => return Some(Err(MarkdownError::InvalidReference(raw))),
+        _ => return None,
+    };
+    Some(Ok(Mention { raw, reference, label }))
+}
+
+/// Outside of `strict`, invalid mentions stay text instead of failing.
+fn split_mentions(text: &str, strict: bool) -> Result<Vec<Segment>, MarkdownError> {
+    let mut segments = vec![];
+    let mut rest = text;
+    while let Some(start) = rest.find("[[") {
+        let Some(len) = rest[start + 2..].find("]]") else {
+            break
+        };
+        let inner = &rest[start + 2..start + 2 + len];
+        let end = start + 2 + len + 2;
+        match parse_mention(inner) {
+            Some(Err(_)) if !strict => segments.push(Segment::Text(rest[..end].to_string())),
+            Some(mention) => {
+                if start > 0 {
+                    segments.push(Segment::Text(rest[..start].to_string()));
+                }
+                segments.push(Segment::Mention(mention?));
+            }
+            None => segments.push(Segment::Text(rest[..end].to_string())),
+        }
+        rest = &rest[end..];
+    }
+    if !rest.is_empty() {
+        segments.push(Segment::Text(rest.to_string()));
+    }
+    Ok(segments)
+}
+
+struct MatchInfo {
+    server_link: String,
+    map: String,
+    started_at: Option<OffsetDateTime>,
+    human_score: Option<i16>,
+    zombie_score: Option<i16>,
+}
+
+#[derive(Default)]
+struct Resolved {
+    maps: HashSet<String>,
+    /// Names are left out for players hidden in any community
+    players: HashMap<String, Option<String>>,
+    matches: HashMap<(i32, Option<i64>), MatchInfo>,
+    /// Name and YouTube video id
+    music: HashMap<Uuid, (String, Option<String>)>,
+}
+
+async fn resolve(app: &AppData, references: &HashSet<Reference>) -> Result<Resolved, MarkdownError> {
+    let pool = &*app.pool;
+    let mut resolved = Resolved::default();
+    let mut maps = vec![];
+    let mut players = vec![];
+    let mut music = vec![];
+    for reference in references {
+        match reference {
+            Reference::Map(name) => maps.push(name.clone()),
+            Reference::Player(id) => players.push(id.clone()),
+            Reference::Music(id) => music.push(*id),
+            Reference::Match { time_id, offset_secs } => {
+                let row = sqlx::query!(
+                    r#"SELECT smp.map, smp.started_at, s.server_id, s.readable_link,
+                              md.human_score AS "human_score?", md.zombie_score AS "zombie_score?"
+                       FROM server_map_played smp
+                       JOIN server s ON s.server_id = smp.server_id
+                       LEFT JOIN LATERAL (
+                           SELECT human_score, zombie_score FROM match_data
+                           WHERE time_id = smp.time_id
+                             AND ($2::float8 IS NULL OR occurred_at <= smp.started_at + make_interval(secs => $2))
+                           ORDER BY occurred_at DESC
+                           LIMIT 1
+                       ) md ON TRUE
+                       WHERE smp.time_id = $1"#,
+                    time_id, offset_secs.map(|s| s as f64)
+                )
+                .fetch_optional(pool)
+                .await?;
+                if let Some(row) = row {
+                    resolved.matches.insert((*time_id, *offset_secs), MatchInfo {
+                        server_link: row.readable_link.unwrap_or(row.server_id),
+                        map: row.map,
+                        started_at: row.started_at,
+                        human_score: row.human_score,
+                        zombie_score: row.zombie_score,
+                    });
+                }
+            }
+        }
+    }
+    if !maps.is_empty() {
+        resolved.maps = sqlx::query_scalar!("SELECT DISTINCT map FROM server_map WHERE map = ANY($1)", &maps as &[String])
+            .fetch_all(pool)
+            .await?
+            .into_iter()
+            .collect();
+    }
+    if !players.is_empty() {
+        // Stored for every viewer, so nobody's view of anonymization applies
+        let anonymizer = Anonymizer::everywhere(app, None).await?;
+        let rows = sqlx::query!("SELECT player_id, player_name FROM player WHERE player_id = ANY($1)", &players as &[String])
+            .fetch_all(pool)
+            .await?;
+        for row in rows {
+            let name = (!anonymizer.privacy(&row.player_id).anonymized).then_some(row.player_name);
+            resolved.players.insert(row.player_id, name);
+        }
+    }
+    if !music.is_empty() {
+        let rows = sqlx::query!("SELECT id, music_name, youtube_music FROM map_music WHERE id = ANY($1)", &music as &[Uuid])
+            .fetch_all(pool)
+            .await?;
+        for row in rows {
+            resolved.music.insert(row.id, (row.music_name, row.youtube_music));
+        }
+    }
+    Ok(resolved)
+}
+
+fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+fn format_offset(secs: i64) -> String {
+    match secs / 3600 {
+        0 => format!("{}:{:02}", secs / 60, secs % 60),
+        hours => format!("{hours}:{:02}:{:02}", secs % 3600 / 60, secs % 60),
+    }
+}
+
+/// HTML and excerpt text for one mention, `Err` when what it points at doesn't exist.
+fn render_mention(mention: &Mention, resolved: &Resolved, server_link: Option<&str>) -> Result<(String, String), MarkdownError> {
+    let unknown = || MarkdownError::UnknownReference(mention.raw.clone());
+    let label = |fallback: String| mention.label.clone().unwrap_or(fallback);
+    match &mention.reference {
+        Reference::Map(map) => {
+            if !resolved.maps.contains(map) {
+                return Err(unknown())
+            }
+            let text = label(map.clone());
+            let href = match server_link {
+                Some(server) => format!("/servers/{server}/maps/{map}"),
+                None => format!("/maps/{map}/guides"),
+            };
+            let html = format!(
+                r#"<a class="zw-map" href="{}" data-map="{}">{}</a>"#,
+                escape(&href), escape(map), escape(&text)
+            );
+            Ok((html, text))
+        }
+        Reference::Player(player_id) => {
+            let Some(name) = resolved.players.get(player_id) else {
+                return Err(unknown())
+            };
+            let Some(name) = name else {
+                let html = format!(r#"<span class="zw-player">{ANONYMOUS_NAME}</span>"#);
+                return Ok((html, ANONYMOUS_NAME.to_string()))
+            };
+            let text = label(name.clone());
+            let html = match server_link {
+                Some(server) => format!(
+                    r#"<a class="zw-player" href="{}" data-player-id="{}">{}</a>"#,
+                    escape(&format!("/servers/{server}/players/{player_id}")), escape(player_id), escape(&text)
+                ),
+                None => format!(
+                    r#"<span class="zw-player" data-player-id="{}">{}</span>"#,
+                    escape(player_id), escape(&text)
+                ),
+            };
+            Ok((html, text))
+        }
+        Reference::Match { time_id, offset_secs } => {
+            let Some(info) = resolved.matches.get(&(*time_id, *offset_secs)) else {
+                return Err(unknown())
+            };
+            let mut text = info.map.clone();
+            if let Some(started_at) = info.started_at {
+                text.push_str(&format!(
+                    " on {} {:02}:{:02} UTC",
+                    started_at.date(), started_at.hour(), started_at.minute()
+                ));
+            }
+            if let Some(offset) = offset_secs {
+                text.push_str(&format!(" at {}", format_offset(*offset)));
+            }
+            if let (Some(humans), Some(zombies)) = (info.human_score, info.zombie_score) {
+                text.push_str(&format!(" (Humans {humans} - {zombies} Zombies)"));
+            }
+            let text = label(text);
+            let mut href = format!("/servers/{}/maps/{}/sessions/{time_id}", info.server_link, info.map);
+            if let Some(offset) = offset_secs {
+                href.push_str(&format!("?t={offset}"));
+            }
+            let html = format!(
+                r#"<a class="zw-match" href="{}" data-time-id="{time_id}">{}</a>"#,
+                escape(&href), escape(&text)
+            );
+            Ok((html, text))
+        }
+        Reference::Music(music_id) => {
+            let Some((name, youtube_id)) = resolved.music.get(music_id) else {
+                return Err(unknown())
+            };
+            let text = label(name.clone());
+            let html = match youtube_id {
+                Some(video) => format!(
+                    r#"<a class="zw-music" href="https://www.youtube.com/watch?v={}" data-youtube-id="{}">{}</a>"#,
+                    escape(video), escape(video), escape(&text)
+                ),
+                None => format!(r#"<span class="zw-music">{}</span>"#, escape(&text)),
+            };
+            Ok((html, text))
+        }
+    }
+}
+
+fn sanitize(html: &str) -> String {
+    ammonia::Builder::default()
+        .link_rel(Some(LINK_REL))
+        .url_schemes(["http", "https", "mailto"].into())
+        .add_allowed_classes("a", &["zw-map", "zw-player", "zw-match", "zw-music"])
+        .add_allowed_classes("span", &["zw-player", "zw-music"])
+        .add_tag_attributes("a", &["data-map", "data-player-id", "data-time-id", "data-youtube-id"])
+        .add_tag_attributes("span", &["data-player-id"])
+        .clean(html)
+        .to_string()
+}
+
+fn excerpt_of(text: &str) -> String {
+    let text = text.split_whitespace().collect::<Vec<_>>().join(" ");
+    if text.chars().count() <= EXCERPT_LEN {
+        return text
+    }
+    let cut: String = text.chars().take(EXCERPT_LEN).collect();
+    let cut = match cut.rfind(' ') {
+        Some(space) if space > EXCERPT_LEN / 2 => &cut[..space],
+        _ => cut.as_str(),
+    };
+    format!("{}…", cut.trim_end_matches(|c: char| c.is_ascii_punctuation()))
+}
+
+/// Validates and renders guide or comment Markdown. Raw HTML in the source is shown as text and
+/// the output goes through a sanitizer anyway. `server_id` is where map and player links point.
+pub async fn render_markdown(
+    app: &AppData,
+    source: &str,
+    max_len: usize,
+    server_id: Option<&str>,
+) -> Result<RenderedMarkdown, MarkdownError> {
+    if source.chars().count() > max_len {
+        return Err(MarkdownError::TooLong(max_len))
+    }
+    render(app, source, server_id, true).await
+}
+
+/// Without `strict`, for content that was already accepted: mentions that are invalid or point
+/// at something gone are left as text and nothing but the database can fail.
+async fn render(
+    app: &AppData,
+    source: &str,
+    server_id: Option<&str>,
+    strict: bool,
+) -> Result<RenderedMarkdown, MarkdownError> {
+    let options = Options::ENABLE_TABLES | Options::ENABLE_STRIKETHROUGH;
+    let events: Vec<Event> = TextMergeStream::new(Parser::new_ext(source, options)).collect();
+
+    let mut in_code_block = false;
+    let mut references = HashSet::new();
+    let mut split: HashMap<usize, Vec<Segment>> = HashMap::new();
+    for (i, event) in events.iter().enumerate() {
+        match event {
+            Event::Start(Tag::CodeBlock(_)) => in_code_block = true,
+            Event::End(TagEnd::CodeBlock) => in_code_block = false,
+            Event::Text(text) if !in_code_block && text.contains("[[") => {
+                let segments = split_mentions(text, strict)?;
+                for segment in &segments {
+                    if let Segment::Mention(mention) = segment {
+                        references.insert(mention.reference.clone());
+                    }
+                }
+                split.insert(i, segments);
+            }
+            _ => {}
+        }
+    }
+    if strict && references.len() > MAX_REFERENCES {
+        return Err(MarkdownError::TooManyReferences)
+    }
+    let resolved = match references.is_empty() {
+        true => Resolved::default(),
+        false => resolve(app, &references).await?,
+    };
+    let server_link = match server_id {
+        Some(id) => Some(
+            get_server(&app.pool, &app.cache, id).await
+                .and_then(|s| s.readable_link)
+                .unwrap_or_else(|| id.to_string())
+        ),
+        None => None,
+    };
+
+    let mut rendered = vec![];
+    let mut plain = String::new();
+    in_code_block = false;
+    for (i, event) in events.into_iter().enumerate() {
+        if let Some(segments) = split.remove(&i) {
+            for segment in segments {
+                match segment {
+                    Segment::Text(text) => {
+                        plain.push_str(&text);
+                        rendered.push(Event::Text(CowStr::from(text)));
+                    }
+                    Segment::Mention(mention) => {
+                        match render_mention(&mention, &resolved, server_link.as_deref()) {
+                            Ok((html, text)) => {
+                                plain.push_str(&text);
+                                rendered.push(Event::InlineHtml(CowStr::from(html)));
+                            }
+                            Err(e) if strict => return Err(e),
+                            Err(_) => {
+                                plain.push_str(&mention.raw);
+                                rendered.push(Event::Text(CowStr::from(mention.raw)));
+                            }
+                        }
+                    }
+                }
+            }
+            continue
+        }
+        match &event {
+            Event::Start(Tag::CodeBlock(_)) => in_code_block = true,
+            Event::End(TagEnd::CodeBlock) => {
+                in_code_block = false;
+                plain.push(' ');
+            }
+            Event::Text(text) | Event::Code(text) if !in_code_block => plain.push_str(text),
+            Event::SoftBreak | Event::HardBreak
+            | Event::End(TagEnd::Paragraph | TagEnd::Heading(_) | TagEnd::Item | TagEnd::TableCell) => plain.push(' '),
+            _ => {}
+        }
+        match event {
+            Event::Html(html) | Event::InlineHtml(html) => rendered.push(Event::Text(html)),
+            event => rendered.push(event),
+        }
+    }
+
+    let mut html = String::new();
+    html::push_html(&mut html, rendered.into_iter());
+    Ok(RenderedMarkdown {
+        html: sanitize(&html),
+        excerpt: excerpt_of(&plain),
+    })
+}
+
+/// Stored HTML carries the names of mentioned players, so rows mentioning a player whose
+/// anonymization changed are cleared for [`run_markdown_renders`] to render again.
+pub async fn forget_player_mentions(app: &AppData, player_id: i64) -> Result<(), sqlx::Error> {
+    let pool = &*app.pool;
+    let pattern = format!(r"\[\[\s*player\s*:\s*{player_id}\s*(\||\]\])");
+    sqlx::query!(
+        "UPDATE website.guides SET content_html = NULL WHERE content_html IS NOT NULL AND content ~ $1",
+        pattern
+    )
+    .execute(pool)
+    .await?;
+    sqlx::query!(
+        "UPDATE website.guide_comments SET content_html = NULL WHERE content_html IS NOT NULL AND content ~ $1",
+        pattern
+    )
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+/// Renders up to a batch of guides and of comments without `content_html`, how many of each
+/// were looked at. Rows edited in the meantime keep what the edit rendered.
+async fn render_missing(app: &AppData) -> Result<usize, MarkdownError> {
+    let pool = &*app.pool;
+    let guides = sqlx::query!(
+        "SELECT id, content, server_id FROM website.guides
+         WHERE content_html IS NULL
+         ORDER BY updated_at
+         LIMIT $1",
+        RENDER_BATCH
+    )
+    .fetch_all(pool)
+    .await?;
+    for guide in &guides {
+        let rendered = render(app, &guide.content, guide.server_id.as_deref(), false).await?;
+        sqlx::query!(
+            "UPDATE website.guides SET content_html = $2, excerpt = $3
+             WHERE id = $1 AND content = $4 AND content_html IS NULL",
+            guide.id, rendered.html, rendered.excerpt, guide.content
+        )
+        .execute(pool)
+        .await?;
+    }
+
+    let comments = sqlx::query!(
+        "SELECT c.id, c.content, g.server_id FROM website.guide_comments c
+         JOIN website.guides g ON g.id = c.guide_id
+         WHERE c.content_html IS NULL
+         ORDER BY c.updated_at
+         LIMIT $1",
+        RENDER_BATCH
+    )
+    .fetch_all(pool)
+    .await?;
+    for comment in &comments {
+        let rendered = render(app, &comment.content, comment.server_id.as_deref(), false).await?;
+        sqlx::query!(
+            "UPDATE website.guide_comments SET content_html = $2
+             WHERE id = $1 AND content = $3 AND content_html IS NULL",
+            comment.id, rendered.html, comment.content
+        )
+        .execute(pool)
+        .await?;
+    }
+    Ok(guides.len().max(comments.len()))
+}
+
+/// Fills in `content_html` for guides and comments written before it was stored and for those
+/// [`forget_player_mentions`] cleared. Clients show `content` meanwhile. Disable with
+/// `MARKDOWN_RENDERS=false`.
+pub async fn run_markdown_renders(app: AppData) {
+    if !get_env_bool("MARKDOWN_RENDERS", true) {
+        tracing::info!("Markdown renders are disabled");
+        return
+    }
+    loop {
+        let looked_at = match render_missing(&app).await {
+            Ok(looked_at) => looked_at,
+            Err(e) => {
+                tracing::error!("Failed to render guides and comments: {e}");
+                0
+            }
+        };
+        if looked_at < RENDER_BATCH as usize {
+            tokio::time::sleep(RENDER_POLL_INTERVAL).await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Text segments as is and mentions as their raw form in brackets, for comparing
+    fn describe(segments: &[Segment]) -> Vec<String> {
+        segments.iter()
+            .map(|segment| match segment {
+                Segment::Text(text) => text.clone(),
+                Segment::Mention(mention) => format!("<{}>", mention.raw),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn parses_offsets() {
+        assert_eq!(parse_offset("12:30"), Some(750));
+        assert_eq!(parse_offset("0:05"), Some(5));
+        assert_eq!(parse_offset("1:02:03"), Some(3723));
+        assert_eq!(parse_offset("90:00"), Some(5400));
+        assert_eq!(parse_offset("12:60"), None);
+        assert_eq!(parse_offset("1:60:00"), None);
+        assert_eq!(parse_offset("-1:30"), None);
+        assert_eq!(parse_offset("30"), None);
+        assert_eq!(parse_offset("1:2:3:4"), None);
+        assert_eq!(parse_offset("a:30"), None);
+    }
+
+    #[test]
+    fn splits_mentions_from_text() {
+        let segments = split_mentions("Play [[map:ze_example]] with [[player:7656|me]]!", true).ok().unwrap();
+        assert_eq!(describe(&segments), ["Play ", "<[[map:ze_example]]>", " with ", "<[[player:7656|me]]>", "!"]);
+        let Segment::Mention(mention) = &segments[3] else { panic!("not a mention") };
+        assert!(mention.reference == Reference::Player("7656".to_string()));
+        assert_eq!(mention.label.as_deref(), Some("me"));
+
+        let segments = split_mentions("[[match: 12 @ 1:02:03 ]]", true).ok().unwrap();
+        let Segment::Mention(mention) = &segments[0] else { panic!("not a mention") };
+        assert!(mention.reference == Reference::Match { time_id: 12, offset_secs: Some(3723) });
+        assert_eq!(mention.label, None);
+    }
+
+    #[test]
+    fn leaves_other_brackets_as_text() {
+        let segments = split_mentions("[[not ours]] and [[wiki:page]] and [[unclosed", true).ok().unwrap();
+        assert_eq!(describe(&segments), ["[[not ours]]", " and [[wiki:page]]", " and [[unclosed"]);
+    }
+
+    #[test]
+    fn invalid_mentions_fail_only_when_strict() {
+        for text in ["[[map:]]", "[[player: ]]", "[[match:abc]]", "[[match:1@12:99]]", "[[music:nope]]"] {
+            assert!(matches!(split_mentions(text, true), Err(MarkdownError::InvalidReference(_))), "{text}");
+            let segments = split_mentions(text, false).ok().unwrap();
+            assert_eq!(describe(&segments), [text], "{text}");
+        }
+    }
+
+    #[test]
+    fn excerpts_collapse_whitespace_and_cut_at_words() {
+        assert_eq!(excerpt_of("  A short\n\nguide  "), "A short guide");
+
+        let long = "word ".repeat(100);
+        let excerpt = excerpt_of(&long);
+        assert!(excerpt.ends_with("word…"));
+        assert!(excerpt.chars().count() <= EXCERPT_LEN + 1);
+
+        let excerpt = excerpt_of(&format!("{}, and more", "x".repeat(EXCERPT_LEN)));
+        assert_eq!(excerpt, format!("{}…", "x".repeat(EXCERPT_LEN)));
+
+        let excerpt = excerpt_of(&format!("{}. {}", "a".repeat(150), "b".repeat(100)));
+        assert_eq!(excerpt, format!("{}…", "a".repeat(150)));
+    }
+
+    #[test]
+    fn sanitize_keeps_mentions_and_drops_scripts() {
+        let html = sanitize(r#"<p><a class="zw-player" href="/servers/ze/players/1" data-player-id="1">me</a></p>"#);
+        assert!(html.contains(r#"class="zw-player""#));
+        assert!(html.contains(r#"data-player-id="1""#));
+        assert!(html.contains(&format!(r#"rel="{LINK_REL}""#)));
+
+        let html = sanitize(r#"<p onclick="x()">hi<script>alert(1)</script></p><a href="javascript:alert(1)">link</a>"#);
+        assert!(!html.contains("script"));
+        assert!(!html.contains("onclick"));
+        assert!(!html.contains("javascript:"));
+        assert!(html.contains("hi"));
+
+        let html = sanitize(r#"<span class="zw-map other" data-map="ze_x">x</span>"#);
+        assert!(!html.contains("zw-map"));
+        assert!(!html.contains("data-map"));
+    }
+}
@@ -119,6 +119,7 @@ pub struct DbGuideSitemap{
     pub server_readable_link: Option<String>,
     pub slug: String,
     pub updated_at: OffsetDateTime,
+    pub excerpt: Option<String>,
 }
 #[derive(Clone)]
 #[auto_serde_with]
@@ -259,7 +260,8 @@ impl Into<PlayerSeen> for DbPlayerSeen{
             id: self.player_id,
             name: self.player_name,
             total_time_together: self.total_time_together.map(pg_interval_to_f64).unwrap_or(0.),
-            last_seen: db_to_utc(self.last_seen.unwrap_or(smallest_date()))
+            last_seen: db_to_utc(self.last_seen.unwrap_or(smallest_date())),
+            is_anonymous: false,
         }
     }
 }
@@ -273,12 +275,41 @@ pub struct DbPlayer{
     pub associated_player_id: Option<String>
 }
 
-#[derive(Clone)]
 #[auto_serde_with]
-pub struct DbPlayerAnonymized{
-    pub player_id: String,
-    pub player_name: String,
-    pub is_anonymous: bool
+pub struct DbSearchResult {
+    pub kind: String,
+    pub id: String,
+    pub title: String,
+    pub snippet: Option<String>,
+    pub map_name: Option<String>,
+    pub guide_slug: Option<String>,
+    pub server_id: Option<String>,
+    pub score: f64,
+}
+
+impl DbSearchResult {
+    /// `None` for kinds this build doesn't know about
+    pub fn into_result(self) -> Option<SearchResult> {
+        let kind = match self.kind.as_str() {
+            "guide" => SearchKind::Guide,
+            "comment" => SearchKind::Comment,
+            "map" => SearchKind::Map,
+            "music" => SearchKind::Music,
+            "player" => SearchKind::Player,
+            "server" => SearchKind::Server,
+            _ => return None,
+        };
+        Some(SearchResult {
+            kind,
+            id: self.id,
+            title: self.title,
+            snippet: self.snippet,
+            map_name: self.map_name,
+            guide_slug: self.guide_slug,
+            server_id: self.server_id,
+            score: self.score,
+        })
+    }
 }
 
 #[derive(Clone)]
@@ -310,15 +341,6 @@ impl Into<SearchPlayer> for DbPlayer {
     }
 }
 
-impl Into<SearchPlayer> for DbPlayerAnonymized {
-    fn into(self) -> SearchPlayer {
-        SearchPlayer{
-            name: self.player_name,
-            id: self.player_id,
-            is_anonymous: self.is_anonymous
-        }
-    }
-}
 #[derive(Clone)]
 #[auto_serde_with]
 pub struct DbPlayerDetail{
@@ -507,6 +529,27 @@ impl Into<PlayerTableRank> for DbPlayerTable{
 }
 #[derive(Clone)]
 #[auto_serde_with]
+pub struct DbLeaderboardEntry{
+    pub rank: Option<i64>,
+    pub position: Option<i64>,
+    pub player_id: String,
+    pub player_name: Option<String>,
+    pub value: Option<f64>,
+    pub total_players: Option<i64>,
+}
+impl Into<LeaderboardEntry> for DbLeaderboardEntry{
+    fn into(self) -> LeaderboardEntry {
+        LeaderboardEntry {
+            rank: self.rank.unwrap_or(-1),
+            id: self.player_id,
+            name: self.player_name.unwrap_or("Unknown Player".to_string()),
+            value: self.value.unwrap_or(0.),
+            is_anonymous: false,
+        }
+    }
+}
+#[derive(Clone)]
+#[auto_serde_with]
 pub struct DbPlayerBrief{
     pub player_id: String,
     pub player_name: String,
@@ -530,6 +573,7 @@ impl Into<PlayerBrief> for DbPlayerBrief {
             online_since: self.online_since.map(db_to_utc),
             last_played: db_to_utc(self.last_played.unwrap_or(smallest_date())),
             last_played_duration: self.last_played_duration.map(pg_interval_to_f64).unwrap_or(0.),
+            is_anonymous: false,
         }
     }
 }
@@ -1090,6 +1134,7 @@ impl Into<CountryPlayer> for DbCountryPlayer{
             total_playtime: self.total_playtime.map(pg_interval_to_f64).unwrap_or_default(),
             total_player_count: self.total_player_count.unwrap_or(0),
             session_count: self.session_count.unwrap_or(0),
+            is_anonymous: false,
         }
     }
 }
@@ -1168,6 +1213,7 @@ impl Into<SteamProfile> for DbSteam{
             locstatecode: None,
             loccityid: None,
             is_superuser: Some(false),
+            discord: None,
         }
     }
 }
@@ -1335,6 +1381,8 @@ pub struct DbGuide {
     pub server_id: Option<String>,
     pub title: String,
     pub content: String,
+    pub content_html: Option<String>,
+    pub excerpt: Option<String>,
     pub category: String,
     pub created_at: OffsetDateTime,
     pub updated_at: OffsetDateTime,
@@ -1359,6 +1407,8 @@ impl Into<Guide> for DbGuide {
             server_id: self.server_id,
             title: self.title,
             content: self.content,
+            content_html: self.content_html,
+            excerpt: self.excerpt,
             category: self.category,
             author: GuideAuthor {
                 id: self.author_id.to_string(),
@@ -1376,6 +1426,61 @@ impl Into<Guide> for DbGuide {
     }
 }
 
+#[auto_serde_with]
+pub struct DbGuideRevision {
+    pub guide_id: uuid::Uuid,
+    pub revision: i32,
+    pub title: String,
+    pub content: String,
+    pub category: String,
+    pub server_id: Option<String>,
+    pub editor_id: i64,
+    pub editor_name: Option<String>,
+    pub editor_avatar: Option<String>,
+    pub restored_from: Option<i32>,
+    pub created_at: OffsetDateTime,
+}
+
+impl DbGuideRevision {
+    fn editor(&self) -> GuideAuthor {
+        GuideAuthor {
+            id: self.editor_id.to_string(),
+            name: self.editor_name.clone().unwrap_or("Unknown".into()),
+            avatar: self.editor_avatar.clone(),
+        }
+    }
+}
+
+impl Into<GuideRevisionBrief> for DbGuideRevision {
+    fn into(self) -> GuideRevisionBrief {
+        GuideRevisionBrief {
+            editor: self.editor(),
+            revision: self.revision,
+            title: self.title,
+            category: self.category,
+            server_id: self.server_id,
+            restored_from: self.restored_from,
+            created_at: db_to_utc(self.created_at),
+        }
+    }
+}
+
+impl Into<GuideRevision> for DbGuideRevision {
+    fn into(self) -> GuideRevision {
+        GuideRevision {
+            editor: self.editor(),
+            guide_id: self.guide_id.to_string(),
+            revision: self.revision,
+            title: self.title,
+            content: self.content,
+            category: self.category,
+            server_id: self.server_id,
+            restored_from: self.restored_from,
+            created_at: db_to_utc(self.created_at),
+        }
+    }
+}
+
 #[auto_serde_with]
 pub struct DbGuideComment {
     pub id: uuid::Uuid,
@@ -1384,6 +1489,7 @@ pub struct DbGuideComment {
     pub author_name: Option<String>,
     pub author_avatar: Option<String>,
     pub content: String,
+    pub content_html: Option<String>,
     pub created_at: OffsetDateTime,
     pub updated_at: OffsetDateTime,
     pub upvotes: i64,
@@ -1403,6 +1509,7 @@ impl Into<GuideComment> for DbGuideComment {
                 avatar: self.author_avatar
             },
             content: self.content,
+            content_html: self.content_html,
             created_at: db_to_utc(self.created_at),
             updated_at: db_to_utc(self.updated_at),
             upvotes: self.upvotes,
@@ -1447,6 +1554,8 @@ pub struct DbGuideReportFull {
     pub reporter_name: Option<String>,
     pub resolver_name: Option<String>,
     pub total_reports: Option<i64>,
+    pub reported_revision: Option<i32>,
+    pub reported_content: Option<String>,
 }
 
 impl Into<GuideReportAdmin> for DbGuideReportFull {
@@ -1467,6 +1576,8 @@ impl Into<GuideReportAdmin> for DbGuideReportFull {
             resolver_name: self.resolver_name,
             resolved_at: self.resolved_at.map(db_to_utc),
             created_at: db_to_utc(self.timestamp),
+            reported_revision: self.reported_revision,
+            reported_content: self.reported_content,
         }
     }
 }
@@ -1713,6 +1824,10 @@ pub struct DbMap3DModel {
     pub file_size: i64,
     pub created_at: OffsetDateTime,
     pub updated_at: OffsetDateTime,
+    pub content_hash: Option<String>,
+    pub glb_info: Option<serde_json::Value>,
+    pub thumbnail_path: Option<String>,
+    pub generated: bool,
 }
 
 impl Into<Map3DModel> for DbMap3DModel {
@@ -1728,6 +1843,10 @@ impl Into<Map3DModel> for DbMap3DModel {
             file_size: self.file_size,
             created_at: db_to_utc(self.created_at),
             updated_at: db_to_utc(self.updated_at),
+            content_hash: self.content_hash,
+            glb_info: self.glb_info.and_then(|v| serde_json::from_value(v).ok()),
+            thumbnail_path: self.thumbnail_path,
+            generated: self.generated,
         }
     }
 }
@@ -1745,6 +1864,7 @@ pub struct DbCharacter3DModel {
     pub file_size: i64,
     pub created_at: OffsetDateTime,
     pub updated_at: OffsetDateTime,
+    pub glb_info: Option<serde_json::Value>,
 }
 
 impl Into<Character3DModel> for DbCharacter3DModel {
@@ -1762,6 +1882,7 @@ impl Into<Character3DModel> for DbCharacter3DModel {
             file_size: self.file_size,
             created_at: db_to_utc(self.created_at),
             updated_at: db_to_utc(self.updated_at),
+            glb_info: self.glb_info.and_then(|v| serde_json::from_value(v).ok()),
         }
     }
 }
@@ -1827,3 +1948,152 @@ impl Into<ServerRequestAdmin> for DbServerRequest {
         }
     }
 }
+
+pub struct DbAchievementStats {
+    pub total_playtime: Option<PgInterval>,
+    pub rotation_maps: Option<i64>,
+    pub rotation_maps_played: Option<i64>,
+    pub best_map_rank: Option<i64>,
+    pub longest_streak: Option<i64>,
+    pub night_sessions: Option<i64>,
+}
+
+pub struct DbPlayerAchievement {
+    pub server_id: String,
+    pub achievement_key: String,
+    pub unlocked_at: OffsetDateTime,
+}
+
+pub struct DbPlayerCalendarDay {
+    pub day: Option<sqlx::types::time::Date>,
+    pub played: Option<PgInterval>,
+}
+
+pub struct DbPlayedDay {
+    pub day: Option<sqlx::types::time::Date>,
+}
+
+pub struct DbSessionBounds {
+    pub time_id: i32,
+    pub map: String,
+    pub started_at: OffsetDateTime,
+    pub ended_at: Option<OffsetDateTime>,
+}
+
+pub struct DbTimelineInterval {
+    pub player_id: String,
+    pub player_name: Option<String>,
+    pub joined_at: Option<OffsetDateTime>,
+    pub left_at: Option<OffsetDateTime>,
+}
+
+pub struct DbTimelineMatch {
+    pub zombie_score: i16,
+    pub human_score: i16,
+    pub extend_count: Option<i16>,
+    pub occurred_at: Option<OffsetDateTime>,
+}
+
+pub struct DbMap3DModelVersion {
+    pub id: i32,
+    pub map_name: String,
+    pub res_type: String,
+    pub content_hash: Option<String>,
+    pub link_path: String,
+    pub credit: Option<String>,
+    pub uploaded_by: Option<i64>,
+    pub file_size: i64,
+    pub created_at: OffsetDateTime,
+}
+
+pub struct DbModelJob {
+    pub id: i32,
+    pub target: serde_json::Value,
+    pub status: String,
+    pub error: Option<String>,
+    pub attempts: i32,
+    pub created_at: OffsetDateTime,
+    pub started_at: Option<OffsetDateTime>,
+    pub finished_at: Option<OffsetDateTime>,
+}
+
+pub struct DbDataExport {
+    pub id: uuid::Uuid,
+    pub user_id: i64,
+    pub status: String,
+    pub progress: i16,
+    pub step: Option<String>,
+    pub error: Option<String>,
+    pub file_path: Option<String>,
+    pub file_size: Option<i64>,
+    pub attempts: i32,
+    pub created_at: OffsetDateTime,
+    pub started_at: Option<OffsetDateTime>,
+    pub finished_at: Option<OffsetDateTime>,
+    pub expires_at: Option<OffsetDateTime>,
+}
+
+pub struct DbAccountDeletion {
+    pub id: uuid::Uuid,
+    pub user_id: i64,
+    pub status: String,
+    pub requested_at: OffsetDateTime,
+    pub scheduled_for: OffsetDateTime,
+    pub cancelled_at: Option<OffsetDateTime>,
+    pub started_at: Option<OffsetDateTime>,
+    pub completed_at: Option<OffsetDateTime>,
+    pub attempts: i32,
+    pub error: Option<String>,
+    pub summary: Option<serde_json::Value>,
+}
+
+#[auto_serde_with]
+pub struct DbUserSession {
+    pub session_id: uuid::Uuid,
+    pub device_id: String,
+    pub device_name: Option<String>,
+    pub user_agent: Option<String>,
+    pub ip_address: Option<String>,
+    pub created_at: OffsetDateTime,
+    pub updated_at: OffsetDateTime,
+    pub expires_at: OffsetDateTime,
+}
+
+impl Into<UserSession> for DbUserSession {
+    fn into(self) -> UserSession {
+        UserSession {
+            id: self.session_id.to_string(),
+            device_id: self.device_id,
+            device_name: self.device_name,
+            user_agent: self.user_agent,
+            ip_address: self.ip_address,
+            created_at: db_to_utc(self.created_at),
+            last_refreshed_at: db_to_utc(self.updated_at),
+            expires_at: db_to_utc(self.expires_at),
+            current: false,
+        }
+    }
+}
+
+#[auto_serde_with]
+pub struct DbDiscordLink {
+    pub user_id: i64,
+    pub username: Option<String>,
+    pub display_name: Option<String>,
+    pub avatar: Option<String>,
+    pub linked_at: OffsetDateTime,
+    pub roles_synced_at: Option<OffsetDateTime>,
+}
+
+impl Into<DiscordLink> for DbDiscordLink {
+    fn into(self) -> DiscordLink {
+        DiscordLink {
+            discord_id: self.user_id.to_string(),
+            username: self.username,
+            display_name: self.display_name,
+            avatar: self.avatar,
+            linked_at: db_to_utc(self.linked_at),
+            roles_synced_at: self.roles_synced_at.map(db_to_utc),
+        }
+    }
+}
@@ -0,0 +1,493 @@
+use std::io::Cursor;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use image::{ImageFormat, Rgba, RgbaImage};
+use serde::{Deserialize, Serialize};
+use sqlx::{Pool, Postgres};
+
+use crate::AppData;
+use crate::core::api_models::GlbInfo;
+use crate::core::asset_storage::{content_hash, content_hash_file};
+use crate::core::audit::record_audit;
+use crate::core::glb::{inspect_glb_file, sample_positions_file};
+use crate::core::model::{DbMap3DModel, DbModelJob};
+use crate::core::utils::{get_env_bool, get_env_default};
+
+const POLL_INTERVAL: Duration = Duration::from_secs(10);
+/// Running jobs older than this were lost to a restart and get picked up again.
+const STUCK_AFTER_SECS: f64 = 3600.0;
+const MAX_ATTEMPTS: i32 = 3;
+const OPTIMIZER_TIMEOUT: Duration = Duration::from_secs(30 * 60);
+const DEFAULT_OPTIMIZER_CMD: &str = "gltf-transform optimize {input} {output} \
+    --compress draco --texture-compress webp --texture-size 1024 --simplify-ratio 0.25";
+const THUMBNAIL_SIZE: u32 = 512;
+const THUMBNAIL_MARGIN: f32 = 24.0;
+const MAX_SAMPLE_POINTS: usize = 250_000;
+
+#[derive(Clone, Copy)]
+pub enum ModelSource<'a> {
+    Bytes(&'a [u8]),
+    /// Already on local disk, consumed when stored.
+    File(&'a Path),
+}
+
+/// Which model a job works on.
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum ModelJobTarget {
+    MapModel { map_name: String, res_type: String },
+    CharacterModel { server_id: String, model_id: String },
+}
+
+/// Stores a map model under its content hash, records it as a version and makes it current.
+/// Uploading bytes that were already stored for this map/res reuses the existing object.
+pub async fn save_map_model_version(
+    app: &AppData,
+    map_name: &str,
+    res_type: &str,
+    credit: Option<String>,
+    uploaded_by: Option<i64>,
+    generated: bool,
+    source: ModelSource<'_>,
+    file_size: i64,
+    glb_info: &GlbInfo,
+) -> Result<DbMap3DModel, String> {
+    let glb_info = serde_json::to_value(glb_info).ok();
+    let content_hash = match source {
+        ModelSource::Bytes(bytes) => content_hash(bytes),
+        ModelSource::File(path) => content_hash_file(path).await?,
+    };
+    let storage_id = (map_name, res_type, Some(content_hash.as_str()));
+
+    let existing = sqlx::query_scalar!(
+        "SELECT link_path FROM website.map_3d_model_version
+         WHERE map_name = $1 AND res_type = $2 AND content_hash = $3",
+        map_name, res_type, content_hash
+    )
+    .fetch_optional(&*app.pool)
+    .await
+    .map_err(|e| format!("Database error: {e}"))?;
+
+    let link_path = match (existing, source) {
+        (Some(link_path), ModelSource::File(path)) => {
+            let _ = tokio::fs::remove_file(path).await;
+            link_path
+        }
+        (Some(link_path), ModelSource::Bytes(_)) => link_path,
+        (None, ModelSource::Bytes(bytes)) => app.map_storage.store_bytes(storage_id, bytes).await?,
+        (None, ModelSource::File(path)) => app.map_storage.store_file(storage_id, path).await?,
+    };
+
+    let mut tx = app.pool.begin()
+        .await
+        .map_err(|e| format!("Database error: {e}"))?;
+
+    sqlx::query!(
+        "INSERT INTO website.map_3d_model_version
+         (map_name, res_type, content_hash, link_path, credit, uploaded_by, file_size, glb_info, generated)
+         VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9)
+         ON CONFLICT (map_name, res_type, content_hash) DO NOTHING",
+        map_name, res_type, content_hash, link_path, credit, uploaded_by, file_size, glb_info, generated,
+    )
+    .execute(&mut *tx)
+    .await
+    .map_err(|e| format!("Database error: {e}"))?;
+
+    let model = sqlx::query_as!(
+        DbMap3DModel,
+        r#"
+        INSERT INTO website.map_3d_model
+        (map_name, res_type, credit, link_path, uploaded_by, file_size, content_hash, glb_info, generated)
+        VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9)
+        ON CONFLICT (map_name, res_type)
+        DO UPDATE SET
+            credit = EXCLUDED.credit,
+            link_path = EXCLUDED.link_path,
+            uploaded_by = EXCLUDED.uploaded_by,
+            file_size = EXCLUDED.file_size,
+            content_hash = EXCLUDED.content_hash,
+            glb_info = EXCLUDED.glb_info,
+            generated = EXCLUDED.generated,
+            updated_at = NOW()
+        RETURNING *
+        "#,
+        map_name, res_type, credit, link_path, uploaded_by, file_size, content_hash, glb_info, generated,
+    )
+    .fetch_one(&mut *tx)
+    .await
+    .map_err(|e| format!("Database error: {e}"))?;
+
+    tx.commit().await.map_err(|e| format!("Database error: {e}"))?;
+    Ok(model)
+}
+
+/// What audit entries record about a map model.
+pub fn map_model_audit(model: &DbMap3DModel) -> serde_json::Value {
+    serde_json::json!({
+        "content_hash": model.content_hash,
+        "file_size": model.file_size,
+        "credit": model.credit,
+        "uploaded_by": model.uploaded_by,
+        "generated": model.generated,
+    })
+}
+
+/// Queues processing for a freshly stored model. A job that is still queued for the
+/// same model covers the new upload as well, so no duplicate is added.
+pub async fn enqueue_model_job(pool: &Pool<Postgres>, target: ModelJobTarget) {
+    let Ok(target) = serde_json::to_value(&target) else {
+        return
+    };
+    let result = sqlx::query!(
+        "INSERT INTO website.model_job (target)
+         SELECT $1::JSONB
+         WHERE NOT EXISTS (
+             SELECT 1 FROM website.model_job WHERE status = 'queued' AND target = $1::JSONB
+         )",
+        target
+    )
+    .execute(pool)
+    .await;
+    if let Err(e) = result {
+        tracing::error!("Failed to enqueue model job for {target}: {e}");
+    }
+}
+
+async fn claim_job(pool: &Pool<Postgres>) -> Result<Option<DbModelJob>, sqlx::Error> {
+    sqlx::query!(
+        "UPDATE website.model_job
+         SET status = CASE WHEN attempts < $1 THEN 'queued' ELSE 'failed' END,
+             error = CASE WHEN attempts < $1 THEN NULL ELSE 'Interrupted too many times' END,
+             finished_at = CASE WHEN attempts < $1 THEN NULL ELSE NOW() END
+         WHERE status = 'running' AND started_at < NOW() - make_interval(secs => $2)",
+        MAX_ATTEMPTS, STUCK_AFTER_SECS
+    )
+    .execute(pool)
+    .await?;
+
+    sqlx::query_as!(
+        DbModelJob,
+        "UPDATE website.model_job
+         SET status = 'running', started_at = NOW(), finished_at = NULL, attempts = attempts + 1
+         WHERE id = (
+             SELECT id FROM website.model_job
+             WHERE status = 'queued'
+             ORDER BY created_at
+             LIMIT 1
+             FOR UPDATE SKIP LOCKED
+         )
+         RETURNING *"
+    )
+    .fetch_optional(pool)
+    .await
+}
+
+async fn finish_job(pool: &Pool<Postgres>, job_id: i32, error: Option<String>) {
+    let status = if error.is_some() { "failed" } else { "done" };
+    let result = sqlx::query!(
+        "UPDATE website.model_job SET status = $2, error = $3, finished_at = NOW() WHERE id = $1",
+        job_id, status, error
+    )
+    .execute(pool)
+    .await;
+    if let Err(e) = result {
+        tracing::error!("Failed to update model job {job_id}: {e}");
+    }
+}
+
+/// Works through `website.model_job` one job at a time. Several instances can run this
+/// side by side, jobs are claimed with `SKIP LOCKED`. Disable with `MODEL_JOBS=false`.
+pub async fn run_model_jobs(app: AppData) {
+    if !get_env_bool("MODEL_JOBS", true) {
+        tracing::info!("Model jobs are disabled");
+        return
+    }
+    // Thumbnails are the one thing every job writes, don't let them land relative to the working directory
+    if let Err(e) = thumbnail_dir("") {
+        tracing::error!("Model jobs are disabled: {e}");
+        return
+    }
+    loop {
+        let job = match claim_job(&app.pool).await {
+            Ok(Some(job)) => job,
+            Ok(None) => {
+                tokio::time::sleep(POLL_INTERVAL).await;
+                continue
+            }
+            Err(e) => {
+                tracing::error!("Failed to claim model job: {e}");
+                tokio::time::sleep(POLL_INTERVAL).await;
+                continue
+            }
+        };
+
+        let work_dir = job_dir(job.id);
+        let result = match serde_json::from_value::<ModelJobTarget>(job.target.clone()) {
+            Ok(target) => process_job(&app, &target, &work_dir).await,
+            Err(e) => Err(format!("Invalid job target: {e}")),
+        };
+        let _ = tokio::fs::remove_dir_all(&work_dir).await;
+
+        match &result {
+            Ok(()) => tracing::info!("Model job {} done: {}", job.id, job.target),
+            Err(e) => tracing::warn!("Model job {} failed: {}: {}", job.id, job.target, e),
+        }
+        finish_job(&app.pool, job.id, result.err()).await;
+    }
+}
+
+fn job_dir(job_id: i32) -> PathBuf {
+    let store_upload = get_env_default("STORE_UPLOAD").unwrap_or_else(|| "./maps".to_string());
+    Path::new(&store_upload).join(".tmp").join(format!("job-{job_id}"))
+}
+
+fn thumbnail_dir(kind: &str) -> Result<PathBuf, String> {
+    let cache_dir = get_env_default("CACHE_THUMBNAIL").ok_or("CACHE_THUMBNAIL is not set")?;
+    Ok(Path::new(&cache_dir).join(kind))
+}
+
+async fn process_job(app: &AppData, target: &ModelJobTarget, work_dir: &Path) -> Result<(), String> {
+    tokio::fs::create_dir_all(work_dir)
+        .await
+        .map_err(|e| format!("Failed to create job directory: {e}"))?;
+    match target {
+        ModelJobTarget::MapModel { map_name, res_type } => {
+            process_map_model(app, map_name, res_type, work_dir).await
+        }
+        ModelJobTarget::CharacterModel { server_id, model_id } => {
+            process_character_model(app, server_id, model_id, work_dir).await
+        }
+    }
+}
+
+async fn process_map_model(app: &AppData, map_name: &str, res_type: &str, work_dir: &Path) -> Result<(), String> {
+    let model = sqlx::query_as!(
+        DbMap3DModel,
+        "SELECT * FROM website.map_3d_model WHERE map_name = $1 AND res_type = $2",
+        map_name, res_type
+    )
+    .fetch_optional(&*app.pool)
+    .await
+    .map_err(|e| format!("Database error: {e}"))?
+    .ok_or("Model no longer exists")?;
+
+    let source = work_dir.join("source.glb");
+    app.map_storage
+        .download((map_name, res_type, model.content_hash.as_deref()), &source)
+        .await?;
+
+    let seed = model.content_hash.clone().unwrap_or_else(|| format!("{map_name}/{res_type}"));
+    let png = render_thumbnail_file(&source, View::Top, seed).await?;
+    let filename = format!("{map_name}_{res_type}.png");
+    write_thumbnail("map-models", &filename, &png).await?;
+    set_map_thumbnail(app, map_name, res_type, &filename).await?;
+
+    if res_type != "high" {
+        return Ok(())
+    }
+
+    // An uploaded low model always wins over a generated one.
+    let manual_low = sqlx::query_scalar!(
+        "SELECT NOT generated FROM website.map_3d_model WHERE map_name = $1 AND res_type = 'low'",
+        map_name
+    )
+    .fetch_optional(&*app.pool)
+    .await
+    .map_err(|e| format!("Database error: {e}"))?
+    .flatten()
+    .unwrap_or(false);
+    if manual_low {
+        return Ok(())
+    }
+
+    let optimized = work_dir.join("low.glb");
+    optimize_model(&source, &optimized).await?;
+    let glb_info = inspect_glb_file(&optimized)
+        .await
+        .map_err(|e| format!("Optimizer produced an invalid GLB: {e}"))?;
+    let file_size = tokio::fs::metadata(&optimized)
+        .await
+        .map_err(|e| format!("Failed to read optimized model: {e}"))?
+        .len() as i64;
+
+    let low = save_map_model_version(
+        app, map_name, "low", model.credit, None, true, ModelSource::File(&optimized), file_size, &glb_info,
+    ).await?;
+    record_audit(
+        &app.pool, None, "generate", "map_model", &format!("{map_name}/low"), None, Some(map_model_audit(&low)),
+    ).await;
+    set_map_thumbnail(app, map_name, "low", &filename).await
+}
+
+async fn set_map_thumbnail(app: &AppData, map_name: &str, res_type: &str, filename: &str) -> Result<(), String> {
+    sqlx::query!(
+        "UPDATE website.map_3d_model SET thumbnail_path = $3 WHERE map_name = $1 AND res_type = $2",
+        map_name, res_type, filename
+    )
+    .execute(&*app.pool)
+    .await
+    .map_err(|e| format!("Database error: {e}"))?;
+    Ok(())
+}
+
+async fn process_character_model(app: &AppData, server_id: &str, model_id: &str, work_dir: &Path) -> Result<(), String> {
+    let exists = sqlx::query_scalar!(
+        "SELECT EXISTS(SELECT 1 FROM website.character_3d_model WHERE server_id = $1 AND model_id = $2)",
+        server_id, model_id
+    )
+    .fetch_one(&*app.pool)
+    .await
+    .map_err(|e| format!("Database error: {e}"))?
+    .unwrap_or(false);
+    if !exists {
+        return Err("Model no longer exists".to_string())
+    }
+
+    let source = work_dir.join("source.glb");
+    app.character_storage.download(model_id, &source).await?;
+
+    let png = render_thumbnail_file(&source, View::Front, model_id.to_string()).await?;
+    let filename = format!("{model_id}.generated.png");
+    write_thumbnail("characters", &filename, &png).await?;
+
+    // Thumbnails uploaded through the API are kept, only missing or generated ones are replaced.
+    sqlx::query!(
+        "UPDATE website.character_3d_model
+         SET thumbnail_path = $3
+         WHERE server_id = $1 AND model_id = $2 AND (thumbnail_path IS NULL OR thumbnail_path = $3)",
+        server_id, model_id, filename
+    )
+    .execute(&*app.pool)
+    .await
+    .map_err(|e| format!("Database error: {e}"))?;
+    Ok(())
+}
+
+async fn write_thumbnail(kind: &str, filename: &str, png: &[u8]) -> Result<(), String> {
+    let dir = thumbnail_dir(kind)?;
+    tokio::fs::create_dir_all(&dir)
+        .await
+        .map_err(|e| format!("Failed to create thumbnail directory: {e}"))?;
+    tokio::fs::write(dir.join(filename), png)
+        .await
+        .map_err(|e| format!("Failed to write thumbnail: {e}"))
+}
+
+/// Runs `MODEL_OPTIMIZER_CMD` with `{input}` and `{output}` substituted,
+/// by default the same gltf-transform pipeline `compress_map_model.sh` approximates.
+async fn optimize_model(input: &Path, output: &Path) -> Result<(), String> {
+    let command = get_env_default("MODEL_OPTIMIZER_CMD").unwrap_or_else(|| DEFAULT_OPTIMIZER_CMD.to_string());
+    let input = input.to_string_lossy();
+    let output = output.to_string_lossy();
+    let mut parts = command
+        .split_whitespace()
+        .map(|part| part.replace("{input}", &input).replace("{output}", &output));
+    let program = parts.next().ok_or("MODEL_OPTIMIZER_CMD is empty")?;
+
+    let child = tokio::process::Command::new(&program)
+        .args(parts)
+        .kill_on_drop(true)
+        .output();
+    let result = tokio::time::timeout(OPTIMIZER_TIMEOUT, child)
+        .await
+        .map_err(|_| "Optimizer timed out".to_string())?
+        .map_err(|e| format!("Failed to run {program}: {e}"))?;
+
+    if !result.status.success() {
+        let stderr = String::from_utf8_lossy(&result.stderr);
+        let tail: String = stderr.chars().rev().take(500).collect::<Vec<_>>().into_iter().rev().collect();
+        return Err(format!("Optimizer exited with {}: {}", result.status, tail.trim()))
+    }
+    Ok(())
+}
+
+#[derive(Clone, Copy)]
+enum View {
+    /// Looking down the Y axis, suits maps.
+    Top,
+    /// Looking along the Z axis, suits characters.
+    Front,
+}
+
+async fn render_thumbnail_file(path: &Path, view: View, seed: String) -> Result<Vec<u8>, String> {
+    let path = path.to_path_buf();
+    tokio::task::spawn_blocking(move || {
+        let points = sample_positions_file(&path, MAX_SAMPLE_POINTS)?;
+        render_thumbnail(points, view, &seed)
+    })
+    .await
+    .map_err(|e| format!("Thumbnail render panicked: {e}"))?
+}
+
+/// Point cloud render of the sampled vertex positions, shaded by depth. Models whose geometry we
+/// cannot read (Draco/meshopt) get a placeholder in colours derived from `seed` instead.
+fn render_thumbnail(points: Vec<[f32; 3]>, view: View, seed: &str) -> Result<Vec<u8>, String> {
+    let (background, near, far) = palette(seed);
+    let mut image = RgbaImage::from_pixel(THUMBNAIL_SIZE, THUMBNAIL_SIZE, background);
+
+    let points: Vec<(f32, f32, f32)> = points
+        .into_iter()
+        .filter(|p| p.iter().all(|v| v.is_finite()))
+        .map(|[x, y, z]| match view {
+            View::Top => (x, z, y),
+            View::Front => (x, -y, z),
+        })
+        .collect();
+
+    if points.is_empty() {
+        draw_placeholder(&mut image, near, far);
+    } else {
+        let bounds = |f: fn(&(f32, f32, f32)) -> f32| {
+            points.iter().map(f).fold((f32::MAX, f32::MIN), |(lo, hi), v| (lo.min(v), hi.max(v)))
+        };
+        let (min_u, max_u) = bounds(|p| p.0);
+        let (min_v, max_v) = bounds(|p| p.1);
+        let (min_d, max_d) = bounds(|p| p.2);
+        let usable = THUMBNAIL_SIZE as f32 - 2.0 * THUMBNAIL_MARGIN;
+        let scale = usable / (max_u - min_u).max(max_v - min_v).max(f32::EPSILON);
+        let offset_u = THUMBNAIL_MARGIN + (usable - (max_u - min_u) * scale) / 2.0;
+        let offset_v = THUMBNAIL_MARGIN + (usable - (max_v - min_v) * scale) / 2.0;
+        let depth_range = (max_d - min_d).max(f32::EPSILON);
+
+        for (u, v, d) in points {
+            let px = (offset_u + (u - min_u) * scale) as u32;
+            let py = (offset_v + (v - min_v) * scale) as u32;
+            let color = mix(far, near, (d - min_d) / depth_range);
+            for (dx, dy) in [(0, 0), (1, 0), (0, 1), (1, 1)] {
+                if px + dx < THUMBNAIL_SIZE && py + dy < THUMBNAIL_SIZE {
+                    image.put_pixel(px + dx, py + dy, color);
+                }
+            }
+        }
+    }
+
+    let mut png = Vec::new();
+    image.write_to(&mut Cursor::new(&mut png), ImageFormat::Png)
+        .map_err(|e| format!("Failed to encode thumbnail: {e}"))?;
+    Ok(png)
+}
+
+fn draw_placeholder(image: &mut RgbaImage, top: Rgba<u8>, bottom: Rgba<u8>) {
+    let size = THUMBNAIL_SIZE as f32;
+    for (x, y, pixel) in image.enumerate_pixels_mut() {
+        let t = (x as f32 + y as f32) / (2.0 * size);
+        *pixel = mix(top, bottom, t);
+    }
+}
+
+/// Background, near and far colours picked from a hash of `seed`.
+fn palette(seed: &str) -> (Rgba<u8>, Rgba<u8>, Rgba<u8>) {
+    let hash = hex::decode(content_hash(seed.as_bytes())).unwrap_or_default();
+    let byte = |i: usize| hash.get(i).copied().unwrap_or(128);
+    let background = Rgba([byte(0) / 8 + 12, byte(1) / 8 + 12, byte(2) / 8 + 16, 255]);
+    let near = Rgba([byte(3) / 2 + 128, byte(4) / 2 + 128, byte(5) / 2 + 128, 255]);
+    let far = Rgba([byte(6) / 3 + 40, byte(7) / 3 + 40, byte(8) / 3 + 60, 255]);
+    (background, near, far)
+}
+
+fn mix(a: Rgba<u8>, b: Rgba<u8>, t: f32) -> Rgba<u8> {
+    let t = t.clamp(0.0, 1.0);
+    let channel = |i: usize| (a.0[i] as f32 + (b.0[i] as f32 - a.0[i] as f32) * t) as u8;
+    Rgba([channel(0), channel(1), channel(2), 255])
+}
@@ -0,0 +1,165 @@
+use uuid::Uuid;
+
+use crate::AppData;
+
+/// Named permissions, bundled into roles by `website.role`. A role holding `*` has all of them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Permission {
+    /// Guide and comment reports, guide bans, removing other people's guides
+    GuidesModerate,
+    /// Music reports and YouTube overrides on `map_music`
+    MusicModerate,
+    MapsMetadataEdit,
+    /// Uploading, restoring and deleting map and character 3D models
+    ModelsUpload,
+    /// Model processing jobs and storage reports
+    ModelsManage,
+    DonationsManage,
+    /// Communities, servers, server browsers and server requests
+    ServersManage,
+    AnnouncementsManage,
+    PushManage,
+    /// Changing other users' anonymization
+    PlayersAnonymize,
+    /// Seeing anonymized players as they are
+    PlayersViewAnonymized,
+    AuditView,
+    /// Granting and revoking roles within the scope held
+    RolesManage,
+    /// Rate limits and quotas of other users' API keys
+    ApiKeysManage,
+}
+
+impl Permission {
+    pub const ALL: &'static [Permission] = &[
+        Permission::GuidesModerate,
+        Permission::MusicModerate,
+        Permission::MapsMetadataEdit,
+        Permission::ModelsUpload,
+        Permission::ModelsManage,
+        Permission::DonationsManage,
+        Permission::ServersManage,
+        Permission::AnnouncementsManage,
+        Permission::PushManage,
+        Permission::PlayersAnonymize,
+        Permission::PlayersViewAnonymized,
+        Permission::AuditView,
+        Permission::RolesManage,
+        Permission::ApiKeysManage,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Permission::GuidesModerate => "guides.moderate",
+            Permission::MusicModerate => "music.moderate",
+            Permission::MapsMetadataEdit => "maps.metadata.edit",
+            Permission::ModelsUpload => "models.upload",
+            Permission::ModelsManage => "models.manage",
+            Permission::DonationsManage => "donations.manage",
+            Permission::ServersManage => "servers.manage",
+            Permission::AnnouncementsManage => "announcements.manage",
+            Permission::PushManage => "push.manage",
+            Permission::PlayersAnonymize => "players.anonymize",
+            Permission::PlayersViewAnonymized => "players.view_anonymized",
+            Permission::AuditView => "audit.view",
+            Permission::RolesManage => "roles.manage",
+            Permission::ApiKeysManage => "api_keys.manage",
+        }
+    }
+
+    /// Accepts every permission name plus the `*` wildcard.
+    pub fn is_valid_name(name: &str) -> bool {
+        name == "*" || Permission::ALL.iter().any(|p| p.name() == name)
+    }
+}
+
+/// Where a permission is needed. Global grants cover every scope and community grants
+/// cover the community's servers.
+#[derive(Debug, Clone, Copy)]
+pub enum Scope<'a> {
+    Global,
+    Community(Uuid),
+    Server(&'a str),
+}
+
+pub async fn has_permission(app: &AppData, user_id: i64, permission: Permission, scope: Scope<'_>) -> bool {
+    let (community_id, server_id) = match scope {
+        Scope::Global => (None, None),
+        Scope::Community(id) => (Some(id), None),
+        Scope::Server(id) => (None, Some(id)),
+    };
+    let Ok(allowed) = sqlx::query_scalar!(
+        "SELECT website.has_permission($1, $2, $3, $4)",
+        user_id, permission.name(), community_id, server_id
+    )
+        .fetch_one(&*app.pool)
+        .await else {
+        return false
+    };
+
+    allowed == Some(true)
+}
+
+pub async fn is_superuser(app: &AppData, user_id: i64) -> bool {
+    let Ok(is_superuser) = sqlx::query_scalar!("SELECT website.is_superuser($1)", user_id)
+        .fetch_one(&*app.pool)
+        .await else {
+        return false
+    };
+
+    is_superuser == Some(true)
+}
+
+/// `models.upload` globally, or on one of the servers running `map_name`.
+pub async fn check_map_models(app: &AppData, user_id: i64, map_name: &str) -> bool {
+    let Ok(allowed) = sqlx::query_scalar!(
+        "SELECT website.has_permission($1, 'models.upload') OR EXISTS(
+            SELECT 1 FROM server_map sm
+            WHERE sm.map = $2 AND website.has_permission($1, 'models.upload', NULL, sm.server_id)
+        )",
+        user_id, map_name
+    )
+        .fetch_one(&*app.pool)
+        .await else {
+        return false
+    };
+
+    allowed == Some(true)
+}
+
+/// `models.upload` on `server_id`. Character files are stored by model id alone, so the
+/// permission must also cover every other server using that model id.
+pub async fn check_character_models(app: &AppData, user_id: i64, server_id: &str, model_id: &str) -> bool {
+    let Ok(allowed) = sqlx::query_scalar!(
+        "SELECT website.has_permission($1, 'models.upload', NULL, $2) AND NOT EXISTS(
+            SELECT 1 FROM website.character_3d_model c
+            WHERE c.model_id = $3
+              AND NOT website.has_permission($1, 'models.upload', NULL, c.server_id)
+        )",
+        user_id, server_id, model_id
+    )
+        .fetch_one(&*app.pool)
+        .await else {
+        return false
+    };
+
+    allowed == Some(true)
+}
+
+/// Whether `permission` is held in any scope, for listings that each admin narrows down themselves.
+pub async fn has_permission_anywhere(app: &AppData, user_id: i64, permission: Permission) -> bool {
+    let Ok(allowed) = sqlx::query_scalar!(
+        "SELECT EXISTS(
+            SELECT 1 FROM website.role_grant g
+            JOIN website.role r ON r.id = g.role_id
+            WHERE g.user_id = $1 AND ($2 = ANY(r.permissions) OR '*' = ANY(r.permissions))
+        )",
+        user_id, permission.name()
+    )
+        .fetch_one(&*app.pool)
+        .await else {
+        return false
+    };
+
+    allowed == Some(true)
+}
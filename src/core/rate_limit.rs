@@ -0,0 +1,310 @@
+use std::net::IpAddr;
+use std::sync::{Arc, LazyLock};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use poem::http::StatusCode;
+use poem::{Endpoint, IntoResponse, Middleware, Request};
+
+use crate::AppData;
+use crate::core::api_keys::API_KEY_HEADER;
+use crate::core::api_models::{route_pattern, UriExtension};
+use crate::core::utils::get_env_default;
+
+/// Private ranges cover the bundled nginx on the compose network.
+const DEFAULT_TRUSTED_PROXIES: &str = "127.0.0.0/8,::1/128,10.0.0.0/8,172.16.0.0/12,192.168.0.0/16";
+
+/// Routes that run a heavy query on a cold cache, as `pattern=requests/seconds`.
+const DEFAULT_RATE_LIMITS: &str = "\
+    /radars/{server_id}/live_query=30/60,\
+    /servers/{server_id}/players/table=60/60,\
+    /graph/{server_id}/top_players=30/60,\
+    /servers/{server_id}/maps/{map_name}/top_players=30/60,\
+    /servers/{server_id}/maps/all/sessions=30/60,\
+    /search=30/60";
+
+struct Cidr {
+    addr: IpAddr,
+    prefix: u32,
+}
+
+impl Cidr {
+    fn parse(s: &str) -> Option<Cidr> {
+        let (addr, prefix) = match s.split_once('/') {
+            Some((addr, prefix)) => (addr.parse::<IpAddr>().ok()?, Some(prefix.parse::<u32>().ok()?)),
+            None => (s.parse::<IpAddr>().ok()?, None),
+        };
+        let max = if addr.is_ipv4() { 32 } else { 128 };
+        let prefix = prefix.unwrap_or(max);
+        (prefix <= max).then_some(Cidr { addr, prefix })
+    }
+
+    fn contains(&self, ip: IpAddr) -> bool {
+        let ip = match ip {
+            IpAddr::V6(v6) => v6.to_ipv4_mapped().map(IpAddr::V4).unwrap_or(ip),
+            v4 => v4,
+        };
+        match (self.addr, ip) {
+            (IpAddr::V4(net), IpAddr::V4(ip)) => {
+                let mask = u32::MAX.checked_shl(32 - self.prefix).unwrap_or(0);
+                u32::from(net) & mask == u32::from(ip) & mask
+            }
+            (IpAddr::V6(net), IpAddr::V6(ip)) => {
+                let mask = u128::MAX.checked_shl(128 - self.prefix).unwrap_or(0);
+                u128::from(net) & mask == u128::from(ip) & mask
+            }
+            _ => false,
+        }
+    }
+}
+
+/// `TRUSTED_PROXIES`, comma separated addresses or CIDR ranges.
+static TRUSTED_PROXIES: LazyLock<Vec<Cidr>> = LazyLock::new(|| {
+    let raw = get_env_default("TRUSTED_PROXIES").unwrap_or_else(|| DEFAULT_TRUSTED_PROXIES.to_string());
+    raw.split(',')
+        .map(str::trim)
+        .filter(|s| !s.is_empty())
+        .filter_map(|s| {
+            let cidr = Cidr::parse(s);
+            if cidr.is_none() {
+                tracing::warn!("Ignoring invalid TRUSTED_PROXIES entry {s}");
+            }
+            cidr
+        })
+        .collect()
+});
+
+fn is_trusted_proxy(ip: IpAddr) -> bool {
+    TRUSTED_PROXIES.iter().any(|cidr| cidr.contains(ip))
+}
+
+/// The address the request came from. Forwarding headers are only believed when the peer is
+/// a trusted proxy, and X-Forwarded-For is walked from the right so clients can't prepend to it.
+pub fn client_ip(req: &Request) -> Option<IpAddr> {
+    let peer = req.remote_addr().as_socket_addr().map(|addr| addr.ip())?;
+    Some(resolve_client_ip(peer, req.header("X-Forwarded-For"), req.header("X-Real-IP")))
+}
+
+fn resolve_client_ip(peer: IpAddr, forwarded: Option<&str>, real_ip: Option<&str>) -> IpAddr {
+    if !is_trusted_proxy(peer) {
+        return peer
+    }
+
+    if let Some(forwarded) = forwarded {
+        let hops: Vec<IpAddr> = forwarded
+            .split(',')
+            .filter_map(|hop| hop.trim().parse().ok())
+            .collect();
+        if let Some(client) = hops.iter().rev().find(|ip| !is_trusted_proxy(**ip)) {
+            return *client
+        }
+        if let Some(first) = hops.first() {
+            return *first
+        }
+    }
+    real_ip
+        .and_then(|ip| ip.trim().parse().ok())
+        .unwrap_or(peer)
+}
+
+struct RouteLimit {
+    pattern: String,
+    limit: u64,
+    window_secs: u64,
+}
+
+/// `RATE_LIMITS`, comma separated `pattern=requests/seconds`, patterns as `UriPatternExt` lists them.
+fn load_limits() -> Vec<RouteLimit> {
+    let raw = get_env_default("RATE_LIMITS").unwrap_or_else(|| DEFAULT_RATE_LIMITS.to_string());
+    raw.split(',')
+        .map(str::trim)
+        .filter(|s| !s.is_empty())
+        .filter_map(|entry| {
+            let parsed = entry.split_once('=').and_then(|(pattern, rule)| {
+                let (limit, window) = rule.split_once('/')?;
+                Some(RouteLimit {
+                    pattern: pattern.trim().to_string(),
+                    limit: limit.trim().parse::<u64>().ok().filter(|l| *l > 0)?,
+                    window_secs: window.trim().parse::<u64>().ok().filter(|w| *w > 0)?,
+                })
+            });
+            if parsed.is_none() {
+                tracing::warn!("Ignoring invalid RATE_LIMITS entry {entry}");
+            }
+            parsed
+        })
+        .collect()
+}
+
+static SLIDING_WINDOW: LazyLock<redis::Script> = LazyLock::new(|| redis::Script::new(r#"
+local now = tonumber(ARGV[1])
+local window = tonumber(ARGV[2])
+local limit = tonumber(ARGV[3])
+redis.call('ZREMRANGEBYSCORE', KEYS[1], '-inf', now - window)
+local count = redis.call('ZCARD', KEYS[1])
+if count < limit then
+    redis.call('ZADD', KEYS[1], now, ARGV[4])
+    redis.call('PEXPIRE', KEYS[1], window)
+    return 0
+end
+local oldest = redis.call('ZRANGE', KEYS[1], 0, 0, 'WITHSCORES')
+return math.max(1, tonumber(oldest[2]) + window - now)
+"#));
+
+/// Counts the request in the last `window_secs`. Returns the milliseconds until a slot frees up
+/// once `limit` is reached, and lets everything through when Redis is down.
+async fn check_window(app: &AppData, key: &str, limit: u64, window_secs: u64) -> Result<(), u64> {
+    let Ok(mut conn) = app.cache.redis_pool.get().await else {
+        return Ok(())
+    };
+    let now_ms = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_millis() as u64;
+    let member = format!("{now_ms}:{}", rand::random::<u32>());
+    let result: redis::RedisResult<u64> = SLIDING_WINDOW
+        .key(key)
+        .arg(now_ms)
+        .arg(window_secs * 1000)
+        .arg(limit)
+        .arg(member)
+        .invoke_async(&mut conn)
+        .await;
+    match result {
+        Ok(0) => Ok(()),
+        Ok(retry_after_ms) => Err(retry_after_ms),
+        Err(e) => {
+            tracing::warn!("Rate limiter unavailable: {e}");
+            Ok(())
+        }
+    }
+}
+
+/// Sliding-window limit per client IP on the routes in `RATE_LIMITS`. Requests carrying an API
+/// key are left to `ApiKeyGate`, and loopback clients (the precalculator) are never limited.
+pub struct RateLimiter {
+    routers: Vec<Arc<UriExtension>>,
+    limits: Arc<Vec<RouteLimit>>,
+}
+
+impl RateLimiter {
+    pub fn new(apis: Vec<Arc<UriExtension>>) -> RateLimiter {
+        RateLimiter { routers: apis, limits: Arc::new(load_limits()) }
+    }
+}
+
+impl<E: Endpoint<Output = poem::Response>> Middleware<E> for RateLimiter {
+    type Output = RateLimiterEndpoint<E>;
+
+    fn transform(&self, ep: E) -> Self::Output {
+        RateLimiterEndpoint { ep, apis: self.routers.clone(), limits: self.limits.clone() }
+    }
+}
+
+pub struct RateLimiterEndpoint<E> {
+    ep: E,
+    apis: Vec<Arc<UriExtension>>,
+    limits: Arc<Vec<RouteLimit>>,
+}
+
+impl<E> Endpoint for RateLimiterEndpoint<E>
+where
+    E: Endpoint<Output = poem::Response>,
+{
+    type Output = poem::Response;
+
+    async fn call(&self, req: Request) -> poem::Result<Self::Output> {
+        if self.limits.is_empty() || req.header(API_KEY_HEADER).is_some() {
+            return self.ep.call(req).await
+        }
+        let Some(ip) = client_ip(&req).filter(|ip| !ip.is_loopback()) else {
+            return self.ep.call(req).await
+        };
+        let Some(pattern) = route_pattern(&self.apis, req.uri().path()) else {
+            return self.ep.call(req).await
+        };
+        let Some(rule) = self.limits.iter().find(|l| l.pattern == pattern) else {
+            return self.ep.call(req).await
+        };
+        let Some(app) = req.data::<AppData>().cloned() else {
+            return self.ep.call(req).await
+        };
+
+        let key = format!("rate_limit:{pattern}:{ip}");
+        if let Err(retry_after_ms) = check_window(&app, &key, rule.limit, rule.window_secs).await {
+            let retry_after = retry_after_ms.div_ceil(1000).max(1);
+            let mut resp = "Too many requests, slow down"
+                .with_status(StatusCode::TOO_MANY_REQUESTS)
+                .into_response();
+            resp.headers_mut().insert("Retry-After", retry_after.into());
+            resp.headers_mut().insert("X-RateLimit-Limit", rule.limit.into());
+            return Ok(resp)
+        }
+        self.ep.call(req).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ip(s: &str) -> IpAddr {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn parses_cidr_ranges() {
+        let net = Cidr::parse("172.16.0.0/12").unwrap();
+        assert!(net.contains(ip("172.16.0.1")));
+        assert!(net.contains(ip("172.31.255.255")));
+        assert!(!net.contains(ip("172.32.0.1")));
+        assert!(!net.contains(ip("::1")));
+
+        let single = Cidr::parse("10.1.2.3").unwrap();
+        assert_eq!(single.prefix, 32);
+        assert!(single.contains(ip("10.1.2.3")));
+        assert!(!single.contains(ip("10.1.2.4")));
+
+        let v6 = Cidr::parse("fd00::/8").unwrap();
+        assert!(v6.contains(ip("fd12:3456::1")));
+        assert!(!v6.contains(ip("fe80::1")));
+
+        assert!(Cidr::parse("0.0.0.0/0").unwrap().contains(ip("8.8.8.8")));
+    }
+
+    #[test]
+    fn matches_ipv4_mapped_addresses() {
+        let net = Cidr::parse("192.168.0.0/16").unwrap();
+        assert!(net.contains(ip("::ffff:192.168.1.10")));
+    }
+
+    #[test]
+    fn rejects_malformed_cidr_ranges() {
+        assert!(Cidr::parse("10.0.0.0/33").is_none());
+        assert!(Cidr::parse("::/129").is_none());
+        assert!(Cidr::parse("10.0.0.0/").is_none());
+        assert!(Cidr::parse("10.0.0/8").is_none());
+        assert!(Cidr::parse("nginx").is_none());
+    }
+
+    #[test]
+    fn untrusted_peers_ignore_forwarding_headers() {
+        let peer = ip("203.0.113.7");
+        let resolved = resolve_client_ip(peer, Some("198.51.100.1"), Some("198.51.100.2"));
+        assert_eq!(resolved, peer);
+    }
+
+    #[test]
+    fn walks_forwarded_for_from_the_right() {
+        let peer = ip("172.18.0.2");
+        // A client prepending its own hop doesn't get to pick the address
+        let forwarded = "1.1.1.1, 203.0.113.7, 172.18.0.5";
+        assert_eq!(resolve_client_ip(peer, Some(forwarded), None), ip("203.0.113.7"));
+        assert_eq!(resolve_client_ip(peer, Some(" 203.0.113.7 ,garbage"), None), ip("203.0.113.7"));
+    }
+
+    #[test]
+    fn falls_back_when_every_hop_is_trusted() {
+        let peer = ip("172.18.0.2");
+        assert_eq!(resolve_client_ip(peer, Some("10.0.0.4, 172.18.0.5"), None), ip("10.0.0.4"));
+        assert_eq!(resolve_client_ip(peer, Some("garbage"), Some("203.0.113.9")), ip("203.0.113.9"));
+        assert_eq!(resolve_client_ip(peer, None, Some("203.0.113.9")), ip("203.0.113.9"));
+        assert_eq!(resolve_client_ip(peer, None, None), peer);
+    }
+}
@@ -0,0 +1,151 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use jsonwebtoken::{encode, EncodingKey, Header};
+use rand::distr::Alphanumeric;
+use rand::Rng;
+use sha2::{Digest, Sha256};
+use uuid::Uuid;
+
+use crate::AppData;
+use crate::core::api_models::Claims;
+use crate::core::utils::{get_env, get_env_default, UserToken, ISSUER};
+
+const DEFAULT_ACCESS_TTL_SECS: u64 = 15 * 60;
+const DEFAULT_REFRESH_TTL_DAYS: i64 = 30;
+const REFRESH_TOKEN_LEN: usize = 64;
+
+fn now_secs() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs()
+}
+
+/// `SESSION_ACCESS_TTL_SECS`, how long an access token lives. Revoking a session only has to
+/// be remembered this long.
+pub fn access_ttl_secs() -> u64 {
+    get_env_default("SESSION_ACCESS_TTL_SECS")
+        .and_then(|s| s.parse().ok())
+        .unwrap_or(DEFAULT_ACCESS_TTL_SECS)
+}
+
+/// `SESSION_REFRESH_TTL_DAYS`, how long a session lasts without being refreshed.
+pub fn refresh_ttl_days() -> i64 {
+    get_env_default("SESSION_REFRESH_TTL_DAYS")
+        .and_then(|s| s.parse().ok())
+        .unwrap_or(DEFAULT_REFRESH_TTL_DAYS)
+}
+
+/// Signed like the NextAuth tokens so `TokenBearer` accepts both, plus the session it belongs to.
+/// Returns the token and its expiry in unix seconds.
+pub fn issue_access_token(user_id: i64, name: &str, session_id: Uuid) -> Result<(String, u64), jsonwebtoken::Error> {
+    let now = now_secs();
+    let exp = now + access_ttl_secs();
+    let claims = Claims {
+        sub: user_id.to_string(),
+        name: name.to_string(),
+        exp: exp as usize,
+        iss: ISSUER.to_string(),
+        iat: Some(now as usize),
+        sid: Some(session_id.to_string()),
+    };
+    let token = encode(
+        &Header::default(),
+        &claims,
+        &EncodingKey::from_secret(get_env("NEXTAUTH_SECRET").as_ref()),
+    )?;
+    Ok((token, exp))
+}
+
+/// A fresh refresh token and the hash stored for it.
+pub fn new_refresh_token() -> (String, String) {
+    let token: String = rand::rng()
+        .sample_iter(&Alphanumeric)
+        .take(REFRESH_TOKEN_LEN)
+        .map(char::from)
+        .collect();
+    let hash = hash_refresh_token(&token);
+    (token, hash)
+}
+
+pub fn hash_refresh_token(token: &str) -> String {
+    hex::encode(Sha256::digest(token.as_bytes()))
+}
+
+fn denied_session_key(session_id: Uuid) -> String {
+    format!("session_denied:{session_id}")
+}
+
+fn denied_before_key(user_id: i64) -> String {
+    format!("tokens_denied_before:{user_id}")
+}
+
+/// Access tokens of the session stop working at once instead of when they expire.
+pub async fn deny_session(app: &AppData, session_id: Uuid) {
+    let Ok(mut conn) = app.cache.redis_pool.get().await else {
+        tracing::warn!("Couldn't deny session {session_id}, Redis is unavailable");
+        return
+    };
+    let result: redis::RedisResult<()> = redis::cmd("SET")
+        .arg(denied_session_key(session_id))
+        .arg(1)
+        .arg("EX")
+        .arg(access_ttl_secs())
+        .query_async(&mut conn)
+        .await;
+    if let Err(e) = result {
+        tracing::warn!("Couldn't deny session {session_id}: {e}");
+    }
+}
+
+/// Every token issued to the user before this second stops working, including ones NextAuth
+/// signed outside of any session.
+pub async fn deny_tokens_before_now(app: &AppData, user_id: i64) {
+    let Ok(mut conn) = app.cache.redis_pool.get().await else {
+        tracing::warn!("Couldn't deny tokens of {user_id}, Redis is unavailable");
+        return
+    };
+    let ttl = (refresh_ttl_days().max(1) as u64) * 24 * 60 * 60;
+    let result: redis::RedisResult<()> = redis::cmd("SET")
+        .arg(denied_before_key(user_id))
+        .arg(now_secs())
+        .arg("EX")
+        .arg(ttl)
+        .query_async(&mut conn)
+        .await;
+    if let Err(e) = result {
+        tracing::warn!("Couldn't deny tokens of {user_id}: {e}");
+    }
+}
+
+/// Whether the token was revoked before expiring. Fails open when Redis is down, like the
+/// rate limiters.
+pub async fn is_token_denied(app: &AppData, token: &UserToken) -> bool {
+    let Ok(mut conn) = app.cache.redis_pool.get().await else {
+        return false
+    };
+    let session_key = token.session_id
+        .map(denied_session_key)
+        .unwrap_or_else(|| "session_denied:none".to_string());
+    let result: redis::RedisResult<(Option<String>, Option<u64>)> = redis::cmd("MGET")
+        .arg(session_key)
+        .arg(denied_before_key(token.id))
+        .query_async(&mut conn)
+        .await;
+    match result {
+        Ok((session_denied, denied_before)) => {
+            if token.session_id.is_some() && session_denied.is_some() {
+                return true
+            }
+            match (denied_before, token.issued_at) {
+                // `iat` is in whole seconds, so the second of the revocation is let through
+                // for the token issued right after it, revoked sessions are denied by id above
+                (Some(before), Some(issued_at)) => issued_at < before,
+                // Tokens without `iat` can't be placed in time, so they go too
+                (Some(_), None) => true,
+                _ => false,
+            }
+        }
+        Err(e) => {
+            tracing::warn!("Couldn't check the token denylist: {e}");
+            false
+        }
+    }
+}
@@ -13,6 +13,7 @@ use crate::FastCache;
 use crate::core::model::*;
 use crate::core::utils::*;
 use crate::core::push_service::{PushNotificationService, NotificationType};
+use crate::core::uploads::{UploadStore, UPLOAD_DIR_PREFIX};
 
 struct Updater{
     client: Client,
@@ -607,7 +608,8 @@ async fn send_map_change_notifications(
 }
 
 /// Cleanup stale upload sessions and temporary directories
-pub async fn cleanup_stale_uploads(store_upload: String) {
+pub async fn cleanup_stale_uploads(store_upload: String, redis_pool: deadpool_redis::Pool) {
+    let uploads = UploadStore::new(redis_pool, &store_upload);
     let mut interval = tokio::time::interval(Duration::from_secs(3600)); // Run every hour
     loop {
         interval.tick().await;
@@ -630,6 +632,16 @@ pub async fn cleanup_stale_uploads(store_upload: String) {
                 continue;
             }
 
+            // Resumable uploads expire together with their Redis state
+            let dir_name = entry.file_name().to_string_lossy().to_string();
+            if let Some(upload_id) = dir_name.strip_prefix(UPLOAD_DIR_PREFIX) {
+                if !uploads.exists(upload_id).await {
+                    uploads.delete(upload_id).await;
+                    tracing::info!("Cleaned up expired upload: {}", upload_id);
+                }
+                continue;
+            }
+
             // Get directory metadata
             let Ok(metadata) = tokio::fs::metadata(&path).await else {
                 continue;
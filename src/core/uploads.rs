@@ -0,0 +1,291 @@
+use std::io::SeekFrom;
+use std::path::{Path, PathBuf};
+
+use chrono::{DateTime, Utc};
+use deadpool_redis::Pool;
+use redis::AsyncCommands;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncSeekExt, AsyncWriteExt};
+
+use crate::core::utils::{acquire_redis_lock, get_env_default, release_redis_lock};
+
+pub const TUS_VERSION: &str = "1.0.0";
+pub const TUS_EXTENSIONS: &str = "creation,expiration,checksum,termination";
+pub const TUS_CHECKSUM_ALGORITHMS: &str = "sha256";
+pub const MAX_UPLOAD_SIZE: u64 = 500 * 1024 * 1024;
+/// Uploads that are not finished within this window expire, `cleanup_stale_uploads` drops their data.
+pub const UPLOAD_TTL_SECS: u64 = 24 * 3600;
+/// Upload directories under `{STORE_UPLOAD}/.tmp` start with this, followed by the upload id.
+pub const UPLOAD_DIR_PREFIX: &str = "tus-";
+const LOCK_TTL_SECS: i64 = 300;
+
+/// What a finished upload turns into.
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum UploadTarget {
+    MapModel { map_name: String, res_type: String },
+    CharacterModel { server_id: String, model_id: String, name: Option<String> },
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct TusUpload {
+    pub id: String,
+    pub target: UploadTarget,
+    pub credit: Option<String>,
+    pub length: u64,
+    pub uploaded_by: i64,
+    pub created_at: DateTime<Utc>,
+    pub expires_at: DateTime<Utc>,
+}
+
+pub enum UploadError {
+    NotFound,
+    /// Client offset does not match, carries the current one.
+    OffsetMismatch(u64),
+    /// Another PATCH is being written for this upload.
+    Locked,
+    ChecksumMismatch,
+    TooLarge,
+    BadRequest(String),
+    Internal(String),
+}
+
+/// Raised by the model specific handlers once all bytes are in.
+pub enum FinishError {
+    /// The file itself is not acceptable, e.g. not a valid GLB.
+    Rejected(String),
+    Failed(String),
+}
+
+pub struct UploadStore {
+    redis: Pool,
+    root: PathBuf,
+}
+
+impl UploadStore {
+    pub fn new(redis: Pool, store_upload: &str) -> Self {
+        Self { redis, root: Path::new(store_upload).join(".tmp") }
+    }
+
+    pub fn from_env(redis: Pool) -> Self {
+        let store_upload = get_env_default("STORE_UPLOAD").unwrap_or_else(|| "./maps".to_string());
+        Self::new(redis, &store_upload)
+    }
+
+    fn key(id: &str) -> String {
+        format!("tus_upload:{id}")
+    }
+
+    fn offset_key(id: &str) -> String {
+        format!("tus_upload:{id}:offset")
+    }
+
+    pub fn data_path(&self, id: &str) -> PathBuf {
+        self.root.join(format!("{UPLOAD_DIR_PREFIX}{id}")).join("data")
+    }
+
+    pub async fn create(
+        &self, target: UploadTarget, credit: Option<String>, length: u64, uploaded_by: i64,
+    ) -> Result<TusUpload, UploadError> {
+        if length > MAX_UPLOAD_SIZE {
+            return Err(UploadError::TooLarge)
+        }
+        let now = Utc::now();
+        let upload = TusUpload {
+            id: uuid::Uuid::new_v4().to_string(),
+            target,
+            credit,
+            length,
+            uploaded_by,
+            created_at: now,
+            expires_at: now + chrono::Duration::seconds(UPLOAD_TTL_SECS as i64),
+        };
+
+        let path = self.data_path(&upload.id);
+        if let Some(parent) = path.parent() {
+            tokio::fs::create_dir_all(parent)
+                .await
+                .map_err(|e| UploadError::Internal(format!("Failed to create upload directory: {e}")))?;
+        }
+        tokio::fs::File::create(&path)
+            .await
+            .map_err(|e| UploadError::Internal(format!("Failed to create upload file: {e}")))?;
+
+        let json = serde_json::to_string(&upload)
+            .map_err(|e| UploadError::Internal(format!("Failed to serialize upload: {e}")))?;
+        let mut conn = self.redis.get()
+            .await
+            .map_err(|e| UploadError::Internal(format!("Failed to get Redis connection: {e}")))?;
+        let _: () = redis::pipe()
+            .atomic()
+            .set_ex(Self::key(&upload.id), json, UPLOAD_TTL_SECS)
+            .set_ex(Self::offset_key(&upload.id), 0u64, UPLOAD_TTL_SECS)
+            .query_async(&mut conn)
+            .await
+            .map_err(|e| UploadError::Internal(format!("Failed to store upload: {e}")))?;
+
+        Ok(upload)
+    }
+
+    /// Returns the upload and how many bytes of it we have.
+    pub async fn get(&self, id: &str) -> Result<(TusUpload, u64), UploadError> {
+        let mut conn = self.redis.get()
+            .await
+            .map_err(|e| UploadError::Internal(format!("Failed to get Redis connection: {e}")))?;
+        let (json, offset): (Option<String>, Option<u64>) = conn
+            .mget((Self::key(id), Self::offset_key(id)))
+            .await
+            .map_err(|e| UploadError::Internal(format!("Failed to read upload: {e}")))?;
+        let (Some(json), Some(offset)) = (json, offset) else {
+            return Err(UploadError::NotFound)
+        };
+        let upload = serde_json::from_str(&json)
+            .map_err(|e| UploadError::Internal(format!("Failed to parse upload: {e}")))?;
+        Ok((upload, offset))
+    }
+
+    /// Writes a PATCH body at `client_offset`. Concurrent PATCHes on the same upload are refused
+    /// instead of interleaved, and the stored offset only moves once the bytes are on disk.
+    pub async fn append<R: AsyncRead + Unpin>(
+        &self, id: &str, client_offset: u64, checksum: Option<&str>, body: R,
+    ) -> Result<(TusUpload, u64), UploadError> {
+        let expected = checksum.map(parse_checksum).transpose()?;
+        let lock = self.lock(id).await?;
+        let result = self.append_locked(id, client_offset, expected, body).await;
+        self.unlock(id, &lock).await;
+        result
+    }
+
+    fn lock_key(id: &str) -> String {
+        format!("tus_upload:{id}:lock")
+    }
+
+    /// Takes the lock PATCHes write under, storing the finished model holds it as well.
+    pub async fn lock(&self, id: &str) -> Result<String, UploadError> {
+        acquire_redis_lock(&self.redis, &Self::lock_key(id), LOCK_TTL_SECS, 1)
+            .await
+            .ok_or(UploadError::Locked)
+    }
+
+    pub async fn unlock(&self, id: &str, lock: &str) {
+        release_redis_lock(&self.redis, &Self::lock_key(id), lock).await;
+    }
+
+    async fn append_locked<R: AsyncRead + Unpin>(
+        &self, id: &str, client_offset: u64, expected: Option<Vec<u8>>, mut body: R,
+    ) -> Result<(TusUpload, u64), UploadError> {
+        let (upload, offset) = self.get(id).await?;
+        if offset != client_offset {
+            return Err(UploadError::OffsetMismatch(offset))
+        }
+
+        let io_error = |e: std::io::Error| UploadError::Internal(format!("Failed to write upload: {e}"));
+        let mut file = tokio::fs::OpenOptions::new()
+            .write(true)
+            .open(self.data_path(id))
+            .await
+            .map_err(io_error)?;
+        file.seek(SeekFrom::Start(offset)).await.map_err(io_error)?;
+
+        let remaining = upload.length - offset;
+        let mut hasher = Sha256::new();
+        let mut buffer = vec![0u8; 1024 * 1024];
+        let mut written: u64 = 0;
+        loop {
+            let read = match body.read(&mut buffer).await {
+                Ok(read) => read,
+                Err(e) => {
+                    let _ = file.set_len(offset).await;
+                    return Err(UploadError::BadRequest(format!("Failed to read request body: {e}")))
+                }
+            };
+            if read == 0 {
+                break
+            }
+            written += read as u64;
+            if written > remaining {
+                let _ = file.set_len(offset).await;
+                return Err(UploadError::TooLarge)
+            }
+            hasher.update(&buffer[..read]);
+            file.write_all(&buffer[..read]).await.map_err(io_error)?;
+        }
+
+        if let Some(expected) = expected {
+            if hasher.finalize().as_slice() != expected.as_slice() {
+                let _ = file.set_len(offset).await;
+                return Err(UploadError::ChecksumMismatch)
+            }
+        }
+
+        let new_offset = offset + written;
+        // Drops bytes left behind by a PATCH that was written but never acknowledged.
+        file.set_len(new_offset).await.map_err(io_error)?;
+        file.flush().await.map_err(io_error)?;
+
+        let mut conn = self.redis.get()
+            .await
+            .map_err(|e| UploadError::Internal(format!("Failed to get Redis connection: {e}")))?;
+        let _: () = redis::cmd("SET")
+            .arg(Self::offset_key(id))
+            .arg(new_offset)
+            .arg("KEEPTTL")
+            .query_async(&mut conn)
+            .await
+            .map_err(|e| UploadError::Internal(format!("Failed to update offset: {e}")))?;
+
+        Ok((upload, new_offset))
+    }
+
+    pub async fn delete(&self, id: &str) {
+        if let Ok(mut conn) = self.redis.get().await {
+            let _: redis::RedisResult<()> = conn.del((Self::key(id), Self::offset_key(id))).await;
+        }
+        if let Some(dir) = self.data_path(id).parent() {
+            let _ = tokio::fs::remove_dir_all(dir).await;
+        }
+    }
+
+    /// Whether the upload that owns `{UPLOAD_DIR_PREFIX}{id}` is still alive.
+    pub async fn exists(&self, id: &str) -> bool {
+        let Ok(mut conn) = self.redis.get().await else {
+            return true
+        };
+        conn.exists(Self::key(id)).await.unwrap_or(true)
+    }
+}
+
+/// `Upload-Checksum: sha256 <base64 digest>`
+fn parse_checksum(header: &str) -> Result<Vec<u8>, UploadError> {
+    use base64::Engine;
+    let Some((algorithm, digest)) = header.trim().split_once(' ') else {
+        return Err(UploadError::BadRequest("Malformed Upload-Checksum".to_string()))
+    };
+    if !algorithm.eq_ignore_ascii_case("sha256") {
+        return Err(UploadError::BadRequest(format!("Unsupported checksum algorithm {algorithm}")))
+    }
+    base64::engine::general_purpose::STANDARD
+        .decode(digest.trim())
+        .map_err(|_| UploadError::BadRequest("Malformed Upload-Checksum".to_string()))
+}
+
+/// `Upload-Metadata: key base64,key2 base64`
+pub fn parse_metadata(header: &str) -> std::collections::HashMap<String, String> {
+    use base64::Engine;
+    header
+        .split(',')
+        .filter_map(|pair| {
+            let mut parts = pair.trim().splitn(2, ' ');
+            let key = parts.next().filter(|k| !k.is_empty())?.to_string();
+            let value = match parts.next() {
+                Some(encoded) => {
+                    let bytes = base64::engine::general_purpose::STANDARD.decode(encoded.trim()).ok()?;
+                    String::from_utf8(bytes).ok()?
+                }
+                None => String::new(),
+            };
+            Some((key, value))
+        })
+        .collect()
+}
@@ -26,6 +26,8 @@ use crate::{response, FastCache, AppData};
 use crate::core::model::*;
 use crate::core::api_models::*;
 use crate::core::workers::*;
+use crate::core::sessions::is_token_denied;
+use crate::core::anonymization::Anonymizer;
 
 pub const DAY: u64 = 24 * 60 * 60;
 pub fn get_env(name: &str) -> String{
@@ -35,8 +37,9 @@ pub const ISSUER: &str = "ze-graph";
 
 pub struct UserToken{
     pub id: i64,
-    #[allow(dead_code)]
     pub global_name: String,
+    pub session_id: Option<Uuid>,
+    pub issued_at: Option<u64>,
 }
 
 fn parse_user_from_token(token: &str) -> Option<UserToken> {
@@ -51,16 +54,35 @@ fn parse_user_from_token(token: &str) -> Option<UserToken> {
         let Ok(id) = token_data.claims.sub.parse::<i64>() else {
             return None
         };
-        let token = UserToken { id, global_name: token_data.claims.name };
+        let token = UserToken {
+            id,
+            global_name: token_data.claims.name,
+            session_id: token_data.claims.sid.and_then(|sid| sid.parse().ok()),
+            issued_at: token_data.claims.iat.map(|iat| iat as u64),
+        };
         return Some(token)
     }
     None
 }
 
 pub struct TokenBearer(pub UserToken);
+/// Drops tokens on the session denylist, which needs Redis so only the async extractors check it.
+async fn reject_denied(req: &Request, user_token: UserToken) -> Option<UserToken> {
+    let Some(data) = req.data::<AppData>() else {
+        return Some(user_token)
+    };
+    if is_token_denied(data, &user_token).await {
+        return None
+    }
+    Some(user_token)
+}
+
 impl<'a> FromRequest<'a> for TokenBearer {
     async fn from_request(req: &'a Request, _body: &mut poem::RequestBody) -> poem::Result<Self> {
-        <Self as BearerAuthorization>::from_request(req)
+        let Self(user_token) = <Self as BearerAuthorization>::from_request(req)?;
+        let user_token = reject_denied(req, user_token).await
+            .ok_or_else(|| poem::Error::from_string("Token revoked", StatusCode::UNAUTHORIZED))?;
+        Ok(Self(user_token))
     }
 }
 
@@ -77,7 +99,11 @@ pub struct OptionalTokenBearer(pub Option<UserToken>);
 
 impl<'a> FromRequest<'a> for OptionalTokenBearer {
     async fn from_request(req: &'a Request, _body: &mut poem::RequestBody) -> poem::Result<Self> {
-        <Self as BearerAuthorization>::from_request(req)
+        let Self(user_token) = <Self as BearerAuthorization>::from_request(req)?;
+        let Some(user_token) = user_token else {
+            return Ok(Self(None))
+        };
+        Ok(Self(reject_denied(req, user_token).await))
     }
 }
 
@@ -94,114 +120,11 @@ impl BearerAuthorization for OptionalTokenBearer {
     }
 }
 
-async fn check_player_anonymization_internal(
-    data: &AppData,
-    player_id: &str,
-    server_id: &str,
-    user_token: &UserToken,
-) -> Result<bool, StatusCode> {
-    if user_token.id.to_string() == player_id{
-        return Ok(true)
-    }
-    struct ServerCommunity {
-        community_id: Option<Uuid>,
-    }
-
-    let server_community = sqlx::query_as!(
-        ServerCommunity,
-        "SELECT community_id FROM server WHERE server_id = $1",
-        server_id
-    )
-    .fetch_optional(&*data.pool)
-    .await
-    .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
-
-    let Some(server_comm) = server_community else {
-        return Ok(false);
-    };
-
-    let Some(community_id) = server_comm.community_id else {
-        return Ok(false);
-    };
-
-    struct AnonymizationCheck {
-        anonymized: bool,
-    }
-
-    let player_id_i64 = match player_id.parse::<i64>() {
-        Ok(id) => id,
-        Err(_) => {
-            // If player_id is not a valid i64 (Steam ID), no anonymization applies
-            return Ok(false);
-        }
-    };
-
-    let anonymization = sqlx::query_as!(
-        AnonymizationCheck,
-        "SELECT anonymized FROM website.user_anonymization
-         WHERE user_id = $1 AND community_id = $2",
-        player_id_i64,
-        community_id
-    )
-    .fetch_optional(&*data.pool)
-    .await
-    .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
-
-    let Some(anon) = anonymization else {
-        return Ok(false);
-    };
-
-    if !anon.anonymized {
-        return Ok(false);
-    }
-
-    let is_superuser = sqlx::query_scalar!(
-        "SELECT website.is_superuser($1)",
-        user_token.id
-    )
-    .fetch_optional(&*data.pool)
-    .await
-    .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
-
-    if is_superuser == Some(Some(true)) {
-        return Ok(true);
-    }
-
-    let is_admin = sqlx::query_scalar!(
-        "SELECT website.is_community_admin($1, $2)",
-        user_token.id,
-        community_id
-    )
-    .fetch_optional(&*data.pool)
-    .await
-    .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
-
-    if is_admin == Some(Some(true)) {
-        return Ok(true);
-    }
-    // TODO: Why is this returning forbidden i dont rember.
-    Err(StatusCode::FORBIDDEN)
-}
-
-pub async fn check_superuser(app: &AppData, user_id: i64) -> bool{
-    let Ok(is_superuser) = sqlx::query_scalar!(
-        "SELECT website.is_superuser($1)",
-        user_id
-    )
-        .fetch_optional(&*app.pool)
-        .await else {
-        return false
-    };
-
-    is_superuser == Some(Some(true))
-}
-
-#[allow(dead_code)]
-pub struct UserTokenAuthorized{
-    user_token: UserToken,
-    authorized: bool,
-}
-pub struct OptionalAnonymousTokenBearer(pub Option<UserTokenAuthorized>);
+/// Viewer of a `{server_id}/{player_id}` route. When the player is anonymized in the server's
+/// community and the viewer can't see through it, the route answers 404 as if the player didn't
+/// exist. Routes without a `{server_id}` check every community instead. Carries the
+/// [`Anonymizer`] for any other players in the response.
+pub struct OptionalAnonymousTokenBearer(pub Anonymizer);
 
 impl<'a> FromRequest<'a> for OptionalAnonymousTokenBearer {
     async fn from_request(req: &'a Request, _body: &mut poem::RequestBody) -> poem::Result<Self> {
@@ -210,23 +133,30 @@ impl<'a> FromRequest<'a> for OptionalAnonymousTokenBearer {
         let player_id = req.raw_path_param("player_id")
             .ok_or_else(|| poem::Error::from_string("Missing player_id", StatusCode::BAD_REQUEST))?;
 
-        let server_id = req.raw_path_param("server_id")
-            .ok_or_else(|| poem::Error::from_string("Missing server_id", StatusCode::BAD_REQUEST))?;
+        let server_id = req.raw_path_param("server_id");
 
         let data: &AppData = req.data()
             .ok_or_else(|| poem::Error::from_string("Missing AppData", StatusCode::INTERNAL_SERVER_ERROR))?;
 
-        let Some(user_token) = auth.and_then(|bearer| parse_user_from_token(&bearer.token)) else {
-            return Ok(Self(None));
+        let user_token = match auth.and_then(|bearer| parse_user_from_token(&bearer.token)) {
+            Some(user_token) => reject_denied(req, user_token).await,
+            None => None,
         };
 
-        // Explicitly only true if we know its user == player_id, or user == superuser, or user == community admin
-        let authorized = check_player_anonymization_internal(data, player_id, server_id, &user_token)
-            .await
-            .map_err(|status| poem::Error::from_string("Access forbidden", status))?;
-
+        let anonymizer = match server_id {
+            Some(server_id) => Anonymizer::for_server(data, server_id, user_token.as_ref()).await,
+            None => Anonymizer::everywhere(data, user_token.as_ref()).await,
+        };
+        let anonymizer = anonymizer
+            .map_err(|e| {
+                tracing::error!("Failed to load anonymization of {}: {e}", server_id.unwrap_or("every community"));
+                poem::Error::from_string("Internal server error", StatusCode::INTERNAL_SERVER_ERROR)
+            })?;
+        if anonymizer.privacy(player_id).anonymized {
+            return Err(poem::Error::from_string("Player not found", StatusCode::NOT_FOUND))
+        }
 
-        Ok(Self(Some(UserTokenAuthorized { user_token, authorized })))
+        Ok(Self(anonymizer))
     }
 }
 pub fn get_env_default(name: &str) -> Option<String>{
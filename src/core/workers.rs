@@ -8,7 +8,7 @@ use serde::{Deserialize, Serialize};
 use sqlx::{Pool, Postgres};
 use tokio::sync::{RwLock, Semaphore};
 use async_trait::async_trait;
-use chrono::{DateTime, Utc};
+use chrono::{DateTime, Datelike, NaiveDate, Utc};
 use sqlx::postgres::PgQueryResult;
 use sqlx::postgres::types::PgInterval;
 use crate::core::model::*;
@@ -755,7 +755,10 @@ impl WorkerQuery<Option<DbMapMeta>> for MapBasicQuery<Option<DbMapMeta>> {
     type Error = sqlx::Error;
     async fn execute(&self) -> Result<Option<DbMapMeta>, Self::Error> {
         let ctx = &self.context;
-        sqlx::query_as!(DbMapMeta, "SELECT * FROM map_metadata WHERE name=$1 LIMIT 1", ctx.data.map_name)
+        sqlx::query_as!(DbMapMeta, "
+            SELECT name, image_url, creators, workshop_id, file_bytes, is_tryhard, is_casual, has_lasers, resolved_workshop_id
+            FROM map_metadata WHERE name=$1 LIMIT 1
+        ", ctx.data.map_name)
             .fetch_optional(&*ctx.pool).await
     }
 
@@ -1240,15 +1243,19 @@ impl WorkerQuery<Option<DbPlayerRank>> for PlayerBasicQuery<Option<DbPlayerRank>
 impl WorkerQuery<Vec<DbMapRank>> for PlayerBasicQuery<Vec<DbMapRank>> {
     type Error = sqlx::Error;
     async fn execute(&self) -> Result<Vec<DbMapRank>, Self::Error> {
+        // Ranked live off idx_player_map_time_rank so a finished session moves the rank
+        // as soon as it's accounted for, the {session} cache key drops the old result.
         sqlx::query_as!(DbMapRank, "
-            SELECT pmr.map, pmr.map_rank AS rank, pmt.total_playtime
-            FROM website.player_map_rank pmr
-            JOIN website.player_map_time pmt 
-                ON pmt.player_id = pmr.player_id 
-                    AND pmt.map = pmr.map
-                    AND pmt.server_id = pmr.server_id
-            WHERE pmr.server_id=$1 AND pmr.player_id=$2
-            ORDER BY pmr.map_rank, pmt.total_playtime DESC
+            SELECT pmt.map, (
+                    SELECT COUNT(*) + 1
+                    FROM website.player_map_time other
+                    WHERE other.server_id = pmt.server_id
+                        AND other.map = pmt.map
+                        AND other.total_playtime > pmt.total_playtime
+                ) AS rank, pmt.total_playtime
+            FROM website.player_map_time pmt
+            WHERE pmt.server_id=$1 AND pmt.player_id=$2
+            ORDER BY rank, pmt.total_playtime DESC
         ", self.context.data.server_id, self.context.data.player_id)
             .fetch_all(&*self.context.pool.clone()).await
     }
@@ -1792,6 +1799,29 @@ impl PlayerWorker {
         detail.aliases = aliases_filtered.iter_into();
         Ok(detail)
     }
+    /// Cache version for calendar data, changes whenever any worker finishes accounting a new session for the player.
+    async fn get_player_worker_version(&self, player_id: &str) -> WorkResult<String> {
+        let version = sqlx::query_scalar!("
+            SELECT md5(string_agg(server_id || ':' || type || ':' || last_calculated::TEXT, ',' ORDER BY server_id, type))
+            FROM website.player_server_worker
+            WHERE player_id=$1
+        ", player_id).fetch_one(&*self.pool).await?;
+        Ok(version.unwrap_or(String::from("first-time")))
+    }
+    pub async fn get_calendar(&self, player_id: &str, server_id: Option<&str>, year: i32) -> WorkResult<PlayerCalendar> {
+        let version = self.get_player_worker_version(player_id).await?;
+        let key = format!("player-calendar:{}:{}:{}:{}", player_id, server_id.unwrap_or("all"), year, version);
+        let pool = self.pool.clone();
+        let player_id = player_id.to_string();
+        let server_id = server_id.map(String::from);
+        let result = self.background_worker.execute(&key, 60 * 60, move || {
+            let pool = pool.clone();
+            let player_id = player_id.clone();
+            let server_id = server_id.clone();
+            async move { calculate_player_calendar(&pool, &player_id, server_id.as_deref(), year).await }
+        }).await?;
+        Ok(result.result)
+    }
     pub async fn get_hour_of_day(&self, context: &PlayerContext) -> WorkResult<Vec<PlayerHourDay>> {
         let result: Vec<DbPlayerHourCount> = self.query_player(context).await?;
 
@@ -1805,6 +1835,117 @@ impl PlayerWorker {
     }
 }
 
+fn to_naive_date(date: sqlx::types::time::Date) -> Option<NaiveDate> {
+    NaiveDate::from_yo_opt(date.year(), date.ordinal() as u32)
+}
+
+async fn calculate_player_calendar(
+    pool: &Pool<Postgres>, player_id: &str, server_id: Option<&str>, year: i32
+) -> Result<PlayerCalendar, sqlx::Error> {
+    let daily = sqlx::query_as!(DbPlayerCalendarDay, "
+        WITH bounds AS (
+            SELECT
+                make_date($3, 1, 1)::TIMESTAMP AT TIME ZONE 'UTC' AS year_start,
+                make_date($3 + 1, 1, 1)::TIMESTAMP AT TIME ZONE 'UTC' AS year_end
+        ),
+        sessions AS (
+            SELECT
+                started_at,
+                COALESCE(ended_at, LEAST(CURRENT_TIMESTAMP, started_at + INTERVAL '12 hours')) AS ended_at
+            FROM player_server_session
+            WHERE player_id = $1
+                AND ($2::TEXT IS NULL OR server_id = $2)
+                AND started_at < (SELECT year_end FROM bounds)
+                AND COALESCE(ended_at, CURRENT_TIMESTAMP) >= (SELECT year_start FROM bounds)
+        ),
+        days AS (
+            SELECT generate_series(
+                (SELECT year_start FROM bounds),
+                (SELECT year_end FROM bounds) - INTERVAL '1 day',
+                INTERVAL '1 day'
+            ) AS day
+        )
+        SELECT
+            (d.day AT TIME ZONE 'UTC')::DATE AS day,
+            SUM(LEAST(s.ended_at, d.day + INTERVAL '1 day') - GREATEST(s.started_at, d.day)) AS played
+        FROM days d
+        JOIN sessions s
+            ON s.started_at < d.day + INTERVAL '1 day'
+            AND s.ended_at > d.day
+        GROUP BY d.day
+        ORDER BY d.day
+    ", player_id, server_id, year).fetch_all(pool).await?;
+
+    let played_days = sqlx::query_as!(DbPlayedDay, "
+        SELECT DISTINCT (started_at AT TIME ZONE 'UTC')::DATE AS day
+        FROM player_server_session
+        WHERE player_id = $1 AND ($2::TEXT IS NULL OR server_id = $2)
+        ORDER BY day
+    ", player_id, server_id).fetch_all(pool).await?;
+
+    let played_days: Vec<NaiveDate> = played_days.into_iter()
+        .filter_map(|e| e.day.and_then(to_naive_date))
+        .collect();
+    let mut longest_streak = 0;
+    let mut running = 0;
+    let mut previous: Option<NaiveDate> = None;
+    for day in played_days.iter() {
+        running = match previous {
+            Some(prev) if prev.succ_opt() == Some(*day) => running + 1,
+            _ => 1,
+        };
+        longest_streak = longest_streak.max(running);
+        previous = Some(*day);
+    }
+    // A streak is still alive if the player hasn't missed today yet.
+    let today = Utc::now().date_naive();
+    let current_streak = match previous {
+        Some(last) if last == today || last.succ_opt() == Some(today) => running,
+        _ => 0,
+    };
+
+    let mut hours_by_day: HashMap<NaiveDate, f64> = HashMap::new();
+    let mut days = vec![];
+    for row in daily {
+        let Some(date) = row.day.and_then(to_naive_date) else {
+            continue
+        };
+        let hours = row.played.map(pg_interval_to_f64).unwrap_or(0.) / 3600.;
+        hours_by_day.insert(date, hours);
+        days.push(PlayerCalendarDay { date: date.to_string(), hours });
+    }
+
+    let mut weekday_totals = [0f64; 7];
+    let mut weekday_counts = [0u32; 7];
+    if let (Some(start), Some(end)) = (NaiveDate::from_ymd_opt(year, 1, 1), NaiveDate::from_ymd_opt(year, 12, 31)) {
+        let end = end.min(today);
+        let mut date = start;
+        while date <= end {
+            let weekday = date.weekday().num_days_from_monday() as usize;
+            weekday_totals[weekday] += hours_by_day.get(&date).copied().unwrap_or(0.);
+            weekday_counts[weekday] += 1;
+            let Some(next) = date.succ_opt() else {
+                break
+            };
+            date = next;
+        }
+    }
+    let weekday_averages = (0..7)
+        .map(|i| PlayerWeekdayAverage {
+            weekday: i as u8,
+            average_hours: if weekday_counts[i] == 0 { 0. } else { weekday_totals[i] / weekday_counts[i] as f64 },
+        })
+        .collect();
+
+    Ok(PlayerCalendar {
+        year,
+        days,
+        current_streak,
+        longest_streak,
+        weekday_averages,
+    })
+}
+
 pub struct MapWorker {
     background_worker: Arc<BackgroundWorker>,
     pool: Arc<Pool<Postgres>>,
@@ -1,5 +1,5 @@
 use poem::middleware::Cors;
-use poem::{listener::TcpListener, EndpointExt, Route, Server};
+use poem::{listener::TcpListener, Endpoint, EndpointExt, Route, Server};
 use poem_openapi::OpenApiService;
 mod routers;
 mod global_serializer;
@@ -32,13 +32,29 @@ use moka::future::Cache;
 use crate::core::utils::*;
 use crate::core::workers::*;
 use crate::core::push_service::*;
-use crate::core::map_storage::{MapStorage, CharacterStorage};
+use crate::core::asset_storage::{MapStorage, CharacterStorage, ExportStorage};
+use crate::core::model_processing::run_model_jobs;
+use crate::core::data_export::run_data_exports;
+use crate::core::account_deletion::run_account_deletions;
+use crate::core::achievements::run_achievement_evaluations;
+use crate::core::discord::run_discord_role_syncs;
+use crate::core::markdown::run_markdown_renders;
+use crate::core::api_keys::ApiKeyGate;
+use crate::core::rate_limit::RateLimiter;
 use crate::routers::accounts::AccountsApi;
 use crate::routers::characters::CharacterApi;
 use crate::routers::servers::ServerApi;
 use crate::routers::donations::DonationsApi;
 use crate::routers::admin_maps::AdminMapsApi;
 use crate::routers::admin_servers::AdminServersApi;
+use crate::routers::admin_audit::AdminAuditApi;
+use crate::routers::admin_roles::AdminRolesApi;
+use crate::routers::uploads::UploadsApi;
+use crate::routers::api_keys::ApiKeysApi;
+use crate::routers::discord::DiscordApi;
+use crate::routers::data_export::DataExportApi;
+use crate::routers::account_deletion::AccountDeletionApi;
+use crate::routers::search::SearchApi;
 
 #[derive(Clone)]
 struct AppData{
@@ -50,6 +66,7 @@ struct AppData{
     push_service: Arc<PushNotificationService>,
     map_storage: Arc<MapStorage>,
     character_storage: Arc<CharacterStorage>,
+    export_storage: Arc<ExportStorage>,
 }
 #[derive(Clone)]
 struct FastCache{
@@ -64,26 +81,89 @@ fn make_redis_pool() -> deadpool_redis::Pool {
         .expect("Failed to create pool")
 }
 
-async fn run_main() {
-    let environment = get_env_default("ENVIRONMENT").unwrap_or(String::from("DEVELOPMENT"));
-    let pre_calculate = get_env_bool("PRECALCULATE", false);
-    let tracing_filter = EnvFilter::default()
-        .add_directive(LevelFilter::INFO.into());
+type Apis = (
+    ServerApi,
+    PlayerApi,
+    GraphApi,
+    MapApi,
+    RadarApi,
+    MiscApi,
+    AccountsApi,
+    CharacterApi,
+    DonationsApi,
+    AdminMapsApi,
+    AdminServersApi,
+    AdminAuditApi,
+    AdminRolesApi,
+    UploadsApi,
+    ApiKeysApi,
+    DiscordApi,
+    DataExportApi,
+    AccountDeletionApi,
+    SearchApi,
+);
 
-    tracing_subscriber::registry()
-        .with(tracing_subscriber::fmt::layer())
-        .with(tracing_filter)
-        .init();
-    tracing::info!("ENVIRONMENT: {environment}");
-    let pg_conn = get_env("DATABASE_URL");
-    let pool = PgPoolOptions::new()
-        .max_connections(20)
-        .min_connections(5)
-        .acquire_timeout(Duration::from_secs(30))
-        .idle_timeout(Duration::from_secs(300))
-        .connect(&pg_conn).await
-        .expect("Couldn't load postgresql connection!");
+fn apis() -> Apis {
+    (
+        ServerApi,
+        PlayerApi,
+        GraphApi,
+        MapApi,
+        RadarApi,
+        MiscApi,
+        AccountsApi,
+        CharacterApi,
+        DonationsApi,
+        AdminMapsApi,
+        AdminServersApi,
+        AdminAuditApi,
+        AdminRolesApi,
+        UploadsApi,
+        ApiKeysApi,
+        DiscordApi,
+        DataExportApi,
+        AccountDeletionApi,
+        SearchApi,
+    )
+}
+
+// For logging endpoints, because poem dev rly makes it hard for me
+fn registered_apis() -> Vec<Arc<UriExtension>> {
+    vec![
+        Arc::new(MapApi),
+        Arc::new(ServerApi),
+        Arc::new(PlayerApi),
+        Arc::new(GraphApi),
+        Arc::new(RadarApi),
+        Arc::new(MiscApi),
+        Arc::new(AccountsApi),
+        Arc::new(CharacterApi),
+        Arc::new(DonationsApi),
+        Arc::new(AdminMapsApi),
+        Arc::new(AdminServersApi),
+        Arc::new(AdminAuditApi),
+        Arc::new(AdminRolesApi),
+        Arc::new(UploadsApi),
+        Arc::new(ApiKeysApi),
+        Arc::new(DiscordApi),
+        Arc::new(DataExportApi),
+        Arc::new(AccountDeletionApi),
+        Arc::new(SearchApi),
+    ]
+}
+
+fn with_middleware(route: Route, data: AppData) -> impl Endpoint {
+    let registered = registered_apis();
+    route
+        .with(ApiKeyGate::new(registered.clone()))
+        .with(RateLimiter::new(registered.clone()))
+        .with(Cors::new()) // 600MB limit for large file uploads
+        .with(PatternLogger::new(registered))
+        .with(CookieSession::new(CookieConfig::default()))
+        .data(data)
+}
 
+async fn app_data(pool: PgPool) -> AppData {
     let memory = Arc::new(Cache::builder()
         .time_to_live(Duration::from_secs(60))
         .max_capacity(10_000)
@@ -101,8 +181,6 @@ async fn run_main() {
             .expect("Failed to initialize push notification service")
     );
 
-    init_map_change_listener(pool.clone(), push_service.clone()).await;
-
     let map_storage = Arc::new(
         MapStorage::from_env()
             .await
@@ -115,7 +193,13 @@ async fn run_main() {
             .expect("Failed to initialize character storage")
     );
 
-    let data = AppData {
+    let export_storage = Arc::new(
+        ExportStorage::from_env()
+            .await
+            .expect("Failed to initialize export storage")
+    );
+
+    AppData {
         pool,
         steam_provider: Some("http://pfp-provider:3000/api".to_string()),
         cache,
@@ -124,37 +208,61 @@ async fn run_main() {
         push_service,
         map_storage,
         character_storage,
-    };
+        export_storage,
+    }
+}
+
+async fn run_main() {
+    let environment = get_env_default("ENVIRONMENT").unwrap_or(String::from("DEVELOPMENT"));
+    let pre_calculate = get_env_bool("PRECALCULATE", false);
+    let tracing_filter = EnvFilter::default()
+        .add_directive(LevelFilter::INFO.into());
+
+    tracing_subscriber::registry()
+        .with(tracing_subscriber::fmt::layer())
+        .with(tracing_filter)
+        .init();
+    tracing::info!("ENVIRONMENT: {environment}");
+    let pg_conn = get_env("DATABASE_URL");
+    let pool = PgPoolOptions::new()
+        .max_connections(20)
+        .min_connections(5)
+        .acquire_timeout(Duration::from_secs(30))
+        .idle_timeout(Duration::from_secs(300))
+        .connect(&pg_conn).await
+        .expect("Couldn't load postgresql connection!");
+
+    let data = app_data(pool).await;
+    init_map_change_listener(data.pool.clone(), data.push_service.clone()).await;
+    let upload_redis_pool = data.cache.redis_pool.clone();
+
+    let job_data = data.clone();
+    tokio::spawn(async move {
+        run_model_jobs(job_data).await;
+    });
+    let export_data = data.clone();
+    tokio::spawn(async move {
+        run_data_exports(export_data).await;
+    });
+    let deletion_data = data.clone();
+    tokio::spawn(async move {
+        run_account_deletions(deletion_data).await;
+    });
+    let achievement_data = data.clone();
+    tokio::spawn(async move {
+        run_achievement_evaluations(achievement_data).await;
+    });
+    let discord_data = data.clone();
+    tokio::spawn(async move {
+        run_discord_role_syncs(discord_data).await;
+    });
+    let markdown_data = data.clone();
+    tokio::spawn(async move {
+        run_markdown_renders(markdown_data).await;
+    });
 
-    let apis = (
-        ServerApi,
-        PlayerApi,
-        GraphApi,
-        MapApi,
-        RadarApi,
-        MiscApi,
-        AccountsApi,
-        CharacterApi,
-        DonationsApi,
-        AdminMapsApi,
-        AdminServersApi,
-    );
-    // For logging endpoints, because poem dev rly makes it hard for me
-    let registered: Vec<Arc<dyn UriPatternExt + Send + Sync>> = vec![
-        Arc::new(MapApi),
-        Arc::new(ServerApi),
-        Arc::new(PlayerApi),
-        Arc::new(GraphApi),
-        Arc::new(RadarApi),
-        Arc::new(MiscApi),
-        Arc::new(AccountsApi),
-        Arc::new(CharacterApi),
-        Arc::new(DonationsApi),
-        Arc::new(AdminMapsApi),
-        Arc::new(AdminServersApi),
-    ];
     let port = "3000";
-    let api_service = OpenApiService::new(apis, "ZE Watcher", "0.2")
+    let api_service = OpenApiService::new(apis(), "ZE Watcher", "0.2")
         .server(format!("http://127.0.0.1:{port}/"));
 
     let mut route = Route::new();
@@ -162,11 +270,7 @@ async fn run_main() {
         let ui = api_service.swagger_ui();
         route = route.nest("/ui", ui);
     }
-    let app = route.nest("/", api_service)
-        .with(Cors::new()) // 600MB limit for large file uploads
-        .with(PatternLogger::new(registered))
-        .with(CookieSession::new(CookieConfig::default()))
-        .data(data);
+    let app = with_middleware(route.nest("/", api_service), data);
 
     if pre_calculate{
         init_precalculate(port).await;
@@ -182,7 +286,7 @@ async fn run_main() {
     let store_upload_clone = get_env_default("STORE_UPLOAD")
         .unwrap_or_else(|| "./maps".to_string());
     tokio::spawn(async move {
-        cleanup_stale_uploads(store_upload_clone).await;
+        cleanup_stale_uploads(store_upload_clone, upload_redis_pool).await;
     });
 
     Server::new(TcpListener::bind(format!("0.0.0.0:{port}")))
@@ -254,3 +358,419 @@ fn main(){
             run_main().await
         });
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use poem::test::TestClient;
+    use rand::Rng;
+    use serde_json::{json, Value};
+    use uuid::Uuid;
+
+    fn block_on<F: std::future::Future>(future: F) -> F::Output {
+        tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap()
+            .block_on(future)
+    }
+
+    fn spec() -> Value {
+        serde_json::from_str(&OpenApiService::new(apis(), "ZE Watcher", "0.2").spec()).unwrap()
+    }
+
+    /// `(method, path)` of every operation, paths written like `get_all_patterns` writes them
+    fn operations(spec: &Value) -> Vec<(String, String)> {
+        spec["paths"].as_object().unwrap().iter()
+            .flat_map(|(path, ops)| {
+                ops.as_object().unwrap().keys().map(move |method| (method.clone(), path.clone()))
+            })
+            .collect()
+    }
+
+    fn with_sample_params(path: &str) -> String {
+        path.split('/')
+            .map(|segment| match segment.starts_with('{') {
+                true => "sample",
+                false => segment,
+            })
+            .collect::<Vec<_>>()
+            .join("/")
+    }
+
+    #[test]
+    fn every_route_is_logged_under_its_own_pattern() {
+        let registered = registered_apis();
+        for (method, path) in operations(&spec()) {
+            assert_eq!(
+                route_pattern(&registered, &with_sample_params(&path)).as_deref(),
+                Some(path.as_str()),
+                "{method} {path} is missing from get_all_patterns"
+            );
+        }
+    }
+
+    #[test]
+    fn every_logged_pattern_is_a_route() {
+        let paths: Vec<String> = operations(&spec()).into_iter().map(|(_, path)| path).collect();
+        for api in registered_apis() {
+            for pattern in api.get_all_patterns() {
+                assert!(paths.iter().any(|p| p == pattern.uri()), "{} isn't served by any route", pattern.uri());
+            }
+        }
+    }
+
+    async fn insert_steam_user(pool: &PgPool, user_id: i64, name: &str) {
+        sqlx::query(
+            "INSERT INTO website.steam_user (
+                user_id, community_visibility_state, profile_state, persona_name, profile_url, avatar,
+                avatar_medium, avatar_full, avatar_hash, last_log_off, persona_state, primary_clan_id,
+                time_created, persona_state_flags, comment_permission
+            ) VALUES ($1, 'Private', 0, $2, '', '', '', '', '', 0, 'Offline', '', 0, 0, FALSE)"
+        ).bind(user_id).bind(name).execute(pool).await.unwrap();
+    }
+
+    /// The server's app data against its database, `None` unless run with `ROUTE_TESTS=true`.
+    async fn route_test_data() -> Option<AppData> {
+        dotenv().ok();
+        if !get_env_bool("ROUTE_TESTS", false) {
+            eprintln!("ROUTE_TESTS isn't set, skipping");
+            return None
+        }
+        let pool = PgPoolOptions::new()
+            .max_connections(5)
+            .connect(&get_env("DATABASE_URL")).await
+            .expect("Couldn't load postgresql connection!");
+        let mut data = app_data(pool).await;
+        data.steam_provider = None;
+        Some(data)
+    }
+
+    fn client(data: &AppData) -> TestClient<impl Endpoint> {
+        let service = OpenApiService::new(apis(), "ZE Watcher", "0.2");
+        TestClient::new(with_middleware(Route::new().nest("/", service), data.clone()))
+    }
+
+    /// A community with one player who anonymized themselves, one who hides their location and
+    /// one who does neither, all playing the same map on one server.
+    struct Seed {
+        community_id: Uuid,
+        server_id: String,
+        map_name: String,
+        time_id: i32,
+        hidden: (String, String, Uuid),
+        located: (String, String, Uuid),
+        visible: String,
+    }
+
+    const COUNTRY: &str = "AQ";
+
+    impl Seed {
+        async fn insert(pool: &PgPool) -> Seed {
+            let n: u32 = rand::rng().random_range(0..1_000_000_000);
+            let base = 76_561_190_000_000_000i64 + n as i64 * 4;
+            let community_id = Uuid::new_v4();
+            let server_id = format!("route-test-{n}");
+            let map_name = format!("ze_route_test_{n}");
+
+            sqlx::query("INSERT INTO community (community_id, community_name) VALUES ($1, 'Route test')")
+                .bind(community_id).execute(pool).await.unwrap();
+            sqlx::query("INSERT INTO server (server_id, server_name, community_id) VALUES ($1, 'Route test', $2)")
+                .bind(&server_id).bind(community_id).execute(pool).await.unwrap();
+            sqlx::query("INSERT INTO server_map (server_id, map) VALUES ($1, $2)")
+                .bind(&server_id).bind(&map_name).execute(pool).await.unwrap();
+            let time_id: i32 = sqlx::query_scalar(
+                "INSERT INTO server_map_played (server_id, map, player_count, started_at)
+                 VALUES ($1, $2, 3, CURRENT_TIMESTAMP - INTERVAL '1 hour') RETURNING time_id"
+            ).bind(&server_id).bind(&map_name).fetch_one(pool).await.unwrap();
+
+            let players = [
+                (base, format!("HiddenRouteTest{n}"), None, true, false),
+                (base + 1, format!("LocatedRouteTest{n}"), Some(json!({ "country": COUNTRY })), false, true),
+                (base + 2, format!("VisibleRouteTest{n}"), None, false, false),
+            ];
+            let mut sessions = vec![];
+            for (id, name, location, anonymized, hide_location) in players.iter().cloned() {
+                sqlx::query(
+                    "INSERT INTO player (player_id, player_name, location_code, location)
+                     VALUES ($1, $2, $3, CASE WHEN $3 IS NULL THEN NULL ELSE ST_SetSRID(ST_MakePoint(0, -80), 4326) END)"
+                ).bind(id.to_string()).bind(&name).bind(location).execute(pool).await.unwrap();
+                let session_id: Uuid = sqlx::query_scalar(
+                    "INSERT INTO player_server_session (player_id, server_id, started_at)
+                     VALUES ($1, $2, CURRENT_TIMESTAMP - INTERVAL '1 hour') RETURNING session_id"
+                ).bind(id.to_string()).bind(&server_id).fetch_one(pool).await.unwrap();
+                sessions.push(session_id);
+                if !anonymized && !hide_location {
+                    continue
+                }
+                insert_steam_user(pool, id, &name).await;
+                sqlx::query(
+                    "INSERT INTO website.user_anonymization (user_id, community_id, anonymized, hide_location)
+                     VALUES ($1, $2, $3, $4)"
+                ).bind(id).bind(community_id).bind(anonymized).bind(hide_location).execute(pool).await.unwrap();
+            }
+
+            Seed {
+                community_id,
+                server_id,
+                map_name,
+                time_id,
+                hidden: (players[0].0.to_string(), players[0].1.clone(), sessions[0]),
+                located: (players[1].0.to_string(), players[1].1.clone(), sessions[1]),
+                visible: players[2].0.to_string(),
+            }
+        }
+
+        async fn remove(&self, pool: &PgPool) {
+            let players = [&self.hidden.0, &self.located.0, &self.visible];
+            let _ = sqlx::query("DELETE FROM server WHERE server_id = $1")
+                .bind(&self.server_id).execute(pool).await;
+            for player_id in players {
+                let _ = sqlx::query("DELETE FROM player WHERE player_id = $1")
+                    .bind(player_id).execute(pool).await;
+                let _ = sqlx::query("DELETE FROM website.steam_user WHERE user_id = $1::TEXT::BIGINT")
+                    .bind(player_id).execute(pool).await;
+            }
+            let _ = sqlx::query("DELETE FROM community WHERE community_id = $1")
+                .bind(self.community_id).execute(pool).await;
+        }
+
+        /// The path with its parameters filled in, once per seeded player for player routes.
+        fn paths(&self, path: &str) -> Vec<String> {
+            let players = match path.contains("{player_id}") {
+                true => vec![&self.hidden, &self.located],
+                false => vec![&self.hidden],
+            };
+            players.into_iter()
+                .map(|(player_id, _, session_id)| {
+                    // Sessions under a player are theirs, the others are map sessions
+                    let session = match path.contains("{player_id}") {
+                        true => session_id.to_string(),
+                        false => self.time_id.to_string(),
+                    };
+                    path.split('/')
+                        .map(|segment| match segment {
+                            "{server_id}" => self.server_id.clone(),
+                            "{player_id}" => player_id.clone(),
+                            "{map_name}" => self.map_name.clone(),
+                            "{session_id}" => session.clone(),
+                            "{community_id}" => self.community_id.to_string(),
+                            s if s.starts_with('{') => "0".to_string(),
+                            s => s.to_string(),
+                        })
+                        .collect::<Vec<_>>()
+                        .join("/")
+                })
+                .collect()
+        }
+
+        /// Required query parameters plus `country`, searches look for the anonymized player.
+        fn query(&self, spec: &Value, operation: &Value) -> String {
+            let mut query = url::form_urlencoded::Serializer::new(String::new());
+            let parameters = operation["parameters"].as_array().cloned().unwrap_or_default();
+            for parameter in parameters.iter().filter(|p| p["in"] == "query") {
+                let name = parameter["name"].as_str().unwrap();
+                if name == "country" {
+                    query.append_pair(name, COUNTRY);
+                    continue
+                }
+                if parameter["required"] != true {
+                    continue
+                }
+                let mut schema = &parameter["schema"];
+                if let Some(reference) = schema["$ref"].as_str() {
+                    let name = reference.trim_start_matches("#/components/schemas/");
+                    schema = &spec["components"]["schemas"][name];
+                }
+                let value = match (schema["type"].as_str(), schema["format"].as_str()) {
+                    _ if schema["enum"].is_array() => schema["enum"][0].as_str().unwrap_or_default().to_string(),
+                    (_, Some("date-time")) => chrono::Utc::now().to_rfc3339(),
+                    (Some("integer" | "number"), _) => "1".to_string(),
+                    (Some("boolean"), _) => "false".to_string(),
+                    _ => self.hidden.1.clone(),
+                };
+                query.append_pair(name, &value);
+            }
+            query.finish()
+        }
+
+        /// What the body shows that it shouldn't. `by_country` is set when the request was
+        /// filtered by the located player's country, which places anyone it lists.
+        fn leak(&self, body: &str, by_country: bool) -> Option<String> {
+            let (hidden_id, hidden_name, _) = &self.hidden;
+            if body.contains(hidden_id.as_str()) || body.contains(hidden_name.as_str()) {
+                return Some("the anonymized player".to_string())
+            }
+            let (located_id, located_name, _) = &self.located;
+            let is_located = |v: &Value| v.as_str().is_some_and(|s| s == located_id || s == located_name)
+                || v.as_i64().is_some_and(|i| i.to_string() == *located_id);
+            if by_country && (body.contains(located_id.as_str()) || body.contains(located_name.as_str())) {
+                return Some("the located player in a country filter".to_string())
+            }
+            let Ok(value) = serde_json::from_str::<Value>(body) else {
+                return None
+            };
+            let mut stack = vec![&value];
+            while let Some(value) = stack.pop() {
+                match value {
+                    Value::Object(map) => {
+                        if map.values().any(|v| is_located(v)) && value.to_string().contains(&format!("\"{COUNTRY}\"")) {
+                            return Some("the located player's country".to_string())
+                        }
+                        stack.extend(map.values());
+                    }
+                    Value::Array(items) => stack.extend(items),
+                    _ => {}
+                }
+            }
+            None
+        }
+    }
+
+    /// Asks every GET route about seeded players and checks nothing they hide comes back.
+    /// Needs the server's environment and its database, run with `ROUTE_TESTS=true`.
+    #[test]
+    fn no_route_shows_what_players_hide() {
+        block_on(async {
+            let Some(data) = route_test_data().await else {
+                return
+            };
+            let seed = Seed::insert(&data.pool).await;
+
+            let spec = spec();
+            let client = client(&data);
+            let mut leaks = vec![];
+            for (method, path) in operations(&spec) {
+                if method != "get" {
+                    continue
+                }
+                let query = seed.query(&spec, &spec["paths"][&path][&method]);
+                for uri in seed.paths(&path) {
+                    let uri = match query.is_empty() {
+                        true => uri,
+                        false => format!("{uri}?{query}"),
+                    };
+                    let request = client.get(&uri).send();
+                    let Ok(response) = tokio::time::timeout(Duration::from_secs(60), request).await else {
+                        leaks.push(format!("GET {uri} timed out"));
+                        continue
+                    };
+                    let body = response.0.into_body().into_string().await.unwrap_or_default();
+                    if let Some(what) = seed.leak(&body, query.contains("country=")) {
+                        leaks.push(format!("GET {uri} shows {what}"));
+                    }
+                }
+            }
+
+            seed.remove(&data.pool).await;
+            assert!(leaks.is_empty(), "{}", leaks.join("\n"));
+        })
+    }
+
+    /// A global `roles.manage` holder can't write a role holding more than they do, neither by
+    /// creating one nor by editing one. Run with `ROUTE_TESTS=true`.
+    #[test]
+    fn role_managers_only_write_permissions_they_hold() {
+        block_on(async {
+            let Some(data) = route_test_data().await else {
+                return
+            };
+            let pool = &data.pool;
+            let n: u32 = rand::rng().random_range(0..1_000_000_000);
+            let user_id = 76_561_195_000_000_000i64 + n as i64;
+            let prefix = format!("route-test-{n}");
+            insert_steam_user(pool, user_id, &prefix).await;
+            let manager: i32 = sqlx::query_scalar(
+                "INSERT INTO website.role (name, permissions) VALUES ($1, '{roles.manage}') RETURNING id"
+            ).bind(format!("{prefix}-manager")).fetch_one(pool).await.unwrap();
+            let everything: i32 = sqlx::query_scalar(
+                "INSERT INTO website.role (name, permissions) VALUES ($1, '{*}') RETURNING id"
+            ).bind(format!("{prefix}-everything")).fetch_one(pool).await.unwrap();
+            sqlx::query("INSERT INTO website.role_grant (user_id, role_id) VALUES ($1, $2)")
+                .bind(user_id).bind(manager).execute(pool).await.unwrap();
+            let (token, _) = crate::core::sessions::issue_access_token(user_id, &prefix, Uuid::new_v4()).unwrap();
+
+            let client = client(&data);
+            let attempts = [
+                ("post", "/admin/roles".to_string(), json!({ "name": format!("{prefix}-created"), "permissions": ["*"] })),
+                ("post", "/admin/roles".to_string(), json!({ "name": format!("{prefix}-created"), "permissions": ["guides.moderate"] })),
+                ("put", format!("/admin/roles/{manager}"), json!({ "permissions": ["roles.manage", "*"] })),
+                ("put", format!("/admin/roles/{everything}"), json!({ "description": "Taken over" })),
+            ];
+            let mut codes = vec![];
+            for (method, uri, body) in attempts {
+                let request = match method {
+                    "post" => client.post(&uri),
+                    _ => client.put(&uri),
+                };
+                let response = request
+                    .header("Authorization", format!("Bearer {token}"))
+                    .body_json(&body)
+                    .send().await;
+                let body: Value = response.0.into_body().into_json().await.unwrap_or_default();
+                codes.push((format!("{method} {uri}"), body["code"].as_i64()));
+            }
+            let created: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM website.role WHERE name = $1")
+                .bind(format!("{prefix}-created")).fetch_one(pool).await.unwrap();
+            let permissions: Vec<String> = sqlx::query_scalar("SELECT permissions FROM website.role WHERE id = $1")
+                .bind(manager).fetch_one(pool).await.unwrap();
+
+            sqlx::query("DELETE FROM website.role WHERE name LIKE $1 || '-%'")
+                .bind(&prefix).execute(pool).await.unwrap();
+            sqlx::query("DELETE FROM website.steam_user WHERE user_id = $1")
+                .bind(user_id).execute(pool).await.unwrap();
+
+            for (attempt, code) in codes {
+                assert_eq!(code, Some(403), "{attempt} wasn't rejected");
+            }
+            assert_eq!(created, 0);
+            assert_eq!(permissions, vec!["roles.manage".to_string()]);
+        })
+    }
+
+    /// A key with a daily quota of two gets its third request of the day turned away until
+    /// midnight UTC. Run with `ROUTE_TESTS=true`, needs Redis as well.
+    #[test]
+    fn api_key_quota_is_enforced() {
+        block_on(async {
+            let Some(data) = route_test_data().await else {
+                return
+            };
+            let pool = &data.pool;
+            let n: u32 = rand::rng().random_range(0..1_000_000_000);
+            let user_id = 76_561_196_000_000_000i64 + n as i64;
+            insert_steam_user(pool, user_id, &format!("quota-test-{n}")).await;
+            let (key, prefix) = crate::core::api_keys::generate_api_key();
+            sqlx::query(
+                "INSERT INTO website.api_key (user_id, name, prefix, key_hash, scopes, daily_quota)
+                 VALUES ($1, 'Quota test', $2, $3, '{servers.read}', 2)"
+            ).bind(user_id).bind(&prefix).bind(crate::core::api_keys::hash_api_key(&key))
+                .execute(pool).await.unwrap();
+
+            let client = client(&data);
+            let mut responses = vec![];
+            for _ in 0..3 {
+                let response = client.get("/communities")
+                    .header(crate::core::api_keys::API_KEY_HEADER, &key)
+                    .send().await;
+                let headers = response.0.headers().clone();
+                responses.push((response.0.status(), headers));
+            }
+
+            sqlx::query("DELETE FROM website.steam_user WHERE user_id = $1")
+                .bind(user_id).execute(pool).await.unwrap();
+
+            for (status, _) in &responses[..2] {
+                assert_eq!(*status, poem::http::StatusCode::OK);
+            }
+            let (status, headers) = &responses[2];
+            assert_eq!(*status, poem::http::StatusCode::TOO_MANY_REQUESTS);
+            let tomorrow = (chrono::Utc::now().date_naive() + chrono::Duration::days(1))
+                .and_time(Default::default())
+                .and_utc();
+            let reset: i64 = headers["X-Quota-Reset"].to_str().unwrap().parse().unwrap();
+            assert_eq!(reset, tomorrow.timestamp());
+            assert!(headers.contains_key("Retry-After"));
+        })
+    }
+}
@@ -8,4 +8,12 @@ pub mod accounts;
 pub mod characters;
 pub mod donations;
 pub mod admin_maps;
-pub mod admin_servers;
\ No newline at end of file
+pub mod admin_servers;
+pub mod admin_audit;
+pub mod admin_roles;
+pub mod uploads;
+pub mod api_keys;
+pub mod discord;
+pub mod data_export;
+pub mod account_deletion;
+pub mod search;
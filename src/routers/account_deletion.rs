@@ -0,0 +1,163 @@
+use chrono::{DateTime, Utc};
+use poem::web::Data;
+use poem_openapi::{Object, OpenApi};
+use serde::{Deserialize, Serialize};
+
+use crate::core::account_deletion::grace_days;
+use crate::core::api_models::*;
+use crate::core::audit::record_audit;
+use crate::core::model::DbAccountDeletion;
+use crate::core::utils::*;
+use crate::{response, AppData};
+
+pub struct AccountDeletionApi;
+
+#[derive(Debug, Serialize, Deserialize, Object, Clone)]
+pub struct AccountDeletion {
+    pub id: String,
+    pub user_id: String,
+    /// `scheduled`, `cancelled`, `running`, `done` or `failed`
+    pub status: String,
+    pub requested_at: DateTime<Utc>,
+    /// Until then it can be undone
+    pub scheduled_for: DateTime<Utc>,
+    pub cancelled_at: Option<DateTime<Utc>>,
+    pub completed_at: Option<DateTime<Utc>>,
+    pub error: Option<String>,
+    /// Row counts of what the deletion changed, once done
+    pub summary: Option<serde_json::Value>,
+}
+
+impl From<DbAccountDeletion> for AccountDeletion {
+    fn from(d: DbAccountDeletion) -> Self {
+        AccountDeletion {
+            id: d.id.to_string(),
+            user_id: d.user_id.to_string(),
+            status: d.status,
+            requested_at: db_to_utc(d.requested_at),
+            scheduled_for: db_to_utc(d.scheduled_for),
+            cancelled_at: d.cancelled_at.map(db_to_utc),
+            completed_at: d.completed_at.map(db_to_utc),
+            error: d.error,
+            summary: d.summary,
+        }
+    }
+}
+
+#[OpenApi]
+impl AccountDeletionApi {
+    /// Schedules the signed in account for deletion after `ACCOUNT_DELETION_GRACE_DAYS`. Until
+    /// then everything keeps working and `POST /accounts/me/deletion/cancel` undoes it.
+    /// Asking again while one is scheduled returns that one.
+    #[oai(path = "/accounts/me", method = "delete")]
+    async fn delete_my_account(
+        &self,
+        Data(data): Data<&AppData>,
+        TokenBearer(user_token): TokenBearer,
+    ) -> Response<AccountDeletion> {
+        match pending_deletion(data, user_token.id).await {
+            Ok(Some(deletion)) => return response!(ok deletion.into()),
+            Ok(None) => {}
+            Err(e) => {
+                tracing::error!("Failed to fetch account deletion: {}", e);
+                return response!(internal_server_error);
+            }
+        }
+
+        let result = sqlx::query_as!(
+            DbAccountDeletion,
+            "INSERT INTO website.account_deletion (user_id, scheduled_for)
+             SELECT $1, NOW() + make_interval(days => $2)
+             WHERE EXISTS (SELECT 1 FROM website.steam_user WHERE user_id = $1 AND deleted_at IS NULL)
+             RETURNING *",
+            user_token.id,
+            grace_days()
+        )
+        .fetch_optional(&*data.pool)
+        .await;
+        match result {
+            Ok(Some(deletion)) => {
+                record_audit(
+                    &data.pool, Some(user_token.id), "request_account_deletion", "user",
+                    &user_token.id.to_string(), None,
+                    Some(serde_json::json!({ "scheduled_for": db_to_utc(deletion.scheduled_for) })),
+                ).await;
+                response!(ok deletion.into())
+            }
+            Ok(None) => response!(err "User does not exist!", ErrorCode::NotFound),
+            Err(e) => {
+                tracing::error!("Failed to schedule account deletion: {}", e);
+                response!(internal_server_error)
+            }
+        }
+    }
+
+    /// The pending deletion of the signed in account
+    #[oai(path = "/accounts/me/deletion", method = "get")]
+    async fn get_my_account_deletion(
+        &self,
+        Data(data): Data<&AppData>,
+        TokenBearer(user_token): TokenBearer,
+    ) -> Response<AccountDeletion> {
+        match pending_deletion(data, user_token.id).await {
+            Ok(Some(deletion)) => response!(ok deletion.into()),
+            Ok(None) => response!(err "No deletion scheduled", ErrorCode::NotFound),
+            Err(e) => {
+                tracing::error!("Failed to fetch account deletion: {}", e);
+                response!(internal_server_error)
+            }
+        }
+    }
+
+    /// Undoes a scheduled deletion, as long as the grace period isn't over
+    #[oai(path = "/accounts/me/deletion/cancel", method = "post")]
+    async fn cancel_my_account_deletion(
+        &self,
+        Data(data): Data<&AppData>,
+        TokenBearer(user_token): TokenBearer,
+    ) -> Response<AccountDeletion> {
+        let result = sqlx::query_as!(
+            DbAccountDeletion,
+            "UPDATE website.account_deletion SET status = 'cancelled', cancelled_at = NOW()
+             WHERE user_id = $1 AND status = 'scheduled'
+             RETURNING *",
+            user_token.id
+        )
+        .fetch_optional(&*data.pool)
+        .await;
+        match result {
+            Ok(Some(deletion)) => {
+                record_audit(
+                    &data.pool, Some(user_token.id), "cancel_account_deletion", "user",
+                    &user_token.id.to_string(), None, None,
+                ).await;
+                response!(ok deletion.into())
+            }
+            Ok(None) => response!(err "No deletion to cancel, it may already be under way", ErrorCode::NotFound),
+            Err(e) => {
+                tracing::error!("Failed to cancel account deletion: {}", e);
+                response!(internal_server_error)
+            }
+        }
+    }
+}
+
+async fn pending_deletion(data: &AppData, user_id: i64) -> Result<Option<DbAccountDeletion>, sqlx::Error> {
+    sqlx::query_as!(
+        DbAccountDeletion,
+        "SELECT * FROM website.account_deletion WHERE user_id = $1 AND status IN ('scheduled', 'running')",
+        user_id
+    )
+    .fetch_optional(&*data.pool)
+    .await
+}
+
+impl UriPatternExt for AccountDeletionApi {
+    fn get_all_patterns(&self) -> Vec<RoutePattern<'_>> {
+        vec![
+            "/accounts/me",
+            "/accounts/me/deletion",
+            "/accounts/me/deletion/cancel",
+        ].iter_into()
+    }
+}
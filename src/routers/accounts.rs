@@ -1,16 +1,28 @@
-use chrono::Utc;
+use chrono::{DateTime, Utc};
 use indexmap::IndexMap;
+use poem::Request;
 use poem::web::Data;
 use poem_openapi::payload::Json;
 use poem_openapi::{Object, OpenApi};
 use poem_openapi::param::{Path, Query};
 use reqwest::StatusCode;
 use serde::{Deserialize, Serialize};
+use sqlx::types::time::OffsetDateTime;
 use tokio::time::sleep;
 use uuid::Uuid;
 
+use crate::core::anonymization::forget_privacy;
+use crate::core::markdown::forget_player_mentions;
 use crate::core::api_models::*;
+use crate::core::audit::record_audit;
+use crate::core::discord::fetch_discord_link;
 use crate::core::model::*;
+use crate::core::permissions::{has_permission, is_superuser, Permission, Scope};
+use crate::core::rate_limit::client_ip;
+use crate::core::sessions::{
+    deny_session, deny_tokens_before_now, hash_refresh_token, issue_access_token, new_refresh_token,
+    refresh_ttl_days,
+};
 use crate::core::utils::*;
 use crate::core::workers::PlayerContext;
 use crate::{response, AppData};
@@ -97,71 +109,6 @@ pub struct ServerRequestStatusDto {
     pub status: String,
 }
 
-#[derive(Debug, Clone)]
-enum UserRole {
-    Superuser,
-    CommunityAdmin(Uuid),
-    Regular,
-}
-
-
-async fn get_user_role(data: &AppData, user_id: i64) -> Result<UserRole, ErrorCode> {
-    // Check if superuser
-    let is_superuser = sqlx::query_scalar!(
-        "SELECT website.is_superuser($1) ",
-        user_id
-    )
-    .fetch_optional(&*data.pool)
-    .await
-    .map_err(|_| ErrorCode::InternalServerError)?;
-
-    if is_superuser == Some(Some(true)) {
-        return Ok(UserRole::Superuser);
-    }
-
-    struct AdminCommunity {
-        community_id: Option<Uuid>,
-    }
-    let admin_communities = sqlx::query_as!(
-        AdminCommunity,
-        "SELECT community_id FROM website.user_roles
-         WHERE user_id = $1 AND role = 'community_admin'",
-        user_id
-    )
-    .fetch_all(&*data.pool)
-    .await
-    .map_err(|_| ErrorCode::InternalServerError)?;
-
-    if let Some(admin_comm) = admin_communities.first() {
-        if let Some(community_id) = admin_comm.community_id{
-            return Ok(UserRole::CommunityAdmin(community_id));
-        }
-    }
-
-    Ok(UserRole::Regular)
-}
-
-async fn check_permission(
-    data: &AppData,
-    requester_id: i64,
-    target_user_id: i64,
-    community_id: Uuid
-) -> Result<bool, ErrorCode> {
-    if requester_id == target_user_id {
-        return Ok(true);
-    }
-
-    let role = get_user_role(data, requester_id).await?;
-
-    match role {
-        UserRole::Superuser => Ok(true),
-        UserRole::CommunityAdmin(admin_community) => {
-            Ok(admin_community == community_id)
-        }
-        UserRole::Regular => Ok(false),
-    }
-}
-
 async fn fetch_steam_info(steam_id: &i64) -> Result<SteamProfile, ErrorCode> {
     let base_url = "https://api.steampowered.com/ISteamUser/GetPlayerSummaries/v0002";
     let client = reqwest::Client::new();
@@ -231,7 +178,7 @@ impl AccountsApi {
                 time_created,
                 persona_state_flags,
                 comment_permission
-            FROM website.steam_user WHERE user_id=$1 LIMIT 1", user_id
+            FROM website.steam_user WHERE user_id=$1 AND deleted_at IS NULL LIMIT 1", user_id
         ).fetch_one(&*data.pool).await {
             return response!(err "User existed!", ErrorCode::Conflict)
         };
@@ -279,7 +226,26 @@ impl AccountsApi {
                 time_created,
                 persona_state_flags,
                 comment_permission)
-             VALUES($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13, $14, $15) RETURNING
+             VALUES($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13, $14, $15)
+             -- Signing up again after a deletion starts over on the scrubbed row
+             ON CONFLICT (user_id) DO UPDATE SET
+                community_visibility_state = EXCLUDED.community_visibility_state,
+                profile_state = EXCLUDED.profile_state,
+                persona_name = EXCLUDED.persona_name,
+                profile_url = EXCLUDED.profile_url,
+                avatar = EXCLUDED.avatar,
+                avatar_medium = EXCLUDED.avatar_medium,
+                avatar_full = EXCLUDED.avatar_full,
+                avatar_hash = EXCLUDED.avatar_hash,
+                last_log_off = EXCLUDED.last_log_off,
+                persona_state = EXCLUDED.persona_state,
+                primary_clan_id = EXCLUDED.primary_clan_id,
+                time_created = EXCLUDED.time_created,
+                persona_state_flags = EXCLUDED.persona_state_flags,
+                comment_permission = EXCLUDED.comment_permission,
+                deleted_at = NULL
+             WHERE website.steam_user.deleted_at IS NOT NULL
+             RETURNING
              user_id,
                 community_visibility_state AS \"community_visibility_state: CommunityVisibilityState\",
                 profile_state,
@@ -338,14 +304,21 @@ impl AccountsApi {
                 time_created,
                 persona_state_flags,
                 comment_permission
-            FROM website.steam_user WHERE user_id=$1 LIMIT 1", user_token.id
+            FROM website.steam_user WHERE user_id=$1 AND deleted_at IS NULL LIMIT 1", user_token.id
         ).fetch_one(&*data.pool).await else {
             return response!(err "User does not exist!", ErrorCode::NotFound)
         };
 
-        let is_superuser = check_superuser(data, user_token.id).await;
+        let is_superuser = is_superuser(data, user_token.id).await;
         let mut profile: SteamProfile = user.into();
         profile.is_superuser = Some(is_superuser);
+        profile.discord = match fetch_discord_link(data, user_token.id).await {
+            Ok(link) => link,
+            Err(e) => {
+                tracing::warn!("Failed to fetch Discord link: {}", e);
+                None
+            }
+        };
 
         response!(ok profile)
     }
@@ -440,6 +413,10 @@ impl AccountsApi {
 
         match result {
             Ok(setting) => {
+                forget_privacy(data, uuid).await;
+                if let Err(e) = forget_player_mentions(data, user_id).await {
+                    tracing::error!("Failed to clear mentions of {user_id}: {e}");
+                }
                 response!(ok setting.into())
             }
             Err(e) => {
@@ -533,12 +510,10 @@ impl AccountsApi {
             return response!(err "Invalid community ID", ErrorCode::BadRequest);
         };
 
-        let has_permission = match check_permission(data, requester_id, target_user_id, uuid).await {
-            Ok(p) => p,
-            Err(_) => return response!(internal_server_error)
-        };
+        let allowed = requester_id == target_user_id
+            || has_permission(data, requester_id, Permission::PlayersAnonymize, Scope::Community(uuid)).await;
 
-        if !has_permission {
+        if !allowed {
             return response!(err "Insufficient permissions", ErrorCode::Forbidden);
         }
 
@@ -556,6 +531,16 @@ impl AccountsApi {
             return response!(err "Target user not found", ErrorCode::NotFound);
         }
 
+        let before = sqlx::query_scalar!(
+            "SELECT to_jsonb(a) FROM website.user_anonymization a WHERE a.user_id = $1 AND a.community_id = $2",
+            target_user_id, uuid
+        )
+        .fetch_optional(&*data.pool)
+        .await
+        .ok()
+        .flatten()
+        .flatten();
+
         // Insert or update anonymization setting
         let result = sqlx::query_as!(DbUserAnonymization,
             "INSERT INTO website.user_anonymization (user_id, community_id, anonymized, hide_location)
@@ -573,6 +558,19 @@ impl AccountsApi {
 
         match result {
             Ok(setting) => {
+                forget_privacy(data, uuid).await;
+                if let Err(e) = forget_player_mentions(data, target_user_id).await {
+                    tracing::error!("Failed to clear mentions of {target_user_id}: {e}");
+                }
+                record_audit(
+                    &data.pool, Some(requester_id), "set_anonymization", "user",
+                    &format!("{target_user_id}/{uuid}"),
+                    before,
+                    Some(serde_json::json!({
+                        "anonymized": setting.anonymized,
+                        "hide_location": setting.hide_location,
+                    })),
+                ).await;
                 response!(ok  setting.into())
             }
             Err(e) => {
@@ -615,7 +613,7 @@ impl AccountsApi {
         Query(page): Query<Option<i64>>,
         Query(status): Query<Option<String>>,
     ) -> Response<GuideReportsPaginated> {
-        if !check_superuser(data, user_token.id).await {
+        if !has_permission(data, user_token.id, Permission::GuidesModerate, Scope::Global).await {
             return response!(err "Unauthorized", ErrorCode::Forbidden);
         }
 
@@ -644,9 +642,12 @@ impl AccountsApi {
                 COALESCE(author.persona_name, NULL) AS guide_author_name,
                 COALESCE(reporter.persona_name, NULL) AS reporter_name,
                 COALESCE(resolver.persona_name, NULL) AS resolver_name,
-                COUNT(*) OVER() AS total_reports
+                COUNT(*) OVER() AS total_reports,
+                r.revision AS reported_revision,
+                rev.content AS "reported_content?"
             FROM website.report_guide r
             LEFT JOIN website.guides g ON r.guide_id = g.id
+            LEFT JOIN website.guide_revision rev ON rev.guide_id = r.guide_id AND rev.revision = r.revision
             LEFT JOIN website.steam_user author ON g.author_id = author.user_id
             LEFT JOIN website.steam_user reporter ON r.user_id = reporter.user_id
             LEFT JOIN website.steam_user resolver ON r.resolved_by = resolver.user_id
@@ -684,7 +685,7 @@ impl AccountsApi {
         Query(page): Query<Option<i64>>,
         Query(status): Query<Option<String>>,
     ) -> Response<CommentReportsPaginated> {
-        if !check_superuser(data, user_token.id).await {
+        if !has_permission(data, user_token.id, Permission::GuidesModerate, Scope::Global).await {
             return response!(err "Unauthorized", ErrorCode::Forbidden);
         }
 
@@ -753,7 +754,7 @@ impl AccountsApi {
         Path(report_id): Path<String>,
         Json(payload): Json<UpdateReportStatusDto>,
     ) -> Response<GuideReportAdmin> {
-        if !check_superuser(data, user_token.id).await {
+        if !has_permission(data, user_token.id, Permission::GuidesModerate, Scope::Global).await {
             return response!(err "Unauthorized", ErrorCode::Forbidden);
         }
 
@@ -767,6 +768,8 @@ impl AccountsApi {
 
         let resolved_by = if payload.status == "pending" { None } else { Some(user_token.id) };
 
+        let before = report_snapshot(data, "report_guide", report_uuid).await;
+
         let report = match sqlx::query_as!(
             DbGuideReportFull,
             r#"
@@ -789,7 +792,10 @@ impl AccountsApi {
                 (SELECT persona_name FROM website.steam_user WHERE user_id = (SELECT author_id FROM website.guides WHERE id = guide_id)) AS guide_author_name,
                 (SELECT persona_name FROM website.steam_user WHERE user_id = report_guide.user_id) AS reporter_name,
                 (SELECT persona_name FROM website.steam_user WHERE user_id = report_guide.resolved_by) AS resolver_name,
-                1::bigint AS total_reports
+                1::bigint AS total_reports,
+                revision AS reported_revision,
+                (SELECT content FROM website.guide_revision rev
+                 WHERE rev.guide_id = report_guide.guide_id AND rev.revision = report_guide.revision) AS reported_content
             "#,
             payload.status,
             resolved_by,
@@ -806,6 +812,11 @@ impl AccountsApi {
             }
         };
 
+        record_audit(
+            &data.pool, Some(user_token.id), "set_status", "guide_report", &report_id,
+            before, report_snapshot(data, "report_guide", report_uuid).await,
+        ).await;
+
         response!(ok report.into())
     }
 
@@ -817,7 +828,7 @@ impl AccountsApi {
         Path(report_id): Path<String>,
         Json(payload): Json<UpdateReportStatusDto>,
     ) -> Response<CommentReportAdmin> {
-        if !check_superuser(data, user_token.id).await {
+        if !has_permission(data, user_token.id, Permission::GuidesModerate, Scope::Global).await {
             return response!(err "Unauthorized", ErrorCode::Forbidden);
         }
 
@@ -831,6 +842,8 @@ impl AccountsApi {
 
         let resolved_by = if payload.status == "pending" { None } else { Some(user_token.id) };
 
+        let before = report_snapshot(data, "report_guide_comment", report_uuid).await;
+
         let report = match sqlx::query_as!(
             DbCommentReportFull,
             r#"
@@ -870,6 +883,11 @@ impl AccountsApi {
             }
         };
 
+        record_audit(
+            &data.pool, Some(user_token.id), "set_status", "comment_report", &report_id,
+            before, report_snapshot(data, "report_guide_comment", report_uuid).await,
+        ).await;
+
         response!(ok report.into())
     }
 
@@ -881,7 +899,7 @@ impl AccountsApi {
         Query(page): Query<Option<i64>>,
         Query(status): Query<Option<String>>,
     ) -> Response<MapMusicReportsPaginated> {
-        if !check_superuser(data, user_token.id).await {
+        if !has_permission(data, user_token.id, Permission::MusicModerate, Scope::Global).await {
             return response!(err "Unauthorized", ErrorCode::Forbidden);
         }
 
@@ -955,7 +973,7 @@ impl AccountsApi {
         Path(report_id): Path<String>,
         Json(payload): Json<UpdateReportStatusDto>,
     ) -> Response<MapMusicReportAdmin> {
-        if !check_superuser(data, user_token.id).await {
+        if !has_permission(data, user_token.id, Permission::MusicModerate, Scope::Global).await {
             return response!(err "Unauthorized", ErrorCode::Forbidden);
         }
 
@@ -969,6 +987,8 @@ impl AccountsApi {
 
         let resolved_by = if payload.status == "pending" { None } else { Some(user_token.id) };
 
+        let before = report_snapshot(data, "report_map_music", report_uuid).await;
+
         let report = match sqlx::query_as!(
             DbMapMusicReportFull,
             r#"
@@ -1010,10 +1030,16 @@ impl AccountsApi {
             }
         };
 
+        record_audit(
+            &data.pool, Some(user_token.id), "set_status", "music_report", &report_id,
+            before, report_snapshot(data, "report_map_music", report_uuid).await,
+        ).await;
+
         // If report is resolved and has a suggested YouTube URL, credit the reporter
         if payload.status == "resolved" {
             if let Some(ref suggested_url) = report.suggested_youtube_url {
                 if let Some(video_id) = extract_youtube_id(suggested_url) {
+                    let before = map_music_snapshot(data, report.music_id).await;
                     // Update map_music with the suggested video and credit the reporter
                     let update_result = sqlx::query!(
                         "UPDATE map_music SET youtube_music = $1, yt_source = $2 WHERE id = $3",
@@ -1024,9 +1050,15 @@ impl AccountsApi {
                     .execute(&*data.pool)
                     .await;
 
-                    if let Err(e) = update_result {
-                        tracing::error!("Failed to update music with reporter credit: {}", e);
-                        // Don't fail the whole request, just log the error
+                    match update_result {
+                        Ok(_) => record_audit(
+                            &data.pool, Some(user_token.id), "youtube_override", "map_music", &report.music_id.to_string(),
+                            before, map_music_snapshot(data, report.music_id).await,
+                        ).await,
+                        Err(e) => {
+                            tracing::error!("Failed to update music with reporter credit: {}", e);
+                            // Don't fail the whole request, just log the error
+                        }
                     }
                 }
             }
@@ -1043,7 +1075,7 @@ impl AccountsApi {
         Path(music_id): Path<String>,
         Json(payload): Json<UpdateMapMusicDto>,
     ) -> Response<String> {
-        if !check_superuser(data, user_token.id).await {
+        if !has_permission(data, user_token.id, Permission::MusicModerate, Scope::Global).await {
             return response!(err "Unauthorized", ErrorCode::Forbidden);
         }
 
@@ -1051,6 +1083,8 @@ impl AccountsApi {
             return response!(err "Invalid music ID", ErrorCode::BadRequest);
         };
 
+        let before = map_music_snapshot(data, music_uuid).await;
+
         // Update youtube_music field and set yt_source to admin's Steam ID
         let result = sqlx::query!(
             r#"
@@ -1070,6 +1104,10 @@ impl AccountsApi {
                 if result.rows_affected() == 0 {
                     return response!(err "Music track not found", ErrorCode::NotFound);
                 }
+                record_audit(
+                    &data.pool, Some(user_token.id), "youtube_override", "map_music", &music_id,
+                    before, map_music_snapshot(data, music_uuid).await,
+                ).await;
                 response!(ok "Updated successfully".into())
             }
             Err(e) => {
@@ -1087,7 +1125,7 @@ impl AccountsApi {
         Query(page): Query<Option<i64>>,
         Query(active_only): Query<Option<bool>>,
     ) -> Response<GuideBansPaginated> {
-        if !check_superuser(data, user_token.id).await {
+        if !has_permission(data, user_token.id, Permission::GuidesModerate, Scope::Global).await {
             return response!(err "Unauthorized", ErrorCode::Forbidden);
         }
 
@@ -1148,7 +1186,7 @@ impl AccountsApi {
         Path(user_id): Path<i64>,
         Json(payload): Json<CreateBanDto>,
     ) -> Response<GuideBanAdmin> {
-        if !check_superuser(data, user_token.id).await {
+        if !has_permission(data, user_token.id, Permission::GuidesModerate, Scope::Global).await {
             return response!(err "Unauthorized", ErrorCode::Forbidden);
         }
 
@@ -1168,6 +1206,7 @@ impl AccountsApi {
             return response!(err "User not found", ErrorCode::NotFound);
         }
 
+        let before = guide_ban_snapshot(data, user_id).await;
         let ban = match sqlx::query_as!(
             DbGuideBan,
             r#"
@@ -1207,6 +1246,11 @@ impl AccountsApi {
             }
         };
 
+        record_audit(
+            &data.pool, Some(user_token.id), "guide_ban", "user", &user_id.to_string(),
+            before, guide_ban_snapshot(data, user_id).await,
+        ).await;
+
         response!(ok ban.into())
     }
 
@@ -1217,10 +1261,11 @@ impl AccountsApi {
         TokenBearer(user_token): TokenBearer,
         Path(user_id): Path<i64>,
     ) -> Response<String> {
-        if !check_superuser(data, user_token.id).await {
+        if !has_permission(data, user_token.id, Permission::GuidesModerate, Scope::Global).await {
             return response!(err "Unauthorized", ErrorCode::Forbidden);
         }
 
+        let before = guide_ban_snapshot(data, user_id).await;
         let result = match sqlx::query!(
             "UPDATE website.guide_user_ban SET is_active = false WHERE user_id = $1 AND is_active = true",
             user_id
@@ -1239,6 +1284,11 @@ impl AccountsApi {
             return response!(err "User is not banned", ErrorCode::NotFound);
         }
 
+        record_audit(
+            &data.pool, Some(user_token.id), "guide_unban", "user", &user_id.to_string(),
+            before, guide_ban_snapshot(data, user_id).await,
+        ).await;
+
         response!(ok "User unbanned successfully".to_string())
     }
 
@@ -1249,7 +1299,7 @@ impl AccountsApi {
         TokenBearer(user_token): TokenBearer,
         Path(user_id): Path<i64>,
     ) -> Response<BanStatus> {
-        if !check_superuser(data, user_token.id).await {
+        if !has_permission(data, user_token.id, Permission::GuidesModerate, Scope::Global).await {
             return response!(err "Unauthorized", ErrorCode::Forbidden);
         }
 
@@ -1297,7 +1347,7 @@ impl AccountsApi {
         Query(status): Query<Option<AnnouncementStatus>>,
         Query(r#type): Query<Option<AnnouncementType>>,
     ) -> Response<AnnouncementsPaginated> {
-        if !check_superuser(data, user_token.id).await {
+        if !has_permission(data, user_token.id, Permission::AnnouncementsManage, Scope::Global).await {
             return response!(err "Unauthorized", ErrorCode::Forbidden);
         }
 
@@ -1379,7 +1429,7 @@ impl AccountsApi {
         TokenBearer(user_token): TokenBearer,
         Json(payload): Json<CreateAnnouncementDto>,
     ) -> Response<Announcement> {
-        if !check_superuser(data, user_token.id).await {
+        if !has_permission(data, user_token.id, Permission::AnnouncementsManage, Scope::Global).await {
             return response!(err "Unauthorized", ErrorCode::Forbidden);
         }
         match payload.r#type{
@@ -1436,6 +1486,11 @@ impl AccountsApi {
             }
         };
 
+        record_audit(
+            &data.pool, Some(user_token.id), "create", "announcement", &announcement.id,
+            None, announcement_snapshot(data, &announcement.id).await,
+        ).await;
+
         response!(ok announcement.into())
     }
 
@@ -1447,7 +1502,7 @@ impl AccountsApi {
         Path(id): Path<String>,
         Json(payload): Json<UpdateAnnouncementDto>,
     ) -> Response<Announcement> {
-        if !check_superuser(data, user_token.id).await {
+        if !has_permission(data, user_token.id, Permission::AnnouncementsManage, Scope::Global).await {
             return response!(err "Unauthorized", ErrorCode::Forbidden);
         }
 
@@ -1486,6 +1541,8 @@ impl AccountsApi {
             }
         };
 
+        let before = announcement_snapshot(data, &id).await;
+
         // Update only provided fields
         let new_type: AnnouncementTypeState = payload.r#type.map(|e| e.into()).unwrap_or(current.r#type);
         let new_title = payload.title.or(current.title);
@@ -1520,6 +1577,11 @@ impl AccountsApi {
             }
         };
 
+        record_audit(
+            &data.pool, Some(user_token.id), "update", "announcement", &id,
+            before, announcement_snapshot(data, &id).await,
+        ).await;
+
         response!(ok updated.into())
     }
 
@@ -1530,10 +1592,11 @@ impl AccountsApi {
         TokenBearer(user_token): TokenBearer,
         Path(id): Path<String>,
     ) -> Response<String> {
-        if !check_superuser(data, user_token.id).await {
+        if !has_permission(data, user_token.id, Permission::AnnouncementsManage, Scope::Global).await {
             return response!(err "Unauthorized", ErrorCode::Forbidden);
         }
 
+        let before = announcement_snapshot(data, &id).await;
         let result = match sqlx::query!(
             "DELETE FROM website.announce WHERE id = $1::TEXT::UUID",
             id
@@ -1552,6 +1615,7 @@ impl AccountsApi {
             return response!(err "Announcement not found", ErrorCode::NotFound);
         }
 
+        record_audit(&data.pool, Some(user_token.id), "delete", "announcement", &id, before, None).await;
         response!(ok "Announcement deleted successfully".to_string())
     }
 
@@ -2111,7 +2175,7 @@ impl AccountsApi {
         TokenBearer(user_token): TokenBearer,
         Json(test_notif): Json<TestNotificationDto>,
     ) -> Response<NotificationSendResult> {
-        if !check_superuser(data, user_token.id).await {
+        if !has_permission(data, user_token.id, Permission::PushManage, Scope::Global).await {
             return response!(err "Unauthorized", ErrorCode::Forbidden);
         }
 
@@ -2160,7 +2224,7 @@ impl AccountsApi {
         TokenBearer(user_token): TokenBearer,
         Query(page): Query<Option<i64>>,
     ) -> Response<PushSubscriptionsPaginated> {
-        if !check_superuser(data, user_token.id).await {
+        if !has_permission(data, user_token.id, Permission::PushManage, Scope::Global).await {
             return response!(err "Unauthorized", ErrorCode::Forbidden);
         }
 
@@ -2269,7 +2333,7 @@ impl AccountsApi {
         Query(page): Query<Option<i64>>,
         Query(status): Query<Option<String>>,
     ) -> Response<ServerRequestsPaginated> {
-        if !check_superuser(data, user_token.id).await {
+        if !has_permission(data, user_token.id, Permission::ServersManage, Scope::Global).await {
             return response!(err "Unauthorized", ErrorCode::Forbidden);
         }
 
@@ -2333,7 +2397,7 @@ impl AccountsApi {
         Path(request_id): Path<String>,
         Json(dto): Json<ServerRequestStatusDto>,
     ) -> Response<ServerRequestAdmin> {
-        if !check_superuser(data, user_token.id).await {
+        if !has_permission(data, user_token.id, Permission::ServersManage, Scope::Global).await {
             return response!(err "Unauthorized", ErrorCode::Forbidden);
         }
 
@@ -2346,6 +2410,8 @@ impl AccountsApi {
             Err(_) => return response!(err "Invalid request ID", ErrorCode::BadRequest),
         };
 
+        let before = server_request_snapshot(data, request_id).await;
+
         // If approving, insert community and server_browser entries
         if dto.status == "approved" {
             let req = match sqlx::query!(
@@ -2404,6 +2470,10 @@ impl AccountsApi {
             }
 
             tracing::info!("Approved server request {}: new community_id={}", request_id, community_id);
+            record_audit(
+                &data.pool, Some(user_token.id), "create", "community", &community_id.to_string(),
+                None, Some(serde_json::json!({ "server_request": request_id, "community_name": req.community_name })),
+            ).await;
         }
 
         let rows_affected = match sqlx::query!(
@@ -2452,8 +2522,408 @@ impl AccountsApi {
             }
         };
 
+        record_audit(
+            &data.pool, Some(user_token.id), "set_status", "server_request", &request_id.to_string(),
+            before, server_request_snapshot(data, request_id).await,
+        ).await;
         response!(ok updated.into())
     }
+
+    // ========================================================================
+    // SESSION ENDPOINTS
+    // ========================================================================
+
+    /// Starts a session for this device from a signed in token. Signing in again on the same
+    /// device replaces its previous session. Session access tokens can't start another one, or
+    /// a stolen one would outlive the session it came from.
+    #[oai(path = "/auth/callback", method = "post")]
+    async fn create_session(
+        &self,
+        req: &Request,
+        Data(data): Data<&AppData>,
+        TokenBearer(user_token): TokenBearer,
+        Json(payload): Json<CreateSessionDto>,
+    ) -> Response<SessionTokens> {
+        if user_token.session_id.is_some() {
+            return response!(err "Sessions can only be started from a sign in token", ErrorCode::Forbidden);
+        }
+        let device_id = payload.device_id.trim();
+        if device_id.is_empty() || device_id.len() > 128 {
+            return response!(err "device_id must be 1-128 characters", ErrorCode::BadRequest);
+        }
+        let device_name = payload.device_name
+            .as_deref()
+            .map(|name| name.trim().chars().take(128).collect::<String>())
+            .filter(|name| !name.is_empty());
+        let user_agent = req.header("User-Agent").map(|ua| ua.chars().take(512).collect::<String>());
+        let ip_address = client_ip(req).map(|ip| ip.to_string());
+        let (refresh_token, refresh_hash) = new_refresh_token();
+
+        let mut tx = match data.pool.begin().await {
+            Ok(tx) => tx,
+            Err(e) => {
+                tracing::error!("Failed to start session transaction: {}", e);
+                return response!(internal_server_error);
+            }
+        };
+        let replaced = sqlx::query_scalar!(
+            "DELETE FROM website.user_refresh_tokens
+             WHERE user_id = $1 AND device_id = $2
+             RETURNING session_id",
+            user_token.id,
+            device_id
+        )
+        .fetch_optional(&mut *tx)
+        .await;
+        let created = sqlx::query!(
+            "INSERT INTO website.user_refresh_tokens
+                (user_id, refresh_token_hash, expires_at, device_id, device_name, user_agent, ip_address)
+             VALUES ($1, $2, NOW() + make_interval(days => $3), $4, $5, $6, $7)
+             RETURNING session_id, expires_at",
+            user_token.id,
+            refresh_hash,
+            refresh_ttl_days() as i32,
+            device_id,
+            device_name,
+            user_agent,
+            ip_address
+        )
+        .fetch_one(&mut *tx)
+        .await;
+
+        let (replaced, created) = match (replaced, created) {
+            (Ok(replaced), Ok(created)) => (replaced, created),
+            (Err(e), _) | (_, Err(e)) => {
+                tracing::error!("Failed to create session: {}", e);
+                return response!(internal_server_error);
+            }
+        };
+        if let Err(e) = tx.commit().await {
+            tracing::error!("Failed to commit session: {}", e);
+            return response!(internal_server_error);
+        }
+        if let Some(replaced) = replaced {
+            deny_session(data, replaced).await;
+        }
+
+        match session_tokens(user_token.id, &user_token.global_name, created.session_id, refresh_token, created.expires_at) {
+            Ok(tokens) => response!(ok tokens),
+            Err(e) => {
+                tracing::error!("Failed to sign access token: {}", e);
+                response!(internal_server_error)
+            }
+        }
+    }
+
+    /// Trades a refresh token for a new access and refresh token. Each refresh token works once,
+    /// presenting one again revokes the whole session since only a copy could do that.
+    #[oai(path = "/auth/refresh", method = "post")]
+    async fn refresh_session(
+        &self,
+        req: &Request,
+        Data(data): Data<&AppData>,
+        Json(payload): Json<RefreshSessionDto>,
+    ) -> Response<SessionTokens> {
+        let presented_hash = hash_refresh_token(&payload.refresh_token);
+        let (refresh_token, refresh_hash) = new_refresh_token();
+        let user_agent = req.header("User-Agent").map(|ua| ua.chars().take(512).collect::<String>());
+        let ip_address = client_ip(req).map(|ip| ip.to_string());
+
+        let mut tx = match data.pool.begin().await {
+            Ok(tx) => tx,
+            Err(e) => {
+                tracing::error!("Failed to start refresh transaction: {}", e);
+                return response!(internal_server_error);
+            }
+        };
+        let rotated = match sqlx::query!(
+            r#"
+            WITH old AS (
+                SELECT session_id, refresh_token_hash
+                FROM website.user_refresh_tokens
+                WHERE refresh_token_hash = $1 AND revoked_at IS NULL AND expires_at > NOW()
+                FOR UPDATE
+            ), used AS (
+                INSERT INTO website.used_refresh_tokens (refresh_token_hash, session_id)
+                SELECT refresh_token_hash, session_id FROM old
+            )
+            UPDATE website.user_refresh_tokens t SET
+                refresh_token_hash = $2,
+                expires_at = NOW() + make_interval(days => $3),
+                updated_at = NOW(),
+                user_agent = COALESCE($4, t.user_agent),
+                ip_address = COALESCE($5, t.ip_address)
+            FROM old
+            WHERE t.session_id = old.session_id
+            RETURNING t.session_id, t.user_id, t.expires_at,
+                (SELECT persona_name FROM website.steam_user u WHERE u.user_id = t.user_id) AS "name?"
+            "#,
+            presented_hash,
+            refresh_hash,
+            refresh_ttl_days() as i32,
+            user_agent,
+            ip_address
+        )
+        .fetch_optional(&mut *tx)
+        .await
+        {
+            Ok(r) => r,
+            Err(e) => {
+                tracing::error!("Failed to rotate refresh token: {}", e);
+                return response!(internal_server_error);
+            }
+        };
+
+        if let Some(rotated) = rotated {
+            if let Err(e) = tx.commit().await {
+                tracing::error!("Failed to commit refresh token rotation: {}", e);
+                return response!(internal_server_error);
+            }
+            let name = rotated.name.unwrap_or_default();
+            return match session_tokens(rotated.user_id, &name, rotated.session_id, refresh_token, rotated.expires_at) {
+                Ok(tokens) => response!(ok tokens),
+                Err(e) => {
+                    tracing::error!("Failed to sign access token: {}", e);
+                    response!(internal_server_error)
+                }
+            }
+        }
+
+        let reused = match sqlx::query!(
+            "UPDATE website.user_refresh_tokens t SET revoked_at = COALESCE(t.revoked_at, NOW())
+             FROM website.used_refresh_tokens u
+             WHERE u.refresh_token_hash = $1 AND t.session_id = u.session_id
+             RETURNING t.session_id, t.user_id",
+            presented_hash
+        )
+        .fetch_optional(&mut *tx)
+        .await
+        {
+            Ok(r) => r,
+            Err(e) => {
+                tracing::error!("Failed to check refresh token reuse: {}", e);
+                return response!(internal_server_error);
+            }
+        };
+        if let Err(e) = tx.commit().await {
+            tracing::error!("Failed to commit refresh token check: {}", e);
+            return response!(internal_server_error);
+        }
+
+        match reused {
+            Some(reused) => {
+                tracing::warn!("Refresh token reused on session {} of {}, revoking it", reused.session_id, reused.user_id);
+                deny_session(data, reused.session_id).await;
+                response!(err "Refresh token was already used, the session has been revoked", ErrorCode::Forbidden)
+            }
+            None => response!(err "Invalid or expired refresh token", ErrorCode::Forbidden),
+        }
+    }
+
+    /// Ends the session the token belongs to
+    #[oai(path = "/auth/logout", method = "post")]
+    async fn logout_session(
+        &self,
+        Data(data): Data<&AppData>,
+        TokenBearer(user_token): TokenBearer,
+    ) -> Response<bool> {
+        let Some(session_id) = user_token.session_id else {
+            return response!(err "This token isn't tied to a session", ErrorCode::BadRequest);
+        };
+        revoke_session(data, user_token.id, session_id).await
+    }
+
+    /// Active sessions of the signed in user, most recently refreshed first
+    #[oai(path = "/accounts/me/sessions", method = "get")]
+    async fn get_my_sessions(
+        &self,
+        Data(data): Data<&AppData>,
+        TokenBearer(user_token): TokenBearer,
+    ) -> Response<Vec<UserSession>> {
+        let result = sqlx::query_as!(
+            DbUserSession,
+            r#"
+            SELECT session_id, device_id, device_name, user_agent, ip_address, created_at, updated_at, expires_at
+            FROM website.user_refresh_tokens
+            WHERE user_id = $1 AND revoked_at IS NULL AND expires_at > NOW()
+            ORDER BY updated_at DESC
+            "#,
+            user_token.id
+        )
+        .fetch_all(&*data.pool)
+        .await;
+
+        match result {
+            Ok(rows) => {
+                let sessions = rows.into_iter().map(|row| {
+                    let current = user_token.session_id == Some(row.session_id);
+                    let mut session: UserSession = row.into();
+                    session.current = current;
+                    session
+                }).collect();
+                response!(ok sessions)
+            }
+            Err(e) => {
+                tracing::error!("Failed to get sessions: {}", e);
+                response!(internal_server_error)
+            }
+        }
+    }
+
+    #[oai(path = "/accounts/me/sessions/:session_id", method = "delete")]
+    async fn revoke_my_session(
+        &self,
+        Data(data): Data<&AppData>,
+        TokenBearer(user_token): TokenBearer,
+        Path(session_id): Path<String>,
+    ) -> Response<bool> {
+        let Ok(session_id) = Uuid::parse_str(&session_id) else {
+            return response!(err "Invalid session ID", ErrorCode::BadRequest);
+        };
+        revoke_session(data, user_token.id, session_id).await
+    }
+
+    /// Revokes every session. Unless `keep_current` is set, every token signed for the user
+    /// so far stops working too, including ones not tied to a session.
+    #[oai(path = "/accounts/me/sessions", method = "delete")]
+    async fn revoke_all_my_sessions(
+        &self,
+        Data(data): Data<&AppData>,
+        TokenBearer(user_token): TokenBearer,
+        Query(keep_current): Query<Option<bool>>,
+    ) -> Response<i64> {
+        let keep = if keep_current.unwrap_or(false) { user_token.session_id } else { None };
+        let revoked = match sqlx::query_scalar!(
+            "UPDATE website.user_refresh_tokens SET revoked_at = NOW()
+             WHERE user_id = $1 AND revoked_at IS NULL AND session_id IS DISTINCT FROM $2
+             RETURNING session_id",
+            user_token.id,
+            keep
+        )
+        .fetch_all(&*data.pool)
+        .await
+        {
+            Ok(r) => r,
+            Err(e) => {
+                tracing::error!("Failed to revoke sessions: {}", e);
+                return response!(internal_server_error);
+            }
+        };
+
+        for session_id in &revoked {
+            deny_session(data, *session_id).await;
+        }
+        if !keep_current.unwrap_or(false) {
+            deny_tokens_before_now(data, user_token.id).await;
+        }
+        response!(ok revoked.len() as i64)
+    }
+}
+
+// ========================================================================
+// SESSIONS
+// ========================================================================
+
+fn session_tokens(
+    user_id: i64,
+    name: &str,
+    session_id: Uuid,
+    refresh_token: String,
+    refresh_expires_at: OffsetDateTime,
+) -> Result<SessionTokens, jsonwebtoken::Error> {
+    let (access_token, access_exp) = issue_access_token(user_id, name, session_id)?;
+    Ok(SessionTokens {
+        session_id: session_id.to_string(),
+        access_token,
+        access_token_expires_at: DateTime::from_timestamp(access_exp as i64, 0).unwrap_or_default(),
+        refresh_token,
+        refresh_token_expires_at: db_to_utc(refresh_expires_at),
+    })
+}
+
+async fn revoke_session(data: &AppData, user_id: i64, session_id: Uuid) -> Response<bool> {
+    let result = sqlx::query!(
+        "UPDATE website.user_refresh_tokens SET revoked_at = NOW()
+         WHERE session_id = $1 AND user_id = $2 AND revoked_at IS NULL",
+        session_id,
+        user_id
+    )
+    .execute(&*data.pool)
+    .await;
+
+    match result {
+        Ok(r) if r.rows_affected() == 0 => response!(err "Session not found", ErrorCode::NotFound),
+        Ok(_) => {
+            deny_session(data, session_id).await;
+            response!(ok true)
+        }
+        Err(e) => {
+            tracing::error!("Failed to revoke session: {}", e);
+            response!(internal_server_error)
+        }
+    }
+}
+
+// ========================================================================
+// AUDIT SNAPSHOTS
+// ========================================================================
+
+async fn report_snapshot(data: &AppData, table: &str, report_id: Uuid) -> Option<serde_json::Value> {
+    let snapshot = match table {
+        "report_guide" => sqlx::query_scalar!(
+            "SELECT to_jsonb(r) FROM website.report_guide r WHERE r.id = $1", report_id
+        ).fetch_optional(&*data.pool).await,
+        "report_guide_comment" => sqlx::query_scalar!(
+            "SELECT to_jsonb(r) FROM website.report_guide_comment r WHERE r.id = $1", report_id
+        ).fetch_optional(&*data.pool).await,
+        _ => sqlx::query_scalar!(
+            "SELECT to_jsonb(r) FROM website.report_map_music r WHERE r.id = $1", report_id
+        ).fetch_optional(&*data.pool).await,
+    };
+    snapshot.ok().flatten().flatten()
+}
+
+async fn map_music_snapshot(data: &AppData, music_id: Uuid) -> Option<serde_json::Value> {
+    sqlx::query_scalar!(
+        "SELECT jsonb_build_object('youtube_music', youtube_music, 'yt_source', yt_source) FROM map_music WHERE id = $1",
+        music_id
+    )
+    .fetch_optional(&*data.pool)
+    .await
+    .ok()
+    .flatten()
+    .flatten()
+}
+
+async fn guide_ban_snapshot(data: &AppData, user_id: i64) -> Option<serde_json::Value> {
+    sqlx::query_scalar!("SELECT to_jsonb(b) FROM website.guide_user_ban b WHERE b.user_id = $1", user_id)
+        .fetch_optional(&*data.pool)
+        .await
+        .ok()
+        .flatten()
+        .flatten()
+}
+
+async fn announcement_snapshot(data: &AppData, id: &str) -> Option<serde_json::Value> {
+    sqlx::query_scalar!("SELECT to_jsonb(a) FROM website.announce a WHERE a.id = $1::TEXT::UUID", id)
+        .fetch_optional(&*data.pool)
+        .await
+        .ok()
+        .flatten()
+        .flatten()
+}
+
+async fn server_request_snapshot(data: &AppData, request_id: Uuid) -> Option<serde_json::Value> {
+    sqlx::query_scalar!(
+        "SELECT jsonb_build_object('status', status, 'reviewed_by', reviewed_by, 'reviewed_at', reviewed_at)
+         FROM website.server_requests WHERE id = $1",
+        request_id
+    )
+    .fetch_optional(&*data.pool)
+    .await
+    .ok()
+    .flatten()
+    .flatten()
 }
 impl UriPatternExt for AccountsApi{
     fn get_all_patterns(&self) -> Vec<RoutePattern<'_>> {
@@ -2461,9 +2931,13 @@ impl UriPatternExt for AccountsApi{
             "/auth/refresh",
             "/auth/callback",
             "/auth/logout",
+            "/accounts/create",
             "/accounts/me",
+            "/accounts/me/communities",
             "/accounts/me/anonymize",
             "/accounts/me/guide-ban",
+            "/accounts/me/sessions",
+            "/accounts/me/sessions/{session_id}",
             "/accounts/{user_id}/anonymize",
             "/admin/reports/guides",
             "/admin/reports/comments",
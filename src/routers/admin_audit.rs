@@ -0,0 +1,215 @@
+use chrono::{DateTime, Utc};
+use poem::web::Data;
+use poem_openapi::{Object, OpenApi};
+use poem_openapi::param::Query;
+use serde::{Deserialize, Serialize};
+use sqlx::types::time::OffsetDateTime;
+
+use crate::core::api_models::*;
+use crate::core::model::DbAccountDeletion;
+use crate::core::permissions::{has_permission, Permission, Scope};
+use crate::core::utils::*;
+use crate::routers::account_deletion::AccountDeletion;
+use crate::{response, AppData};
+
+pub struct AdminAuditApi;
+
+#[derive(Debug, Serialize, Deserialize, Object, Clone)]
+pub struct AuditEntry {
+    pub id: i64,
+    /// `None` for background jobs and deleted accounts
+    pub actor_id: Option<String>,
+    pub actor_name: Option<String>,
+    pub action: String,
+    pub target_type: String,
+    pub target_id: String,
+    /// Changed fields before the action, the whole target for deletes
+    pub before: Option<serde_json::Value>,
+    /// Changed fields after the action, the whole target for creates
+    pub after: Option<serde_json::Value>,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Object, Clone)]
+pub struct AuditLogPaginated {
+    pub total: i64,
+    pub entries: Vec<AuditEntry>,
+}
+
+struct DbAuditEntry {
+    id: i64,
+    total: Option<i64>,
+    actor_id: Option<i64>,
+    actor_name: Option<String>,
+    action: String,
+    target_type: String,
+    target_id: String,
+    before: Option<serde_json::Value>,
+    after: Option<serde_json::Value>,
+    created_at: OffsetDateTime,
+}
+
+impl From<DbAuditEntry> for AuditEntry {
+    fn from(e: DbAuditEntry) -> Self {
+        AuditEntry {
+            id: e.id,
+            actor_id: e.actor_id.map(|id| id.to_string()),
+            actor_name: e.actor_name,
+            action: e.action,
+            target_type: e.target_type,
+            target_id: e.target_id,
+            before: e.before,
+            after: e.after,
+            created_at: db_to_utc(e.created_at),
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Object, Clone)]
+pub struct AccountDeletionsPaginated {
+    pub total: i64,
+    pub deletions: Vec<AccountDeletion>,
+}
+
+#[OpenApi]
+impl AdminAuditApi {
+    /// Admin actions, newest first
+    #[oai(path = "/admin/audit", method = "get")]
+    #[allow(clippy::too_many_arguments)]
+    async fn get_audit_log(
+        &self,
+        Data(data): Data<&AppData>,
+        TokenBearer(user_token): TokenBearer,
+        Query(actor_id): Query<Option<i64>>,
+        Query(action): Query<Option<String>>,
+        Query(target_type): Query<Option<String>>,
+        Query(target_id): Query<Option<String>>,
+        Query(since): Query<Option<DateTime<Utc>>>,
+        Query(until): Query<Option<DateTime<Utc>>>,
+        Query(page): Query<Option<i64>>,
+        Query(limit): Query<Option<i64>>,
+    ) -> Response<AuditLogPaginated> {
+        if !has_permission(data, user_token.id, Permission::AuditView, Scope::Global).await {
+            return response!(err "Unauthorized", ErrorCode::Forbidden);
+        }
+
+        let page = page.unwrap_or(1).max(1);
+        let limit = limit.unwrap_or(50).min(200).max(1);
+        let offset = (page - 1) * limit;
+
+        let rows = match sqlx::query_as!(
+            DbAuditEntry,
+            r#"
+            SELECT
+                a.id,
+                COUNT(*) OVER() AS total,
+                a.actor_id,
+                u.persona_name AS "actor_name?",
+                a.action,
+                a.target_type,
+                a.target_id,
+                a.before,
+                a.after,
+                a.created_at
+            FROM website.audit_log a
+            LEFT JOIN website.steam_user u ON u.user_id = a.actor_id
+            WHERE ($1::BIGINT IS NULL OR a.actor_id = $1)
+              AND ($2::TEXT IS NULL OR a.action = $2)
+              AND ($3::TEXT IS NULL OR a.target_type = $3)
+              AND ($4::TEXT IS NULL OR a.target_id = $4)
+              AND ($5::TIMESTAMPTZ IS NULL OR a.created_at >= $5)
+              AND ($6::TIMESTAMPTZ IS NULL OR a.created_at < $6)
+            ORDER BY a.created_at DESC, a.id DESC
+            LIMIT $7 OFFSET $8
+            "#,
+            actor_id,
+            action,
+            target_type,
+            target_id,
+            since.map(|t| t.to_db_time()),
+            until.map(|t| t.to_db_time()),
+            limit,
+            offset
+        )
+        .fetch_all(&*data.pool)
+        .await
+        {
+            Ok(r) => r,
+            Err(e) => {
+                tracing::error!("Failed to fetch audit log: {}", e);
+                return response!(internal_server_error);
+            }
+        };
+
+        let total = rows.first().and_then(|r| r.total).unwrap_or(0);
+        response!(ok AuditLogPaginated {
+            total,
+            entries: rows.into_iter().map(Into::into).collect(),
+        })
+    }
+
+    /// Account deletions, newest first. Finished ones are the confirmation that an account
+    /// was deleted and what it took.
+    #[oai(path = "/admin/account-deletions", method = "get")]
+    async fn get_account_deletions(
+        &self,
+        Data(data): Data<&AppData>,
+        TokenBearer(user_token): TokenBearer,
+        Query(status): Query<Option<String>>,
+        Query(page): Query<Option<i64>>,
+        Query(limit): Query<Option<i64>>,
+    ) -> Response<AccountDeletionsPaginated> {
+        if !has_permission(data, user_token.id, Permission::AuditView, Scope::Global).await {
+            return response!(err "Unauthorized", ErrorCode::Forbidden);
+        }
+
+        let page = page.unwrap_or(1).max(1);
+        let limit = limit.unwrap_or(50).min(200).max(1);
+        let offset = (page - 1) * limit;
+
+        let total = match sqlx::query_scalar!(
+            "SELECT COUNT(*) FROM website.account_deletion WHERE ($1::TEXT IS NULL OR status = $1)",
+            status
+        )
+        .fetch_one(&*data.pool)
+        .await
+        {
+            Ok(count) => count.unwrap_or(0),
+            Err(e) => {
+                tracing::error!("Failed to count account deletions: {}", e);
+                return response!(internal_server_error);
+            }
+        };
+        match sqlx::query_as!(
+            DbAccountDeletion,
+            "SELECT * FROM website.account_deletion
+             WHERE ($1::TEXT IS NULL OR status = $1)
+             ORDER BY requested_at DESC
+             LIMIT $2 OFFSET $3",
+            status,
+            limit,
+            offset
+        )
+        .fetch_all(&*data.pool)
+        .await
+        {
+            Ok(rows) => response!(ok AccountDeletionsPaginated {
+                total,
+                deletions: rows.into_iter().map(Into::into).collect(),
+            }),
+            Err(e) => {
+                tracing::error!("Failed to fetch account deletions: {}", e);
+                response!(internal_server_error)
+            }
+        }
+    }
+}
+
+impl UriPatternExt for AdminAuditApi {
+    fn get_all_patterns(&self) -> Vec<RoutePattern<'_>> {
+        vec![
+            "/admin/audit",
+            "/admin/account-deletions",
+        ].iter_into()
+    }
+}
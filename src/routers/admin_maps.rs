@@ -3,9 +3,14 @@ use poem_openapi::payload::Json;
 use poem_openapi::{Object, OpenApi};
 use poem_openapi::param::Query;
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
+use chrono::{DateTime, Utc};
+use sqlx::types::time::OffsetDateTime;
 
 use crate::core::api_models::*;
+use crate::core::asset_storage::StoredObject;
+use crate::core::audit::record_audit;
+use crate::core::permissions::{has_permission, Permission, Scope};
 use crate::core::utils::*;
 use crate::{response, AppData};
 
@@ -43,6 +48,86 @@ pub struct AdminMapMetadataResponse {
     pub maps: Vec<AdminMapEntry>,
 }
 
+#[derive(Object, Serialize)]
+pub struct AdminModelJob {
+    pub id: i32,
+    /// `{"kind": "map_model", "map_name", "res_type"}` or `{"kind": "character_model", "server_id", "model_id"}`
+    pub target: serde_json::Value,
+    /// queued, running, done or failed
+    pub status: String,
+    pub error: Option<String>,
+    pub attempts: i32,
+    pub created_at: DateTime<Utc>,
+    pub started_at: Option<DateTime<Utc>>,
+    pub finished_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Object, Serialize)]
+pub struct AdminModelJobsResponse {
+    pub total: i64,
+    pub jobs: Vec<AdminModelJob>,
+}
+
+#[derive(Object, Serialize)]
+pub struct StorageUsageEntry {
+    /// Map name or server id
+    pub name: String,
+    pub bytes: i64,
+    pub objects: i64,
+}
+
+#[derive(Object, Serialize)]
+pub struct StorageUploaderUsage {
+    pub user_id: Option<i64>,
+    pub persona_name: Option<String>,
+    pub bytes: i64,
+    pub models: i64,
+}
+
+#[derive(Object, Serialize, Clone)]
+pub struct StorageOrphan {
+    /// map or character
+    pub kind: String,
+    pub key: String,
+    pub bytes: i64,
+    pub modified_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Object, Serialize)]
+pub struct StorageDanglingRow {
+    /// map_model, map_model_version or character_model
+    pub kind: String,
+    pub row_id: i32,
+    /// `map_name/res_type` or `server_id/model_id`
+    pub name: String,
+    pub key: String,
+}
+
+#[derive(Object, Serialize)]
+pub struct AdminStorageReport {
+    pub map_bytes: i64,
+    pub character_bytes: i64,
+    /// Stored bytes per map, every version and orphan included
+    pub by_map: Vec<StorageUsageEntry>,
+    /// Character model bytes per server
+    pub by_server: Vec<StorageUsageEntry>,
+    /// Bytes of every model version each user uploaded
+    pub by_uploader: Vec<StorageUploaderUsage>,
+    /// Objects no row points at
+    pub orphans: Vec<StorageOrphan>,
+    /// Rows whose object is missing from storage
+    pub dangling_rows: Vec<StorageDanglingRow>,
+}
+
+#[derive(Object, Serialize)]
+pub struct AdminStorageGcResult {
+    pub applied: bool,
+    /// Orphans old enough to be collected
+    pub orphans: Vec<StorageOrphan>,
+    pub deleted: i64,
+    pub failed: Vec<String>,
+}
+
 // ─── Request DTOs ─────────────────────────────────────────────────────────────
 
 #[derive(Object, Deserialize)]
@@ -100,6 +185,207 @@ struct DbAdminMapServerRow {
     max_players: Option<i16>,
 }
 
+struct DbAdminModelJobRow {
+    id: i32,
+    total: Option<i64>,
+    target: serde_json::Value,
+    status: String,
+    error: Option<String>,
+    attempts: i32,
+    created_at: OffsetDateTime,
+    started_at: Option<OffsetDateTime>,
+    finished_at: Option<OffsetDateTime>,
+}
+
+struct DbStorageMapRow {
+    id: i32,
+    map_name: String,
+    res_type: String,
+    content_hash: Option<String>,
+    uploaded_by: Option<i64>,
+    file_size: i64,
+}
+
+struct DbStorageCharacterRow {
+    id: i32,
+    server_id: String,
+    model_id: String,
+    uploaded_by: Option<i64>,
+    file_size: i64,
+}
+
+/// Objects younger than this may belong to an upload whose row is not written yet.
+const ORPHAN_GRACE_SECS: i64 = 3600;
+
+// ─── Storage reconciliation ───────────────────────────────────────────────────
+
+async fn build_storage_report(data: &AppData) -> Result<AdminStorageReport, String> {
+    let map_objects = data.map_storage.list().await?;
+    let character_objects = data.character_storage.list().await?;
+
+    let versions = sqlx::query_as!(
+        DbStorageMapRow,
+        r#"SELECT id, map_name, res_type, content_hash AS "content_hash?", uploaded_by, file_size
+           FROM website.map_3d_model_version"#
+    )
+    .fetch_all(&*data.pool)
+    .await
+    .map_err(|e| format!("Failed to fetch model versions: {e}"))?;
+    let current = sqlx::query_as!(
+        DbStorageMapRow,
+        "SELECT id, map_name, res_type, content_hash, uploaded_by, file_size FROM website.map_3d_model"
+    )
+    .fetch_all(&*data.pool)
+    .await
+    .map_err(|e| format!("Failed to fetch map models: {e}"))?;
+    let characters = sqlx::query_as!(
+        DbStorageCharacterRow,
+        "SELECT id, server_id, model_id, uploaded_by, file_size FROM website.character_3d_model"
+    )
+    .fetch_all(&*data.pool)
+    .await
+    .map_err(|e| format!("Failed to fetch character models: {e}"))?;
+
+    let map_key = |row: &DbStorageMapRow| data.map_storage.object_key(
+        (row.map_name.as_str(), row.res_type.as_str(), row.content_hash.as_deref())
+    );
+    let stored_maps: HashSet<&str> = map_objects.iter().map(|o| o.key.as_str()).collect();
+    let stored_characters: HashSet<&str> = character_objects.iter().map(|o| o.key.as_str()).collect();
+
+    let mut dangling_rows = vec![];
+    let mut referenced_maps = HashSet::new();
+    for (kind, rows) in [("map_model_version", &versions), ("map_model", &current)] {
+        for row in rows {
+            let key = map_key(row);
+            if !stored_maps.contains(key.as_str()) {
+                dangling_rows.push(StorageDanglingRow {
+                    kind: kind.to_string(),
+                    row_id: row.id,
+                    name: format!("{}/{}", row.map_name, row.res_type),
+                    key: key.clone(),
+                });
+            }
+            referenced_maps.insert(key);
+        }
+    }
+    let mut referenced_characters = HashSet::new();
+    for row in &characters {
+        let key = data.character_storage.object_key(&row.model_id);
+        if !stored_characters.contains(key.as_str()) {
+            dangling_rows.push(StorageDanglingRow {
+                kind: "character_model".to_string(),
+                row_id: row.id,
+                name: format!("{}/{}", row.server_id, row.model_id),
+                key: key.clone(),
+            });
+        }
+        referenced_characters.insert(key);
+    }
+
+    let orphan = |kind: &str, object: &StoredObject| StorageOrphan {
+        kind: kind.to_string(),
+        key: object.key.clone(),
+        bytes: object.size as i64,
+        modified_at: object.modified_at,
+    };
+    let orphans = map_objects.iter()
+        .filter(|o| !referenced_maps.contains(&o.key))
+        .map(|o| orphan("map", o))
+        .chain(character_objects.iter()
+            .filter(|o| !referenced_characters.contains(&o.key))
+            .map(|o| orphan("character", o)))
+        .collect();
+
+    // Map keys are `{prefix}/{map_name}/...`, the map is the first segment after the prefix.
+    let prefix = data.map_storage.object_prefix();
+    let prefix_len = if prefix.is_empty() { 0 } else { prefix.split('/').count() };
+    let mut by_map: HashMap<String, (i64, i64)> = HashMap::new();
+    for object in &map_objects {
+        let Some(map_name) = object.key.split('/').nth(prefix_len) else {
+            continue
+        };
+        let entry = by_map.entry(map_name.to_string()).or_default();
+        entry.0 += object.size as i64;
+        entry.1 += 1;
+    }
+    let mut by_server: HashMap<String, (i64, i64)> = HashMap::new();
+    let mut uploaders: HashMap<Option<i64>, (i64, i64)> = HashMap::new();
+    for row in &characters {
+        let entry = by_server.entry(row.server_id.clone()).or_default();
+        entry.0 += row.file_size;
+        entry.1 += 1;
+    }
+    // Legacy map models have no version row, they count through their current row.
+    let legacy = current.iter().filter(|row| row.content_hash.is_none());
+    let uploads = versions.iter().chain(legacy).map(|row| (row.uploaded_by, row.file_size))
+        .chain(characters.iter().map(|row| (row.uploaded_by, row.file_size)));
+    for (uploaded_by, file_size) in uploads {
+        let entry = uploaders.entry(uploaded_by).or_default();
+        entry.0 += file_size;
+        entry.1 += 1;
+    }
+
+    let user_ids: Vec<i64> = uploaders.keys().flatten().copied().collect();
+    let names: HashMap<i64, Option<String>> = sqlx::query!(
+        "SELECT user_id, persona_name FROM website.steam_user WHERE user_id = ANY($1)",
+        &user_ids as &[i64]
+    )
+    .fetch_all(&*data.pool)
+    .await
+    .map_err(|e| format!("Failed to fetch uploader names: {e}"))?
+    .into_iter()
+    .map(|row| (row.user_id, row.persona_name))
+    .collect();
+
+    let usage = |entries: HashMap<String, (i64, i64)>| {
+        let mut entries: Vec<StorageUsageEntry> = entries.into_iter()
+            .map(|(name, (bytes, objects))| StorageUsageEntry { name, bytes, objects })
+            .collect();
+        entries.sort_by(|a, b| b.bytes.cmp(&a.bytes));
+        entries
+    };
+    let mut by_uploader: Vec<StorageUploaderUsage> = uploaders.into_iter()
+        .map(|(user_id, (bytes, models))| StorageUploaderUsage {
+            user_id,
+            persona_name: user_id.and_then(|id| names.get(&id).cloned().flatten()),
+            bytes,
+            models,
+        })
+        .collect();
+    by_uploader.sort_by(|a, b| b.bytes.cmp(&a.bytes));
+
+    Ok(AdminStorageReport {
+        map_bytes: map_objects.iter().map(|o| o.size as i64).sum(),
+        character_bytes: character_objects.iter().map(|o| o.size as i64).sum(),
+        by_map: usage(by_map),
+        by_server: usage(by_server),
+        by_uploader,
+        orphans,
+        dangling_rows,
+    })
+}
+
+async fn map_metadata_snapshot(data: &AppData, map_name: &str) -> Option<serde_json::Value> {
+    sqlx::query_scalar!("SELECT to_jsonb(m) FROM map_metadata m WHERE m.name = $1", map_name)
+        .fetch_optional(&*data.pool)
+        .await
+        .ok()
+        .flatten()
+        .flatten()
+}
+
+async fn server_map_snapshot(data: &AppData, server_id: &str, map_name: &str) -> Option<serde_json::Value> {
+    sqlx::query_scalar!(
+        "SELECT to_jsonb(sm) FROM server_map sm WHERE sm.server_id = $1 AND sm.map = $2",
+        server_id, map_name
+    )
+    .fetch_optional(&*data.pool)
+    .await
+    .ok()
+    .flatten()
+    .flatten()
+}
+
 // ─── API ──────────────────────────────────────────────────────────────────────
 
 #[OpenApi]
@@ -114,7 +400,7 @@ impl AdminMapsApi {
         Query(limit): Query<Option<i64>>,
         Query(search): Query<Option<String>>,
     ) -> Response<AdminMapMetadataResponse> {
-        if !check_superuser(data, user_token.id).await {
+        if !has_permission(data, user_token.id, Permission::MapsMetadataEdit, Scope::Global).await {
             return response!(err "Unauthorized", ErrorCode::Forbidden);
         }
 
@@ -238,10 +524,11 @@ impl AdminMapsApi {
         TokenBearer(user_token): TokenBearer,
         Json(dto): Json<UpdateGlobalMapMetadataDto>,
     ) -> Response<bool> {
-        if !check_superuser(data, user_token.id).await {
+        if !has_permission(data, user_token.id, Permission::MapsMetadataEdit, Scope::Global).await {
             return response!(err "Unauthorized", ErrorCode::Forbidden);
         }
 
+        let before = map_metadata_snapshot(data, &dto.map_name).await;
         match sqlx::query!(
             r#"
             INSERT INTO map_metadata (name, workshop_id, is_tryhard, is_casual, resolved_workshop_id, has_lasers)
@@ -263,7 +550,13 @@ impl AdminMapsApi {
         .execute(&*data.pool)
         .await
         {
-            Ok(_) => response!(ok true),
+            Ok(_) => {
+                record_audit(
+                    &data.pool, Some(user_token.id), "update_metadata", "map", &dto.map_name,
+                    before, map_metadata_snapshot(data, &dto.map_name).await,
+                ).await;
+                response!(ok true)
+            }
             Err(e) => {
                 tracing::error!("Failed to update global map metadata for {}: {}", dto.map_name, e);
                 response!(internal_server_error)
@@ -279,10 +572,24 @@ impl AdminMapsApi {
         TokenBearer(user_token): TokenBearer,
         poem_openapi::param::Path(map_name): poem_openapi::param::Path<String>,
     ) -> Response<bool> {
-        if !check_superuser(data, user_token.id).await {
+        if !has_permission(data, user_token.id, Permission::MapsMetadataEdit, Scope::Global).await {
             return response!(err "Unauthorized", ErrorCode::Forbidden);
         }
 
+        let before = sqlx::query_scalar!(
+            r#"
+            SELECT jsonb_build_object(
+                'metadata', (SELECT to_jsonb(m) FROM map_metadata m WHERE m.name = $1),
+                'servers', (SELECT jsonb_agg(to_jsonb(sm)) FROM server_map sm WHERE sm.map = $1)
+            )
+            "#,
+            map_name
+        )
+        .fetch_one(&*data.pool)
+        .await
+        .ok()
+        .flatten();
+
         let mut tx = match data.pool.begin().await {
             Ok(t) => t,
             Err(e) => {
@@ -311,6 +618,7 @@ impl AdminMapsApi {
             return response!(internal_server_error);
         }
 
+        record_audit(&data.pool, Some(user_token.id), "delete", "map", &map_name, before, None).await;
         response!(ok true)
     }
 
@@ -322,7 +630,7 @@ impl AdminMapsApi {
         TokenBearer(user_token): TokenBearer,
         Json(dto): Json<UpdateServerMapMetadataDto>,
     ) -> Response<bool> {
-        if !check_superuser(data, user_token.id).await {
+        if !has_permission(data, user_token.id, Permission::MapsMetadataEdit, Scope::Global).await {
             return response!(err "Unauthorized", ErrorCode::Forbidden);
         }
 
@@ -340,6 +648,7 @@ impl AdminMapsApi {
             return response!(err "Map not found for this server", ErrorCode::NotFound);
         }
 
+        let before = server_map_snapshot(data, &dto.server_id, &dto.map_name).await;
         match sqlx::query!(
             r#"
             UPDATE server_map SET
@@ -365,7 +674,14 @@ impl AdminMapsApi {
         .execute(&*data.pool)
         .await
         {
-            Ok(_) => response!(ok true),
+            Ok(_) => {
+                record_audit(
+                    &data.pool, Some(user_token.id), "update_metadata", "server_map",
+                    &format!("{}/{}", dto.server_id, dto.map_name),
+                    before, server_map_snapshot(data, &dto.server_id, &dto.map_name).await,
+                ).await;
+                response!(ok true)
+            }
             Err(e) => {
                 tracing::error!(
                     "Failed to update server map metadata for {} / {}: {}",
@@ -375,6 +691,179 @@ impl AdminMapsApi {
             }
         }
     }
+
+    /// Background jobs for uploaded 3D models, newest first
+    #[oai(path = "/admin/models/jobs", method = "get")]
+    async fn get_model_jobs(
+        &self,
+        Data(data): Data<&AppData>,
+        TokenBearer(user_token): TokenBearer,
+        Query(status): Query<Option<String>>,
+        Query(page): Query<Option<i64>>,
+        Query(limit): Query<Option<i64>>,
+    ) -> Response<AdminModelJobsResponse> {
+        if !has_permission(data, user_token.id, Permission::ModelsManage, Scope::Global).await {
+            return response!(err "Unauthorized", ErrorCode::Forbidden);
+        }
+
+        let page = page.unwrap_or(1).max(1);
+        let limit = limit.unwrap_or(50).min(200).max(1);
+        let offset = (page - 1) * limit;
+
+        let rows = match sqlx::query_as!(
+            DbAdminModelJobRow,
+            r#"
+            SELECT id, COUNT(*) OVER() AS total, target, status, error, attempts,
+                   created_at, started_at, finished_at
+            FROM website.model_job
+            WHERE ($1::TEXT IS NULL OR status = $1)
+            ORDER BY created_at DESC
+            LIMIT $2 OFFSET $3
+            "#,
+            status,
+            limit,
+            offset
+        )
+        .fetch_all(&*data.pool)
+        .await
+        {
+            Ok(r) => r,
+            Err(e) => {
+                tracing::error!("Failed to fetch model jobs: {}", e);
+                return response!(internal_server_error);
+            }
+        };
+
+        let total = rows.first().and_then(|r| r.total).unwrap_or(0);
+        let jobs = rows
+            .into_iter()
+            .map(|row| AdminModelJob {
+                id: row.id,
+                target: row.target,
+                status: row.status,
+                error: row.error,
+                attempts: row.attempts,
+                created_at: db_to_utc(row.created_at),
+                started_at: row.started_at.map(db_to_utc),
+                finished_at: row.finished_at.map(db_to_utc),
+            })
+            .collect();
+
+        response!(ok AdminModelJobsResponse { total, jobs })
+    }
+
+    /// Put a failed model job back in the queue
+    #[oai(path = "/admin/models/jobs/:job_id/retry", method = "post")]
+    async fn retry_model_job(
+        &self,
+        Data(data): Data<&AppData>,
+        TokenBearer(user_token): TokenBearer,
+        poem_openapi::param::Path(job_id): poem_openapi::param::Path<i32>,
+    ) -> Response<bool> {
+        if !has_permission(data, user_token.id, Permission::ModelsManage, Scope::Global).await {
+            return response!(err "Unauthorized", ErrorCode::Forbidden);
+        }
+
+        match sqlx::query!(
+            r#"
+            UPDATE website.model_job
+            SET status = 'queued', error = NULL, attempts = 0, started_at = NULL, finished_at = NULL
+            WHERE id = $1 AND status = 'failed'
+            "#,
+            job_id
+        )
+        .execute(&*data.pool)
+        .await
+        {
+            Ok(r) if r.rows_affected() == 0 => response!(err "No failed job with this id", ErrorCode::NotFound),
+            Ok(_) => {
+                record_audit(&data.pool, Some(user_token.id), "retry", "model_job", &job_id.to_string(), None, None).await;
+                response!(ok true)
+            }
+            Err(e) => {
+                tracing::error!("Failed to retry model job {}: {}", job_id, e);
+                response!(internal_server_error)
+            }
+        }
+    }
+
+    /// Storage usage per map, server and uploader, plus orphan objects and dangling rows
+    #[oai(path = "/admin/storage/report", method = "get")]
+    async fn get_storage_report(
+        &self,
+        Data(data): Data<&AppData>,
+        TokenBearer(user_token): TokenBearer,
+    ) -> Response<AdminStorageReport> {
+        if !has_permission(data, user_token.id, Permission::ModelsManage, Scope::Global).await {
+            return response!(err "Unauthorized", ErrorCode::Forbidden);
+        }
+
+        match build_storage_report(data).await {
+            Ok(report) => response!(ok report),
+            Err(e) => {
+                tracing::error!("Failed to build storage report: {}", e);
+                response!(internal_server_error)
+            }
+        }
+    }
+
+    /// Delete objects no model row points at. Dry run unless `apply=true`.
+    #[oai(path = "/admin/storage/gc", method = "post")]
+    async fn collect_storage_garbage(
+        &self,
+        Data(data): Data<&AppData>,
+        TokenBearer(user_token): TokenBearer,
+        Query(apply): Query<Option<bool>>,
+    ) -> Response<AdminStorageGcResult> {
+        if !has_permission(data, user_token.id, Permission::ModelsManage, Scope::Global).await {
+            return response!(err "Unauthorized", ErrorCode::Forbidden);
+        }
+        let applied = apply.unwrap_or(false);
+
+        let report = match build_storage_report(data).await {
+            Ok(report) => report,
+            Err(e) => {
+                tracing::error!("Failed to build storage report: {}", e);
+                return response!(internal_server_error);
+            }
+        };
+
+        let cutoff = Utc::now() - chrono::Duration::seconds(ORPHAN_GRACE_SECS);
+        let orphans: Vec<StorageOrphan> = report.orphans
+            .into_iter()
+            .filter(|o| o.modified_at.map(|m| m < cutoff).unwrap_or(true))
+            .collect();
+
+        let mut deleted = 0;
+        let mut failed = vec![];
+        if applied {
+            for orphan in &orphans {
+                let result = match orphan.kind.as_str() {
+                    "map" => data.map_storage.delete_key(&orphan.key).await,
+                    _ => data.character_storage.delete_key(&orphan.key).await,
+                };
+                match result {
+                    Ok(()) => deleted += 1,
+                    Err(e) => {
+                        tracing::warn!("Failed to delete orphan {}: {}", orphan.key, e);
+                        failed.push(orphan.key.clone());
+                    }
+                }
+            }
+            tracing::info!("Storage GC by {} deleted {} orphans", user_token.id, deleted);
+            let removed: Vec<&str> = orphans
+                .iter()
+                .map(|o| o.key.as_str())
+                .filter(|key| !failed.iter().any(|f| f == key))
+                .collect();
+            record_audit(
+                &data.pool, Some(user_token.id), "gc", "storage", "models",
+                Some(serde_json::json!({ "keys": removed })), None,
+            ).await;
+        }
+
+        response!(ok AdminStorageGcResult { applied, orphans, deleted, failed })
+    }
 }
 
 impl UriPatternExt for AdminMapsApi {
@@ -383,7 +872,11 @@ impl UriPatternExt for AdminMapsApi {
             "/admin/maps/metadata",
             "/admin/maps/metadata/global",
             "/admin/maps/metadata/server",
-            "/admin/maps/:map_name",
+            "/admin/maps/{map_name}",
+            "/admin/models/jobs",
+            "/admin/models/jobs/{job_id}/retry",
+            "/admin/storage/report",
+            "/admin/storage/gc",
         ].iter_into()
     }
 }
@@ -0,0 +1,834 @@
+use chrono::{DateTime, Utc};
+use poem::web::Data;
+use poem_openapi::payload::Json;
+use poem_openapi::{Object, OpenApi};
+use poem_openapi::param::Path;
+use serde::{Deserialize, Serialize};
+use sqlx::types::time::OffsetDateTime;
+use uuid::Uuid;
+
+use crate::core::api_models::*;
+use crate::core::audit::record_audit;
+use crate::core::permissions::{has_permission, has_permission_anywhere, Permission, Scope};
+use crate::core::utils::*;
+use crate::{response, AppData};
+
+pub struct AdminRolesApi;
+
+// ─── Roles ────────────────────────────────────────────────────────────────────
+
+#[derive(Debug, Serialize, Deserialize, Object, Clone)]
+pub struct AdminRole {
+    pub id: i32,
+    pub name: String,
+    pub description: Option<String>,
+    /// Permission names, `*` for all of them
+    pub permissions: Vec<String>,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Object)]
+pub struct CreateRolePayload {
+    pub name: String,
+    pub description: Option<String>,
+    pub permissions: Vec<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Object)]
+pub struct UpdateRolePayload {
+    pub description: Option<String>,
+    pub permissions: Option<Vec<String>>,
+}
+
+struct DbRole {
+    id: i32,
+    name: String,
+    description: Option<String>,
+    permissions: Vec<String>,
+    created_at: OffsetDateTime,
+}
+
+impl From<DbRole> for AdminRole {
+    fn from(r: DbRole) -> Self {
+        AdminRole {
+            id: r.id,
+            name: r.name,
+            description: r.description,
+            permissions: r.permissions,
+            created_at: db_to_utc(r.created_at),
+        }
+    }
+}
+
+// ─── Grants ───────────────────────────────────────────────────────────────────
+
+#[derive(Debug, Serialize, Deserialize, Object, Clone)]
+pub struct RoleGrant {
+    pub id: i64,
+    pub user_id: String,
+    pub role_id: i32,
+    pub role_name: String,
+    pub permissions: Vec<String>,
+    /// global, community or server
+    pub scope: String,
+    pub community_id: Option<String>,
+    pub community_name: Option<String>,
+    pub server_id: Option<String>,
+    pub server_name: Option<String>,
+    pub granted_by: Option<String>,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Object)]
+pub struct GrantRolePayload {
+    pub role_id: i32,
+    /// Leave both empty for a global grant
+    pub community_id: Option<String>,
+    pub server_id: Option<String>,
+}
+
+struct DbRoleGrant {
+    id: i64,
+    user_id: i64,
+    role_id: i32,
+    role_name: String,
+    permissions: Vec<String>,
+    community_id: Option<Uuid>,
+    community_name: Option<String>,
+    server_id: Option<String>,
+    server_name: Option<String>,
+    granted_by: Option<i64>,
+    created_at: OffsetDateTime,
+}
+
+impl From<DbRoleGrant> for RoleGrant {
+    fn from(g: DbRoleGrant) -> Self {
+        let scope = match (&g.community_id, &g.server_id) {
+            (Some(_), _) => "community",
+            (_, Some(_)) => "server",
+            _ => "global",
+        };
+        RoleGrant {
+            id: g.id,
+            user_id: g.user_id.to_string(),
+            role_id: g.role_id,
+            role_name: g.role_name,
+            permissions: g.permissions,
+            scope: scope.to_string(),
+            community_id: g.community_id.map(|u| u.to_string()),
+            community_name: g.community_name,
+            server_id: g.server_id,
+            server_name: g.server_name,
+            granted_by: g.granted_by.map(|id| id.to_string()),
+            created_at: db_to_utc(g.created_at),
+        }
+    }
+}
+
+// ─── Discord role mappings ────────────────────────────────────────────────────
+
+#[derive(Debug, Serialize, Deserialize, Object, Clone)]
+pub struct DiscordRoleMapping {
+    pub id: i32,
+    pub guild_id: String,
+    pub discord_role_id: String,
+    pub role_id: i32,
+    pub role_name: String,
+    pub community_id: Option<String>,
+    pub server_id: Option<String>,
+    pub created_by: Option<String>,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Object)]
+pub struct CreateDiscordRoleMappingPayload {
+    pub guild_id: String,
+    pub discord_role_id: String,
+    pub role_id: i32,
+    /// Leave both empty for a global grant
+    pub community_id: Option<String>,
+    pub server_id: Option<String>,
+}
+
+struct DbDiscordRoleMapping {
+    id: i32,
+    guild_id: i64,
+    discord_role_id: i64,
+    role_id: i32,
+    role_name: String,
+    community_id: Option<Uuid>,
+    server_id: Option<String>,
+    created_by: Option<i64>,
+    created_at: OffsetDateTime,
+}
+
+impl From<DbDiscordRoleMapping> for DiscordRoleMapping {
+    fn from(m: DbDiscordRoleMapping) -> Self {
+        DiscordRoleMapping {
+            id: m.id,
+            guild_id: m.guild_id.to_string(),
+            discord_role_id: m.discord_role_id.to_string(),
+            role_id: m.role_id,
+            role_name: m.role_name,
+            community_id: m.community_id.map(|u| u.to_string()),
+            server_id: m.server_id,
+            created_by: m.created_by.map(|id| id.to_string()),
+            created_at: db_to_utc(m.created_at),
+        }
+    }
+}
+
+fn grant_scope(community_id: Option<Uuid>, server_id: Option<&str>) -> Scope<'_> {
+    match (community_id, server_id) {
+        (Some(id), _) => Scope::Community(id),
+        (_, Some(id)) => Scope::Server(id),
+        _ => Scope::Global,
+    }
+}
+
+/// Empty ids count as absent, and a scope is a community or a server but never both.
+fn parse_scope(community_id: Option<&str>, server_id: Option<String>) -> Result<(Option<Uuid>, Option<String>), &'static str> {
+    let community_id = match community_id {
+        Some(id) if !id.is_empty() => Some(Uuid::parse_str(id).map_err(|_| "Invalid community_id")?),
+        _ => None,
+    };
+    let server_id = server_id.filter(|id| !id.is_empty());
+    if community_id.is_some() && server_id.is_some() {
+        return Err("A grant is scoped to a community or a server, not both")
+    }
+    Ok((community_id, server_id))
+}
+
+fn validate_permissions(permissions: &[String]) -> Result<(), String> {
+    match permissions.iter().find(|p| !Permission::is_valid_name(p)) {
+        Some(unknown) => Err(format!("Unknown permission '{unknown}'")),
+        None => Ok(()),
+    }
+}
+
+async fn holds_role_permissions(
+    data: &AppData,
+    user_id: i64,
+    role_id: i32,
+    community_id: Option<Uuid>,
+    server_id: Option<&str>,
+) -> Result<bool, sqlx::Error> {
+    let covered = sqlx::query_scalar!(
+        r#"
+        SELECT COALESCE(bool_and(website.has_permission($1, p, $3, $4)), TRUE)
+        FROM website.role r, unnest(r.permissions) AS p
+        WHERE r.id = $2
+        "#,
+        user_id,
+        role_id,
+        community_id,
+        server_id,
+    )
+    .fetch_one(&*data.pool)
+    .await?;
+    Ok(covered == Some(true))
+}
+
+/// Same check as [`holds_role_permissions`] for a role that doesn't exist yet.
+async fn holds_permissions(
+    data: &AppData,
+    user_id: i64,
+    permissions: &[String],
+    community_id: Option<Uuid>,
+    server_id: Option<&str>,
+) -> Result<bool, sqlx::Error> {
+    let covered = sqlx::query_scalar!(
+        r#"
+        SELECT COALESCE(bool_and(website.has_permission($1, p, $3, $4)), TRUE)
+        FROM unnest($2::TEXT[]) AS p
+        "#,
+        user_id,
+        permissions,
+        community_id,
+        server_id,
+    )
+    .fetch_one(&*data.pool)
+    .await?;
+    Ok(covered == Some(true))
+}
+
+/// Grants held by `user_id`. With a `manager`, only those in scopes where the manager
+/// holds `roles.manage` are returned.
+async fn fetch_grants(data: &AppData, user_id: i64, manager: Option<i64>) -> Result<Vec<DbRoleGrant>, sqlx::Error> {
+    sqlx::query_as!(
+        DbRoleGrant,
+        r#"
+        SELECT g.id, g.user_id, g.role_id, r.name AS role_name, r.permissions,
+               g.community_id, c.community_name AS "community_name?",
+               g.server_id, s.server_fullname AS "server_name?",
+               g.granted_by, g.created_at
+        FROM website.role_grant g
+        JOIN website.role r ON r.id = g.role_id
+        LEFT JOIN community c ON c.community_id = g.community_id
+        LEFT JOIN server s ON s.server_id = g.server_id
+        WHERE g.user_id = $1
+          AND ($2::BIGINT IS NULL OR website.has_permission($2, 'roles.manage', g.community_id, g.server_id))
+        ORDER BY g.created_at
+        "#,
+        user_id,
+        manager,
+    )
+    .fetch_all(&*data.pool)
+    .await
+}
+
+#[OpenApi]
+impl AdminRolesApi {
+    /// Every permission a role can hold
+    #[oai(path = "/admin/permissions", method = "get")]
+    async fn list_permissions(
+        &self,
+        Data(data): Data<&AppData>,
+        TokenBearer(user_token): TokenBearer,
+    ) -> Response<Vec<String>> {
+        if !has_permission_anywhere(data, user_token.id, Permission::RolesManage).await {
+            return response!(err "Unauthorized", ErrorCode::Forbidden);
+        }
+        response!(ok Permission::ALL.iter().map(|p| p.name().to_string()).collect())
+    }
+
+    /// Roles the caller could grant in some scope they hold `roles.manage` in
+    #[oai(path = "/admin/roles", method = "get")]
+    async fn list_roles(
+        &self,
+        Data(data): Data<&AppData>,
+        TokenBearer(user_token): TokenBearer,
+    ) -> Response<Vec<AdminRole>> {
+        if !has_permission_anywhere(data, user_token.id, Permission::RolesManage).await {
+            return response!(err "Unauthorized", ErrorCode::Forbidden);
+        }
+
+        match sqlx::query_as!(
+            DbRole,
+            r#"
+            SELECT r.id, r.name, r.description, r.permissions, r.created_at
+            FROM website.role r
+            WHERE EXISTS (
+                SELECT 1 FROM website.role_grant g
+                WHERE g.user_id = $1
+                  AND website.has_permission($1, 'roles.manage', g.community_id, g.server_id)
+                  AND NOT EXISTS (
+                      SELECT 1 FROM unnest(r.permissions) AS p
+                      WHERE NOT website.has_permission($1, p, g.community_id, g.server_id)
+                  )
+            )
+            ORDER BY r.id
+            "#,
+            user_token.id
+        )
+        .fetch_all(&*data.pool)
+        .await
+        {
+            Ok(rows) => response!(ok rows.into_iter().map(Into::into).collect()),
+            Err(e) => {
+                tracing::error!("Failed to list roles: {}", e);
+                response!(internal_server_error)
+            }
+        }
+    }
+
+    #[oai(path = "/admin/roles", method = "post")]
+    async fn create_role(
+        &self,
+        Data(data): Data<&AppData>,
+        TokenBearer(user_token): TokenBearer,
+        Json(payload): Json<CreateRolePayload>,
+    ) -> Response<AdminRole> {
+        if !has_permission(data, user_token.id, Permission::RolesManage, Scope::Global).await {
+            return response!(err "Unauthorized", ErrorCode::Forbidden);
+        }
+        let name = payload.name.trim();
+        if name.is_empty() || name.len() > 64 {
+            return response!(err "Name must be 1-64 characters", ErrorCode::BadRequest);
+        }
+        if let Err(e) = validate_permissions(&payload.permissions) {
+            return response!(err &e, ErrorCode::BadRequest);
+        }
+        // Roles are granted anywhere, so only what the caller holds globally can go in one
+        match holds_permissions(data, user_token.id, &payload.permissions, None, None).await {
+            Ok(true) => {}
+            Ok(false) => return response!(err "You can't grant permissions you don't hold", ErrorCode::Forbidden),
+            Err(e) => {
+                tracing::error!("Failed to check role permissions: {}", e);
+                return response!(internal_server_error);
+            }
+        }
+
+        let role = match sqlx::query_as!(
+            DbRole,
+            r#"
+            INSERT INTO website.role (name, description, permissions)
+            VALUES ($1, $2, $3)
+            ON CONFLICT (name) DO NOTHING
+            RETURNING id, name, description, permissions, created_at
+            "#,
+            name,
+            payload.description,
+            &payload.permissions,
+        )
+        .fetch_optional(&*data.pool)
+        .await
+        {
+            Ok(Some(r)) => AdminRole::from(r),
+            Ok(None) => return response!(err "A role with this name already exists", ErrorCode::Conflict),
+            Err(e) => {
+                tracing::error!("Failed to create role: {}", e);
+                return response!(internal_server_error);
+            }
+        };
+
+        record_audit(
+            &data.pool, Some(user_token.id), "create", "role", &role.id.to_string(),
+            None, serde_json::to_value(&role).ok(),
+        ).await;
+        response!(ok role)
+    }
+
+    #[oai(path = "/admin/roles/:role_id", method = "put")]
+    async fn update_role(
+        &self,
+        Data(data): Data<&AppData>,
+        TokenBearer(user_token): TokenBearer,
+        Path(role_id): Path<i32>,
+        Json(payload): Json<UpdateRolePayload>,
+    ) -> Response<AdminRole> {
+        if !has_permission(data, user_token.id, Permission::RolesManage, Scope::Global).await {
+            return response!(err "Unauthorized", ErrorCode::Forbidden);
+        }
+        if let Some(permissions) = &payload.permissions {
+            if let Err(e) = validate_permissions(permissions) {
+                return response!(err &e, ErrorCode::BadRequest);
+            }
+        }
+        // Neither the role as it is nor as it will be may hold more than the caller does globally
+        let holds = match &payload.permissions {
+            Some(permissions) => holds_permissions(data, user_token.id, permissions, None, None).await,
+            None => Ok(true),
+        };
+        let holds = match holds {
+            Ok(true) => holds_role_permissions(data, user_token.id, role_id, None, None).await,
+            other => other,
+        };
+        match holds {
+            Ok(true) => {}
+            Ok(false) => return response!(err "You can't grant permissions you don't hold", ErrorCode::Forbidden),
+            Err(e) => {
+                tracing::error!("Failed to check role permissions: {}", e);
+                return response!(internal_server_error);
+            }
+        }
+
+        let before = role_snapshot(data, role_id).await;
+        let role = match sqlx::query_as!(
+            DbRole,
+            r#"
+            UPDATE website.role SET
+                description = CASE WHEN $2 THEN $3 ELSE description END,
+                permissions = COALESCE($4, permissions)
+            WHERE id = $1
+            RETURNING id, name, description, permissions, created_at
+            "#,
+            role_id,
+            payload.description.is_some(),
+            payload.description,
+            payload.permissions.as_deref(),
+        )
+        .fetch_optional(&*data.pool)
+        .await
+        {
+            Ok(Some(r)) => r,
+            Ok(None) => return response!(err "Role not found", ErrorCode::NotFound),
+            Err(e) => {
+                tracing::error!("Failed to update role: {}", e);
+                return response!(internal_server_error);
+            }
+        };
+
+        record_audit(
+            &data.pool, Some(user_token.id), "update", "role", &role_id.to_string(),
+            before, role_snapshot(data, role_id).await,
+        ).await;
+        response!(ok role.into())
+    }
+
+    /// Deleting a role revokes every grant of it
+    #[oai(path = "/admin/roles/:role_id", method = "delete")]
+    async fn delete_role(
+        &self,
+        Data(data): Data<&AppData>,
+        TokenBearer(user_token): TokenBearer,
+        Path(role_id): Path<i32>,
+    ) -> Response<bool> {
+        if !has_permission(data, user_token.id, Permission::RolesManage, Scope::Global).await {
+            return response!(err "Unauthorized", ErrorCode::Forbidden);
+        }
+
+        let before = role_snapshot(data, role_id).await;
+        let result = match sqlx::query!("DELETE FROM website.role WHERE id = $1", role_id)
+            .execute(&*data.pool)
+            .await
+        {
+            Ok(r) => r,
+            Err(e) => {
+                tracing::error!("Failed to delete role: {}", e);
+                return response!(internal_server_error);
+            }
+        };
+
+        if result.rows_affected() == 0 {
+            return response!(err "Role not found", ErrorCode::NotFound);
+        }
+        record_audit(&data.pool, Some(user_token.id), "delete", "role", &role_id.to_string(), before, None).await;
+        response!(ok true)
+    }
+
+    /// Roles held by a user
+    #[oai(path = "/admin/users/:user_id/roles", method = "get")]
+    async fn get_user_grants(
+        &self,
+        Data(data): Data<&AppData>,
+        TokenBearer(user_token): TokenBearer,
+        Path(user_id): Path<i64>,
+    ) -> Response<Vec<RoleGrant>> {
+        if !has_permission_anywhere(data, user_token.id, Permission::RolesManage).await {
+            return response!(err "Unauthorized", ErrorCode::Forbidden);
+        }
+
+        match fetch_grants(data, user_id, Some(user_token.id)).await {
+            Ok(rows) => response!(ok rows.into_iter().map(Into::into).collect()),
+            Err(e) => {
+                tracing::error!("Failed to fetch role grants: {}", e);
+                response!(internal_server_error)
+            }
+        }
+    }
+
+    /// Give a user a role, globally or for one community or server.
+    /// Needs `roles.manage` in that scope and every permission the role carries.
+    #[oai(path = "/admin/users/:user_id/roles", method = "post")]
+    async fn grant_role(
+        &self,
+        Data(data): Data<&AppData>,
+        TokenBearer(user_token): TokenBearer,
+        Path(user_id): Path<i64>,
+        Json(payload): Json<GrantRolePayload>,
+    ) -> Response<Vec<RoleGrant>> {
+        let (community_id, server_id) = match parse_scope(payload.community_id.as_deref(), payload.server_id) {
+            Ok(ids) => ids,
+            Err(e) => return response!(err e, ErrorCode::BadRequest),
+        };
+
+        let scope = grant_scope(community_id, server_id.as_deref());
+        if !has_permission(data, user_token.id, Permission::RolesManage, scope).await {
+            return response!(err "Unauthorized", ErrorCode::Forbidden);
+        }
+
+        // Nobody hands out more than they hold themselves
+        match holds_role_permissions(data, user_token.id, payload.role_id, community_id, server_id.as_deref()).await {
+            Ok(true) => {}
+            Ok(false) => return response!(err "You can't grant permissions you don't hold", ErrorCode::Forbidden),
+            Err(e) => {
+                tracing::error!("Failed to check role permissions: {}", e);
+                return response!(internal_server_error);
+            }
+        }
+
+        let inserted = sqlx::query_scalar!(
+            r#"
+            INSERT INTO website.role_grant (user_id, role_id, community_id, server_id, granted_by)
+            SELECT $1, r.id, $3, $4, $5 FROM website.role r WHERE r.id = $2
+            ON CONFLICT DO NOTHING
+            RETURNING id
+            "#,
+            user_id,
+            payload.role_id,
+            community_id,
+            server_id,
+            user_token.id,
+        )
+        .fetch_optional(&*data.pool)
+        .await;
+        match inserted {
+            Ok(Some(grant_id)) => {
+                record_audit(
+                    &data.pool, Some(user_token.id), "grant_role", "user", &user_id.to_string(), None,
+                    Some(serde_json::json!({
+                        "grant_id": grant_id,
+                        "role_id": payload.role_id,
+                        "community_id": community_id,
+                        "server_id": server_id,
+                    })),
+                ).await;
+            }
+            Ok(None) => {
+                let role_exists = sqlx::query_scalar!(
+                    "SELECT EXISTS(SELECT 1 FROM website.role WHERE id = $1)", payload.role_id
+                )
+                .fetch_one(&*data.pool)
+                .await
+                .unwrap_or(Some(false))
+                .unwrap_or(false);
+                if !role_exists {
+                    return response!(err "Role not found", ErrorCode::NotFound);
+                }
+                // Already held in this scope
+            }
+            Err(e) => {
+                tracing::error!("Failed to grant role: {}", e);
+                return response!(internal_server_error);
+            }
+        }
+
+        match fetch_grants(data, user_id, Some(user_token.id)).await {
+            Ok(rows) => response!(ok rows.into_iter().map(Into::into).collect()),
+            Err(e) => {
+                tracing::error!("Failed to fetch role grants: {}", e);
+                response!(internal_server_error)
+            }
+        }
+    }
+
+    /// Take a role grant away. Needs `roles.manage` in the grant's scope.
+    #[oai(path = "/admin/users/:user_id/roles/:grant_id", method = "delete")]
+    async fn revoke_role(
+        &self,
+        Data(data): Data<&AppData>,
+        TokenBearer(user_token): TokenBearer,
+        Path(user_id): Path<i64>,
+        Path(grant_id): Path<i64>,
+    ) -> Response<bool> {
+        let grant = match sqlx::query!(
+            "SELECT role_id, community_id, server_id FROM website.role_grant WHERE id = $1 AND user_id = $2",
+            grant_id,
+            user_id
+        )
+        .fetch_optional(&*data.pool)
+        .await
+        {
+            Ok(Some(g)) => g,
+            Ok(None) => return response!(err "Grant not found", ErrorCode::NotFound),
+            Err(e) => {
+                tracing::error!("Failed to fetch role grant: {}", e);
+                return response!(internal_server_error);
+            }
+        };
+
+        let scope = grant_scope(grant.community_id, grant.server_id.as_deref());
+        if !has_permission(data, user_token.id, Permission::RolesManage, scope).await {
+            return response!(err "Unauthorized", ErrorCode::Forbidden);
+        }
+
+        if let Err(e) = sqlx::query!("DELETE FROM website.role_grant WHERE id = $1", grant_id)
+            .execute(&*data.pool)
+            .await
+        {
+            tracing::error!("Failed to revoke role grant: {}", e);
+            return response!(internal_server_error);
+        }
+
+        record_audit(
+            &data.pool, Some(user_token.id), "revoke_role", "user", &user_id.to_string(),
+            Some(serde_json::json!({
+                "grant_id": grant_id,
+                "role_id": grant.role_id,
+                "community_id": grant.community_id,
+                "server_id": grant.server_id,
+            })),
+            None,
+        ).await;
+        response!(ok true)
+    }
+
+    /// Roles held by the signed in user
+    #[oai(path = "/accounts/me/roles", method = "get")]
+    async fn get_my_grants(
+        &self,
+        Data(data): Data<&AppData>,
+        TokenBearer(user_token): TokenBearer,
+    ) -> Response<Vec<RoleGrant>> {
+        match fetch_grants(data, user_token.id, None).await {
+            Ok(rows) => response!(ok rows.into_iter().map(Into::into).collect()),
+            Err(e) => {
+                tracing::error!("Failed to fetch role grants: {}", e);
+                response!(internal_server_error)
+            }
+        }
+    }
+
+    /// Guild roles that grant website roles to linked Discord accounts
+    #[oai(path = "/admin/discord/role-mappings", method = "get")]
+    async fn list_discord_role_mappings(
+        &self,
+        Data(data): Data<&AppData>,
+        TokenBearer(user_token): TokenBearer,
+    ) -> Response<Vec<DiscordRoleMapping>> {
+        if !has_permission_anywhere(data, user_token.id, Permission::RolesManage).await {
+            return response!(err "Unauthorized", ErrorCode::Forbidden);
+        }
+
+        match sqlx::query_as!(
+            DbDiscordRoleMapping,
+            r#"
+            SELECT m.id, m.guild_id, m.discord_role_id, m.role_id, r.name AS role_name,
+                   m.community_id, m.server_id, m.created_by, m.created_at
+            FROM website.discord_role_mapping m
+            JOIN website.role r ON r.id = m.role_id
+            ORDER BY m.guild_id, m.id
+            "#
+        )
+        .fetch_all(&*data.pool)
+        .await
+        {
+            Ok(rows) => response!(ok rows.into_iter().map(Into::into).collect()),
+            Err(e) => {
+                tracing::error!("Failed to list Discord role mappings: {}", e);
+                response!(internal_server_error)
+            }
+        }
+    }
+
+    /// Same rules as granting the role by hand: `roles.manage` in the scope and every
+    /// permission the role carries. Members pick it up on their next sync.
+    #[oai(path = "/admin/discord/role-mappings", method = "post")]
+    async fn create_discord_role_mapping(
+        &self,
+        Data(data): Data<&AppData>,
+        TokenBearer(user_token): TokenBearer,
+        Json(payload): Json<CreateDiscordRoleMappingPayload>,
+    ) -> Response<DiscordRoleMapping> {
+        let (Ok(guild_id), Ok(discord_role_id)) = (payload.guild_id.parse::<i64>(), payload.discord_role_id.parse::<i64>()) else {
+            return response!(err "guild_id and discord_role_id must be Discord ids", ErrorCode::BadRequest);
+        };
+        let (community_id, server_id) = match parse_scope(payload.community_id.as_deref(), payload.server_id) {
+            Ok(ids) => ids,
+            Err(e) => return response!(err e, ErrorCode::BadRequest),
+        };
+        if !has_permission(data, user_token.id, Permission::RolesManage, grant_scope(community_id, server_id.as_deref())).await {
+            return response!(err "Unauthorized", ErrorCode::Forbidden);
+        }
+        match holds_role_permissions(data, user_token.id, payload.role_id, community_id, server_id.as_deref()).await {
+            Ok(true) => {}
+            Ok(false) => return response!(err "You can't grant permissions you don't hold", ErrorCode::Forbidden),
+            Err(e) => {
+                tracing::error!("Failed to check role permissions: {}", e);
+                return response!(internal_server_error);
+            }
+        }
+
+        let mapping = match sqlx::query_as!(
+            DbDiscordRoleMapping,
+            r#"
+            WITH inserted AS (
+                INSERT INTO website.discord_role_mapping (guild_id, discord_role_id, role_id, community_id, server_id, created_by)
+                SELECT $1, $2, r.id, $4, $5, $6 FROM website.role r WHERE r.id = $3
+                ON CONFLICT DO NOTHING
+                RETURNING *
+            )
+            SELECT i.id AS "id!", i.guild_id AS "guild_id!", i.discord_role_id AS "discord_role_id!",
+                   i.role_id AS "role_id!", r.name AS role_name,
+                   i.community_id, i.server_id, i.created_by, i.created_at AS "created_at!"
+            FROM inserted i
+            JOIN website.role r ON r.id = i.role_id
+            "#,
+            guild_id,
+            discord_role_id,
+            payload.role_id,
+            community_id,
+            server_id,
+            user_token.id,
+        )
+        .fetch_optional(&*data.pool)
+        .await
+        {
+            Ok(Some(m)) => DiscordRoleMapping::from(m),
+            Ok(None) => return response!(err "Role not found, or the mapping already exists", ErrorCode::Conflict),
+            Err(e) => {
+                tracing::error!("Failed to create Discord role mapping: {}", e);
+                return response!(internal_server_error);
+            }
+        };
+
+        record_audit(
+            &data.pool, Some(user_token.id), "create", "discord_role_mapping", &mapping.id.to_string(),
+            None, serde_json::to_value(&mapping).ok(),
+        ).await;
+        response!(ok mapping)
+    }
+
+    /// Removing a mapping also removes the grants it made
+    #[oai(path = "/admin/discord/role-mappings/:mapping_id", method = "delete")]
+    async fn delete_discord_role_mapping(
+        &self,
+        Data(data): Data<&AppData>,
+        TokenBearer(user_token): TokenBearer,
+        Path(mapping_id): Path<i32>,
+    ) -> Response<bool> {
+        let mapping = match sqlx::query!(
+            "SELECT community_id, server_id FROM website.discord_role_mapping WHERE id = $1",
+            mapping_id
+        )
+        .fetch_optional(&*data.pool)
+        .await
+        {
+            Ok(Some(m)) => m,
+            Ok(None) => return response!(err "Mapping not found", ErrorCode::NotFound),
+            Err(e) => {
+                tracing::error!("Failed to fetch Discord role mapping: {}", e);
+                return response!(internal_server_error);
+            }
+        };
+        let scope = grant_scope(mapping.community_id, mapping.server_id.as_deref());
+        if !has_permission(data, user_token.id, Permission::RolesManage, scope).await {
+            return response!(err "Unauthorized", ErrorCode::Forbidden);
+        }
+
+        let before = sqlx::query_scalar!(
+            "SELECT to_jsonb(m) FROM website.discord_role_mapping m WHERE m.id = $1", mapping_id
+        )
+        .fetch_optional(&*data.pool)
+        .await
+        .ok()
+        .flatten()
+        .flatten();
+        if let Err(e) = sqlx::query!("DELETE FROM website.discord_role_mapping WHERE id = $1", mapping_id)
+            .execute(&*data.pool)
+            .await
+        {
+            tracing::error!("Failed to delete Discord role mapping: {}", e);
+            return response!(internal_server_error);
+        }
+        record_audit(
+            &data.pool, Some(user_token.id), "delete", "discord_role_mapping", &mapping_id.to_string(), before, None,
+        ).await;
+        response!(ok true)
+    }
+}
+
+async fn role_snapshot(data: &AppData, role_id: i32) -> Option<serde_json::Value> {
+    sqlx::query_scalar!("SELECT to_jsonb(r) FROM website.role r WHERE r.id = $1", role_id)
+        .fetch_optional(&*data.pool)
+        .await
+        .ok()
+        .flatten()
+        .flatten()
+}
+
+impl UriPatternExt for AdminRolesApi {
+    fn get_all_patterns(&self) -> Vec<RoutePattern<'_>> {
+        vec![
+            "/admin/permissions",
+            "/admin/roles",
+            "/admin/roles/{role_id}",
+            "/admin/users/{user_id}/roles",
+            "/admin/users/{user_id}/roles/{grant_id}",
+            "/accounts/me/roles",
+            "/admin/discord/role-mappings",
+            "/admin/discord/role-mappings/{mapping_id}",
+        ].iter_into()
+    }
+}
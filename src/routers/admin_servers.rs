@@ -6,6 +6,8 @@ use serde::{Deserialize, Serialize};
 use uuid::Uuid;
 
 use crate::core::api_models::*;
+use crate::core::audit::record_audit;
+use crate::core::permissions::{has_permission, Permission, Scope};
 use crate::core::utils::*;
 use crate::{response, AppData};
 
@@ -98,7 +100,7 @@ impl AdminServersApi {
         Data(data): Data<&AppData>,
         TokenBearer(user_token): TokenBearer,
     ) -> Response<Vec<AdminCommunity>> {
-        if !check_superuser(data, user_token.id).await {
+        if !has_permission(data, user_token.id, Permission::ServersManage, Scope::Global).await {
             return response!(err "Unauthorized", ErrorCode::Forbidden);
         }
 
@@ -147,7 +149,7 @@ impl AdminServersApi {
         TokenBearer(user_token): TokenBearer,
         Json(payload): Json<CreateCommunityPayload>,
     ) -> Response<AdminCommunity> {
-        if !check_superuser(data, user_token.id).await {
+        if !has_permission(data, user_token.id, Permission::ServersManage, Scope::Global).await {
             return response!(err "Unauthorized", ErrorCode::Forbidden);
         }
         if payload.name.trim().is_empty() {
@@ -179,13 +181,18 @@ impl AdminServersApi {
             }
         };
 
-        response!(ok AdminCommunity {
+        let community = AdminCommunity {
             id: row.community_id.to_string(),
             name: Some(payload.name),
             shorten_name: payload.shorten_name,
             icon_url: payload.icon_url,
             server_count: 0,
-        })
+        };
+        record_audit(
+            &data.pool, Some(user_token.id), "create", "community", &community.id,
+            None, community_snapshot(data, row.community_id).await,
+        ).await;
+        response!(ok community)
     }
 
     #[oai(path = "/admin/communities/:id", method = "put")]
@@ -196,7 +203,7 @@ impl AdminServersApi {
         Path(id): Path<String>,
         Json(payload): Json<UpdateCommunityPayload>,
     ) -> Response<AdminCommunity> {
-        if !check_superuser(data, user_token.id).await {
+        if !has_permission(data, user_token.id, Permission::ServersManage, Scope::Global).await {
             return response!(err "Unauthorized", ErrorCode::Forbidden);
         }
         let id = match Uuid::parse_str(&id) {
@@ -216,6 +223,8 @@ impl AdminServersApi {
             community_icon_url: Option<String>,
         }
 
+        let before = community_snapshot(data, id).await;
+
         let row = match sqlx::query_as!(
             DbRow,
             r#"
@@ -253,6 +262,10 @@ impl AdminServersApi {
         .unwrap_or(Some(0))
         .unwrap_or(0);
 
+        record_audit(
+            &data.pool, Some(user_token.id), "update", "community", &id.to_string(),
+            before, community_snapshot(data, id).await,
+        ).await;
         response!(ok AdminCommunity {
             id: row.community_id.to_string(),
             name: row.community_name,
@@ -269,7 +282,7 @@ impl AdminServersApi {
         TokenBearer(user_token): TokenBearer,
         Path(id): Path<String>,
     ) -> Response<bool> {
-        if !check_superuser(data, user_token.id).await {
+        if !has_permission(data, user_token.id, Permission::ServersManage, Scope::Global).await {
             return response!(err "Unauthorized", ErrorCode::Forbidden);
         }
         let id = match Uuid::parse_str(&id) {
@@ -277,6 +290,7 @@ impl AdminServersApi {
             Err(_) => return response!(err "Invalid community ID", ErrorCode::BadRequest),
         };
 
+        let before = community_snapshot(data, id).await;
         let result = match sqlx::query!("DELETE FROM community WHERE community_id = $1", id)
             .execute(&*data.pool)
             .await
@@ -291,6 +305,7 @@ impl AdminServersApi {
         if result.rows_affected() == 0 {
             return response!(err "Community not found", ErrorCode::NotFound);
         }
+        record_audit(&data.pool, Some(user_token.id), "delete", "community", &id.to_string(), before, None).await;
         response!(ok true)
     }
 
@@ -302,7 +317,7 @@ impl AdminServersApi {
         Data(data): Data<&AppData>,
         TokenBearer(user_token): TokenBearer,
     ) -> Response<Vec<AdminServerBrowser>> {
-        if !check_superuser(data, user_token.id).await {
+        if !has_permission(data, user_token.id, Permission::ServersManage, Scope::Global).await {
             return response!(err "Unauthorized", ErrorCode::Forbidden);
         }
 
@@ -334,7 +349,7 @@ impl AdminServersApi {
         TokenBearer(user_token): TokenBearer,
         Json(payload): Json<CreateServerBrowserPayload>,
     ) -> Response<AdminServerBrowser> {
-        if !check_superuser(data, user_token.id).await {
+        if !has_permission(data, user_token.id, Permission::ServersManage, Scope::Global).await {
             return response!(err "Unauthorized", ErrorCode::Forbidden);
         }
         if payload.ip.trim().is_empty() {
@@ -345,6 +360,7 @@ impl AdminServersApi {
             return response!(err "cooldown_type must be unknown, datetime, or map_count", ErrorCode::BadRequest);
         }
         let tracking = payload.tracking.unwrap_or(true);
+        let before = server_browser_snapshot(data, &payload.ip, payload.port).await;
 
         let row = match sqlx::query!(
             r#"
@@ -369,6 +385,12 @@ impl AdminServersApi {
             }
         };
 
+        record_audit(
+            &data.pool, Some(user_token.id), if before.is_some() { "update" } else { "create" },
+            "server_browser", &format!("{}:{}", row.ip, row.port),
+            before, server_browser_snapshot(data, &row.ip, row.port).await,
+        ).await;
+
         response!(ok AdminServerBrowser {
             ip: row.ip,
             port: row.port,
@@ -386,7 +408,7 @@ impl AdminServersApi {
         Query(port): Query<i16>,
         Json(payload): Json<UpdateServerBrowserPayload>,
     ) -> Response<AdminServerBrowser> {
-        if !check_superuser(data, user_token.id).await {
+        if !has_permission(data, user_token.id, Permission::ServersManage, Scope::Global).await {
             return response!(err "Unauthorized", ErrorCode::Forbidden);
         }
         if let Some(ref ct) = payload.cooldown_type {
@@ -395,6 +417,7 @@ impl AdminServersApi {
             }
         }
 
+        let before = server_browser_snapshot(data, &ip, port).await;
         let row = match sqlx::query!(
             r#"
             UPDATE server_browser SET
@@ -419,6 +442,11 @@ impl AdminServersApi {
             }
         };
 
+        record_audit(
+            &data.pool, Some(user_token.id), "update", "server_browser", &format!("{ip}:{port}"),
+            before, server_browser_snapshot(data, &ip, port).await,
+        ).await;
+
         response!(ok AdminServerBrowser {
             ip: row.ip,
             port: row.port,
@@ -435,10 +463,11 @@ impl AdminServersApi {
         Query(ip): Query<String>,
         Query(port): Query<i16>,
     ) -> Response<bool> {
-        if !check_superuser(data, user_token.id).await {
+        if !has_permission(data, user_token.id, Permission::ServersManage, Scope::Global).await {
             return response!(err "Unauthorized", ErrorCode::Forbidden);
         }
 
+        let before = server_browser_snapshot(data, &ip, port).await;
         let result = match sqlx::query!(
             "DELETE FROM server_browser WHERE ip = $1 AND port = $2",
             ip,
@@ -457,6 +486,9 @@ impl AdminServersApi {
         if result.rows_affected() == 0 {
             return response!(err "Entry not found", ErrorCode::NotFound);
         }
+        record_audit(
+            &data.pool, Some(user_token.id), "delete", "server_browser", &format!("{ip}:{port}"), before, None,
+        ).await;
         response!(ok true)
     }
 
@@ -468,7 +500,7 @@ impl AdminServersApi {
         Data(data): Data<&AppData>,
         TokenBearer(user_token): TokenBearer,
     ) -> Response<Vec<AdminServer>> {
-        if !check_superuser(data, user_token.id).await {
+        if !has_permission(data, user_token.id, Permission::ServersManage, Scope::Global).await {
             return response!(err "Unauthorized", ErrorCode::Forbidden);
         }
 
@@ -522,7 +554,7 @@ impl AdminServersApi {
         Path(server_id): Path<String>,
         Json(payload): Json<UpdateServerPayload>,
     ) -> Response<AdminServer> {
-        if !check_superuser(data, user_token.id).await {
+        if !has_permission(data, user_token.id, Permission::ServersManage, Scope::Global).await {
             return response!(err "Unauthorized", ErrorCode::Forbidden);
         }
 
@@ -537,6 +569,7 @@ impl AdminServersApi {
             readable_link: Option<String>,
         }
 
+        let before = server_snapshot(data, &server_id).await;
         let row = match sqlx::query_as!(
             DbRow,
             r#"
@@ -563,6 +596,11 @@ impl AdminServersApi {
             }
         };
 
+        record_audit(
+            &data.pool, Some(user_token.id), "update", "server", &server_id,
+            before, server_snapshot(data, &server_id).await,
+        ).await;
+
         response!(ok AdminServer {
             server_id: row.server_id,
             server_name: row.server_name,
@@ -583,7 +621,7 @@ impl AdminServersApi {
         Path(server_id): Path<String>,
         Json(payload): Json<SetServerCommunityPayload>,
     ) -> Response<AdminServer> {
-        if !check_superuser(data, user_token.id).await {
+        if !has_permission(data, user_token.id, Permission::ServersManage, Scope::Global).await {
             return response!(err "Unauthorized", ErrorCode::Forbidden);
         }
 
@@ -606,6 +644,7 @@ impl AdminServersApi {
             readable_link: Option<String>,
         }
 
+        let before = server_snapshot(data, &server_id).await;
         let row = match sqlx::query_as!(
             DbRow,
             r#"
@@ -628,6 +667,11 @@ impl AdminServersApi {
             }
         };
 
+        record_audit(
+            &data.pool, Some(user_token.id), "set_community", "server", &server_id,
+            before, server_snapshot(data, &server_id).await,
+        ).await;
+
         response!(ok AdminServer {
             server_id: row.server_id,
             server_name: row.server_name,
@@ -647,10 +691,11 @@ impl AdminServersApi {
         TokenBearer(user_token): TokenBearer,
         Path(server_id): Path<String>,
     ) -> Response<bool> {
-        if !check_superuser(data, user_token.id).await {
+        if !has_permission(data, user_token.id, Permission::ServersManage, Scope::Global).await {
             return response!(err "Unauthorized", ErrorCode::Forbidden);
         }
 
+        let before = server_snapshot(data, &server_id).await;
         let result = match sqlx::query!("DELETE FROM server WHERE server_id = $1", server_id)
             .execute(&*data.pool)
             .await
@@ -665,10 +710,40 @@ impl AdminServersApi {
         if result.rows_affected() == 0 {
             return response!(err "Server not found", ErrorCode::NotFound);
         }
+        record_audit(&data.pool, Some(user_token.id), "delete", "server", &server_id, before, None).await;
         response!(ok true)
     }
 }
 
+// ─── Audit snapshots ──────────────────────────────────────────────────────────
+
+async fn community_snapshot(data: &AppData, id: Uuid) -> Option<serde_json::Value> {
+    sqlx::query_scalar!("SELECT to_jsonb(c) FROM community c WHERE c.community_id = $1", id)
+        .fetch_optional(&*data.pool)
+        .await
+        .ok()
+        .flatten()
+        .flatten()
+}
+
+async fn server_browser_snapshot(data: &AppData, ip: &str, port: i16) -> Option<serde_json::Value> {
+    sqlx::query_scalar!("SELECT to_jsonb(b) FROM server_browser b WHERE b.ip = $1 AND b.port = $2", ip, port)
+        .fetch_optional(&*data.pool)
+        .await
+        .ok()
+        .flatten()
+        .flatten()
+}
+
+async fn server_snapshot(data: &AppData, server_id: &str) -> Option<serde_json::Value> {
+    sqlx::query_scalar!("SELECT to_jsonb(s) FROM server s WHERE s.server_id = $1", server_id)
+        .fetch_optional(&*data.pool)
+        .await
+        .ok()
+        .flatten()
+        .flatten()
+}
+
 impl UriPatternExt for AdminServersApi {
     fn get_all_patterns(&self) -> Vec<RoutePattern<'_>> {
         vec![
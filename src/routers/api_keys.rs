@@ -0,0 +1,400 @@
+use chrono::{DateTime, Utc};
+use poem::web::Data;
+use poem_openapi::payload::Json;
+use poem_openapi::{Object, OpenApi};
+use poem_openapi::param::{Path, Query};
+use serde::{Deserialize, Serialize};
+use sqlx::types::time::OffsetDateTime;
+use uuid::Uuid;
+
+use crate::core::api_keys::{
+    api_key_usage, forget_api_key, generate_api_key, hash_api_key, is_valid_scope, API_KEY_SCOPES,
+};
+use crate::core::api_models::*;
+use crate::core::audit::record_audit;
+use crate::core::permissions::{has_permission, Permission, Scope};
+use crate::core::utils::*;
+use crate::{response, AppData};
+
+const MAX_KEYS_PER_USER: i64 = 10;
+const MAX_USAGE_DAYS: i64 = 30;
+
+pub struct ApiKeysApi;
+
+#[derive(Debug, Serialize, Deserialize, Object, Clone)]
+pub struct ApiKey {
+    pub id: String,
+    pub name: String,
+    /// Start of the key, enough to tell keys apart
+    pub prefix: String,
+    pub scopes: Vec<String>,
+    /// `None` uses the server default
+    pub rate_per_minute: Option<i32>,
+    pub burst: Option<i32>,
+    /// Requests per UTC day, `None` uses the server default
+    pub daily_quota: Option<i32>,
+    /// Requests per calendar month, `None` uses the server default
+    pub monthly_quota: Option<i32>,
+    pub created_at: DateTime<Utc>,
+    pub last_used_at: Option<DateTime<Utc>>,
+    pub revoked_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Object, Clone)]
+pub struct CreatedApiKey {
+    /// Only ever returned here, it is stored hashed
+    pub key: String,
+    pub api_key: ApiKey,
+}
+
+#[derive(Debug, Serialize, Deserialize, Object)]
+pub struct CreateApiKeyPayload {
+    pub name: String,
+    pub scopes: Vec<String>,
+}
+
+/// Every limit is replaced, `None` puts it back on the server default
+#[derive(Debug, Serialize, Deserialize, Object)]
+pub struct ApiKeyLimitsPayload {
+    pub rate_per_minute: Option<i32>,
+    pub burst: Option<i32>,
+    pub daily_quota: Option<i32>,
+    pub monthly_quota: Option<i32>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Object, Clone)]
+pub struct ApiKeyScope {
+    pub name: String,
+    /// Route prefixes the scope allows reading
+    pub routes: Vec<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Object, Clone)]
+pub struct ApiKeyDailyUsage {
+    pub day: String,
+    pub requests: i64,
+}
+
+#[derive(Debug, Serialize, Deserialize, Object, Clone)]
+pub struct ApiKeyRouteUsage {
+    pub route: String,
+    pub requests: i64,
+}
+
+#[derive(Debug, Serialize, Deserialize, Object, Clone)]
+pub struct ApiKeyUsage {
+    pub total: i64,
+    pub by_day: Vec<ApiKeyDailyUsage>,
+    /// Busiest routes first
+    pub by_route: Vec<ApiKeyRouteUsage>,
+}
+
+struct DbApiKey {
+    id: Uuid,
+    name: String,
+    prefix: String,
+    scopes: Vec<String>,
+    rate_per_minute: Option<i32>,
+    burst: Option<i32>,
+    daily_quota: Option<i32>,
+    monthly_quota: Option<i32>,
+    created_at: OffsetDateTime,
+    last_used_at: Option<OffsetDateTime>,
+    revoked_at: Option<OffsetDateTime>,
+}
+
+impl From<DbApiKey> for ApiKey {
+    fn from(k: DbApiKey) -> Self {
+        ApiKey {
+            id: k.id.to_string(),
+            name: k.name,
+            prefix: k.prefix,
+            scopes: k.scopes,
+            rate_per_minute: k.rate_per_minute,
+            burst: k.burst,
+            daily_quota: k.daily_quota,
+            monthly_quota: k.monthly_quota,
+            created_at: db_to_utc(k.created_at),
+            last_used_at: k.last_used_at.map(db_to_utc),
+            revoked_at: k.revoked_at.map(db_to_utc),
+        }
+    }
+}
+
+#[OpenApi]
+impl ApiKeysApi {
+    /// Scopes a key can be given
+    #[oai(path = "/accounts/me/api-keys/scopes", method = "get")]
+    async fn list_api_key_scopes(&self) -> Response<Vec<ApiKeyScope>> {
+        response!(ok API_KEY_SCOPES.iter().map(|(name, routes)| ApiKeyScope {
+            name: name.to_string(),
+            routes: routes.iter().map(|r| r.to_string()).collect(),
+        }).collect())
+    }
+
+    /// The signed in user's keys, revoked ones included
+    #[oai(path = "/accounts/me/api-keys", method = "get")]
+    async fn list_api_keys(
+        &self,
+        Data(data): Data<&AppData>,
+        TokenBearer(user_token): TokenBearer,
+    ) -> Response<Vec<ApiKey>> {
+        match sqlx::query_as!(
+            DbApiKey,
+            "SELECT id, name, prefix, scopes, rate_per_minute, burst, daily_quota, monthly_quota, created_at, last_used_at, revoked_at
+             FROM website.api_key
+             WHERE user_id = $1
+             ORDER BY revoked_at IS NOT NULL, created_at DESC",
+            user_token.id
+        )
+        .fetch_all(&*data.pool)
+        .await
+        {
+            Ok(rows) => response!(ok rows.into_iter().map(Into::into).collect()),
+            Err(e) => {
+                tracing::error!("Failed to list API keys: {}", e);
+                response!(internal_server_error)
+            }
+        }
+    }
+
+    /// Creates a key, sent with requests as `X-API-Key`. Keys can only read.
+    #[oai(path = "/accounts/me/api-keys", method = "post")]
+    async fn create_api_key(
+        &self,
+        Data(data): Data<&AppData>,
+        TokenBearer(user_token): TokenBearer,
+        Json(payload): Json<CreateApiKeyPayload>,
+    ) -> Response<CreatedApiKey> {
+        let name = payload.name.trim();
+        if name.is_empty() || name.len() > 64 {
+            return response!(err "Name must be 1-64 characters", ErrorCode::BadRequest);
+        }
+        if payload.scopes.is_empty() {
+            return response!(err "A key needs at least one scope", ErrorCode::BadRequest);
+        }
+        if let Some(scope) = payload.scopes.iter().find(|s| !is_valid_scope(s)) {
+            return response!(err &format!("Unknown scope {scope}"), ErrorCode::BadRequest);
+        }
+        let mut scopes = payload.scopes.clone();
+        scopes.sort();
+        scopes.dedup();
+
+        let active = match sqlx::query_scalar!(
+            "SELECT COUNT(*) FROM website.api_key WHERE user_id = $1 AND revoked_at IS NULL",
+            user_token.id
+        )
+        .fetch_one(&*data.pool)
+        .await
+        {
+            Ok(count) => count.unwrap_or(0),
+            Err(e) => {
+                tracing::error!("Failed to count API keys: {}", e);
+                return response!(internal_server_error);
+            }
+        };
+        if active >= MAX_KEYS_PER_USER {
+            return response!(
+                err &format!("At most {MAX_KEYS_PER_USER} active keys, revoke one first"),
+                ErrorCode::Conflict
+            );
+        }
+
+        let (key, prefix) = generate_api_key();
+        match sqlx::query_as!(
+            DbApiKey,
+            "INSERT INTO website.api_key (user_id, name, prefix, key_hash, scopes)
+             VALUES ($1, $2, $3, $4, $5)
+             RETURNING id, name, prefix, scopes, rate_per_minute, burst, daily_quota, monthly_quota, created_at, last_used_at, revoked_at",
+            user_token.id,
+            name,
+            prefix,
+            hash_api_key(&key),
+            &scopes,
+        )
+        .fetch_one(&*data.pool)
+        .await
+        {
+            Ok(row) => response!(ok CreatedApiKey { key, api_key: row.into() }),
+            Err(e) => {
+                tracing::error!("Failed to create API key: {}", e);
+                response!(internal_server_error)
+            }
+        }
+    }
+
+    /// Revoked keys stop working at once on this instance and within a minute on others
+    #[oai(path = "/accounts/me/api-keys/:key_id", method = "delete")]
+    async fn revoke_api_key(
+        &self,
+        Data(data): Data<&AppData>,
+        TokenBearer(user_token): TokenBearer,
+        Path(key_id): Path<String>,
+    ) -> Response<bool> {
+        let Ok(key_id) = Uuid::parse_str(&key_id) else {
+            return response!(err "Invalid API key ID", ErrorCode::BadRequest);
+        };
+        let key_hash = match sqlx::query_scalar!(
+            "UPDATE website.api_key SET revoked_at = COALESCE(revoked_at, NOW())
+             WHERE id = $1 AND user_id = $2
+             RETURNING key_hash",
+            key_id,
+            user_token.id
+        )
+        .fetch_optional(&*data.pool)
+        .await
+        {
+            Ok(Some(hash)) => hash,
+            Ok(None) => return response!(err "API key not found", ErrorCode::NotFound),
+            Err(e) => {
+                tracing::error!("Failed to revoke API key: {}", e);
+                return response!(internal_server_error);
+            }
+        };
+
+        forget_api_key(&key_hash).await;
+        response!(ok true)
+    }
+
+    /// Requests made with a key over the last `days` days (default 7, at most 30)
+    #[oai(path = "/accounts/me/api-keys/:key_id/usage", method = "get")]
+    async fn get_api_key_usage(
+        &self,
+        Data(data): Data<&AppData>,
+        TokenBearer(user_token): TokenBearer,
+        Path(key_id): Path<String>,
+        Query(days): Query<Option<i64>>,
+    ) -> Response<ApiKeyUsage> {
+        let Ok(key_id) = Uuid::parse_str(&key_id) else {
+            return response!(err "Invalid API key ID", ErrorCode::BadRequest);
+        };
+        match sqlx::query_scalar!(
+            "SELECT EXISTS(SELECT 1 FROM website.api_key WHERE id = $1 AND user_id = $2)",
+            key_id,
+            user_token.id
+        )
+        .fetch_one(&*data.pool)
+        .await
+        {
+            Ok(Some(true)) => {}
+            Ok(_) => return response!(err "API key not found", ErrorCode::NotFound),
+            Err(e) => {
+                tracing::error!("Failed to look up API key: {}", e);
+                return response!(internal_server_error);
+            }
+        }
+
+        let days = days.unwrap_or(7).clamp(1, MAX_USAGE_DAYS);
+        let (by_day, by_route) = match api_key_usage(data, key_id, days).await {
+            Ok(usage) => usage,
+            Err(e) => {
+                tracing::error!("Failed to read API key usage: {}", e);
+                return response!(internal_server_error);
+            }
+        };
+
+        let mut by_route: Vec<ApiKeyRouteUsage> = by_route
+            .into_iter()
+            .map(|(route, requests)| ApiKeyRouteUsage { route, requests })
+            .collect();
+        by_route.sort_by(|a, b| b.requests.cmp(&a.requests));
+        response!(ok ApiKeyUsage {
+            total: by_day.iter().map(|(_, requests)| requests).sum(),
+            by_day: by_day
+                .into_iter()
+                .map(|(day, requests)| ApiKeyDailyUsage { day: day.to_string(), requests })
+                .collect(),
+            by_route,
+        })
+    }
+
+    /// Sets a key's rate limit and quotas (`api_keys.manage`), live on this instance at once
+    /// and within a minute on others
+    #[oai(path = "/admin/api-keys/:key_id/limits", method = "put")]
+    async fn set_api_key_limits(
+        &self,
+        Data(data): Data<&AppData>,
+        TokenBearer(user_token): TokenBearer,
+        Path(key_id): Path<String>,
+        Json(payload): Json<ApiKeyLimitsPayload>,
+    ) -> Response<ApiKey> {
+        if !has_permission(data, user_token.id, Permission::ApiKeysManage, Scope::Global).await {
+            return response!(err "Unauthorized", ErrorCode::Forbidden);
+        }
+        let Ok(key_id) = Uuid::parse_str(&key_id) else {
+            return response!(err "Invalid API key ID", ErrorCode::BadRequest);
+        };
+        let limits = [payload.rate_per_minute, payload.burst, payload.daily_quota, payload.monthly_quota];
+        if limits.iter().flatten().any(|limit| *limit < 1) {
+            return response!(err "Limits must be at least 1", ErrorCode::BadRequest);
+        }
+
+        let before = match fetch_api_key(data, key_id).await {
+            Ok(Some(key)) => key,
+            Ok(None) => return response!(err "API key not found", ErrorCode::NotFound),
+            Err(e) => {
+                tracing::error!("Failed to look up API key: {}", e);
+                return response!(internal_server_error);
+            }
+        };
+        let key_hash = match sqlx::query_scalar!(
+            "UPDATE website.api_key
+             SET rate_per_minute = $2, burst = $3, daily_quota = $4, monthly_quota = $5
+             WHERE id = $1
+             RETURNING key_hash",
+            key_id,
+            payload.rate_per_minute,
+            payload.burst,
+            payload.daily_quota,
+            payload.monthly_quota,
+        )
+        .fetch_one(&*data.pool)
+        .await
+        {
+            Ok(hash) => hash,
+            Err(e) => {
+                tracing::error!("Failed to set API key limits: {}", e);
+                return response!(internal_server_error);
+            }
+        };
+        forget_api_key(&key_hash).await;
+
+        let after = ApiKey {
+            rate_per_minute: payload.rate_per_minute,
+            burst: payload.burst,
+            daily_quota: payload.daily_quota,
+            monthly_quota: payload.monthly_quota,
+            ..before.clone()
+        };
+        record_audit(
+            &data.pool, Some(user_token.id), "update_limits", "api_key", &key_id.to_string(),
+            serde_json::to_value(&before).ok(), serde_json::to_value(&after).ok(),
+        ).await;
+        response!(ok after)
+    }
+}
+
+async fn fetch_api_key(data: &AppData, key_id: Uuid) -> Result<Option<ApiKey>, sqlx::Error> {
+    let row = sqlx::query_as!(
+        DbApiKey,
+        "SELECT id, name, prefix, scopes, rate_per_minute, burst, daily_quota, monthly_quota, created_at, last_used_at, revoked_at
+         FROM website.api_key
+         WHERE id = $1",
+        key_id
+    )
+    .fetch_optional(&*data.pool)
+    .await?;
+    Ok(row.map(Into::into))
+}
+
+impl UriPatternExt for ApiKeysApi {
+    fn get_all_patterns(&self) -> Vec<RoutePattern<'_>> {
+        vec![
+            "/accounts/me/api-keys",
+            "/accounts/me/api-keys/scopes",
+            "/accounts/me/api-keys/{key_id}",
+            "/accounts/me/api-keys/{key_id}/usage",
+            "/admin/api-keys/{key_id}/limits",
+        ].iter_into()
+    }
+}
@@ -1,9 +1,14 @@
-use poem::web::{Data, Json};
+use poem::web::Data;
 use poem_openapi::OpenApi;
 use poem_openapi::param::Path;
-use crate::{response, AppData, FastCache};
+use crate::{response, AppData};
 use crate::core::model::DbCharacter3DModel;
 use crate::core::api_models::*;
+use crate::core::glb::{inspect_glb, inspect_glb_file};
+use crate::core::model_processing::{enqueue_model_job, ModelJobTarget};
+use crate::core::audit::record_audit;
+use crate::core::uploads::FinishError;
+use crate::core::permissions::check_character_models;
 use crate::core::utils::*;
 
 pub struct CharacterApi;
@@ -21,7 +26,7 @@ impl CharacterApi {
             DbCharacter3DModel,
             r#"
             SELECT id, model_id, name, server_id, credit, link_path,
-                   uploaded_by, thumbnail_path, file_size, created_at, updated_at
+                   uploaded_by, thumbnail_path, file_size, created_at, updated_at, glb_info
             FROM website.character_3d_model
             WHERE server_id = $1
             ORDER BY COALESCE(name, model_id)
@@ -48,7 +53,7 @@ impl CharacterApi {
                         None
                     };
                     let mut m: Character3DModel = row.into();
-                    m.link_path = app.character_storage.normalize_link_path(&m.link_path, &m.model_id);
+                    m.link_path = app.character_storage.normalize_link_path(&m.link_path, &m.model_id).await;
                     m.uploader_name = uploader_name;
                     result.push(m);
                 }
@@ -70,7 +75,7 @@ impl CharacterApi {
             DbCharacter3DModel,
             r#"
             SELECT id, model_id, name, server_id, credit, link_path,
-                   uploaded_by, thumbnail_path, file_size, created_at, updated_at
+                   uploaded_by, thumbnail_path, file_size, created_at, updated_at, glb_info
             FROM website.character_3d_model
             WHERE server_id = $1 AND model_id = $2
             "#,
@@ -95,7 +100,7 @@ impl CharacterApi {
                     None
                 };
                 let mut m: Character3DModel = row.into();
-                m.link_path = app.character_storage.normalize_link_path(&m.link_path, &m.model_id);
+                m.link_path = app.character_storage.normalize_link_path(&m.link_path, &m.model_id).await;
                 m.uploader_name = uploader_name;
                 response!(ok m)
             }
@@ -114,7 +119,7 @@ impl CharacterApi {
         TokenBearer(user_token): TokenBearer,
         multipart: poem::web::Multipart,
     ) -> Response<Character3DModel> {
-        if !check_superuser(&app, user_token.id).await {
+        if !check_character_models(&app, user_token.id, &server_id, &model_id).await {
             return response!(err "Forbidden", ErrorCode::Forbidden);
         }
 
@@ -158,6 +163,11 @@ impl CharacterApi {
             return response!(err "File too large (max 500MB)", ErrorCode::BadRequest);
         }
 
+        let glb_info = match inspect_glb(&file_bytes) {
+            Ok(info) => info,
+            Err(e) => return response!(err &format!("Invalid GLB: {e}"), ErrorCode::BadRequest),
+        };
+
         let link_path = match app.character_storage.store_bytes(&model_id, &file_bytes).await {
             Ok(path) => path,
             Err(e) => {
@@ -170,8 +180,8 @@ impl CharacterApi {
             DbCharacter3DModel,
             r#"
             INSERT INTO website.character_3d_model
-            (server_id, model_id, name, credit, link_path, uploaded_by, file_size)
-            VALUES ($1, $2, $3, $4, $5, $6, $7)
+            (server_id, model_id, name, credit, link_path, uploaded_by, file_size, glb_info)
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8)
             ON CONFLICT (server_id, model_id)
             DO UPDATE SET
                 name = EXCLUDED.name,
@@ -179,6 +189,7 @@ impl CharacterApi {
                 link_path = EXCLUDED.link_path,
                 uploaded_by = EXCLUDED.uploaded_by,
                 file_size = EXCLUDED.file_size,
+                glb_info = EXCLUDED.glb_info,
                 updated_at = NOW()
             RETURNING *
             "#,
@@ -189,12 +200,14 @@ impl CharacterApi {
             link_path,
             user_token.id,
             file_bytes.len() as i64,
+            serde_json::to_value(&glb_info).ok(),
         )
         .fetch_one(&*app.pool)
         .await;
 
         match result {
             Ok(model) => {
+                Self::model_changed(app, Some(user_token.id), "upload", &model).await;
                 let uploader_name = sqlx::query_scalar!(
                     "SELECT persona_name FROM website.steam_user WHERE user_id = $1",
                     user_token.id
@@ -208,7 +221,7 @@ impl CharacterApi {
                 api_model.link_path = app.character_storage.normalize_link_path(
                     &api_model.link_path,
                     &api_model.model_id,
-                );
+                ).await;
                 api_model.uploader_name = uploader_name;
                 response!(ok api_model)
             }
@@ -219,370 +232,7 @@ impl CharacterApi {
         }
     }
 
-    /// Initiate a chunked upload session for a large character 3D model
-    #[oai(path = "/servers/:server_id/characters/:model_id/3d/upload/initiate", method = "post")]
-    async fn initiate_character_chunked_upload(
-        &self,
-        Data(app): Data<&AppData>,
-        Path(server_id): Path<String>,
-        Path(model_id): Path<String>,
-        TokenBearer(user_token): TokenBearer,
-        Json(req): Json<serde_json::Value>,
-    ) -> Response<InitiateUploadResponse> {
-        if !check_superuser(&app, user_token.id).await {
-            return response!(err "Forbidden", ErrorCode::Forbidden);
-        }
-
-        let credit = req.get("credit").and_then(|v| v.as_str()).map(|s| s.to_string());
-        let display_name = req.get("name").and_then(|v| v.as_str()).map(|s| s.to_string());
-
-        let file_size = match req.get("file_size").and_then(|v| v.as_u64()) {
-            Some(size) => size,
-            None => return response!(err "file_size is required", ErrorCode::BadRequest),
-        };
-
-        let session_id = uuid::Uuid::new_v4().to_string();
-
-        const CHUNK_SIZE: usize = 10_485_760; // 10MB
-        let total_chunks = ((file_size as f64) / (CHUNK_SIZE as f64)).ceil() as u32;
-
-        let session = CharacterUploadSession {
-            session_id: session_id.clone(),
-            model_id: model_id.clone(),
-            name: display_name,
-            server_id: server_id.clone(),
-            credit,
-            total_chunks,
-            chunk_size: CHUNK_SIZE,
-            total_size: file_size,
-            uploaded_by: user_token.id,
-            created_at: chrono::Utc::now().to_rfc3339(),
-            chunks_received: Vec::new(),
-        };
-
-        let session_key = format!("char_upload_session:{}", session_id);
-        match serde_json::to_string(&session) {
-            Ok(session_json) => {
-                if let Ok(mut conn) = app.cache.redis_pool.get().await {
-                    use redis::AsyncCommands;
-                    let _: redis::RedisResult<()> = conn.set_ex(&session_key, &session_json, 86400).await;
-                } else {
-                    tracing::error!("Failed to get Redis connection");
-                    return response!(err "Failed to create upload session", ErrorCode::InternalServerError);
-                }
-            }
-            Err(e) => {
-                tracing::error!("Failed to serialize upload session: {}", e);
-                return response!(err "Failed to create upload session", ErrorCode::InternalServerError);
-            }
-        }
-
-        let store_upload = std::env::var("CHARACTER_STORE_UPLOAD")
-            .unwrap_or_else(|_| "./characters".to_string());
-        let temp_dir = format!("{}/.tmp/{}", store_upload, session_id);
-        if let Err(e) = tokio::fs::create_dir_all(&temp_dir).await {
-            tracing::error!("Failed to create temp directory: {}", e);
-            return response!(err "Failed to create upload session", ErrorCode::InternalServerError);
-        }
-
-        tracing::info!("Character upload session initiated: {}, model: {}, server: {}, size: {}", session_id, model_id, server_id, file_size);
-
-        response!(ok InitiateUploadResponse {
-            session_id,
-            chunk_size: CHUNK_SIZE,
-            total_chunks,
-        })
-    }
-
-    /// Upload an individual chunk for a character model
-    #[oai(path = "/servers/:server_id/characters/:model_id/3d/upload/chunk/:session_id", method = "post")]
-    async fn upload_character_chunk(
-        &self,
-        Data(app): Data<&AppData>,
-        Path(server_id): Path<String>,
-        Path(model_id): Path<String>,
-        Path(session_id): Path<String>,
-        TokenBearer(user_token): TokenBearer,
-        upload: poem::web::Multipart,
-    ) -> Response<ChunkUploadResponse> {
-        if !check_superuser(&app, user_token.id).await {
-            return response!(err "Forbidden", ErrorCode::Forbidden);
-        }
-
-        let session = match Self::get_char_upload_session(&app.cache, &session_id).await {
-            Ok(s) => s,
-            Err(e) => return response!(err e, ErrorCode::NotFound),
-        };
-
-        if session.uploaded_by != user_token.id {
-            return response!(err "Forbidden", ErrorCode::Forbidden);
-        }
-
-        if session.model_id != model_id {
-            return response!(err "Model ID mismatch", ErrorCode::BadRequest);
-        }
-
-        if session.server_id != server_id {
-            return response!(err "Server ID mismatch", ErrorCode::BadRequest);
-        }
-
-        let mut chunk_index: Option<u32> = None;
-        let mut chunk_data: Option<Vec<u8>> = None;
-
-        let mut upload = upload;
-        while let Ok(Some(field)) = upload.next_field().await {
-            let field_name = field.name().map(|s| s.to_string());
-            match field_name.as_deref() {
-                Some("chunk_index") => {
-                    if let Ok(text) = field.text().await {
-                        chunk_index = text.parse::<u32>().ok();
-                    }
-                }
-                Some("chunk_data") => {
-                    if let Ok(bytes) = field.bytes().await {
-                        chunk_data = Some(bytes.to_vec());
-                    }
-                }
-                _ => {}
-            }
-        }
-
-        let chunk_index = match chunk_index {
-            Some(idx) => idx,
-            None => return response!(err "chunk_index is required", ErrorCode::BadRequest),
-        };
-        let chunk_data = match chunk_data {
-            Some(data) => data,
-            None => return response!(err "chunk_data is required", ErrorCode::BadRequest),
-        };
-
-        if chunk_index >= session.total_chunks {
-            return response!(err "Invalid chunk_index", ErrorCode::BadRequest);
-        }
-
-        let already_received = session.chunks_received.contains(&chunk_index);
-
-        let store_upload = std::env::var("CHARACTER_STORE_UPLOAD")
-            .unwrap_or_else(|_| "./characters".to_string());
-        let chunk_path = format!("{}/.tmp/{}/chunk_{}", store_upload, session_id, chunk_index);
-
-        if !already_received {
-            if let Err(e) = tokio::fs::write(&chunk_path, &chunk_data).await {
-                tracing::error!("Failed to write chunk {}: {}", chunk_index, e);
-                return response!(err "Failed to write chunk", ErrorCode::InternalServerError);
-            }
-
-            let mut updated_session = session.clone();
-            updated_session.chunks_received.push(chunk_index);
-            updated_session.chunks_received.sort_unstable();
-
-            if let Err(e) = Self::update_char_upload_session(&app.cache, &updated_session).await {
-                tracing::error!("Failed to update upload session: {}", e);
-                return response!(err "Failed to update session", ErrorCode::InternalServerError);
-            }
-        }
-
-        let chunks_remaining = session.total_chunks
-            - (session.chunks_received.len() as u32)
-            - if already_received { 0 } else { 1 };
-
-        response!(ok ChunkUploadResponse {
-            chunk_index,
-            received: true,
-            chunks_remaining,
-        })
-    }
-
-    /// Complete chunked upload and assemble the character model file
-    #[oai(path = "/servers/:server_id/characters/:model_id/3d/upload/complete/:session_id", method = "post")]
-    async fn complete_character_chunked_upload(
-        &self,
-        Data(app): Data<&AppData>,
-        Path(server_id): Path<String>,
-        Path(model_id): Path<String>,
-        Path(session_id): Path<String>,
-        TokenBearer(user_token): TokenBearer,
-    ) -> Response<Character3DModel> {
-        if !check_superuser(&app, user_token.id).await {
-            return response!(err "Forbidden", ErrorCode::Forbidden);
-        }
-
-        let session = match Self::get_char_upload_session(&app.cache, &session_id).await {
-            Ok(s) => s,
-            Err(e) => return response!(err e, ErrorCode::NotFound),
-        };
-
-        if session.uploaded_by != user_token.id {
-            return response!(err "Forbidden", ErrorCode::Forbidden);
-        }
-
-        if session.model_id != model_id {
-            return response!(err "Model ID mismatch", ErrorCode::BadRequest);
-        }
-
-        if session.server_id != server_id {
-            return response!(err "Server ID mismatch", ErrorCode::BadRequest);
-        }
-
-        if session.chunks_received.len() != session.total_chunks as usize {
-            let msg = format!("Missing chunks: {}/{}", session.chunks_received.len(), session.total_chunks);
-            return response!(err &msg, ErrorCode::BadRequest);
-        }
-
-        let store_upload = std::env::var("CHARACTER_STORE_UPLOAD")
-            .unwrap_or_else(|_| "./characters".to_string());
-
-        let target_path = if app.character_storage.is_local() {
-            match app.character_storage.local_path(&session.model_id) {
-                Some(path) => path.to_string_lossy().to_string(),
-                None => {
-                    tracing::error!("Local character storage path is not configured");
-                    return response!(err "Storage misconfigured", ErrorCode::InternalServerError);
-                }
-            }
-        } else {
-            format!("{}/.tmp/{}/assembled.glb", store_upload, session_id)
-        };
-
-        let final_path = match Self::assemble_char_chunks(&session, &store_upload, &target_path).await {
-            Ok(path) => path,
-            Err(e) => {
-                tracing::error!("Chunk assembly failed: {}, error: {}", session_id, e);
-                let _ = Self::cleanup_char_temp_dir(&session_id, &store_upload).await;
-                return response!(err "Failed to assemble chunks", ErrorCode::InternalServerError);
-            }
-        };
-
-        match tokio::fs::metadata(&final_path).await {
-            Ok(metadata) => {
-                if metadata.len() != session.total_size {
-                    tracing::error!("File size mismatch: expected {}, got {}", session.total_size, metadata.len());
-                    let _ = tokio::fs::remove_file(&final_path).await;
-                    let _ = Self::cleanup_char_temp_dir(&session_id, &store_upload).await;
-                    return response!(err "File size mismatch", ErrorCode::InternalServerError);
-                }
-            }
-            Err(e) => {
-                tracing::error!("Failed to verify assembled file: {}", e);
-                let _ = Self::cleanup_char_temp_dir(&session_id, &store_upload).await;
-                return response!(err "Failed to verify file", ErrorCode::InternalServerError);
-            }
-        }
-
-        let file_size = session.total_size as i64;
-        let link_path = match app.character_storage
-            .store_file(&session.model_id, std::path::Path::new(&final_path))
-            .await
-        {
-            Ok(path) => path,
-            Err(e) => {
-                tracing::error!("Failed to store assembled character model: {}", e);
-                let _ = Self::cleanup_char_temp_dir(&session_id, &store_upload).await;
-                return response!(err "Failed to store file", ErrorCode::InternalServerError);
-            }
-        };
-
-        let result = sqlx::query_as!(
-            DbCharacter3DModel,
-            r#"
-            INSERT INTO website.character_3d_model (server_id, model_id, name, credit, link_path, uploaded_by, file_size)
-            VALUES ($1, $2, $3, $4, $5, $6, $7)
-            ON CONFLICT (server_id, model_id)
-            DO UPDATE SET
-                name = EXCLUDED.name,
-                credit = EXCLUDED.credit,
-                link_path = EXCLUDED.link_path,
-                uploaded_by = EXCLUDED.uploaded_by,
-                file_size = EXCLUDED.file_size,
-                updated_at = NOW()
-            RETURNING *
-            "#,
-            session.server_id,
-            session.model_id,
-            session.name,
-            session.credit,
-            link_path,
-            session.uploaded_by,
-            file_size,
-        )
-        .fetch_one(&*app.pool)
-        .await;
-
-        let _ = Self::cleanup_char_temp_dir(&session_id, &store_upload).await;
-        let _ = Self::delete_char_upload_session(&app.cache, &session_id).await;
-
-        match result {
-            Ok(model) => {
-                tracing::info!("Character upload completed: {}, final size: {}", session_id, file_size);
-
-                let uploader_name = sqlx::query_scalar!(
-                    "SELECT persona_name FROM website.steam_user WHERE user_id = $1",
-                    model.uploaded_by
-                )
-                .fetch_optional(&*app.pool)
-                .await
-                .ok()
-                .flatten();
-
-                let mut api_model: Character3DModel = model.into();
-                api_model.link_path = app.character_storage.normalize_link_path(
-                    &api_model.link_path,
-                    &api_model.model_id,
-                );
-                api_model.uploader_name = uploader_name;
-                response!(ok api_model)
-            }
-            Err(e) => {
-                tracing::error!("Database error: {}", e);
-                response!(err "Database error", ErrorCode::InternalServerError)
-            }
-        }
-    }
-
-    /// Cancel a chunked character model upload
-    #[oai(path = "/servers/:server_id/characters/:model_id/3d/upload/cancel/:session_id", method = "delete")]
-    async fn cancel_character_chunked_upload(
-        &self,
-        Data(app): Data<&AppData>,
-        Path(server_id): Path<String>,
-        Path(model_id): Path<String>,
-        Path(session_id): Path<String>,
-        TokenBearer(user_token): TokenBearer,
-    ) -> Response<String> {
-        if !check_superuser(&app, user_token.id).await {
-            return response!(err "Forbidden", ErrorCode::Forbidden);
-        }
-
-        let store_upload = std::env::var("CHARACTER_STORE_UPLOAD")
-            .unwrap_or_else(|_| "./characters".to_string());
-
-        let session = match Self::get_char_upload_session(&app.cache, &session_id).await {
-            Ok(s) => s,
-            Err(_) => {
-                let _ = Self::cleanup_char_temp_dir(&session_id, &store_upload).await;
-                return response!(ok "Upload cancelled".to_string());
-            }
-        };
-
-        if session.uploaded_by != user_token.id {
-            return response!(err "Forbidden", ErrorCode::Forbidden);
-        }
-
-        if session.model_id != model_id {
-            return response!(err "Model ID mismatch", ErrorCode::BadRequest);
-        }
-
-        if session.server_id != server_id {
-            return response!(err "Server ID mismatch", ErrorCode::BadRequest);
-        }
-
-        let _ = Self::cleanup_char_temp_dir(&session_id, &store_upload).await;
-        let _ = Self::delete_char_upload_session(&app.cache, &session_id).await;
-
-        response!(ok "Upload cancelled".to_string())
-    }
-
-    /// Delete a character 3D model (superuser only)
+    /// Delete a character 3D model (`models.upload` on the server)
     #[oai(path = "/servers/:server_id/characters/:model_id/3d", method = "delete")]
     async fn delete_character_3d_model(
         &self,
@@ -591,13 +241,13 @@ impl CharacterApi {
         Path(model_id): Path<String>,
         TokenBearer(user_token): TokenBearer,
     ) -> Response<String> {
-        if !check_superuser(&app, user_token.id).await {
+        if !check_character_models(&app, user_token.id, &server_id, &model_id).await {
             return response!(err "Forbidden", ErrorCode::Forbidden);
         }
 
         let model = sqlx::query_as!(
             DbCharacter3DModel,
-            "SELECT id, model_id, name, server_id, credit, link_path, uploaded_by, thumbnail_path, file_size, created_at, updated_at FROM website.character_3d_model WHERE server_id = $1 AND model_id = $2",
+            "SELECT id, model_id, name, server_id, credit, link_path, uploaded_by, thumbnail_path, file_size, created_at, updated_at, glb_info FROM website.character_3d_model WHERE server_id = $1 AND model_id = $2",
             server_id,
             model_id
         )
@@ -629,7 +279,13 @@ impl CharacterApi {
         .await;
 
         match result {
-            Ok(_) => response!(ok "3D model deleted successfully".to_string()),
+            Ok(_) => {
+                record_audit(
+                    &app.pool, Some(user_token.id), "delete", "character_model", &format!("{server_id}/{model_id}"),
+                    Some(character_model_audit(&model)), None,
+                ).await;
+                response!(ok "3D model deleted successfully".to_string())
+            }
             Err(e) => {
                 tracing::error!("Database error: {}", e);
                 response!(internal_server_error)
@@ -637,7 +293,7 @@ impl CharacterApi {
         }
     }
 
-    /// Upload a thumbnail image for a character 3D model (superuser only)
+    /// Upload a thumbnail image for a character 3D model (`models.upload` on the server)
     #[oai(path = "/servers/:server_id/characters/:model_id/3d/thumbnail", method = "post")]
     async fn upload_character_thumbnail(
         &self,
@@ -647,7 +303,7 @@ impl CharacterApi {
         TokenBearer(user_token): TokenBearer,
         multipart: poem::web::Multipart,
     ) -> Response<Character3DModel> {
-        if !check_superuser(&app, user_token.id).await {
+        if !check_character_models(&app, user_token.id, &server_id, &model_id).await {
             return response!(err "Forbidden", ErrorCode::Forbidden);
         }
 
@@ -695,7 +351,7 @@ impl CharacterApi {
             SET thumbnail_path = $1, updated_at = NOW()
             WHERE server_id = $2 AND model_id = $3
             RETURNING id, model_id, name, server_id, credit, link_path,
-                      uploaded_by, thumbnail_path, file_size, created_at, updated_at
+                      uploaded_by, thumbnail_path, file_size, created_at, updated_at, glb_info
             "#,
             filename,
             server_id,
@@ -706,6 +362,10 @@ impl CharacterApi {
 
         match updated {
             Ok(Some(model)) => {
+                record_audit(
+                    &app.pool, Some(user_token.id), "thumbnail", "character_model", &format!("{server_id}/{model_id}"),
+                    None, Some(character_model_audit(&model)),
+                ).await;
                 let uploader_name = if let Some(uid) = model.uploaded_by {
                     sqlx::query_scalar!(
                         "SELECT persona_name FROM website.steam_user WHERE user_id = $1",
@@ -722,7 +382,7 @@ impl CharacterApi {
                 api_model.link_path = app.character_storage.normalize_link_path(
                     &api_model.link_path,
                     &api_model.model_id,
-                );
+                ).await;
                 api_model.uploader_name = uploader_name;
                 response!(ok api_model)
             }
@@ -734,74 +394,73 @@ impl CharacterApi {
         }
     }
 
-    // -------------------------------------------------------------------------
-    // Upload session helpers
-    // -------------------------------------------------------------------------
-
-    async fn get_char_upload_session(
-        cache: &FastCache,
-        session_id: &str,
-    ) -> Result<CharacterUploadSession, &'static str> {
-        use redis::AsyncCommands;
-        let key = format!("char_upload_session:{}", session_id);
-        let mut conn = cache.redis_pool.get().await
-            .map_err(|_| "Failed to get Redis connection")?;
-        let json: String = conn.get(&key).await
-            .map_err(|_| "Session not found or expired")?;
-        serde_json::from_str(&json)
-            .map_err(|_| "Failed to parse session")
-    }
-
-    async fn update_char_upload_session(
-        cache: &FastCache,
-        session: &CharacterUploadSession,
-    ) -> Result<(), &'static str> {
-        use redis::AsyncCommands;
-        let key = format!("char_upload_session:{}", session.session_id);
-        let json = serde_json::to_string(session)
-            .map_err(|_| "Failed to serialize session")?;
-        let mut conn = cache.redis_pool.get().await
-            .map_err(|_| "Failed to get Redis connection")?;
-        let _: redis::RedisResult<()> = conn.set_ex(&key, &json, 86400).await;
-        Ok(())
-    }
+    /// Stores a finished resumable upload as the character's model.
+    pub(crate) async fn finish_model_upload(
+        app: &AppData,
+        server_id: &str,
+        model_id: &str,
+        name: Option<String>,
+        credit: Option<String>,
+        uploaded_by: i64,
+        path: &std::path::Path,
+    ) -> Result<(), FinishError> {
+        let glb_info = inspect_glb_file(path)
+            .await
+            .map_err(|e| FinishError::Rejected(format!("Invalid GLB: {e}")))?;
+        let file_size = tokio::fs::metadata(path)
+            .await
+            .map_err(|e| FinishError::Failed(format!("Failed to verify file: {e}")))?
+            .len() as i64;
+        let link_path = app.character_storage
+            .store_file(model_id, path)
+            .await
+            .map_err(FinishError::Failed)?;
 
-    async fn delete_char_upload_session(
-        cache: &FastCache,
-        session_id: &str,
-    ) -> Result<(), ()> {
-        use redis::AsyncCommands;
-        let key = format!("char_upload_session:{}", session_id);
-        if let Ok(mut conn) = cache.redis_pool.get().await {
-            let _: redis::RedisResult<()> = conn.del(&key).await;
-        }
+        let model = sqlx::query_as!(
+            DbCharacter3DModel,
+            r#"
+            INSERT INTO website.character_3d_model (server_id, model_id, name, credit, link_path, uploaded_by, file_size, glb_info)
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8)
+            ON CONFLICT (server_id, model_id)
+            DO UPDATE SET
+                name = EXCLUDED.name,
+                credit = EXCLUDED.credit,
+                link_path = EXCLUDED.link_path,
+                uploaded_by = EXCLUDED.uploaded_by,
+                file_size = EXCLUDED.file_size,
+                glb_info = EXCLUDED.glb_info,
+                updated_at = NOW()
+            RETURNING id, model_id, name, server_id, credit, link_path,
+                      uploaded_by, thumbnail_path, file_size, created_at, updated_at, glb_info
+            "#,
+            server_id,
+            model_id,
+            name,
+            credit,
+            link_path,
+            uploaded_by,
+            file_size,
+            serde_json::to_value(&glb_info).ok(),
+        )
+        .fetch_one(&*app.pool)
+        .await
+        .map_err(|e| FinishError::Failed(format!("Database error: {e}")))?;
+        Self::model_changed(app, Some(uploaded_by), "upload", &model).await;
         Ok(())
     }
 
-    async fn assemble_char_chunks(
-        session: &CharacterUploadSession,
-        store_upload: &str,
-        target_path: &str,
-    ) -> Result<String, std::io::Error> {
-        if let Some(parent) = std::path::Path::new(target_path).parent() {
-            tokio::fs::create_dir_all(parent).await?;
-        }
-        let mut target_file = tokio::fs::File::create(target_path).await?;
-        for chunk_index in 0..session.total_chunks {
-            let chunk_path = format!("{}/.tmp/{}/chunk_{}", store_upload, session.session_id, chunk_index);
-            let mut chunk_file = tokio::fs::File::open(&chunk_path).await?;
-            tokio::io::copy(&mut chunk_file, &mut target_file).await?;
-        }
-        Ok(target_path.to_string())
+    /// The model changed: audit it and have a thumbnail generated unless one was uploaded.
+    async fn model_changed(app: &AppData, actor_id: Option<i64>, action: &str, model: &DbCharacter3DModel) {
+        record_audit(
+            &app.pool, actor_id, action, "character_model", &format!("{}/{}", model.server_id, model.model_id),
+            None, Some(character_model_audit(model)),
+        ).await;
+        enqueue_model_job(&app.pool, ModelJobTarget::CharacterModel {
+            server_id: model.server_id.clone(),
+            model_id: model.model_id.clone(),
+        }).await;
     }
 
-    async fn cleanup_char_temp_dir(
-        session_id: &str,
-        store_upload: &str,
-    ) -> Result<(), std::io::Error> {
-        let temp_dir = format!("{}/.tmp/{}", store_upload, session_id);
-        tokio::fs::remove_dir_all(&temp_dir).await
-    }
 }
 
 impl UriPatternExt for CharacterApi {
@@ -810,11 +469,18 @@ impl UriPatternExt for CharacterApi {
             "/servers/{server_id}/characters",
             "/servers/{server_id}/characters/{model_id}/3d",
             "/servers/{server_id}/characters/{model_id}/3d/upload",
-            "/servers/{server_id}/characters/{model_id}/3d/upload/initiate",
-            "/servers/{server_id}/characters/{model_id}/3d/upload/chunk/{session_id}",
-            "/servers/{server_id}/characters/{model_id}/3d/upload/complete/{session_id}",
-            "/servers/{server_id}/characters/{model_id}/3d/upload/cancel/{session_id}",
             "/servers/{server_id}/characters/{model_id}/3d/thumbnail",
         ].iter_into()
     }
 }
+
+/// What audit entries record about a character model.
+fn character_model_audit(model: &DbCharacter3DModel) -> serde_json::Value {
+    serde_json::json!({
+        "name": model.name,
+        "credit": model.credit,
+        "file_size": model.file_size,
+        "thumbnail_path": model.thumbnail_path,
+        "uploaded_by": model.uploaded_by,
+    })
+}
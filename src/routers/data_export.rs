@@ -0,0 +1,208 @@
+use chrono::{DateTime, Utc};
+use poem::web::Data;
+use poem_openapi::param::{Path, Query};
+use poem_openapi::payload::{Binary, PlainText};
+use poem_openapi::{ApiResponse, Object, OpenApi};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::core::api_models::*;
+use crate::core::data_export::{download_url, verify_download};
+use crate::core::model::DbDataExport;
+use crate::core::utils::*;
+use crate::{response, AppData};
+
+pub struct DataExportApi;
+
+#[derive(Debug, Serialize, Deserialize, Object, Clone)]
+pub struct DataExport {
+    pub id: String,
+    /// `queued`, `running`, `done`, `failed` or `expired`
+    pub status: String,
+    /// 0-100
+    pub progress: i16,
+    /// Section being collected while running
+    pub step: Option<String>,
+    pub error: Option<String>,
+    pub file_size: Option<i64>,
+    pub created_at: DateTime<Utc>,
+    pub finished_at: Option<DateTime<Utc>>,
+    /// When the export is deleted
+    pub expires_at: Option<DateTime<Utc>>,
+    /// Only while done, short lived. Fetch the export again for a new one.
+    pub download_url: Option<String>,
+}
+
+impl From<DbDataExport> for DataExport {
+    fn from(e: DbDataExport) -> Self {
+        let download_url = match (e.status.as_str(), e.expires_at) {
+            ("done", Some(expires_at)) => download_url(e.id, expires_at.unix_timestamp()),
+            _ => None,
+        };
+        DataExport {
+            id: e.id.to_string(),
+            status: e.status,
+            progress: e.progress,
+            step: e.step,
+            error: e.error,
+            file_size: e.file_size,
+            created_at: db_to_utc(e.created_at),
+            finished_at: e.finished_at.map(db_to_utc),
+            expires_at: e.expires_at.map(db_to_utc),
+            download_url,
+        }
+    }
+}
+
+#[derive(ApiResponse)]
+enum DataExportDownloadResponse {
+    #[oai(status = 200, content_type = "application/json")]
+    Ok(
+        Binary<poem::Body>,
+        #[oai(header = "Content-Disposition")] String,
+        #[oai(header = "Cache-Control")] String,
+    ),
+    #[oai(status = 302)]
+    Redirect(#[oai(header = "Location")] String, #[oai(header = "Cache-Control")] String),
+    #[oai(status = 403)]
+    Forbidden(PlainText<String>),
+    #[oai(status = 404)]
+    NotFound(PlainText<String>),
+}
+
+#[OpenApi]
+impl DataExportApi {
+    /// Requests a JSON export of the records stored about the signed in user, one document
+    /// with a list of rows per section. Files they uploaded, such as 3D models, aren't part of
+    /// it. It's built in the background, poll `GET /accounts/me/export` for progress. While an
+    /// export is queued, running or still downloadable, that one is returned instead of
+    /// starting another.
+    #[oai(path = "/accounts/me/export", method = "post")]
+    async fn request_data_export(
+        &self,
+        Data(data): Data<&AppData>,
+        TokenBearer(user_token): TokenBearer,
+    ) -> Response<DataExport> {
+        // A request racing this one can insert first, the second pass returns what it queued
+        for _ in 0..2 {
+            let result = sqlx::query_as!(
+                DbDataExport,
+                "WITH current AS (
+                    SELECT * FROM website.data_export
+                    WHERE user_id = $1 AND status IN ('queued', 'running', 'done')
+                    ORDER BY created_at DESC
+                    LIMIT 1
+                ), created AS (
+                    INSERT INTO website.data_export (user_id)
+                    SELECT $1 WHERE NOT EXISTS (SELECT 1 FROM current)
+                    ON CONFLICT (user_id) WHERE status IN ('queued', 'running') DO NOTHING
+                    RETURNING *
+                )
+                SELECT id AS \"id!\", user_id AS \"user_id!\", status AS \"status!\", progress AS \"progress!\",
+                       step, error, file_path, file_size, attempts AS \"attempts!\", created_at AS \"created_at!\",
+                       started_at, finished_at, expires_at
+                FROM current
+                UNION ALL
+                SELECT * FROM created",
+                user_token.id
+            )
+            .fetch_optional(&*data.pool)
+            .await;
+            match result {
+                Ok(Some(export)) => return response!(ok export.into()),
+                Ok(None) => continue,
+                Err(e) => {
+                    tracing::error!("Failed to request data export: {}", e);
+                    return response!(internal_server_error);
+                }
+            }
+        }
+        response!(internal_server_error)
+    }
+
+    /// The latest export, with a download link once it's done
+    #[oai(path = "/accounts/me/export", method = "get")]
+    async fn get_data_export(
+        &self,
+        Data(data): Data<&AppData>,
+        TokenBearer(user_token): TokenBearer,
+    ) -> Response<DataExport> {
+        let result = sqlx::query_as!(
+            DbDataExport,
+            "SELECT * FROM website.data_export WHERE user_id = $1 ORDER BY created_at DESC LIMIT 1",
+            user_token.id
+        )
+        .fetch_optional(&*data.pool)
+        .await;
+        match result {
+            Ok(Some(export)) => response!(ok export.into()),
+            Ok(None) => response!(err "No export requested yet", ErrorCode::NotFound),
+            Err(e) => {
+                tracing::error!("Failed to fetch data export: {}", e);
+                response!(internal_server_error)
+            }
+        }
+    }
+
+    /// Serves the JSON export through the signed link from [`DataExport::download_url`]. The
+    /// link is the credential, so it works without a bearer token. Exports on S3 compatible
+    /// storage are handed off to a short lived presigned URL.
+    #[oai(path = "/accounts/me/export/:export_id/download", method = "get")]
+    async fn download_data_export(
+        &self,
+        Data(data): Data<&AppData>,
+        Path(export_id): Path<String>,
+        Query(expires): Query<i64>,
+        Query(signature): Query<String>,
+    ) -> DataExportDownloadResponse {
+        let Ok(export_id) = Uuid::parse_str(&export_id) else {
+            return DataExportDownloadResponse::NotFound(PlainText("Not found".to_string()))
+        };
+        if !verify_download(export_id, expires, &signature) {
+            return DataExportDownloadResponse::Forbidden(PlainText("Invalid or expired link".to_string()))
+        }
+        let available = sqlx::query_scalar!(
+            "SELECT EXISTS(
+                SELECT 1 FROM website.data_export
+                WHERE id = $1 AND status = 'done' AND expires_at > NOW()
+            )",
+            export_id
+        )
+        .fetch_one(&*data.pool)
+        .await;
+        if !matches!(available, Ok(Some(true))) {
+            return DataExportDownloadResponse::NotFound(PlainText("Export no longer available".to_string()))
+        }
+
+        let key = data.export_storage.object_key(export_id);
+        match data.export_storage.presigned_download(&key).await {
+            Some(Ok(url)) => return DataExportDownloadResponse::Redirect(url, "no-store".to_string()),
+            Some(Err(e)) => {
+                tracing::warn!("Presigning download of export {export_id} failed: {e}");
+                return DataExportDownloadResponse::NotFound(PlainText("Not found".to_string()))
+            }
+            None => {}
+        }
+        match data.export_storage.open_local(&key).await {
+            Some(Ok(file)) => DataExportDownloadResponse::Ok(
+                Binary(poem::Body::from_async_read(file)),
+                format!("attachment; filename=\"zewatcher-export-{export_id}.json\""),
+                "private, no-store".to_string(),
+            ),
+            Some(Err(e)) => {
+                tracing::warn!("Download of export {export_id} failed: {e}");
+                DataExportDownloadResponse::NotFound(PlainText("Not found".to_string()))
+            }
+            None => DataExportDownloadResponse::NotFound(PlainText("Not found".to_string())),
+        }
+    }
+}
+
+impl UriPatternExt for DataExportApi {
+    fn get_all_patterns(&self) -> Vec<RoutePattern<'_>> {
+        vec![
+            "/accounts/me/export",
+            "/accounts/me/export/{export_id}/download",
+        ].iter_into()
+    }
+}
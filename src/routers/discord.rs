@@ -0,0 +1,256 @@
+use poem::web::Data;
+use poem_openapi::payload::Json;
+use poem_openapi::OpenApi;
+use rand::distr::Alphanumeric;
+use rand::Rng;
+
+use crate::core::api_models::*;
+use crate::core::discord::{
+    fetch_discord_link, revoke_discord_grants, save_tokens, sync_discord_roles, DiscordConfig,
+};
+use crate::core::utils::*;
+use crate::{response, AppData};
+
+/// How long a started link stays valid, Discord's consent screen included
+const STATE_TTL_SECS: u64 = 10 * 60;
+const STATE_LEN: usize = 32;
+
+pub struct DiscordApi;
+
+fn state_key(state: &str) -> String {
+    format!("discord_oauth_state:{state}")
+}
+
+#[OpenApi]
+impl DiscordApi {
+    /// Where to send the user to link their Discord account. Discord redirects back to
+    /// `DISCORD_AUTH2_REDIRECT_URI` with the `code` and `state` for the callback.
+    #[oai(path = "/accounts/me/discord/authorize", method = "get")]
+    async fn discord_authorize(
+        &self,
+        Data(data): Data<&AppData>,
+        TokenBearer(user_token): TokenBearer,
+    ) -> Response<String> {
+        let Some(config) = DiscordConfig::from_env() else {
+            return response!(err "Discord linking isn't configured", ErrorCode::NotImplemented);
+        };
+        let state: String = rand::rng()
+            .sample_iter(&Alphanumeric)
+            .take(STATE_LEN)
+            .map(char::from)
+            .collect();
+
+        let Ok(mut conn) = data.cache.redis_pool.get().await else {
+            return response!(internal_server_error);
+        };
+        let stored: redis::RedisResult<()> = redis::cmd("SET")
+            .arg(state_key(&state))
+            .arg(user_token.id)
+            .arg("EX")
+            .arg(STATE_TTL_SECS)
+            .query_async(&mut conn)
+            .await;
+        if let Err(e) = stored {
+            tracing::error!("Failed to store Discord OAuth state: {}", e);
+            return response!(internal_server_error);
+        }
+
+        match config.authorize_link(&state) {
+            Ok(url) => response!(ok url),
+            Err(e) => {
+                tracing::error!("{}", e);
+                response!(internal_server_error)
+            }
+        }
+    }
+
+    /// Finishes linking, replacing any Discord account linked before, then syncs guild roles
+    #[oai(path = "/accounts/me/discord/callback", method = "post")]
+    async fn discord_callback(
+        &self,
+        Data(data): Data<&AppData>,
+        TokenBearer(user_token): TokenBearer,
+        Json(payload): Json<DiscordCallbackDto>,
+    ) -> Response<DiscordLink> {
+        let Some(config) = DiscordConfig::from_env() else {
+            return response!(err "Discord linking isn't configured", ErrorCode::NotImplemented);
+        };
+
+        let Ok(mut conn) = data.cache.redis_pool.get().await else {
+            return response!(internal_server_error);
+        };
+        let owner: redis::RedisResult<Option<i64>> = redis::cmd("GETDEL")
+            .arg(state_key(&payload.state))
+            .query_async(&mut conn)
+            .await;
+        match owner {
+            Ok(Some(owner)) if owner == user_token.id => {}
+            Ok(_) => return response!(err "Invalid or expired state, start linking again", ErrorCode::BadRequest),
+            Err(e) => {
+                tracing::error!("Failed to read Discord OAuth state: {}", e);
+                return response!(internal_server_error);
+            }
+        }
+
+        let tokens = match config.exchange_code(&payload.code).await {
+            Ok(t) => t,
+            Err(e) => {
+                tracing::warn!("Discord code exchange failed: {}", e);
+                return response!(err "Discord rejected the authorization code", ErrorCode::BadRequest);
+            }
+        };
+        let identity = match config.fetch_user(&tokens.access_token).await {
+            Ok(i) => i,
+            Err(e) => {
+                tracing::error!("Failed to fetch Discord user: {}", e);
+                return response!(internal_server_error);
+            }
+        };
+        let Ok(discord_id) = identity.id.parse::<i64>() else {
+            return response!(internal_server_error);
+        };
+
+        let linked_elsewhere = sqlx::query_scalar!(
+            "SELECT EXISTS(
+                SELECT 1 FROM website.discord_user WHERE user_id = $1 AND steam_user_id <> $2
+            )",
+            discord_id,
+            user_token.id
+        )
+        .fetch_one(&*data.pool)
+        .await;
+        match linked_elsewhere {
+            Ok(Some(false)) => {}
+            Ok(_) => return response!(err "This Discord account is linked to another user", ErrorCode::Conflict),
+            Err(e) => {
+                tracing::error!("Failed to check Discord link: {}", e);
+                return response!(internal_server_error);
+            }
+        }
+
+        let display_name = identity.global_name.clone().unwrap_or_else(|| identity.username.clone());
+        let linked = async {
+            let mut tx = data.pool.begin().await?;
+            sqlx::query!(
+                "INSERT INTO discord_user (user_id, display_name, avatar) VALUES ($1, $2, $3)
+                 ON CONFLICT (user_id) DO UPDATE SET display_name = EXCLUDED.display_name, avatar = EXCLUDED.avatar",
+                discord_id,
+                display_name,
+                identity.avatar_url()
+            )
+            .execute(&mut *tx)
+            .await?;
+            sqlx::query!(
+                "DELETE FROM website.discord_user WHERE steam_user_id = $1 AND user_id <> $2",
+                user_token.id,
+                discord_id
+            )
+            .execute(&mut *tx)
+            .await?;
+            sqlx::query!(
+                "INSERT INTO website.discord_user (user_id, steam_user_id, username)
+                 VALUES ($1, $2, $3)
+                 ON CONFLICT (user_id) DO UPDATE SET
+                    steam_user_id = EXCLUDED.steam_user_id,
+                    username = EXCLUDED.username,
+                    roles_sync_failures = 0,
+                    linked_at = CASE
+                        WHEN website.discord_user.steam_user_id IS DISTINCT FROM EXCLUDED.steam_user_id THEN NOW()
+                        ELSE website.discord_user.linked_at
+                    END",
+                discord_id,
+                user_token.id,
+                identity.username
+            )
+            .execute(&mut *tx)
+            .await?;
+            tx.commit().await
+        }.await;
+        if let Err(e) = linked {
+            tracing::error!("Failed to link Discord account: {}", e);
+            return response!(internal_server_error);
+        }
+        if let Err(e) = save_tokens(data, &config, discord_id, &tokens).await {
+            tracing::error!("Failed to store Discord tokens: {}", e);
+            return response!(internal_server_error);
+        }
+
+        if let Err(e) = sync_discord_roles(data, &config, user_token.id).await {
+            tracing::warn!("Discord role sync after linking failed: {}", e);
+        }
+        match fetch_discord_link(data, user_token.id).await {
+            Ok(Some(link)) => response!(ok link),
+            Ok(None) => response!(internal_server_error),
+            Err(e) => {
+                tracing::error!("Failed to fetch Discord link: {}", e);
+                response!(internal_server_error)
+            }
+        }
+    }
+
+    /// Re-reads guild roles, e.g. after being given or losing one on Discord
+    #[oai(path = "/accounts/me/discord/sync", method = "post")]
+    async fn discord_sync(
+        &self,
+        Data(data): Data<&AppData>,
+        TokenBearer(user_token): TokenBearer,
+    ) -> Response<DiscordRoleSync> {
+        let Some(config) = DiscordConfig::from_env() else {
+            return response!(err "Discord linking isn't configured", ErrorCode::NotImplemented);
+        };
+        match fetch_discord_link(data, user_token.id).await {
+            Ok(Some(_)) => {}
+            Ok(None) => return response!(err "No Discord account linked", ErrorCode::NotFound),
+            Err(e) => {
+                tracing::error!("Failed to fetch Discord link: {}", e);
+                return response!(internal_server_error);
+            }
+        }
+        match sync_discord_roles(data, &config, user_token.id).await {
+            Ok(sync) => response!(ok DiscordRoleSync { granted: sync.granted as i64, revoked: sync.revoked as i64 }),
+            Err(e) => {
+                tracing::warn!("Discord role sync failed: {}", e);
+                response!(err "Couldn't reach Discord, try again later", ErrorCode::FailedRetry)
+            }
+        }
+    }
+
+    /// Unlinks Discord and drops every role that came from it
+    #[oai(path = "/accounts/me/discord", method = "delete")]
+    async fn discord_unlink(
+        &self,
+        Data(data): Data<&AppData>,
+        TokenBearer(user_token): TokenBearer,
+    ) -> Response<bool> {
+        let result = sqlx::query!(
+            "DELETE FROM website.discord_user WHERE steam_user_id = $1",
+            user_token.id
+        )
+        .execute(&*data.pool)
+        .await;
+        match result {
+            Ok(r) if r.rows_affected() == 0 => return response!(err "No Discord account linked", ErrorCode::NotFound),
+            Ok(_) => {}
+            Err(e) => {
+                tracing::error!("Failed to unlink Discord: {}", e);
+                return response!(internal_server_error);
+            }
+        }
+        if let Err(e) = revoke_discord_grants(data, user_token.id).await {
+            tracing::error!("Failed to revoke Discord role grants: {}", e);
+            return response!(internal_server_error);
+        }
+        response!(ok true)
+    }
+}
+
+impl UriPatternExt for DiscordApi {
+    fn get_all_patterns(&self) -> Vec<RoutePattern<'_>> {
+        vec![
+            "/accounts/me/discord",
+            "/accounts/me/discord/authorize",
+            "/accounts/me/discord/callback",
+            "/accounts/me/discord/sync",
+        ].iter_into()
+    }
+}
@@ -9,6 +9,8 @@ use sqlx::types::time::OffsetDateTime;
 use uuid::Uuid;
 
 use crate::core::api_models::*;
+use crate::core::audit::record_audit;
+use crate::core::permissions::{has_permission, Permission, Scope};
 use crate::core::utils::*;
 use crate::{response, AppData};
 
@@ -94,7 +96,7 @@ impl DonationsApi {
         TokenBearer(user_token): TokenBearer,
         Json(payload): Json<CreateDonorPayload>,
     ) -> Response<DonorResponse> {
-        if !check_superuser(data, user_token.id).await {
+        if !has_permission(data, user_token.id, Permission::DonationsManage, Scope::Global).await {
             return response!(err "Unauthorized", ErrorCode::Forbidden);
         }
 
@@ -129,7 +131,12 @@ impl DonationsApi {
             }
         };
 
-        response!(ok donor.into())
+        let donor: DonorResponse = donor.into();
+        record_audit(
+            &data.pool, Some(user_token.id), "create", "donor", &donor.id,
+            None, serde_json::to_value(&donor).ok(),
+        ).await;
+        response!(ok donor)
     }
 
     #[oai(path = "/donations/:id", method = "put")]
@@ -144,7 +151,7 @@ impl DonationsApi {
             Ok(u) => u,
             Err(_) => return response!(err "Invalid id", ErrorCode::BadRequest),
         };
-        if !check_superuser(data, user_token.id).await {
+        if !has_permission(data, user_token.id, Permission::DonationsManage, Scope::Global).await {
             return response!(err "Unauthorized", ErrorCode::Forbidden);
         }
 
@@ -158,6 +165,7 @@ impl DonationsApi {
         }
 
         let donated_at = payload.donated_at.map(|d| d.to_db_time());
+        let before = donor_snapshot(data, id).await;
 
         let donor = match sqlx::query_as!(
             DbDonor,
@@ -189,6 +197,10 @@ impl DonationsApi {
             }
         };
 
+        record_audit(
+            &data.pool, Some(user_token.id), "update", "donor", &id.to_string(),
+            before, donor_snapshot(data, id).await,
+        ).await;
         response!(ok donor.into())
     }
 
@@ -203,10 +215,11 @@ impl DonationsApi {
             Ok(u) => u,
             Err(_) => return response!(err "Invalid id", ErrorCode::BadRequest),
         };
-        if !check_superuser(data, user_token.id).await {
+        if !has_permission(data, user_token.id, Permission::DonationsManage, Scope::Global).await {
             return response!(err "Unauthorized", ErrorCode::Forbidden);
         }
 
+        let before = donor_snapshot(data, id).await;
         let result = match sqlx::query!(
             "DELETE FROM website.kofi_donors WHERE id = $1",
             id,
@@ -225,10 +238,20 @@ impl DonationsApi {
             return response!(err "Donor not found", ErrorCode::NotFound);
         }
 
+        record_audit(&data.pool, Some(user_token.id), "delete", "donor", &id.to_string(), before, None).await;
         response!(ok true)
     }
 }
 
+async fn donor_snapshot(data: &AppData, id: Uuid) -> Option<serde_json::Value> {
+    sqlx::query_scalar!("SELECT to_jsonb(d) FROM website.kofi_donors d WHERE d.id = $1", id)
+        .fetch_optional(&*data.pool)
+        .await
+        .ok()
+        .flatten()
+        .flatten()
+}
+
 impl UriPatternExt for DonationsApi {
     fn get_all_patterns(&self) -> Vec<RoutePattern<'_>> {
         vec![
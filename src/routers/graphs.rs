@@ -4,6 +4,7 @@ use std::fmt::Display;
 
 use poem::web::Data;
 use poem_openapi::param::Path;
+use crate::core::anonymization::Anonymizer;
 use crate::core::model::*;
 use crate::core::api_models::*;
 use crate::core::utils::*;
@@ -34,6 +35,50 @@ impl Display for TopPlayersTimeFrame {
 	}
 }
 
+#[derive(Enum, Clone, Copy)]
+#[oai(rename_all = "snake_case")]
+enum LeaderboardMetric{
+	TotalPlaytime,
+	TryhardPlaytime,
+	CasualPlaytime,
+	SessionCount,
+	DistinctMaps,
+	LongestSession,
+}
+
+impl Display for LeaderboardMetric {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		match self {
+			LeaderboardMetric::TotalPlaytime => write!(f, "total_playtime"),
+			LeaderboardMetric::TryhardPlaytime => write!(f, "tryhard_playtime"),
+			LeaderboardMetric::CasualPlaytime => write!(f, "casual_playtime"),
+			LeaderboardMetric::SessionCount => write!(f, "session_count"),
+			LeaderboardMetric::DistinctMaps => write!(f, "distinct_maps"),
+			LeaderboardMetric::LongestSession => write!(f, "longest_session"),
+		}
+	}
+}
+
+/// Opaque leaderboard cursor, the position of the last row seen by the client.
+/// Positions are used instead of player ids so anonymized players can't be recovered from it.
+struct LeaderboardCursor{
+	position: i64,
+}
+impl LeaderboardCursor{
+	fn encode(&self) -> String{
+		use base64::Engine;
+		base64::engine::general_purpose::URL_SAFE_NO_PAD
+			.encode(format!("pos:{}", self.position))
+	}
+	fn decode(cursor: &str) -> Option<Self>{
+		use base64::Engine;
+		let raw = base64::engine::general_purpose::URL_SAFE_NO_PAD.decode(cursor).ok()?;
+		let raw = String::from_utf8(raw).ok()?;
+		let position = raw.strip_prefix("pos:")?.parse().ok()?;
+		Some(Self { position })
+	}
+}
+
 pub struct GraphApi;
 
 #[OpenApi]
@@ -115,7 +160,8 @@ impl GraphApi {
 	async fn get_server_graph_unique_player_session(
 		&self, Data(app): Data<&AppData>,
 		ServerExtractor(server): ServerExtractor,
-		Path(player_id): Path<String>, Path(session_id): Path<String>
+		Path(player_id): Path<String>, Path(session_id): Path<String>,
+		OptionalAnonymousTokenBearer(_anonymizer): OptionalAnonymousTokenBearer,
 	) -> Response<Vec<ServerCountData>> {
 		let pool = &*app.pool.clone();
 		let cache = &app.cache;
@@ -270,8 +316,12 @@ impl GraphApi {
 	}
 	#[oai(path = "/graph/:server_id/top_players", method = "get")]
 	async fn get_server_top_players(
-		&self, data: Data<&AppData>, ServerExtractor(server): ServerExtractor, Query(time_frame): Query<TopPlayersTimeFrame>
+		&self, data: Data<&AppData>, ServerExtractor(server): ServerExtractor, Query(time_frame): Query<TopPlayersTimeFrame>,
+		OptionalTokenBearer(user_token): OptionalTokenBearer,
 	) -> Response<BriefPlayers> {
+		let Ok(anonymizer) = Anonymizer::for_server(&data, &server.server_id, user_token.as_ref()).await else {
+			return response!(internal_server_error)
+		};
 		let pool = &*data.pool.clone();
 		let key = format!("graph-top-players:{}:{}", server.server_id, time_frame);
 		let ttl = match time_frame{
@@ -473,15 +523,162 @@ impl GraphApi {
 
 		let value = BriefPlayers {
 			total_players: total_player_count,
-			players: briefs
+			players: anonymizer.apply_all(briefs)
 		};
 		response!(ok value)
 	}
+	#[oai(path = "/graph/:server_id/leaderboard", method = "get")]
+	async fn get_server_leaderboard(
+		&self, data: Data<&AppData>, ServerExtractor(server): ServerExtractor,
+		Query(start): Query<DateTime<Utc>>, Query(end): Query<DateTime<Utc>>,
+		Query(metric): Query<LeaderboardMetric>, Query(map): Query<Option<String>>,
+		Query(country): Query<Option<String>>, Query(cursor): Query<Option<String>>,
+		Query(limit): Query<Option<i64>>, OptionalTokenBearer(user_token): OptionalTokenBearer,
+	) -> Response<LeaderboardPage> {
+		if start >= end {
+			return response!(err "start must be before end", ErrorCode::BadRequest);
+		}
+		let limit = limit.unwrap_or(20).clamp(1, 100);
+		let cursor = match cursor.as_deref().filter(|c| !c.is_empty()) {
+			Some(raw) => {
+				let Some(parsed) = LeaderboardCursor::decode(raw) else {
+					return response!(err "Invalid cursor", ErrorCode::BadRequest);
+				};
+				Some(parsed)
+			}
+			None => None,
+		};
+		let map = map.filter(|m| !m.trim().is_empty());
+		let country = country.map(|c| c.to_uppercase()).filter(|c| !c.is_empty());
+		let Ok(anonymizer) = Anonymizer::for_server(&data, &server.server_id, user_token.as_ref()).await else {
+			return response!(internal_server_error)
+		};
+		let pool = &*data.pool.clone();
+		let after_position = cursor.as_ref().map(|c| c.position).unwrap_or(0);
+
+		let func = || sqlx::query_as!(DbLeaderboardEntry, r#"
+			WITH sessions AS (
+				SELECT
+					pss.player_id,
+					pss.session_id,
+					GREATEST(pss.started_at, $2) AS clipped_start,
+					LEAST(COALESCE(pss.ended_at, CURRENT_TIMESTAMP), $3) AS clipped_end
+				FROM player_server_session pss
+				JOIN player p ON p.player_id = pss.player_id
+				WHERE pss.server_id = $1
+				  AND pss.started_at <= $3
+				  AND (pss.ended_at IS NULL OR pss.ended_at >= $2)
+				  AND (pss.ended_at IS NOT NULL OR CURRENT_TIMESTAMP - pss.started_at < INTERVAL '12 hours')
+				  -- Filtering by country would place players who hide their location, for every
+				  -- viewer since the result is cached
+				  AND ($6::TEXT IS NULL OR (
+					  p.location_code ->> 'country' = $6
+					  AND NOT EXISTS (
+						  SELECT 1 FROM website.user_anonymization ua
+						  JOIN server sv ON sv.community_id = ua.community_id
+						  WHERE sv.server_id = $1 AND ua.user_id::TEXT = p.player_id AND ua.hide_location
+					  )
+				  ))
+			),
+			segments AS (
+				SELECT
+					s.player_id,
+					s.session_id,
+					smp.map,
+					s.clipped_end - s.clipped_start AS session_duration,
+					LEAST(s.clipped_end, COALESCE(smp.ended_at, CURRENT_TIMESTAMP))
+						- GREATEST(s.clipped_start, smp.started_at) AS duration,
+					COALESCE(sm.is_tryhard, mam.is_tryhard, FALSE) AS is_tryhard,
+					COALESCE(sm.is_casual, mam.is_casual, FALSE) AS is_casual
+				FROM sessions s
+				JOIN server_map_played smp
+					ON smp.server_id = $1
+					AND smp.started_at < s.clipped_end
+					AND COALESCE(smp.ended_at, CURRENT_TIMESTAMP) > s.clipped_start
+				LEFT JOIN server_map sm ON sm.server_id = smp.server_id AND sm.map = smp.map
+				LEFT JOIN map_metadata mam ON mam.name = smp.map
+				WHERE ($5::TEXT IS NULL OR smp.map = $5)
+			),
+			per_player AS (
+				SELECT
+					player_id,
+					CASE
+						WHEN $4 = 'tryhard_playtime' THEN EXTRACT(EPOCH FROM SUM(duration) FILTER (WHERE is_tryhard))
+						WHEN $4 = 'casual_playtime' THEN EXTRACT(EPOCH FROM SUM(duration) FILTER (WHERE is_casual))
+						WHEN $4 = 'session_count' THEN COUNT(DISTINCT session_id)
+						WHEN $4 = 'distinct_maps' THEN COUNT(DISTINCT map)
+						WHEN $4 = 'longest_session' THEN EXTRACT(EPOCH FROM MAX(session_duration))
+						ELSE EXTRACT(EPOCH FROM SUM(duration))
+					END::DOUBLE PRECISION AS value
+				FROM segments
+				WHERE duration > INTERVAL '0'
+				GROUP BY player_id
+			),
+			ranked AS (
+				SELECT
+					player_id,
+					value,
+					RANK() OVER (ORDER BY value DESC) AS rank,
+					ROW_NUMBER() OVER (ORDER BY value DESC, player_id) AS position,
+					COUNT(*) OVER () AS total_players
+				FROM per_player
+				WHERE value > 0
+			)
+			SELECT
+				r.rank,
+				r.position,
+				r.player_id AS "player_id!",
+				p.player_name AS "player_name?",
+				r.value,
+				r.total_players
+			FROM ranked r
+			JOIN player p ON p.player_id = r.player_id
+			WHERE r.position > $7
+			ORDER BY r.position
+			LIMIT $8
+		"#, server.server_id, start.to_db_time(), end.to_db_time(), metric.to_string(),
+			map, country, after_position, limit
+		).fetch_all(pool);
+
+		let key = format!("graph-leaderboard:{}:{}:{}:{}:{}:{}:{}:{}",
+			server.server_id, start.timestamp(), end.timestamp(), metric,
+			map.clone().unwrap_or_default(), country.clone().unwrap_or_default(),
+			after_position, limit
+		);
+		// Windows that are already over will never change, so they can live much longer.
+		let ttl = if end > Utc::now() { 5 * 60 } else { DAY };
+		let Ok(result) = cached_response(&key, &data.cache, ttl, func).await else {
+			return response!(internal_server_error)
+		};
+
+		let rows = result.result;
+		let total_players = rows
+			.first()
+			.and_then(|e| e.total_players)
+			.unwrap_or_default();
+		let next_cursor = if rows.len() as i64 == limit {
+			rows.last().map(|last| LeaderboardCursor {
+				position: last.position.unwrap_or_default(),
+			}.encode())
+		} else {
+			None
+		};
+		let entries: Vec<LeaderboardEntry> = rows.iter_into();
+		response!(ok LeaderboardPage {
+			total_players,
+			entries: anonymizer.apply_all(entries),
+			next_cursor,
+		})
+	}
 	#[oai(path = "/graph/:server_id/players", method = "get")]
 	async fn get_server_players(
 		&self, data: Data<&AppData>, ServerExtractor(server): ServerExtractor,
-		start: Query<Option<DateTime<Utc>>>, end: Query<DateTime<Utc>>, page: Query<usize>
+		start: Query<Option<DateTime<Utc>>>, end: Query<DateTime<Utc>>, page: Query<usize>,
+		OptionalTokenBearer(user_token): OptionalTokenBearer,
 	) -> Response<BriefPlayers>{
+		let Ok(anonymizer) = Anonymizer::for_server(&data, &server.server_id, user_token.as_ref()).await else {
+			return response!(internal_server_error)
+		};
 		let pool = &*data.pool.clone();
 		let pagination_size = 70;
 		let offset = pagination_size * page.0 as i64;
@@ -580,7 +777,7 @@ impl GraphApi {
 		update_online_brief(&pool, &data.cache, &server.server_id, &mut players).await;
 		let value = BriefPlayers {
 			total_players: total_player_count,
-			players
+			players: anonymizer.apply_all(players)
 		};
 		response!(ok value)
 	}
@@ -593,6 +790,7 @@ impl UriPatternExt for GraphApi{
 			"/graph/{server_id}/maps",
 			"/graph/{server_id}/event_count",
 			"/graph/{server_id}/top_players",
+			"/graph/{server_id}/leaderboard",
 			"/graph/{server_id}/players",
 			"/graph/{server_id}/unique_players/maps/{map_name}/sessions/{session_id}",
 			"/graph/{server_id}/unique_players/players/{player_id}/sessions/{session_id}",
@@ -7,8 +7,16 @@ use poem_openapi::types::{ParseFromJSON, ToJSON};
 use serde::{Deserialize, Serialize};
 use sqlx::{Pool, Postgres};
 use crate::{response, AppData, FastCache};
+use crate::core::anonymization::Anonymizer;
 use crate::core::model::*;
 use crate::core::api_models::*;
+use crate::core::guide_revisions::{diff_revisions, record_revision};
+use crate::core::markdown::{render_markdown, MarkdownError, MAX_COMMENT_LEN, MAX_GUIDE_LEN};
+use crate::core::glb::{inspect_glb, inspect_glb_file};
+use crate::core::model_processing::{enqueue_model_job, map_model_audit, save_map_model_version, ModelJobTarget, ModelSource};
+use crate::core::audit::record_audit;
+use crate::core::uploads::FinishError;
+use crate::core::permissions::{check_map_models, has_permission, Permission, Scope};
 use crate::core::utils::*;
 use crate::core::workers::*;
 
@@ -47,6 +55,23 @@ impl Display for GuideSortType {
         }
     }
 }
+#[derive(Enum)]
+#[oai(rename_all = "snake_case")]
+enum MapLeaderboardTimeFrame {
+    Week,
+    Month,
+    AllTime,
+}
+
+impl Display for MapLeaderboardTimeFrame {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            MapLeaderboardTimeFrame::Week => write!(f, "week"),
+            MapLeaderboardTimeFrame::Month => write!(f, "month"),
+            MapLeaderboardTimeFrame::AllTime => write!(f, "all_time"),
+        }
+    }
+}
 #[derive(Serialize, Deserialize)]
 struct SetMapFavorite {
     pub map_name: String,
@@ -135,6 +160,35 @@ async fn get_any_guide_slug(pool: &Pool<Postgres>, cache: &FastCache, map_name:
     let key = format!("any-guide-slug-exist:{map_name}:{guide_slug}");
     cached_response(&key, cache, 60, func).await.and_then(|s| Ok(s.result)).ok()
 }
+async fn get_guide_revision(pool: &Pool<Postgres>, guide_id: uuid::Uuid, revision: i32) -> Result<Option<DbGuideRevision>, sqlx::Error> {
+    sqlx::query_as!(DbGuideRevision,
+            "SELECT r.guide_id,
+                r.revision,
+                r.title,
+                r.content,
+                r.category,
+                r.server_id,
+                r.editor_id,
+                su.persona_name AS editor_name,
+                su.avatar AS editor_avatar,
+                r.restored_from,
+                r.created_at
+            FROM website.guide_revision r
+            LEFT JOIN website.steam_user su ON su.user_id=r.editor_id
+            WHERE r.guide_id=$1 AND r.revision=$2",
+            guide_id, revision
+        )
+        .fetch_optional(pool)
+        .await
+}
+/// History keeps what moderators took down, so only the author and moderators get to read it.
+async fn can_read_guide_history(app: &AppData, guide: &DbGuideBrief, user_id: i64) -> bool {
+    if guide.author_id == user_id {
+        return true
+    }
+    let guide_scope = guide.server_id.as_deref().map_or(Scope::Global, Scope::Server);
+    has_permission(app, user_id, Permission::GuidesModerate, guide_scope).await
+}
 
 async fn get_comment(pool: &Pool<Postgres>, cache: &FastCache, guide_id: &str, comment_id: &str) -> Option<DbGuideCommentBrief> {
     let func = || sqlx::query_as!(DbGuideCommentBrief,
@@ -299,7 +353,6 @@ impl<'a> poem::FromRequest<'a> for GuideSlugExtractor {
 }
 
 struct GuideCommentExtractor{
-    #[allow(dead_code)]
     pub guide: DbGuideBrief,
     pub comment: DbGuideCommentBrief
 }
@@ -662,8 +715,12 @@ impl MapApi{
     }
     #[oai(path="/servers/:server_id/sessions/:session_id/players", method="get")]
     async fn get_map_player_session(
-        &self, Data(app): Data<&AppData>, ServerExtractor(server): ServerExtractor, Path(session_id): Path<i64>
+        &self, Data(app): Data<&AppData>, ServerExtractor(server): ServerExtractor, Path(session_id): Path<i64>,
+        OptionalTokenBearer(user_token): OptionalTokenBearer,
     ) -> Response<Vec<PlayerBrief>>{
+        let Ok(anonymizer) = Anonymizer::for_server(app, &server.server_id, user_token.as_ref()).await else {
+            return response!(internal_server_error)
+        };
         let pool = &*app.pool.clone();
         let cache = &app.cache;
         let time_id =  session_id as i32;
@@ -749,7 +806,113 @@ impl MapApi{
         if !rows.is_new{
             update_online_brief(&pool, cache, &server.server_id, &mut players).await;
         }
-        response!(ok players)
+        response!(ok anonymizer.apply_all(players))
+    }
+    #[oai(path="/servers/:server_id/sessions/:session_id/timeline", method="get")]
+    async fn get_map_session_timeline(
+        &self, Data(app): Data<&AppData>, ServerExtractor(server): ServerExtractor, Path(session_id): Path<i64>,
+        OptionalTokenBearer(user_token): OptionalTokenBearer,
+    ) -> Response<SessionTimeline>{
+        let pool = &*app.pool.clone();
+        let time_id = session_id as i32;
+        let Ok(anonymizer) = Anonymizer::for_server(app, &server.server_id, user_token.as_ref()).await else {
+            return response!(internal_server_error)
+        };
+        let Ok(Some(session)) = sqlx::query_as!(DbSessionBounds, "
+            SELECT time_id, map, started_at, ended_at
+            FROM server_map_played
+            WHERE server_id=$1 AND time_id=$2
+        ", server.server_id, time_id).fetch_optional(pool).await else {
+            return response!(err "No session found with this id.", ErrorCode::NotFound)
+        };
+        let is_playing = session.ended_at.is_none();
+
+        let func = || async {
+            let intervals = sqlx::query_as!(DbTimelineInterval, r#"
+                WITH smp AS (
+                    SELECT started_at, COALESCE(ended_at, CURRENT_TIMESTAMP) AS ended_at
+                    FROM server_map_played
+                    WHERE server_id=$1 AND time_id=$2
+                )
+                SELECT
+                    pss.player_id,
+                    p.player_name AS "player_name?",
+                    GREATEST(pss.started_at, smp.started_at) AS joined_at,
+                    CASE
+                        WHEN pss.ended_at IS NULL OR pss.ended_at >= smp.ended_at THEN NULL
+                        ELSE pss.ended_at
+                    END AS left_at
+                FROM player_server_session pss
+                CROSS JOIN smp
+                JOIN player p ON p.player_id = pss.player_id
+                WHERE pss.server_id = $1
+                    AND pss.started_at < smp.ended_at
+                    AND COALESCE(pss.ended_at, CURRENT_TIMESTAMP) > smp.started_at
+                    AND (pss.ended_at IS NOT NULL OR CURRENT_TIMESTAMP - pss.started_at < INTERVAL '12 hours')
+                ORDER BY pss.player_id, joined_at
+            "#, server.server_id, time_id).fetch_all(pool).await?;
+
+            let matches = sqlx::query_as!(DbTimelineMatch, "
+                SELECT zombie_score, human_score, extend_count, occurred_at
+                FROM match_data
+                WHERE server_id=$1 AND time_id=$2
+                ORDER BY occurred_at
+            ", server.server_id, time_id).fetch_all(pool).await?;
+
+            let mut players: Vec<TimelinePlayer> = vec![];
+            for row in intervals {
+                let Some(joined_at) = row.joined_at else {
+                    continue
+                };
+                let interval = TimelineInterval {
+                    joined_at: db_to_utc(joined_at),
+                    left_at: row.left_at.map(db_to_utc),
+                };
+                match players.last_mut() {
+                    Some(last) if last.id == row.player_id => last.intervals.push(interval),
+                    _ => players.push(TimelinePlayer {
+                        id: row.player_id,
+                        name: row.player_name.unwrap_or("Unknown Player".to_string()),
+                        is_anonymous: false,
+                        intervals: vec![interval],
+                    }),
+                }
+            }
+
+            // Each score update closes a round, the round started where the previous one ended.
+            let mut rounds = vec![];
+            let mut round_start = db_to_utc(session.started_at);
+            let scored = matches.into_iter()
+                .filter_map(|row| Some((row.occurred_at.map(db_to_utc)?, row)));
+            for (i, (occurred_at, row)) in scored.enumerate() {
+                rounds.push(TimelineRound {
+                    round: i as i32 + 1,
+                    started_at: round_start,
+                    ended_at: occurred_at,
+                    zombie_score: row.zombie_score,
+                    human_score: row.human_score,
+                    extend_count: row.extend_count.unwrap_or_default(),
+                });
+                round_start = occurred_at;
+            }
+
+            Ok::<_, sqlx::Error>(SessionTimeline {
+                time_id: session.time_id,
+                map: session.map.clone(),
+                started_at: db_to_utc(session.started_at),
+                ended_at: session.ended_at.map(db_to_utc),
+                players,
+                rounds,
+            })
+        };
+        let key = format!("map_session_timeline:{}:{}", server.server_id, session_id);
+        let ttl = if is_playing { 60 } else { DAY };
+        let Ok(result) = cached_response(&key, &app.cache, ttl, func).await else {
+            return response!(internal_server_error)
+        };
+        let mut timeline = result.result;
+        timeline.players = anonymizer.apply_all(timeline.players);
+        response!(ok timeline)
     }
     #[oai(path="/servers/:server_id/sessions/:session_id/continents", method="get")]
     async fn radar_statistic_session_continents(
@@ -953,17 +1116,130 @@ impl MapApi{
     }
     #[oai(path="/servers/:server_id/maps/:map_name/top_players", method="get")]
     async fn get_map_player_top_10(
-        &self, Data(app): Data<&AppData>, extract: MapExtractor
+        &self, Data(app): Data<&AppData>, extract: MapExtractor,
+        OptionalTokenBearer(user_token): OptionalTokenBearer,
     ) -> Response<Vec<PlayerBrief>>{
         let context = MapContext::from(extract);
+        let Ok(anonymizer) = Anonymizer::for_server(app, &context.server.server_id, user_token.as_ref()).await else {
+            return response!(internal_server_error)
+        };
 
         match app.map_worker.get_top_10_players(&context).await {
-            Ok(result) => response!(ok result),
+            Ok(result) => response!(ok anonymizer.apply_all(result)),
             Err(WorkError::NotFound) => response!(err "No players found for map", ErrorCode::NotFound),
             Err(WorkError::Database(_)) => response!(internal_server_error),
             Err(WorkError::Calculating) => response!(calculating),
         }
     }
+    #[oai(path="/servers/:server_id/maps/:map_name/leaderboard", method="get")]
+    async fn get_map_leaderboard(
+        &self, Data(app): Data<&AppData>, extract: MapExtractor,
+        Query(time_frame): Query<MapLeaderboardTimeFrame>, Query(page): Query<usize>,
+        OptionalTokenBearer(user_token): OptionalTokenBearer,
+    ) -> Response<MapLeaderboard>{
+        let pagination: i64 = 20;
+        let offset = page as i64 * pagination;
+        let user_id = user_token.as_ref().map(|t| t.id);
+        let me_id = user_id.map(|u| u.to_string());
+        let server_id = extract.server.server_id.clone();
+        let map_name = extract.map.map.clone();
+        let Ok(anonymizer) = Anonymizer::for_server(app, &server_id, user_token.as_ref()).await else {
+            return response!(internal_server_error)
+        };
+        let pool = &*app.pool;
+        // all_time reads straight from player_map_time which the player workers keep current,
+        // so ranks move as soon as a session is accounted for instead of waiting on a view refresh.
+        let func = || sqlx::query_as!(DbLeaderboardEntry, r#"
+            WITH time_bounds AS (
+                SELECT CASE
+                    WHEN $3 = 'week' THEN date_trunc('week', CURRENT_TIMESTAMP)
+                    WHEN $3 = 'month' THEN date_trunc('month', CURRENT_TIMESTAMP)
+                END AS min_start
+            ),
+            totals AS (
+                SELECT pmt.player_id, pmt.total_playtime AS played
+                FROM website.player_map_time pmt
+                WHERE $3 = 'all_time' AND pmt.server_id = $1 AND pmt.map = $2
+                UNION ALL
+                SELECT
+                    pss.player_id,
+                    SUM(
+                        LEAST(COALESCE(pss.ended_at, CURRENT_TIMESTAMP), COALESCE(smp.ended_at, CURRENT_TIMESTAMP))
+                        - GREATEST(pss.started_at, smp.started_at, tb.min_start)
+                    ) AS played
+                FROM server_map_played smp
+                CROSS JOIN time_bounds tb
+                JOIN player_server_session pss
+                    ON pss.server_id = smp.server_id
+                    AND pss.started_at < COALESCE(smp.ended_at, CURRENT_TIMESTAMP)
+                    AND COALESCE(pss.ended_at, CURRENT_TIMESTAMP) > GREATEST(smp.started_at, tb.min_start)
+                    AND (pss.ended_at IS NOT NULL OR CURRENT_TIMESTAMP - pss.started_at < INTERVAL '12 hours')
+                WHERE $3 <> 'all_time'
+                    AND smp.server_id = $1
+                    AND smp.map = $2
+                    AND COALESCE(smp.ended_at, CURRENT_TIMESTAMP) > tb.min_start
+                GROUP BY pss.player_id
+            ),
+            ranked AS (
+                SELECT
+                    player_id,
+                    EXTRACT(EPOCH FROM played)::DOUBLE PRECISION AS value,
+                    RANK() OVER (ORDER BY played DESC) AS rank,
+                    ROW_NUMBER() OVER (ORDER BY played DESC, player_id) AS position,
+                    COUNT(*) OVER () AS total_players
+                FROM totals
+                WHERE played > INTERVAL '0'
+            )
+            SELECT
+                r.rank,
+                r.position,
+                r.player_id AS "player_id!",
+                p.player_name AS "player_name?",
+                r.value,
+                r.total_players
+            FROM ranked r
+            JOIN player p ON p.player_id = r.player_id
+            WHERE (r.position > $4 AND r.position <= $4 + $5) OR r.player_id = $6::TEXT
+            ORDER BY r.position
+        "#, server_id, map_name, time_frame.to_string(), offset, pagination, me_id
+        ).fetch_all(pool);
+
+        // The viewer only picks which extra row comes back as `me`
+        let key = format!("map-leaderboard:{}:{}:{}:{}:{}",
+            server_id, map_name, time_frame, page,
+            me_id.clone().unwrap_or_default()
+        );
+        let ttl = match time_frame {
+            MapLeaderboardTimeFrame::AllTime => 2 * 60,
+            MapLeaderboardTimeFrame::Week | MapLeaderboardTimeFrame::Month => 10 * 60,
+        };
+        let Ok(result) = cached_response(&key, &app.cache, ttl, func).await else {
+            return response!(internal_server_error)
+        };
+
+        let rows = result.result;
+        let total_players = rows
+            .first()
+            .and_then(|e| e.total_players)
+            .unwrap_or_default();
+        let mut me = None;
+        let mut players = vec![];
+        for row in rows {
+            let position = row.position.unwrap_or_default();
+            let in_page = position > offset && position <= offset + pagination;
+            if me_id.as_deref() == Some(row.player_id.as_str()) {
+                me = Some(row.clone().into());
+            }
+            if in_page {
+                players.push(row.into());
+            }
+        }
+        response!(ok MapLeaderboard {
+            total_players,
+            players: anonymizer.apply_all(players),
+            me,
+        })
+    }
     #[oai(path="/servers/:server_id/guides", method="get")]
     async fn get_all_map_guides(
         &self, Data(app): Data<&AppData>, ServerExtractor(server): ServerExtractor, OptionalTokenBearer(user): OptionalTokenBearer,
@@ -980,6 +1256,8 @@ impl MapApi{
                       g.server_id,
                       g.title,
                       g.content,
+                      g.content_html,
+                      g.excerpt,
                       g.category,
                       g.created_at,
                       g.updated_at,
@@ -1003,6 +1281,8 @@ impl MapApi{
                   server_id,
                   title,
                   content,
+                  content_html,
+                  excerpt,
                   category,
                   created_at,
                   updated_at,
@@ -1053,6 +1333,8 @@ impl MapApi{
                 g.server_id,
                 g.title,
                 g.content,
+                g.content_html,
+                g.excerpt,
                 g.category,
                 g.created_at,
                 g.updated_at,
@@ -1139,6 +1421,15 @@ impl MapApi{
             None => None,
         };
 
+        let rendered = match render_markdown(app, &payload.content, MAX_GUIDE_LEN, server_id.as_deref()).await {
+            Ok(r) => r,
+            Err(MarkdownError::Database(e)) => {
+                tracing::error!("Failed to render guide: {e}");
+                return response!(internal_server_error)
+            }
+            Err(e) => return response!(err &e.to_string(), ErrorCode::BadRequest),
+        };
+
         // Generate unique slug from title
         let slug = match generate_unique_guide_slug(pool, &map_name, &payload.title).await {
             Ok(s) => s,
@@ -1148,16 +1439,27 @@ impl MapApi{
             }
         };
 
+        let Ok(mut tx) = pool.begin().await else {
+            return response!(err "Failed to create guide", ErrorCode::InternalServerError)
+        };
         let Ok(guide_brief) = sqlx::query_as!(DbGuideBrief,
-            "INSERT INTO website.guides (map_name, title, content, category, author_id, server_id, slug)
-             VALUES ($1, $2, $3, $4, $5, $6, $7)
+            "INSERT INTO website.guides (map_name, title, content, content_html, excerpt, category, author_id, server_id, slug)
+             VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9)
              RETURNING id, map_name, server_id, author_id",
-            map_name, payload.title, payload.content, payload.category, user_id, server_id, slug
+            map_name, payload.title, payload.content, rendered.html, rendered.excerpt,
+            payload.category, user_id, server_id, slug
         )
-        .fetch_one(pool)
+        .fetch_one(&mut *tx)
         .await else {
             return response!(err "Failed to create guide", ErrorCode::InternalServerError)
         };
+        if let Err(e) = record_revision(&mut tx, guide_brief.id, user_id, None).await {
+            tracing::error!("Failed to record first guide revision: {e}");
+            return response!(err "Failed to create guide", ErrorCode::InternalServerError)
+        }
+        if tx.commit().await.is_err() {
+            return response!(err "Failed to create guide", ErrorCode::InternalServerError)
+        }
 
         let Ok(guide) = sqlx::query_as!(DbGuide, "
             SELECT g.id,
@@ -1165,6 +1467,8 @@ impl MapApi{
                 g.server_id,
                 g.title,
                 g.content,
+                g.content_html,
+                g.excerpt,
                 g.category,
                 g.created_at,
                 g.updated_at,
@@ -1198,6 +1502,8 @@ impl MapApi{
                 g.server_id,
                 g.title,
                 g.content,
+                g.content_html,
+                g.excerpt,
                 g.category,
                 g.created_at,
                 g.updated_at,
@@ -1234,6 +1540,8 @@ impl MapApi{
                 g.server_id,
                 g.title,
                 g.content,
+                g.content_html,
+                g.excerpt,
                 g.category,
                 g.created_at,
                 g.updated_at,
@@ -1307,12 +1615,46 @@ impl MapApi{
                 return response!(err "Server not found", ErrorCode::BadRequest);
             }
         }
+
+        // Links in the rendered guide depend on its server, so moving it renders again too
+        let server_id = payload.server_id.clone().unwrap_or_else(|| extract.guide.server_id.clone());
+        let source = match (&payload.content, server_id != extract.guide.server_id) {
+            (Some(content), _) => Some(content.clone()),
+            (None, true) => match sqlx::query_scalar!("SELECT content FROM website.guides WHERE id=$1", guide_id)
+                .fetch_one(pool)
+                .await {
+                Ok(content) => Some(content),
+                Err(e) => {
+                    tracing::error!("Failed to fetch guide content: {e}");
+                    return response!(internal_server_error)
+                }
+            },
+            (None, false) => None,
+        };
+        let rendered = match source {
+            Some(source) => match render_markdown(app, &source, MAX_GUIDE_LEN, server_id.as_deref()).await {
+                Ok(r) => Some(r),
+                Err(MarkdownError::Database(e)) => {
+                    tracing::error!("Failed to render guide: {e}");
+                    return response!(internal_server_error)
+                }
+                Err(e) => return response!(err &e.to_string(), ErrorCode::BadRequest),
+            },
+            None => None,
+        };
+        let (content_html, excerpt) = rendered.map(|r| (r.html, r.excerpt)).unzip();
+
+        let Ok(mut tx) = pool.begin().await else {
+            return response!(err "Failed to update guide", ErrorCode::InternalServerError)
+        };
         let _r = match sqlx::query!(
             "UPDATE website.guides
              SET title = COALESCE($2, title),
                  content = COALESCE($3, content),
                  category = COALESCE($4, category),
                  server_id = CASE WHEN $5::boolean THEN $6 ELSE server_id END,
+                 content_html = COALESCE($7, content_html),
+                 excerpt = COALESCE($8, excerpt),
                  updated_at = NOW()
              WHERE id = $1",
             guide_id,
@@ -1320,9 +1662,11 @@ impl MapApi{
             payload.content,
             payload.category,
             payload.server_id.is_some(),  // Flag if server_id was provided
-            payload.server_id.flatten()   // The actual value (None means global)
+            payload.server_id.flatten(),  // The actual value (None means global)
+            content_html,
+            excerpt
         )
-        .execute(pool)
+        .execute(&mut *tx)
         .await {
             Ok(s) => s,
             Err(e) => {
@@ -1330,6 +1674,13 @@ impl MapApi{
                 return response!(err "Failed to update guide", ErrorCode::InternalServerError)
             }
         };
+        if let Err(e) = record_revision(&mut tx, guide_id, user_id, None).await {
+            tracing::error!("Failed to record guide revision: {e}");
+            return response!(err "Failed to update guide", ErrorCode::InternalServerError)
+        }
+        if tx.commit().await.is_err() {
+            return response!(err "Failed to update guide", ErrorCode::InternalServerError)
+        }
 
         let Ok(updated_guide) = sqlx::query_as!(DbGuide, "
             SELECT g.id,
@@ -1337,6 +1688,8 @@ impl MapApi{
                 g.server_id,
                 g.title,
                 g.content,
+                g.content_html,
+                g.excerpt,
                 g.category,
                 g.created_at,
                 g.updated_at,
@@ -1364,7 +1717,8 @@ impl MapApi{
     async fn delete_map_guide(&self, Data(app): Data<&AppData>, extract: GuideExtractor, TokenBearer(user_token): TokenBearer) -> Response<Guide>{
         let guide = extract.guide;
         if guide.author_id != user_token.id {
-            if !check_superuser(app, user_token.id).await{
+            let guide_scope = guide.server_id.as_deref().map_or(Scope::Global, Scope::Server);
+            if !has_permission(app, user_token.id, Permission::GuidesModerate, guide_scope).await{
                 return response!(err "You're not authorized!", ErrorCode::Forbidden)
             }
         }
@@ -1374,6 +1728,8 @@ impl MapApi{
                 g.server_id,
                 g.title,
                 g.content,
+                g.content_html,
+                g.excerpt,
                 g.category,
                 g.created_at,
                 g.updated_at,
@@ -1393,15 +1749,212 @@ impl MapApi{
         ", guide.id).fetch_one(&*app.pool).await else {
             return response!(err "Failed to fetch delete guide", ErrorCode::InternalServerError)
         };
+        let moderated = guide.author_id != user_token.id;
+        let before = if moderated {
+            sqlx::query_scalar!("SELECT to_jsonb(g) - 'search_vector' FROM website.guides g WHERE g.id=$1", guide.id)
+                .fetch_optional(&*app.pool).await.ok().flatten().flatten()
+        } else {
+            None
+        };
         let Ok(_) = sqlx::query!("
             DELETE FROM website.guides g
             WHERE g.id=$1
         ", guide.id).execute(&*app.pool).await else {
             return response!(err "Failed to fetch delete guide", ErrorCode::InternalServerError)
         };
+        if moderated {
+            record_audit(&app.pool, Some(user_token.id), "delete", "guide", &guide.id.to_string(), before, None).await;
+        }
         response!(ok updated_guide.into())
     }
 
+    /// Every revision of the guide, newest first
+    #[oai(path="/maps/:map_name/guides/:guide_id/revisions", method="get")]
+    async fn get_map_guide_revisions(
+        &self, Data(app): Data<&AppData>, extract: GuideExtractor, TokenBearer(user_token): TokenBearer
+    ) -> Response<Vec<GuideRevisionBrief>>{
+        if !can_read_guide_history(app, &extract.guide, user_token.id).await {
+            return response!(err "You're not authorized!", ErrorCode::Forbidden)
+        }
+        let result = sqlx::query_as!(DbGuideRevision, "
+            SELECT r.guide_id,
+                r.revision,
+                r.title,
+                r.content,
+                r.category,
+                r.server_id,
+                r.editor_id,
+                su.persona_name AS editor_name,
+                su.avatar AS editor_avatar,
+                r.restored_from,
+                r.created_at
+            FROM website.guide_revision r
+            LEFT JOIN website.steam_user su ON su.user_id=r.editor_id
+            WHERE r.guide_id=$1
+            ORDER BY r.revision DESC
+        ", extract.guide.id).fetch_all(&*app.pool).await;
+        match result {
+            Ok(revisions) => response!(ok revisions.iter_into()),
+            Err(e) => {
+                tracing::error!("Failed to fetch guide revisions: {e}");
+                response!(internal_server_error)
+            }
+        }
+    }
+
+    #[oai(path="/maps/:map_name/guides/:guide_id/revisions/:revision", method="get")]
+    async fn get_map_guide_revision(
+        &self, Data(app): Data<&AppData>, extract: GuideExtractor, TokenBearer(user_token): TokenBearer,
+        Path(revision): Path<i32>
+    ) -> Response<GuideRevision>{
+        if !can_read_guide_history(app, &extract.guide, user_token.id).await {
+            return response!(err "You're not authorized!", ErrorCode::Forbidden)
+        }
+        match get_guide_revision(&app.pool, extract.guide.id, revision).await {
+            Ok(Some(revision)) => response!(ok revision.into()),
+            Ok(None) => response!(err "Revision not found", ErrorCode::NotFound),
+            Err(e) => {
+                tracing::error!("Failed to fetch guide revision: {e}");
+                response!(internal_server_error)
+            }
+        }
+    }
+
+    /// Changes from revision `from` to this one. Without `from`, against the revision before it.
+    #[oai(path="/maps/:map_name/guides/:guide_id/revisions/:revision/diff", method="get")]
+    async fn get_map_guide_revision_diff(
+        &self, Data(app): Data<&AppData>, extract: GuideExtractor, TokenBearer(user_token): TokenBearer,
+        Path(revision): Path<i32>, Query(from): Query<Option<i32>>
+    ) -> Response<GuideRevisionDiff>{
+        if !can_read_guide_history(app, &extract.guide, user_token.id).await {
+            return response!(err "You're not authorized!", ErrorCode::Forbidden)
+        }
+        let from = from.unwrap_or(revision - 1);
+        let (to_revision, from_revision) = match (
+            get_guide_revision(&app.pool, extract.guide.id, revision).await,
+            get_guide_revision(&app.pool, extract.guide.id, from).await,
+        ) {
+            (Ok(Some(to_revision)), Ok(Some(from_revision))) => (to_revision, from_revision),
+            (Ok(_), Ok(_)) => return response!(err "Revision not found", ErrorCode::NotFound),
+            (Err(e), _) | (_, Err(e)) => {
+                tracing::error!("Failed to fetch guide revision: {e}");
+                return response!(internal_server_error)
+            }
+        };
+        response!(ok diff_revisions(&from_revision, &to_revision))
+    }
+
+    /// Brings back an older revision. The guide doesn't go back in history, the old state is
+    /// stored again as a new revision pointing at the one restored.
+    #[oai(path="/maps/:map_name/guides/:guide_id/revisions/:revision/restore", method="post")]
+    async fn restore_map_guide_revision(
+        &self, Data(app): Data<&AppData>, extract: GuideExtractor, TokenBearer(user_token): TokenBearer,
+        Path(revision): Path<i32>
+    ) -> Response<Guide>{
+        let guide = extract.guide;
+        let guide_scope = guide.server_id.as_deref().map_or(Scope::Global, Scope::Server);
+        if !has_permission(app, user_token.id, Permission::GuidesModerate, guide_scope).await {
+            return response!(err "You're not authorized!", ErrorCode::Forbidden)
+        }
+        let pool = &*app.pool;
+        let old = match get_guide_revision(pool, guide.id, revision).await {
+            Ok(Some(r)) => r,
+            Ok(None) => return response!(err "Revision not found", ErrorCode::NotFound),
+            Err(e) => {
+                tracing::error!("Failed to fetch guide revision: {e}");
+                return response!(internal_server_error)
+            }
+        };
+        // The revision may put the guide back on a server the moderator has no say over
+        if old.server_id != guide.server_id {
+            let old_scope = old.server_id.as_deref().map_or(Scope::Global, Scope::Server);
+            if !has_permission(app, user_token.id, Permission::GuidesModerate, old_scope).await {
+                return response!(err "You're not authorized!", ErrorCode::Forbidden)
+            }
+        }
+        let rendered = match render_markdown(app, &old.content, MAX_GUIDE_LEN, old.server_id.as_deref()).await {
+            Ok(r) => r,
+            Err(MarkdownError::Database(e)) => {
+                tracing::error!("Failed to render guide: {e}");
+                return response!(internal_server_error)
+            }
+            Err(e) => {
+                let msg = format!("Revision can't be restored: {e}");
+                return response!(err &msg, ErrorCode::BadRequest)
+            }
+        };
+        let Ok(mut tx) = pool.begin().await else {
+            return response!(internal_server_error)
+        };
+        let restored = sqlx::query!(
+            "UPDATE website.guides g
+             SET title = r.title,
+                 content = r.content,
+                 content_html = $3,
+                 excerpt = $4,
+                 category = r.category,
+                 server_id = r.server_id,
+                 updated_at = NOW()
+             FROM website.guide_revision r
+             WHERE g.id = $1 AND r.guide_id = g.id AND r.revision = $2",
+            guide.id, revision, rendered.html, rendered.excerpt
+        )
+        .execute(&mut *tx)
+        .await;
+        match restored {
+            Ok(r) if r.rows_affected() == 0 => return response!(err "Revision not found", ErrorCode::NotFound),
+            Ok(_) => {}
+            Err(e) => {
+                tracing::error!("Failed to restore guide revision: {e}");
+                return response!(internal_server_error)
+            }
+        }
+        let new_revision = match record_revision(&mut tx, guide.id, user_token.id, Some(revision)).await {
+            Ok(r) => r,
+            Err(e) => {
+                tracing::error!("Failed to record guide revision: {e}");
+                return response!(internal_server_error)
+            }
+        };
+        if tx.commit().await.is_err() {
+            return response!(internal_server_error)
+        }
+        record_audit(
+            pool, Some(user_token.id), "restore_revision", "guide", &guide.id.to_string(),
+            Some(serde_json::json!({ "revision": new_revision - 1 })),
+            Some(serde_json::json!({ "revision": new_revision, "restored_from": revision })),
+        ).await;
+
+        let Ok(restored_guide) = sqlx::query_as!(DbGuide, "
+            SELECT g.id,
+                g.map_name,
+                g.server_id,
+                g.title,
+                g.content,
+                g.content_html,
+                g.excerpt,
+                g.category,
+                g.created_at,
+                g.updated_at,
+                g.upvotes,
+                g.downvotes,
+                g.comment_count,
+                NULL AS \"user_vote: DataVoteType\",
+                g.author_id AS author_id,
+                su.persona_name AS author_name,
+                su.avatar AS author_avatar,
+                g.slug,
+                COALESCE(0, null) AS total_guides
+            FROM website.guides g
+            LEFT JOIN website.steam_user su ON su.user_id=g.author_id
+            WHERE g.id=$1
+            LIMIT 1
+        ", guide.id).fetch_one(pool).await else {
+            return response!(err "Failed to fetch restored guide", ErrorCode::InternalServerError)
+        };
+        response!(ok restored_guide.into())
+    }
+
     #[oai(path="/maps/:map_name/guides/:guide_id/report", method="post")]
     async fn report_map_guide(
         &self, Data(app): Data<&AppData>, extract: GuideExtractor, TokenBearer(user_token): TokenBearer,
@@ -1412,8 +1965,8 @@ impl MapApi{
         let guide_id = extract.guide.id;
 
         let Ok(_) = sqlx::query!(
-            "INSERT INTO website.report_guide(guide_id, user_id, reason, details)
-             VALUES ($1, $2, $3, $4)",
+            "INSERT INTO website.report_guide(guide_id, user_id, reason, details, revision)
+             VALUES ($1, $2, $3, $4, (SELECT MAX(revision) FROM website.guide_revision WHERE guide_id = $1))",
             guide_id,
             user_id,
             payload.reason,
@@ -1576,6 +2129,8 @@ impl MapApi{
                 g.server_id,
                 g.title,
                 g.content,
+                g.content_html,
+                g.excerpt,
                 g.category,
                 g.created_at,
                 g.updated_at,
@@ -1635,6 +2190,8 @@ impl MapApi{
                 g.server_id,
                 g.title,
                 g.content,
+                g.content_html,
+                g.excerpt,
                 g.category,
                 g.created_at,
                 g.updated_at,
@@ -1675,6 +2232,7 @@ impl MapApi{
                 su.persona_name AS author_name,
                 su.avatar AS author_avatar,
                 gc.content,
+                gc.content_html,
                 gc.created_at,
                 gc.updated_at,
                 gc.upvotes,
@@ -1724,12 +2282,21 @@ impl MapApi{
         if payload.content.trim().is_empty() {
             return response!(err "Content cannot be empty.", ErrorCode::BadRequest);
         }
+        let server_id = extract.guide.server_id.as_deref();
+        let rendered = match render_markdown(app, &payload.content, MAX_COMMENT_LEN, server_id).await {
+            Ok(r) => r,
+            Err(MarkdownError::Database(e)) => {
+                tracing::error!("Failed to render comment: {e}");
+                return response!(internal_server_error)
+            }
+            Err(e) => return response!(err &e.to_string(), ErrorCode::BadRequest),
+        };
 
         let Ok(comment_id) = sqlx::query_scalar!(
-            "INSERT INTO website.guide_comments (guide_id, author_id, content)
-             VALUES ($1, $2, $3)
+            "INSERT INTO website.guide_comments (guide_id, author_id, content, content_html)
+             VALUES ($1, $2, $3, $4)
              RETURNING id",
-            guide_id, user_id, payload.content
+            guide_id, user_id, payload.content, rendered.html
         )
         .fetch_one(pool)
         .await else {
@@ -1743,6 +2310,7 @@ impl MapApi{
                 su.persona_name AS author_name,
                 su.avatar AS author_avatar,
                 gc.content,
+                gc.content_html,
                 gc.created_at,
                 gc.updated_at,
                 gc.upvotes,
@@ -1767,7 +2335,7 @@ impl MapApi{
         let comment = extract.comment;
 
         if comment.author_id != user_token.id {
-            if !check_superuser(app, user_token.id).await {
+            if !has_permission(app, user_token.id, Permission::GuidesModerate, Scope::Global).await {
                 return response!(err "You're not authorized to delete this comment", ErrorCode::Forbidden)
             }
         }
@@ -1779,6 +2347,7 @@ impl MapApi{
                 su.persona_name AS author_name,
                 su.avatar AS author_avatar,
                 gc.content,
+                gc.content_html,
                 gc.created_at,
                 gc.updated_at,
                 gc.upvotes,
@@ -1793,6 +2362,14 @@ impl MapApi{
             return response!(err "Failed to fetch comment", ErrorCode::InternalServerError)
         };
 
+        let moderated = comment.author_id != user_token.id;
+        let before = if moderated {
+            sqlx::query_scalar!("SELECT to_jsonb(gc) - 'search_vector' FROM website.guide_comments gc WHERE gc.id=$1", comment.id)
+                .fetch_optional(pool).await.ok().flatten().flatten()
+        } else {
+            None
+        };
+
         let Ok(_) = sqlx::query!(
             "DELETE FROM website.guide_comments WHERE id=$1",
             comment.id
@@ -1800,6 +2377,10 @@ impl MapApi{
             return response!(err "Failed to delete comment", ErrorCode::InternalServerError)
         };
 
+        if moderated {
+            record_audit(pool, Some(user_token.id), "delete", "guide_comment", &comment.id.to_string(), before, None).await;
+        }
+
         response!(ok deleted_comment.into())
     }
     #[oai(path="/maps/:map_name/guides/:guide_id/comments/:comment_id", method="put")]
@@ -1822,14 +2403,25 @@ impl MapApi{
         if payload.content.trim().is_empty() {
             return response!(err "Content cannot be empty.", ErrorCode::BadRequest);
         }
+        let server_id = extract.guide.server_id.as_deref();
+        let rendered = match render_markdown(app, &payload.content, MAX_COMMENT_LEN, server_id).await {
+            Ok(r) => r,
+            Err(MarkdownError::Database(e)) => {
+                tracing::error!("Failed to render comment: {e}");
+                return response!(internal_server_error)
+            }
+            Err(e) => return response!(err &e.to_string(), ErrorCode::BadRequest),
+        };
 
         let Ok(_) = sqlx::query!(
             "UPDATE website.guide_comments
              SET content = $2,
+                 content_html = $3,
                  updated_at = NOW()
              WHERE id = $1",
             comment.id,
-            payload.content
+            payload.content,
+            rendered.html
         )
         .execute(pool)
         .await else {
@@ -1843,6 +2435,7 @@ impl MapApi{
                 su.persona_name AS author_name,
                 su.avatar AS author_avatar,
                 gc.content,
+                gc.content_html,
                 gc.created_at,
                 gc.updated_at,
                 gc.upvotes,
@@ -1903,6 +2496,7 @@ impl MapApi{
                 su.persona_name AS author_name,
                 su.avatar AS author_avatar,
                 gc.content,
+                gc.content_html,
                 gc.created_at,
                 gc.updated_at,
                 gc.upvotes,
@@ -1956,6 +2550,7 @@ impl MapApi{
                 su.persona_name AS author_name,
                 su.avatar AS author_avatar,
                 gc.content,
+                gc.content_html,
                 gc.created_at,
                 gc.updated_at,
                 gc.upvotes,
@@ -1984,7 +2579,8 @@ impl MapApi{
             DbMap3DModel,
             r#"
             SELECT m.id, m.map_name, m.res_type, m.credit, m.link_path,
-                   m.uploaded_by, m.file_size, m.created_at, m.updated_at
+                   m.uploaded_by, m.file_size, m.created_at, m.updated_at, m.content_hash, m.glb_info, m.thumbnail_path,
+                   m.generated
             FROM website.map_3d_model m
             WHERE m.map_name IN (
                 SELECT DISTINCT map FROM server_map_played WHERE server_id = $1
@@ -2017,9 +2613,8 @@ impl MapApi{
             let mut api_model: Map3DModel = model.into();
             api_model.link_path = app.map_storage.normalize_link_path(
                 &api_model.link_path,
-                &api_model.map_name,
-                &api_model.res_type,
-            );
+                (api_model.map_name.as_str(), api_model.res_type.as_str(), api_model.content_hash.as_deref()),
+            ).await;
             api_model.uploader_name = uploader_name;
 
             let entry = models_map.entry(api_model.map_name.clone()).or_insert((None, None));
@@ -2099,9 +2694,8 @@ impl MapApi{
             let mut api_model: Map3DModel = model.into();
             api_model.link_path = app.map_storage.normalize_link_path(
                 &api_model.link_path,
-                &api_model.map_name,
-                &api_model.res_type,
-            );
+                (api_model.map_name.as_str(), api_model.res_type.as_str(), api_model.content_hash.as_deref()),
+            ).await;
             api_model.uploader_name = uploader_name;
 
             let entry = models_map.entry(api_model.map_name.clone()).or_insert((None, None));
@@ -2139,7 +2733,7 @@ impl MapApi{
             DbMap3DModel,
             r#"
             SELECT id, map_name, res_type, credit, link_path,
-                   uploaded_by, file_size, created_at, updated_at
+                   uploaded_by, file_size, created_at, updated_at, content_hash, glb_info, thumbnail_path, generated
             FROM website.map_3d_model
             WHERE map_name = $1
             ORDER BY res_type
@@ -2172,9 +2766,8 @@ impl MapApi{
                     let mut api_model: Map3DModel = model.into();
                     api_model.link_path = app.map_storage.normalize_link_path(
                         &api_model.link_path,
-                        &api_model.map_name,
-                        &api_model.res_type,
-                    );
+                        (api_model.map_name.as_str(), api_model.res_type.as_str(), api_model.content_hash.as_deref()),
+                    ).await;
                     api_model.uploader_name = uploader_name;
 
                     if api_model.res_type == "low" {
@@ -2202,7 +2795,7 @@ impl MapApi{
         TokenBearer(user_token): TokenBearer,
         multipart: poem::web::Multipart,
     ) -> Response<Map3DModel> {
-        if !check_superuser(&app, user_token.id).await {
+        if !check_map_models(&app, user_token.id, &map_name).await {
             return response!(err "Forbidden", ErrorCode::Forbidden);
         }
 
@@ -2250,44 +2843,19 @@ impl MapApi{
             return response!(err "File too large (max 500MB)", ErrorCode::BadRequest);
         }
 
-        let link_path = match app.map_storage
-            .store_bytes(&map_name, &res_type_val, &file_bytes)
-            .await
-        {
-            Ok(path) => path,
-            Err(e) => {
-                tracing::error!("Failed to store 3D model: {}", e);
-                return response!(internal_server_error);
-            }
+        let glb_info = match inspect_glb(&file_bytes) {
+            Ok(info) => info,
+            Err(e) => return response!(err &format!("Invalid GLB: {e}"), ErrorCode::BadRequest),
         };
 
-        let result = sqlx::query_as!(
-            DbMap3DModel,
-            r#"
-            INSERT INTO website.map_3d_model
-            (map_name, res_type, credit, link_path, uploaded_by, file_size)
-            VALUES ($1, $2, $3, $4, $5, $6)
-            ON CONFLICT (map_name, res_type)
-            DO UPDATE SET
-                credit = EXCLUDED.credit,
-                link_path = EXCLUDED.link_path,
-                uploaded_by = EXCLUDED.uploaded_by,
-                file_size = EXCLUDED.file_size,
-                updated_at = NOW()
-            RETURNING *
-            "#,
-            map_name,
-            res_type_val,
-            credit,
-            link_path,
-            user_token.id,
-            file_bytes.len() as i64,
-        )
-        .fetch_one(&*app.pool)
-        .await;
+        let result = save_map_model_version(
+            app, &map_name, &res_type_val, credit, Some(user_token.id), false,
+            ModelSource::Bytes(&file_bytes), file_bytes.len() as i64, &glb_info,
+        ).await;
 
         match result {
             Ok(model) => {
+                Self::model_changed(app, Some(user_token.id), "upload", &model).await;
                 let uploader_name = sqlx::query_scalar!(
                     "SELECT persona_name FROM website.steam_user WHERE user_id = $1",
                     user_token.id
@@ -2300,484 +2868,171 @@ impl MapApi{
                 let mut api_model: Map3DModel = model.into();
                 api_model.link_path = app.map_storage.normalize_link_path(
                     &api_model.link_path,
-                    &api_model.map_name,
-                    &api_model.res_type,
-                );
+                    (api_model.map_name.as_str(), api_model.res_type.as_str(), api_model.content_hash.as_deref()),
+                ).await;
                 api_model.uploader_name = uploader_name;
                 response!(ok api_model)
             }
             Err(e) => {
-                tracing::error!("Database error: {}", e);
+                tracing::error!("Failed to store 3D model: {}", e);
                 response!(internal_server_error)
             }
         }
     }
 
-    /// Initiate chunked upload session for large 3D models
-    #[oai(path = "/maps/:map_name/3d/upload/initiate", method = "post")]
-    async fn initiate_chunked_upload(
-        &self,
-        Data(app): Data<&AppData>,
-        Path(map_name): Path<String>,
-        TokenBearer(user_token): TokenBearer,
-        Json(req): Json<serde_json::Value>,
-    ) -> Response<InitiateUploadResponse> {
-        // Check superuser permission
-        if !check_superuser(&app, user_token.id).await {
-            return response!(err "Forbidden", ErrorCode::Forbidden);
-        }
-
-        // Parse request
-        let res_type = match req.get("res_type").and_then(|v| v.as_str()) {
-            Some(rt) if rt == "low" || rt == "high" => rt.to_string(),
-            _ => return response!(err "Invalid res_type. Must be 'low' or 'high'", ErrorCode::BadRequest),
-        };
-
-        let credit = req.get("credit").and_then(|v| v.as_str()).map(|s| s.to_string());
-
-        let file_size = match req.get("file_size").and_then(|v| v.as_u64()) {
-            Some(size) => size,
-            None => return response!(err "file_size is required", ErrorCode::BadRequest),
-        };
-
-        // Generate session ID
-        let session_id = uuid::Uuid::new_v4().to_string();
-
-        // Calculate chunks
-        const CHUNK_SIZE: usize = 10_485_760; // 10MB
-        let total_chunks = ((file_size as f64) / (CHUNK_SIZE as f64)).ceil() as u32;
-
-        // Create upload session
-        let session = UploadSession {
-            session_id: session_id.clone(),
-            map_name: map_name.clone(),
-            res_type: res_type.clone(),
-            credit,
-            total_chunks,
-            chunk_size: CHUNK_SIZE,
-            total_size: file_size,
-            uploaded_by: user_token.id,
-            created_at: chrono::Utc::now().to_rfc3339(),
-            chunks_received: Vec::new(),
-        };
-
-        // Store session in Redis with 24h TTL
-        let session_key = format!("upload_session:{}", session_id);
-        match serde_json::to_string(&session) {
-            Ok(session_json) => {
-                if let Ok(mut conn) = app.cache.redis_pool.get().await {
-                    use redis::AsyncCommands;
-                    let _: redis::RedisResult<()> = conn.set_ex(&session_key, &session_json, 86400).await;
-                } else {
-                    tracing::error!("Failed to get Redis connection");
-                    return response!(err "Failed to create upload session", ErrorCode::InternalServerError);
-                }
-            }
-            Err(e) => {
-                tracing::error!("Failed to serialize upload session: {}", e);
-                return response!(err "Failed to create upload session", ErrorCode::InternalServerError);
-            }
-        }
-
-        // Create temp directory
-        let store_upload = std::env::var("STORE_UPLOAD").unwrap_or_else(|_| "./maps".to_string());
-        let temp_dir = format!("{}/.tmp/{}", store_upload, session_id);
-        if let Err(e) = tokio::fs::create_dir_all(&temp_dir).await {
-            tracing::error!("Failed to create temp directory: {}", e);
-            return response!(err "Failed to create upload session", ErrorCode::InternalServerError);
-        }
-
-        tracing::info!("Upload session initiated: {}, map: {}, size: {}", session_id, map_name, file_size);
-
-        response!(ok InitiateUploadResponse {
-            session_id,
-            chunk_size: CHUNK_SIZE,
-            total_chunks,
-        })
+    /// The current model changed: audit it, then render its thumbnail and,
+    /// for high models, the low variant.
+    async fn model_changed(app: &AppData, actor_id: Option<i64>, action: &str, model: &DbMap3DModel) {
+        record_audit(
+            &app.pool, actor_id, action, "map_model", &format!("{}/{}", model.map_name, model.res_type),
+            None, Some(map_model_audit(model)),
+        ).await;
+        enqueue_model_job(&app.pool, ModelJobTarget::MapModel {
+            map_name: model.map_name.clone(),
+            res_type: model.res_type.clone(),
+        }).await;
+    }
+
+    /// Turns a finished resumable upload into the current model version.
+    pub(crate) async fn finish_model_upload(
+        app: &AppData,
+        map_name: &str,
+        res_type: &str,
+        credit: Option<String>,
+        uploaded_by: i64,
+        path: &std::path::Path,
+    ) -> Result<(), FinishError> {
+        let glb_info = inspect_glb_file(path)
+            .await
+            .map_err(|e| FinishError::Rejected(format!("Invalid GLB: {e}")))?;
+        let file_size = tokio::fs::metadata(path)
+            .await
+            .map_err(|e| FinishError::Failed(format!("Failed to verify file: {e}")))?
+            .len() as i64;
+        let model = save_map_model_version(
+            app, map_name, res_type, credit, Some(uploaded_by), false, ModelSource::File(path), file_size, &glb_info,
+        ).await.map_err(FinishError::Failed)?;
+        Self::model_changed(app, Some(uploaded_by), "upload", &model).await;
+        Ok(())
     }
 
-    /// Upload individual chunk
-    #[oai(path = "/maps/:map_name/3d/upload/chunk/:session_id", method = "post")]
-    async fn upload_chunk(
+    /// List every stored version of a 3D model (`models.upload` on a server running the map)
+    #[oai(path = "/maps/:map_name/3d/:res_type/versions", method = "get")]
+    async fn get_map_3d_model_versions(
         &self,
         Data(app): Data<&AppData>,
         Path(map_name): Path<String>,
-        Path(session_id): Path<String>,
+        Path(res_type): Path<String>,
         TokenBearer(user_token): TokenBearer,
-        upload: poem::web::Multipart,
-    ) -> Response<ChunkUploadResponse> {
-        // Check superuser permission
-        if !check_superuser(&app, user_token.id).await {
-            return response!(err "Forbidden", ErrorCode::Forbidden);
-        }
-
-        // Retrieve session from Redis
-        let session = match Self::get_upload_session(&app.cache, &session_id).await {
-            Ok(s) => s,
-            Err(e) => return response!(err e, ErrorCode::NotFound),
-        };
-
-        // Verify user owns session
-        if session.uploaded_by != user_token.id {
+    ) -> Response<Vec<Map3DModelVersion>> {
+        if !check_map_models(&app, user_token.id, &map_name).await {
             return response!(err "Forbidden", ErrorCode::Forbidden);
         }
 
-        // Verify map name matches
-        if session.map_name != map_name {
-            return response!(err "Map name mismatch", ErrorCode::BadRequest);
-        }
-
-        // Parse multipart form
-        let mut chunk_index: Option<u32> = None;
-        let mut chunk_data: Option<Vec<u8>> = None;
-
-        let mut upload = upload;
-        while let Ok(Some(field)) = upload.next_field().await {
-            let name = field.name().map(|s| s.to_string());
-            match name.as_deref() {
-                Some("chunk_index") => {
-                    if let Ok(text) = field.text().await {
-                        chunk_index = text.parse::<u32>().ok();
-                    }
-                }
-                Some("chunk_data") => {
-                    if let Ok(bytes) = field.bytes().await {
-                        chunk_data = Some(bytes.to_vec());
-                    }
-                }
-                _ => {}
-            }
-        }
-
-        let chunk_index = match chunk_index {
-            Some(idx) => idx,
-            None => return response!(err "chunk_index is required", ErrorCode::BadRequest),
-        };
-
-        let chunk_data = match chunk_data {
-            Some(data) => data,
-            None => return response!(err "chunk_data is required", ErrorCode::BadRequest),
+        let Ok(versions) = sqlx::query_as!(
+            DbMap3DModelVersion,
+            "SELECT id, map_name, res_type, content_hash, link_path, credit, uploaded_by, file_size, created_at
+             FROM website.map_3d_model_version
+             WHERE map_name = $1 AND res_type = $2
+             ORDER BY created_at DESC",
+            map_name,
+            res_type
+        )
+        .fetch_all(&*app.pool)
+        .await else {
+            return response!(internal_server_error);
         };
 
-        // Validate chunk index
-        if chunk_index >= session.total_chunks {
-            return response!(err "Invalid chunk_index", ErrorCode::BadRequest);
-        }
-
-        // Check if chunk already received (idempotent)
-        let already_received = session.chunks_received.contains(&chunk_index);
-
-        // Write chunk to disk
-        let store_upload = std::env::var("STORE_UPLOAD").unwrap_or_else(|_| "./maps".to_string());
-        let chunk_path = format!("{}/.tmp/{}/chunk_{}", store_upload, session_id, chunk_index);
-
-        if !already_received {
-            if let Err(e) = tokio::fs::write(&chunk_path, &chunk_data).await {
-                tracing::error!("Failed to write chunk {}: {}", chunk_index, e);
-                return response!(err "Failed to write chunk", ErrorCode::InternalServerError);
-            }
-
-            // Update session with new chunk
-            let mut updated_session = session.clone();
-            updated_session.chunks_received.push(chunk_index);
-            updated_session.chunks_received.sort_unstable();
-
-            if let Err(e) = Self::update_upload_session(&app.cache, &updated_session).await {
-                tracing::error!("Failed to update upload session: {}", e);
-                return response!(err "Failed to update session", ErrorCode::InternalServerError);
-            }
-
-            tracing::debug!("Chunk {}/{} received for session {}", chunk_index, session.total_chunks, session_id);
+        let current = sqlx::query_scalar!(
+            "SELECT content_hash FROM website.map_3d_model WHERE map_name = $1 AND res_type = $2",
+            map_name,
+            res_type
+        )
+        .fetch_optional(&*app.pool)
+        .await
+        .ok()
+        .flatten();
+
+        let mut result = Vec::with_capacity(versions.len());
+        for v in versions {
+            result.push(Map3DModelVersion {
+                is_current: current.as_ref() == Some(&v.content_hash),
+                link_path: app.map_storage.normalize_link_path(
+                    &v.link_path,
+                    (v.map_name.as_str(), v.res_type.as_str(), v.content_hash.as_deref()),
+                ).await,
+                id: v.id,
+                content_hash: v.content_hash,
+                credit: v.credit,
+                uploaded_by: v.uploaded_by,
+                file_size: v.file_size,
+                created_at: db_to_utc(v.created_at),
+            });
         }
 
-        let chunks_remaining = session.total_chunks - (session.chunks_received.len() as u32) - if already_received { 0 } else { 1 };
-
-        response!(ok ChunkUploadResponse {
-            chunk_index,
-            received: true,
-            chunks_remaining,
-        })
+        response!(ok result)
     }
 
-    /// Complete chunked upload and assemble file
-    #[oai(path = "/maps/:map_name/3d/upload/complete/:session_id", method = "post")]
-    async fn complete_chunked_upload(
+    /// Point a 3D model back at a previously uploaded version (`models.upload` on a server running the map)
+    #[oai(path = "/maps/:map_name/3d/:res_type/versions/:version_id/restore", method = "post")]
+    async fn restore_map_3d_model_version(
         &self,
         Data(app): Data<&AppData>,
         Path(map_name): Path<String>,
-        Path(session_id): Path<String>,
+        Path(res_type): Path<String>,
+        Path(version_id): Path<i32>,
         TokenBearer(user_token): TokenBearer,
     ) -> Response<Map3DModel> {
-        // Check superuser permission
-        if !check_superuser(&app, user_token.id).await {
-            return response!(err "Forbidden", ErrorCode::Forbidden);
-        }
-
-        // Retrieve session from Redis
-        let session = match Self::get_upload_session(&app.cache, &session_id).await {
-            Ok(s) => s,
-            Err(e) => return response!(err e, ErrorCode::NotFound),
-        };
-
-        // Verify user owns session
-        if session.uploaded_by != user_token.id {
+        if !check_map_models(&app, user_token.id, &map_name).await {
             return response!(err "Forbidden", ErrorCode::Forbidden);
         }
 
-        // Verify map name matches
-        if session.map_name != map_name {
-            return response!(err "Map name mismatch", ErrorCode::BadRequest);
-        }
-
-        // Verify all chunks received
-        if session.chunks_received.len() != session.total_chunks as usize {
-            let msg = format!("Missing chunks: {}/{}", session.chunks_received.len(), session.total_chunks);
-            return response!(err &msg, ErrorCode::BadRequest);
-        }
-
-        // Assemble chunks into final file
-        let store_upload = std::env::var("STORE_UPLOAD").unwrap_or_else(|_| "./maps".to_string());
-        let target_path = if app.map_storage.is_local() {
-            match app.map_storage.local_path(&session.map_name, &session.res_type) {
-                Some(path) => path.to_string_lossy().to_string(),
-                None => {
-                    tracing::error!("Local storage path is not configured");
-                    return response!(err "Storage misconfigured", ErrorCode::InternalServerError);
-                }
-            }
-        } else {
-            format!("{}/.tmp/{}/assembled.glb", store_upload, session_id)
-        };
-
-        let final_path = match Self::assemble_chunks(&session, &store_upload, &target_path).await {
-            Ok(path) => path,
-            Err(e) => {
-                tracing::error!("Chunk assembly failed: {}, error: {}", session_id, e);
-                let _ = Self::cleanup_temp_directory(&session_id, &store_upload).await;
-                return response!(err "Failed to assemble chunks", ErrorCode::InternalServerError);
-            }
-        };
-
-        // Verify final file size
-        match tokio::fs::metadata(&final_path).await {
-            Ok(metadata) => {
-                if metadata.len() != session.total_size {
-                    tracing::error!("File size mismatch: expected {}, got {}", session.total_size, metadata.len());
-                    let _ = tokio::fs::remove_file(&final_path).await;
-                    let _ = Self::cleanup_temp_directory(&session_id, &store_upload).await;
-                    return response!(err "File size mismatch", ErrorCode::InternalServerError);
-                }
-            }
-            Err(e) => {
-                tracing::error!("Failed to verify file: {}", e);
-                let _ = Self::cleanup_temp_directory(&session_id, &store_upload).await;
-                return response!(err "Failed to verify file", ErrorCode::InternalServerError);
-            }
-        }
-
-        let file_size = session.total_size as i64;
-        let link_path = match app.map_storage
-            .store_file(&session.map_name, &session.res_type, std::path::Path::new(&final_path))
-            .await
-        {
-            Ok(path) => path,
-            Err(e) => {
-                tracing::error!("Failed to store assembled file: {}", e);
-                let _ = Self::cleanup_temp_directory(&session_id, &store_upload).await;
-                return response!(err "Failed to store file", ErrorCode::InternalServerError);
-            }
-        };
-
-        // Insert/update database
         let result = sqlx::query_as!(
             DbMap3DModel,
             r#"
-            INSERT INTO website.map_3d_model (map_name, res_type, credit, link_path, uploaded_by, file_size)
-            VALUES ($1, $2, $3, $4, $5, $6)
-            ON CONFLICT (map_name, res_type)
-            DO UPDATE SET
-                credit = EXCLUDED.credit,
-                link_path = EXCLUDED.link_path,
-                uploaded_by = EXCLUDED.uploaded_by,
-                file_size = EXCLUDED.file_size,
+            UPDATE website.map_3d_model m
+            SET link_path = v.link_path,
+                credit = v.credit,
+                uploaded_by = v.uploaded_by,
+                file_size = v.file_size,
+                content_hash = v.content_hash,
+                glb_info = v.glb_info,
+                generated = v.generated,
                 updated_at = NOW()
-            RETURNING *
+            FROM website.map_3d_model_version v
+            WHERE v.id = $3
+                AND v.map_name = m.map_name AND v.res_type = m.res_type
+                AND m.map_name = $1 AND m.res_type = $2
+            RETURNING m.id, m.map_name, m.res_type, m.credit, m.link_path,
+                      m.uploaded_by, m.file_size, m.created_at, m.updated_at, m.content_hash, m.glb_info, m.thumbnail_path,
+                      m.generated
             "#,
-            session.map_name,
-            session.res_type,
-            session.credit,
-            link_path,
-            session.uploaded_by,
-            file_size,
+            map_name,
+            res_type,
+            version_id
         )
-        .fetch_one(&*app.pool)
+        .fetch_optional(&*app.pool)
         .await;
 
-        // Cleanup temp directory
-        let _ = Self::cleanup_temp_directory(&session_id, &store_upload).await;
-
-        // Delete Redis session
-        let _ = Self::delete_upload_session(&app.cache, &session_id).await;
-
         match result {
-            Ok(model) => {
-                tracing::info!("Upload completed: {}, final size: {}", session_id, file_size);
-
-                // Get uploader name
-                let uploader_name = sqlx::query_scalar!(
-                    "SELECT persona_name FROM website.steam_user WHERE user_id = $1",
-                    model.uploaded_by
-                )
-                .fetch_optional(&*app.pool)
-                .await
-                .ok()
-                .flatten();
-
+            Ok(Some(model)) => {
+                Self::model_changed(app, Some(user_token.id), "restore", &model).await;
                 let mut api_model: Map3DModel = model.into();
                 api_model.link_path = app.map_storage.normalize_link_path(
                     &api_model.link_path,
-                    &api_model.map_name,
-                    &api_model.res_type,
-                );
-                api_model.uploader_name = uploader_name;
+                    (api_model.map_name.as_str(), api_model.res_type.as_str(), api_model.content_hash.as_deref()),
+                ).await;
                 response!(ok api_model)
             }
+            Ok(None) => response!(err "Version not found", ErrorCode::NotFound),
             Err(e) => {
                 tracing::error!("Database error: {}", e);
-                response!(err "Database error", ErrorCode::InternalServerError)
+                response!(internal_server_error)
             }
         }
     }
 
-    /// Cancel chunked upload
-    #[oai(path = "/maps/:map_name/3d/upload/cancel/:session_id", method = "delete")]
-    async fn cancel_chunked_upload(
-        &self,
-        Data(app): Data<&AppData>,
-        Path(map_name): Path<String>,
-        Path(session_id): Path<String>,
-        TokenBearer(user_token): TokenBearer,
-    ) -> Response<String> {
-        // Check superuser permission
-        if !check_superuser(&app, user_token.id).await {
-            return response!(err "Forbidden", ErrorCode::Forbidden);
-        }
-
-        // Retrieve session from Redis
-        let session = match Self::get_upload_session(&app.cache, &session_id).await {
-            Ok(s) => s,
-            Err(_) => {
-                // Session not found, try to cleanup anyway
-                let store_upload = std::env::var("STORE_UPLOAD").unwrap_or_else(|_| "./maps".to_string());
-                let _ = Self::cleanup_temp_directory(&session_id, &store_upload).await;
-                return response!(ok "Upload cancelled".to_string());
-            }
-        };
-
-        // Verify user owns session
-        if session.uploaded_by != user_token.id {
-            return response!(err "Forbidden", ErrorCode::Forbidden);
-        }
-
-        // Verify map name matches
-        if session.map_name != map_name {
-            return response!(err "Map name mismatch", ErrorCode::BadRequest);
-        }
-
-        tracing::warn!("Upload cancelled: {}, chunks: {}/{}", session_id, session.chunks_received.len(), session.total_chunks);
-
-        // Cleanup temp directory
-        let store_upload = std::env::var("STORE_UPLOAD").unwrap_or_else(|_| "./maps".to_string());
-        let _ = Self::cleanup_temp_directory(&session_id, &store_upload).await;
-
-        // Delete Redis session
-        let _ = Self::delete_upload_session(&app.cache, &session_id).await;
-
-        response!(ok "Upload cancelled".to_string())
-    }
-
-    // Helper functions for chunked upload
-
-    async fn get_upload_session(
-        cache: &FastCache,
-        session_id: &str,
-    ) -> Result<UploadSession, &'static str> {
-        use redis::AsyncCommands;
-
-        let session_key = format!("upload_session:{}", session_id);
-
-        let mut conn = cache.redis_pool.get().await
-            .map_err(|_| "Failed to get Redis connection")?;
-
-        let session_json: String = conn.get(&session_key).await
-            .map_err(|_| "Session not found or expired")?;
-
-        serde_json::from_str(&session_json)
-            .map_err(|_| "Failed to parse session")
-    }
-
-    async fn update_upload_session(
-        cache: &FastCache,
-        session: &UploadSession,
-    ) -> Result<(), &'static str> {
-        use redis::AsyncCommands;
-
-        let session_key = format!("upload_session:{}", session.session_id);
-        let session_json = serde_json::to_string(session)
-            .map_err(|_| "Failed to serialize session")?;
-
-        let mut conn = cache.redis_pool.get().await
-            .map_err(|_| "Failed to get Redis connection")?;
-
-        let _: redis::RedisResult<()> = conn.set_ex(&session_key, &session_json, 86400).await;
-        Ok(())
-    }
-
-    async fn delete_upload_session(
-        cache: &FastCache,
-        session_id: &str,
-    ) -> Result<(), ()> {
-        use redis::AsyncCommands;
-
-        let session_key = format!("upload_session:{}", session_id);
-
-        if let Ok(mut conn) = cache.redis_pool.get().await {
-            let _: redis::RedisResult<()> = conn.del(&session_key).await;
-        }
-
-        Ok(())
-    }
-
-    async fn assemble_chunks(
-        session: &UploadSession,
-        store_upload: &str,
-        target_path: &str,
-    ) -> Result<String, std::io::Error> {
-        if let Some(parent) = std::path::Path::new(target_path).parent() {
-            tokio::fs::create_dir_all(parent).await?;
-        }
-
-        let mut target_file = tokio::fs::File::create(&target_path).await?;
-
-        // Assemble chunks sequentially
-        for chunk_index in 0..session.total_chunks {
-            let chunk_path = format!("{}/.tmp/{}/chunk_{}", store_upload, session.session_id, chunk_index);
-            let mut chunk_file = tokio::fs::File::open(&chunk_path).await?;
-            tokio::io::copy(&mut chunk_file, &mut target_file).await?;
-        }
-
-        Ok(target_path.to_string())
-    }
-
-    async fn cleanup_temp_directory(
-        session_id: &str,
-        store_upload: &str,
-    ) -> Result<(), std::io::Error> {
-        let temp_dir = format!("{}/.tmp/{}", store_upload, session_id);
-        tokio::fs::remove_dir_all(&temp_dir).await
-    }
-
-    /// Delete a 3D model (superuser only)
+    /// Delete a 3D model (`models.upload` on a server running the map)
     #[oai(path = "/maps/:map_name/3d/:res_type", method = "delete")]
     async fn delete_map_3d_model(
         &self,
@@ -2786,8 +3041,8 @@ impl MapApi{
         Path(res_type): Path<String>,
         TokenBearer(user_token): TokenBearer,
     ) -> Response<String> {
-        // Check superuser permission
-        if !check_superuser(&app, user_token.id).await {
+        // Needs models.upload on a server running the map
+        if !check_map_models(&app, user_token.id, &map_name).await {
             return response!(err "Forbidden", ErrorCode::Forbidden);
         }
 
@@ -2806,15 +3061,38 @@ impl MapApi{
         .fetch_optional(&*app.pool)
         .await;
 
-        let Ok(Some(_model)) = model else {
+        let Ok(Some(model)) = model else {
             return response!(err "Model not found", ErrorCode::NotFound);
         };
 
-        // Delete file from storage
-        if let Err(e) = app.map_storage.delete(&map_name, &res_type).await {
-            tracing::warn!("Failed to delete model from storage: {}", e);
-            // Continue with database deletion even if file deletion fails
+        let hashes = sqlx::query_scalar!(
+            "SELECT content_hash FROM website.map_3d_model_version WHERE map_name = $1 AND res_type = $2",
+            map_name,
+            res_type
+        )
+        .fetch_all(&*app.pool)
+        .await
+        .unwrap_or_default();
+
+        // Delete every stored version, plus the legacy object for models uploaded before hashing
+        let mut targets = hashes;
+        if model.content_hash.is_none() && !targets.contains(&None) {
+            targets.push(None);
         }
+        for content_hash in targets {
+            if let Err(e) = app.map_storage.delete((map_name.as_str(), res_type.as_str(), content_hash.as_deref())).await {
+                tracing::warn!("Failed to delete model from storage: {}", e);
+                // Continue with database deletion even if file deletion fails
+            }
+        }
+
+        let _ = sqlx::query!(
+            "DELETE FROM website.map_3d_model_version WHERE map_name = $1 AND res_type = $2",
+            map_name,
+            res_type
+        )
+        .execute(&*app.pool)
+        .await;
 
         // Delete from database
         let result = sqlx::query!(
@@ -2826,7 +3104,13 @@ impl MapApi{
         .await;
 
         match result {
-            Ok(_) => response!(ok "3D model deleted successfully".to_string()),
+            Ok(_) => {
+                record_audit(
+                    &app.pool, Some(user_token.id), "delete", "map_model", &format!("{map_name}/{res_type}"),
+                    Some(map_model_audit(&model)), None,
+                ).await;
+                response!(ok "3D model deleted successfully".to_string())
+            }
             Err(e) => {
                 tracing::error!("Database error: {}", e);
                 response!(internal_server_error)
@@ -2840,6 +3124,8 @@ impl UriPatternExt for MapApi{
         vec![
             "/servers/{server_id}/maps/{map_name}/images",
             "/servers/{server_id}/maps/autocomplete",
+            "/servers/{server_id}/maps/set-favorite",
+            "/servers/{server_id}/maps/{map_name}/unset-favorite",
             "/servers/{server_id}/match-now",
             "/servers/{server_id}/maps/last/sessions",
             "/servers/{server_id}/maps/all/sessions",
@@ -2851,6 +3137,7 @@ impl UriPatternExt for MapApi{
             "/servers/{server_id}/maps/{map_name}/regions",
             "/servers/{server_id}/maps/{map_name}/sessions_distribution",
             "/servers/{server_id}/maps/{map_name}/top_players",
+            "/servers/{server_id}/maps/{map_name}/leaderboard",
             "/servers/{server_id}/maps/{map_name}/player_types",
             "/servers/{server_id}/maps/{map_name}/musics",
             "/servers/{server_id}/sessions/{session_id}/players",
@@ -2858,20 +3145,29 @@ impl UriPatternExt for MapApi{
             "/servers/{server_id}/sessions/{session_id}/match",
             "/servers/{server_id}/sessions/{session_id}/all-match",
             "/servers/{server_id}/sessions/{session_id}/continents",
+            "/servers/{server_id}/sessions/{session_id}/timeline",
             "/maps/{map_name}/guides",
             "/maps/{map_name}/guides/slugs/{guide_slug}",
             "/maps/{map_name}/guides/{guide_id}",
             "/maps/{map_name}/guides/{guide_id}/vote",
             "/maps/{map_name}/guides/{guide_id}/report",
+            "/maps/{map_name}/guides/{guide_id}/revisions",
+            "/maps/{map_name}/guides/{guide_id}/revisions/{revision}",
+            "/maps/{map_name}/guides/{guide_id}/revisions/{revision}/diff",
+            "/maps/{map_name}/guides/{guide_id}/revisions/{revision}/restore",
             "/maps/{map_name}/guides/{guide_id}/comments",
             "/maps/{map_name}/guides/{guide_id}/comments/{comment_id}",
             "/maps/{map_name}/guides/{guide_id}/comments/{comment_id}/vote",
+            "/maps/{map_name}/guides/{guide_id}/comments/{comment_id}/report",
+            "/servers/{server_id}/guides",
             "/music/{music_id}/report",
             "/servers/{server_id}/maps",
             "/maps/all/3d",
             "/maps/{map_name}/3d",
             "/maps/{map_name}/3d/upload",
             "/maps/{map_name}/3d/{res_type}",
+            "/maps/{map_name}/3d/{res_type}/versions",
+            "/maps/{map_name}/3d/{res_type}/versions/{version_id}/restore",
             "/servers/{server_id}/maps/3d",
         ].iter_into()
     }
@@ -21,6 +21,7 @@ use crate::core::utils::*;
 use url;
 extern crate rust_fuzzy_search;
 use crate::core::api_models::*;
+use crate::core::rate_limit::client_ip;
 #[derive(Object, Serialize)]
 struct SitemapServer {
     server_id: String,
@@ -50,6 +51,7 @@ struct SitemapGuide {
     server_readable_link: Option<String>,
     slug: String,
     updated_at: String,
+    description: Option<String>,
 }
 
 #[derive(Object, Serialize)]
@@ -110,16 +112,79 @@ struct OEmbedPlayerResponse {
     url: String,
 }
 
+#[derive(Object)]
+struct OEmbedGuideResponse {
+    r#type: String,
+    version: String,
+    title: String,
+    description: String,
+    author_name: String,
+    author_url: String,
+}
+
 #[derive(ApiResponse)]
 enum OEmbedResponseType{
     #[oai(status = 200, content_type = "application/json+oembed")]
     Player(Json<OEmbedPlayerResponse>),
     #[oai(status = 200, content_type = "application/json+oembed")]
+    Guide(Json<OEmbedGuideResponse>),
+    #[oai(status = 200, content_type = "application/json+oembed")]
     Map(Json<OEmbedMapResponse>),
     #[oai(status = 400)]
     Err(PlainText<String>)
 }
 
+#[derive(ApiResponse)]
+enum AssetDownloadResponse {
+    #[oai(status = 200, content_type = "model/gltf-binary")]
+    Ok(Binary<poem::Body>, #[oai(header = "Cache-Control")] String),
+    #[oai(status = 302)]
+    Redirect(#[oai(header = "Location")] String, #[oai(header = "Cache-Control")] String),
+    #[oai(status = 403)]
+    Forbidden(PlainText<String>),
+    #[oai(status = 404)]
+    NotFound(PlainText<String>),
+    #[oai(status = 429)]
+    TooManyRequests(PlainText<String>, #[oai(header = "Retry-After")] u64),
+}
+
+/// A name that stays inside the folder it's joined onto.
+fn is_plain_filename(filename: &str) -> bool {
+    !filename.is_empty()
+        && !filename.starts_with('.')
+        && !filename.contains("..")
+        && !filename.contains(['/', '\\'])
+}
+
+const DOWNLOAD_WINDOW_SECS: u64 = 60;
+const DEFAULT_DOWNLOADS_PER_WINDOW: i64 = 60;
+
+/// Fixed window per client IP, `ASSET_DOWNLOADS_PER_MINUTE` downloads. Returns the seconds
+/// until the window resets once the limit is hit.
+async fn check_download_rate(app: &AppData, ip: &str) -> Result<(), u64> {
+    let limit = get_env_default("ASSET_DOWNLOADS_PER_MINUTE")
+        .and_then(|s| s.parse().ok())
+        .unwrap_or(DEFAULT_DOWNLOADS_PER_WINDOW);
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs();
+    let window = now / DOWNLOAD_WINDOW_SECS;
+    let key = format!("asset_download:{ip}:{window}");
+
+    let Ok(mut conn) = app.cache.redis_pool.get().await else {
+        return Ok(())
+    };
+    let count: redis::RedisResult<(i64,)> = redis::pipe()
+        .atomic()
+        .incr(&key, 1)
+        .expire(&key, DOWNLOAD_WINDOW_SECS as i64)
+        .ignore()
+        .query_async(&mut conn)
+        .await;
+    match count {
+        Ok((count,)) if count > limit => Err((window + 1) * DOWNLOAD_WINDOW_SECS - now),
+        _ => Ok(()),
+    }
+}
+
 pub struct MiscApi;
 
 
@@ -159,7 +224,8 @@ impl MiscApi {
             return response!(internal_server_error)
         };
         let Ok(guides) = sqlx::query_as!(DbGuideSitemap, r#"
-            SELECT g.map_name as "map_name!", g.server_id, s.readable_link AS server_readable_link, g.slug as "slug!", g.updated_at as "updated_at!"
+            SELECT g.map_name as "map_name!", g.server_id, s.readable_link AS server_readable_link, g.slug as "slug!", g.updated_at as "updated_at!",
+                g.excerpt
             FROM website.guides g
             LEFT JOIN server s ON g.server_id = s.server_id"#,
         ).fetch_all(&*data.pool.clone()).await else {
@@ -198,6 +264,7 @@ impl MiscApi {
                 server_readable_link: g.server_readable_link,
                 slug: g.slug,
                 updated_at: g.updated_at.date().to_string(),
+                description: g.excerpt,
             }
         }).collect();
 
@@ -271,8 +338,19 @@ impl MiscApi {
             },
         }
     }
+    /// Only thumbnails a character model points at are served
     #[oai(path = "/thumbnails/characters/:filename", method = "get")]
-    async fn get_character_thumbnail(&self, filename: Path<String>) -> Binary<Vec<u8>> {
+    async fn get_character_thumbnail(&self, Data(app): Data<&AppData>, filename: Path<String>) -> Binary<Vec<u8>> {
+        if !is_plain_filename(&filename.0) {
+            return Binary(vec![])
+        }
+        let known = sqlx::query_scalar!(
+            "SELECT EXISTS(SELECT 1 FROM website.character_3d_model WHERE thumbnail_path = $1)",
+            filename.0
+        ).fetch_one(&*app.pool).await;
+        if !matches!(known, Ok(Some(true))) {
+            return Binary(vec![])
+        }
         let path = get_env_default("CACHE_THUMBNAIL").unwrap_or_default();
         let file_path = PathBuf::from(path).join("characters").join(&filename.0);
         match fs::read(file_path).await {
@@ -281,6 +359,84 @@ impl MiscApi {
         }
     }
 
+    /// Only thumbnails a map model points at are served
+    #[oai(path = "/thumbnails/map-models/:filename", method = "get")]
+    async fn get_map_model_thumbnail(&self, Data(app): Data<&AppData>, filename: Path<String>) -> Binary<Vec<u8>> {
+        if !is_plain_filename(&filename.0) {
+            return Binary(vec![])
+        }
+        let known = sqlx::query_scalar!(
+            "SELECT EXISTS(SELECT 1 FROM website.map_3d_model WHERE thumbnail_path = $1)",
+            filename.0
+        ).fetch_one(&*app.pool).await;
+        if !matches!(known, Ok(Some(true))) {
+            return Binary(vec![])
+        }
+        let path = get_env_default("CACHE_THUMBNAIL").unwrap_or_default();
+        let file_path = PathBuf::from(path).join("map-models").join(&filename.0);
+        match fs::read(file_path).await {
+            Ok(data) => Binary(data),
+            Err(_) => Binary(vec![]),
+        }
+    }
+
+    /// Serves a signed asset URL, S3 compatible storage is redirected to a short presigned URL
+    #[oai(path = "/assets/:kind/download", method = "get")]
+    async fn download_asset(
+        &self,
+        req: &Request,
+        Data(app): Data<&AppData>,
+        Path(kind): Path<String>,
+        Query(key): Query<String>,
+        Query(expires): Query<i64>,
+        Query(signature): Query<String>,
+    ) -> AssetDownloadResponse {
+        let valid = match kind.as_str() {
+            "map" => app.map_storage.verify_signed(&key, expires, &signature),
+            "character" => app.character_storage.verify_signed(&key, expires, &signature),
+            _ => return AssetDownloadResponse::NotFound(PlainText("Unknown asset kind".to_string())),
+        };
+        if !valid {
+            return AssetDownloadResponse::Forbidden(PlainText("Invalid or expired link".to_string()))
+        }
+        let ip = client_ip(req).map(|ip| ip.to_string()).unwrap_or_default();
+        if let Err(retry_after) = check_download_rate(app, &ip).await {
+            return AssetDownloadResponse::TooManyRequests(PlainText("Too many downloads".to_string()), retry_after)
+        }
+
+        let presigned = match kind.as_str() {
+            "map" => app.map_storage.presigned_download(&key).await,
+            _ => app.character_storage.presigned_download(&key).await,
+        };
+        match presigned {
+            Some(Ok(url)) => return AssetDownloadResponse::Redirect(url, "no-store".to_string()),
+            Some(Err(e)) => {
+                tracing::warn!("Presigning download of {key} failed: {e}");
+                return AssetDownloadResponse::NotFound(PlainText("Not found".to_string()))
+            }
+            None => {}
+        }
+
+        let file = match kind.as_str() {
+            "map" => app.map_storage.open_local(&key).await,
+            _ => app.character_storage.open_local(&key).await,
+        };
+        match file {
+            Some(Ok(file)) => {
+                let max_age = (expires - chrono::Utc::now().timestamp()).max(0);
+                AssetDownloadResponse::Ok(
+                    Binary(poem::Body::from_async_read(file)),
+                    format!("private, max-age={max_age}"),
+                )
+            }
+            Some(Err(e)) => {
+                tracing::warn!("Signed download of {key} failed: {e}");
+                AssetDownloadResponse::NotFound(PlainText("Not found".to_string()))
+            }
+            None => AssetDownloadResponse::NotFound(PlainText("Not found".to_string())),
+        }
+    }
+
     #[oai(path="/meta_thumbnails", method="get")]
     async fn get_meta_thumbnails(
         &self, req: &Request, Data(app): Data<&AppData>, Query(url): Query<String>
@@ -440,6 +596,28 @@ impl MiscApi {
                 };
                 OEmbedResponseType::Player(Json(response))
             },
+            [.., "maps", map_name, "guides", guide_slug] => {
+                let guide = sqlx::query!(
+                    "SELECT g.title, g.excerpt, su.persona_name AS \"author_name?\"
+                     FROM website.guides g
+                     LEFT JOIN website.steam_user su ON su.user_id=g.author_id
+                     WHERE g.map_name=$1 AND g.slug=$2
+                     LIMIT 1",
+                    map_name, guide_slug
+                ).fetch_optional(&*app.pool).await;
+                let Ok(Some(guide)) = guide else {
+                    return OEmbedResponseType::Err(PlainText("Invalid guide".to_string()))
+                };
+                let response = OEmbedGuideResponse {
+                    r#type: "link".to_string(),
+                    version: "1.0".to_string(),
+                    description: guide.excerpt.unwrap_or_else(|| format!("A guide for {map_name}")),
+                    title: guide.title,
+                    author_name: guide.author_name.unwrap_or_default(),
+                    author_url: format!("{host}{}", parsed.path()),
+                };
+                OEmbedResponseType::Guide(Json(response))
+            },
             _ => OEmbedResponseType::Err(PlainText("Invalid URL".to_string()))
         }
     }
@@ -506,6 +684,8 @@ impl UriPatternExt for MiscApi{
             "/meta_thumbnails",
             "/thumbnails/{thumbnail_type}/{filename}",
             "/thumbnails/characters/{filename}",
+            "/thumbnails/map-models/{filename}",
+            "/assets/{kind}/download",
             "/health",
             "/events/data-updates",
             "/sitemap-data",
@@ -1,7 +1,7 @@
 use std::collections::HashMap;
 use std::fmt::Display;
 use std::ops::Add;
-use chrono::{DateTime, TimeDelta, TimeZone, Utc};
+use chrono::{DateTime, Datelike, TimeDelta, TimeZone, Utc};
 use poem::web::Data;
 use poem_openapi::{param::{Path, Query}, Enum, Object, OpenApi};
 use serde::{Deserialize, Deserializer};
@@ -15,6 +15,8 @@ use crate::core::api_models::*;
 use crate::{response, AppData, FastCache};
 use crate::core::utils::*;
 use crate::core::workers::*;
+use crate::core::anonymization::Anonymizer;
+use crate::core::achievements::{get_player_achievements, ACHIEVEMENTS};
 
 pub struct PlayerApi;
 
@@ -290,18 +292,11 @@ impl PlayerApi{
         &self, data: Data<&AppData>, ServerExtractor(server): ServerExtractor, Query(player_name): Query<String>,
         OptionalTokenBearer(user_token): OptionalTokenBearer,
     ) -> Response<Vec<SearchPlayer>>{
-        let user_id = user_token.as_ref().map(|t| t.id);
-        let Ok(result) = sqlx::query_as!(DbPlayerAnonymized, r#"
-            WITH server_community AS (
-                SELECT community_id FROM server WHERE server_id = $3
-            ),
-            user_perms AS (
-                SELECT
-                    COALESCE(website.is_superuser($4), FALSE) AS is_superuser,
-                    COALESCE(website.is_community_admin($4, (SELECT community_id FROM server_community)), FALSE) AS is_community_admin
-                WHERE $4 IS NOT NULL
-            ),
-            matched_players AS (
+        let Ok(anonymizer) = Anonymizer::for_server(&data, &server.server_id, user_token.as_ref()).await else {
+            return response!(internal_server_error)
+        };
+        let Ok(result) = sqlx::query_as!(DbPlayer, r#"
+            WITH matched_players AS (
                 SELECT p.*,
                        CASE WHEN p.player_id = $2 THEN 0 ELSE 1 END AS id_rank,
                        NULLIF(STRPOS(LOWER(p.player_name), LOWER($2)), 0) AS name_rank
@@ -310,26 +305,10 @@ impl PlayerApi{
             )
             SELECT
                 a.player_id AS "player_id!",
-                CASE
-                    WHEN ua.anonymized = TRUE
-                         AND $4::TEXT IS DISTINCT FROM a.player_id
-                         AND NOT COALESCE((SELECT is_superuser FROM user_perms), FALSE)
-                         AND NOT COALESCE((SELECT is_community_admin FROM user_perms), FALSE)
-                    THEN 'Anonymous'
-                    ELSE a.player_name
-                END AS "player_name!",
-                CASE
-                    WHEN ua.anonymized = TRUE
-                         AND $4::TEXT IS DISTINCT FROM a.player_id
-                         AND NOT COALESCE((SELECT is_superuser FROM user_perms), FALSE)
-                         AND NOT COALESCE((SELECT is_community_admin FROM user_perms), FALSE)
-                    THEN TRUE
-                    ELSE FALSE
-                END AS "is_anonymous!"
+                a.player_name AS "player_name!",
+                a.created_at AS "created_at!",
+                a.associated_player_id
             FROM matched_players a
-            CROSS JOIN server_community sc
-            LEFT JOIN website.user_anonymization ua
-                ON ua.user_id::TEXT = a.player_id AND ua.community_id = sc.community_id
             WHERE EXISTS (
                 SELECT 1
                 FROM player_server_session pss
@@ -338,17 +317,13 @@ impl PlayerApi{
             )
             ORDER BY a.id_rank ASC, a.name_rank ASC NULLS LAST
             LIMIT 20;
-        "#, format!("%{}%", player_name.to_lowercase()), player_name, server.server_id, user_id
+        "#, format!("%{}%", player_name.to_lowercase()), player_name, server.server_id
         ).fetch_all(&*data.pool.clone()).await else {
             return response!(ok vec![])
         };
 
         let value: Vec<SearchPlayer> = result.iter_into();
-        // Lazy lol, who cares, they ain't gonna know about this one
-        let filtered: Vec<SearchPlayer> = value.into_iter()
-            .filter(|v| !(v.is_anonymous && v.name == "Anonymous"))
-            .collect::<Vec<_>>();
-        response!(ok filtered)
+        response!(ok anonymizer.apply_all(value))
     }
     #[oai(path = "/servers/:server_id/players/table", method = "get")]
     async fn get_players_table(
@@ -358,7 +333,9 @@ impl PlayerApi{
     ) -> Response<PlayersTableRanked>{
         let pagination = 5;
         let paging = page as i64 * pagination;
-        let user_id = user_token.as_ref().map(|t| t.id);
+        let Ok(anonymizer) = Anonymizer::for_server(&data, &server.server_id, user_token.as_ref()).await else {
+            return response!(internal_server_error)
+        };
         let is_searching = player_name.is_some();
         let result = match player_name {
             Some(player_name) => {
@@ -369,15 +346,6 @@ impl PlayerApi{
                 let player_name_clean = player_name;
                 let player_name = format!("%{player_name}%");
                 sqlx::query_as!(DbPlayerTable, r#"
-                    WITH server_community AS (
-                        SELECT community_id FROM server WHERE server_id = $4
-                    ),
-                    user_perms AS (
-                        SELECT
-                            COALESCE(website.is_superuser($7), FALSE) AS is_superuser,
-                            COALESCE(website.is_community_admin($7, (SELECT community_id FROM server_community)), FALSE) AS is_community_admin
-                        WHERE $7 IS NOT NULL
-                    )
                     SELECT
                         COUNT(*) OVER(PARTITION BY pp.server_id) AS total_players,
                         CASE
@@ -387,30 +355,14 @@ impl PlayerApi{
                             ELSE ppr.playtime_rank
                         END AS ranked,
                         p.player_id AS "player_id!",
-                        CASE
-                            WHEN ua.anonymized = TRUE
-                                 AND $7::TEXT IS DISTINCT FROM p.player_id
-                                 AND NOT COALESCE((SELECT is_superuser FROM user_perms), FALSE)
-                                 AND NOT COALESCE((SELECT is_community_admin FROM user_perms), FALSE)
-                            THEN 'Anonymous'
-                            ELSE p.player_name
-                        END AS "player_name",
+                        p.player_name AS "player_name?",
                         total_playtime,
                         casual_playtime,
                         tryhard_playtime,
-                        CASE
-                            WHEN ua.anonymized = TRUE
-                                 AND $7::TEXT IS DISTINCT FROM p.player_id
-                                 AND NOT COALESCE((SELECT is_superuser FROM user_perms), FALSE)
-                                 AND NOT COALESCE((SELECT is_community_admin FROM user_perms), FALSE)
-                            THEN TRUE
-                            ELSE FALSE
-                        END AS "is_anonymous!"
+                        FALSE AS "is_anonymous!"
                     FROM website.player_playtime pp
                     JOIN player p ON p.player_id=pp.player_id
-                    CROSS JOIN server_community sc
                     LEFT JOIN website.player_playtime_ranks ppr ON ppr.server_id=pp.server_id AND ppr.player_id=pp.player_id
-                    LEFT JOIN website.user_anonymization ua ON ua.user_id::TEXT = p.player_id AND ua.community_id = sc.community_id
                     WHERE pp.server_id=$4 AND (p.player_id=$6 OR p.player_name ILIKE $1)
                     ORDER BY
                          CASE
@@ -420,21 +372,12 @@ impl PlayerApi{
                             ELSE total_playtime
                         END DESC
                     LIMIT $3 OFFSET $2;
-                "#, player_name, paging, pagination, server.server_id, mode.to_string(), player_name_clean, user_id)
+                "#, player_name, paging, pagination, server.server_id, mode.to_string(), player_name_clean)
                     .fetch_all(&*data.pool.clone())
                     .await
             },
             None => {
                 sqlx::query_as!(DbPlayerTable, r#"
-                    WITH server_community AS (
-                        SELECT community_id FROM server WHERE server_id = $3
-                    ),
-                    user_perms AS (
-                        SELECT
-                            COALESCE(website.is_superuser($5), FALSE) AS is_superuser,
-                            COALESCE(website.is_community_admin($5, (SELECT community_id FROM server_community)), FALSE) AS is_community_admin
-                        WHERE $5 IS NOT NULL
-                    )
                     SELECT
                         COUNT(*) OVER(PARTITION BY pp.server_id) AS total_players,
                         CASE
@@ -444,30 +387,14 @@ impl PlayerApi{
                             ELSE ppr.playtime_rank
                         END AS ranked,
                         p.player_id AS "player_id!",
-                        CASE
-                            WHEN ua.anonymized = TRUE
-                                 AND $5::TEXT IS DISTINCT FROM p.player_id
-                                 AND NOT COALESCE((SELECT is_superuser FROM user_perms), FALSE)
-                                 AND NOT COALESCE((SELECT is_community_admin FROM user_perms), FALSE)
-                            THEN 'Anonymous'
-                            ELSE p.player_name
-                        END AS "player_name",
+                        p.player_name AS "player_name?",
                         total_playtime,
                         casual_playtime,
                         tryhard_playtime,
-                        CASE
-                            WHEN ua.anonymized = TRUE
-                                 AND $5::TEXT IS DISTINCT FROM p.player_id
-                                 AND NOT COALESCE((SELECT is_superuser FROM user_perms), FALSE)
-                                 AND NOT COALESCE((SELECT is_community_admin FROM user_perms), FALSE)
-                            THEN TRUE
-                            ELSE FALSE
-                        END AS "is_anonymous!"
+                        FALSE AS "is_anonymous!"
                     FROM website.player_playtime pp
                     JOIN player p ON p.player_id=pp.player_id
-                    CROSS JOIN server_community sc
                     LEFT JOIN website.player_playtime_ranks ppr ON ppr.server_id=pp.server_id AND ppr.player_id=pp.player_id
-                    LEFT JOIN website.user_anonymization ua ON ua.user_id::TEXT = p.player_id AND ua.community_id = sc.community_id
                     WHERE pp.server_id=$3
                     ORDER BY
                          CASE
@@ -477,7 +404,7 @@ impl PlayerApi{
                             ELSE total_playtime
                         END DESC
                     LIMIT $2 OFFSET $1;
-                "#, paging, pagination, server.server_id, mode.to_string(), user_id)
+                "#, paging, pagination, server.server_id, mode.to_string())
                     .fetch_all(&*data.pool.clone())
                     .await
             }
@@ -490,28 +417,18 @@ impl PlayerApi{
             .and_then(|e| e.total_players)
             .unwrap_or_default();
         let mut value: Vec<PlayerTableRank> = resulted.iter_into();
-
-        if !is_searching{
-            for player_table in value.iter_mut(){
-                // Lazy lol, who cares, they ain't gonna know about this one
-                if player_table.is_anonymous && player_table.name == "Anonymous"{
-                    (*player_table).id = uuid::Uuid::new_v4().to_string()
-                }
-            }
-        }else{
-            // Lazy lol, who cares, they ain't gonna know about this one
-            value = value.into_iter()
-                .filter(|v| !(v.is_anonymous && v.name == "Anonymous"))
-                .collect::<Vec<_>>();
+        if is_searching {
+            // Finding an anonymized player by name or id would confirm who they are
+            value.retain(|v| !anonymizer.privacy(&v.id).anonymized);
         }
 
         response!(ok PlayersTableRanked {
             total_players: total_player_count,
-            players: value
+            players: anonymizer.apply_all(value)
         })
     }
     #[oai(path="/servers/:server_id/players/:player_id/legacy_stats", method="get")]
-    async fn get_legacy_stats(&self, Data(app): Data<&AppData>, extract: PlayerExtractor, OptionalAnonymousTokenBearer(_user_token): OptionalAnonymousTokenBearer) -> Response<PlayerWithLegacyRanks>{
+    async fn get_legacy_stats(&self, Data(app): Data<&AppData>, extract: PlayerExtractor, OptionalAnonymousTokenBearer(_anonymizer): OptionalAnonymousTokenBearer) -> Response<PlayerWithLegacyRanks>{
         if extract.server.server_id != "65bdad6379cefd7ebcecce5c"{
             return response!(err "Server does not have this stats", ErrorCode::NotFound)
         }
@@ -565,54 +482,32 @@ impl PlayerApi{
     async fn get_players_playing(&self, Data(app): Data<&AppData>, ServerExtractor(server): ServerExtractor, OptionalTokenBearer(user_token): OptionalTokenBearer) -> Response<Vec<PlayerDetailSession>>{
         let pool = &*app.pool.clone();
         let server_id = server.server_id.clone();
-        let user_id = user_token.as_ref().map(|t| t.id);
+        let Ok(anonymizer) = Anonymizer::for_server(app, &server_id, user_token.as_ref()).await else {
+            return response!(internal_server_error)
+        };
 
         let Ok(result) = sqlx::query_as!(DbPlayerDetailSession, r#"
-            WITH server_community AS (
-                SELECT community_id FROM server WHERE server_id = $1
-            ),
-            user_perms AS (
-                SELECT
-                    COALESCE(website.is_superuser($2), FALSE) AS is_superuser,
-                    COALESCE(website.is_community_admin($2, (SELECT community_id FROM server_community)), FALSE) AS is_community_admin
-                WHERE $2 IS NOT NULL
-            )
             SELECT
                 pss.session_id AS "session_id!",
                 pss.server_id AS "server_id!",
-                CASE
-                    WHEN ua.anonymized = TRUE
-                         AND $2::TEXT IS DISTINCT FROM p.player_id
-                         AND NOT COALESCE((SELECT is_superuser FROM user_perms), FALSE)
-                         AND NOT COALESCE((SELECT is_community_admin FROM user_perms), FALSE)
-                    THEN 'Anonymous'
-                    ELSE p.player_name
-                END AS "player_name",
+                p.player_name AS "player_name?",
                 pss.player_id AS "player_id!",
                 pss.started_at,
                 pss.ended_at,
-                CASE
-                    WHEN ua.anonymized = TRUE
-                         AND $2::TEXT IS DISTINCT FROM p.player_id
-                         AND NOT COALESCE((SELECT is_superuser FROM user_perms), FALSE)
-                         AND NOT COALESCE((SELECT is_community_admin FROM user_perms), FALSE)
-                    THEN TRUE
-                    ELSE FALSE
-                END AS "is_anonymous!"
+                FALSE AS "is_anonymous!"
             FROM player_server_session pss
             JOIN player p ON p.player_id = pss.player_id
-            CROSS JOIN server_community sc
-            LEFT JOIN website.user_anonymization ua ON ua.user_id::TEXT = p.player_id AND ua.community_id = sc.community_id
             WHERE pss.server_id = $1 AND pss.ended_at IS NULL
             ORDER BY pss.started_at
-        "#, server_id, user_id).fetch_all(pool).await else {
+        "#, server_id).fetch_all(pool).await else {
             return response!(internal_server_error)
         };
 
-        response!(ok result.iter_into())
+        let value: Vec<PlayerDetailSession> = result.iter_into();
+        response!(ok anonymizer.apply_all(value))
     }
     #[oai(path="/servers/:server_id/players/:player_id/playing", method="get")]
-    async fn get_last_playing(&self, Data(app): Data<&AppData>, extract: PlayerExtractor, OptionalAnonymousTokenBearer(_user_token): OptionalAnonymousTokenBearer) -> Response<PlayerSession>{
+    async fn get_last_playing(&self, Data(app): Data<&AppData>, extract: PlayerExtractor, OptionalAnonymousTokenBearer(_anonymizer): OptionalAnonymousTokenBearer) -> Response<PlayerSession>{
         let pool = &*app.pool.clone();
         let redis_pool = &app.cache;
         let player_id = extract.player.player_id;
@@ -646,13 +541,13 @@ impl PlayerApi{
         &self,
         Data(app): Data<&AppData>,
         extract: PlayerExtractor,
-        OptionalAnonymousTokenBearer(_user_token): OptionalAnonymousTokenBearer,
+        OptionalAnonymousTokenBearer(_anonymizer): OptionalAnonymousTokenBearer,
     ) -> Response<Vec<PlayerSessionTime>> {
         let context = PlayerContext::from(extract);
         handle_worker_player_result(app.player_worker.get_player_sessions(&context).await)
     }
     #[oai(path="/servers/:server_id/players/:player_id/hours_of_day", method="get")]
-    async fn get_hours_of_day_player(&self, Data(app): Data<&AppData>, extract: PlayerExtractor, OptionalAnonymousTokenBearer(_user_token): OptionalAnonymousTokenBearer) -> Response<Vec<PlayerHourDay>>{
+    async fn get_hours_of_day_player(&self, Data(app): Data<&AppData>, extract: PlayerExtractor, OptionalAnonymousTokenBearer(_anonymizer): OptionalAnonymousTokenBearer) -> Response<Vec<PlayerHourDay>>{
         let context = PlayerContext::from(extract);
         handle_worker_player_result(app.player_worker.get_hour_of_day(&context).await)
     }
@@ -660,7 +555,7 @@ impl PlayerApi{
     async fn get_list_sessions(
         &self, Data(app): Data<&AppData>, extract: PlayerExtractor, Query(page): Query<usize>,
         Query(datetime): Query<Option<DateTime<Utc>>>,
-        OptionalAnonymousTokenBearer(_user_token): OptionalAnonymousTokenBearer,
+        OptionalAnonymousTokenBearer(_anonymizer): OptionalAnonymousTokenBearer,
     ) -> Response<PlayerSessionPage>{
         let pagination = 10;
         let offset = pagination * page as i64;
@@ -699,7 +594,7 @@ impl PlayerApi{
     #[oai(path="/servers/:server_id/players/:player_id/sessions/:session_id/info", method="get")]
     async fn get_session_info(
         &self, Data(app): Data<&AppData>, extract: PlayerExtractor, Path(session_id): Path<String>,
-        OptionalAnonymousTokenBearer(_user_token): OptionalAnonymousTokenBearer,
+        OptionalAnonymousTokenBearer(_anonymizer): OptionalAnonymousTokenBearer,
     ) -> Response<PlayerSession>{
         let Ok(result) = sqlx::query_as!(DbPlayerSession,
             "SELECT player_id, p.server_id, session_id, started_at, ended_at, last_verified, COALESCE(ua.anonymized, NULL) AS is_anonymous
@@ -716,7 +611,7 @@ impl PlayerApi{
     #[oai(path="/servers/:server_id/players/:player_id/sessions/:session_id/maps", method="get")]
     async fn get_session_server_graph(
         &self, Data(app): Data<&AppData>, extract: PlayerExtractor, Path(session_id): Path<String>,
-        OptionalAnonymousTokenBearer(_user_token): OptionalAnonymousTokenBearer,
+        OptionalAnonymousTokenBearer(_anonymizer): OptionalAnonymousTokenBearer,
     ) -> Response<Vec<PlayerSessionMapPlayed>>{
         let map_played = match sqlx::query_as!(DbPlayerSessionMapPlayed,
             "WITH data_session AS (
@@ -769,7 +664,7 @@ impl PlayerApi{
     #[oai(path = "/servers/:server_id/players/:player_id/infraction_update", method="get")]
     async fn get_force_player_infraction_update(
         &self, data: Data<&AppData>, ServerExtractor(server): ServerExtractor, player_id: Path<i64>,
-        OptionalAnonymousTokenBearer(_user_token): OptionalAnonymousTokenBearer,
+        OptionalAnonymousTokenBearer(_anonymizer): OptionalAnonymousTokenBearer,
     ) -> Response<PlayerInfractionUpdate>{
         let pool = &*data.pool.clone();
         let Ok(result) = sqlx::query_as!(DbPlayerInfraction, "
@@ -821,7 +716,7 @@ impl PlayerApi{
         })
     }
     #[oai(path = "/servers/:server_id/players/:player_id/infractions", method = "get")]
-    async fn get_player_infractions(&self, Data(data): Data<&AppData>, extract: PlayerExtractor, OptionalAnonymousTokenBearer(_user_token): OptionalAnonymousTokenBearer) -> Response<Vec<PlayerInfraction>> {
+    async fn get_player_infractions(&self, Data(data): Data<&AppData>, extract: PlayerExtractor, OptionalAnonymousTokenBearer(_anonymizer): OptionalAnonymousTokenBearer) -> Response<Vec<PlayerInfraction>> {
         let pool = &*data.pool.clone();
         let Ok(result) = sqlx::query_as!(DbPlayerInfraction, "
             SELECT 
@@ -843,13 +738,14 @@ impl PlayerApi{
         response!(ok result.iter_into())
     }
     #[oai(path = "/servers/:server_id/players/:player_id/detail", method = "get")]
-    async fn get_player_detail(&self, Data(app): Data<&AppData>, extract: PlayerExtractor, OptionalAnonymousTokenBearer(_user_token): OptionalAnonymousTokenBearer) -> Response<DetailedPlayer>{
+    async fn get_player_detail(&self, Data(app): Data<&AppData>, extract: PlayerExtractor, OptionalAnonymousTokenBearer(_anonymizer): OptionalAnonymousTokenBearer) -> Response<DetailedPlayer>{
         let ctx = PlayerContext::from(extract);
         handle_worker_player_result(app.player_worker.get_detail(&ctx).await)
     }
     #[oai(path = "/players/:player_id/pfp", method = "get")]
     async fn get_player_pfp(
-        &self, Data(app): Data<&AppData>, Path(player_id): Path<String>
+        &self, Data(app): Data<&AppData>, Path(player_id): Path<String>,
+        OptionalAnonymousTokenBearer(_anonymizer): OptionalAnonymousTokenBearer,
     ) -> Response<PlayerProfilePicture>{
         let original_player_id = player_id;
         let Some(provider) = &app.steam_provider else {
@@ -890,18 +786,66 @@ impl PlayerApi{
             medium: url_medium
         })
     }
+    #[oai(path = "/players/:player_id/calendar", method = "get")]
+    async fn get_player_calendar(
+        &self, Data(app): Data<&AppData>, Path(player_id): Path<String>,
+        Query(year): Query<Option<i32>>, Query(server_id): Query<Option<String>>,
+        OptionalAnonymousTokenBearer(_anonymizer): OptionalAnonymousTokenBearer,
+    ) -> Response<PlayerCalendar>{
+        let Some(player) = get_player(&app.pool, &app.cache, &player_id).await else {
+            return response!(err "Player not found", ErrorCode::NotFound)
+        };
+        let current_year = Utc::now().year();
+        let year = year.unwrap_or(current_year);
+        if !(2000..=current_year).contains(&year) {
+            return response!(err "Invalid year", ErrorCode::BadRequest)
+        }
+        let server_id = match server_id {
+            Some(server_id) => {
+                let Some(server) = get_server(&app.pool, &app.cache, &server_id).await else {
+                    return response!(err "Server not found", ErrorCode::NotFound)
+                };
+                Some(server.server_id)
+            }
+            None => None,
+        };
+        handle_worker_player_result(
+            app.player_worker.get_calendar(&player.player_id, server_id.as_deref(), year).await
+        )
+    }
+    #[oai(path = "/players/:player_id/achievements", method = "get")]
+    async fn get_achievements(
+        &self, Data(app): Data<&AppData>, Path(player_id): Path<String>,
+        OptionalAnonymousTokenBearer(_anonymizer): OptionalAnonymousTokenBearer,
+    ) -> Response<PlayerAchievements>{
+        let Some(player) = get_player(&app.pool, &app.cache, &player_id).await else {
+            return response!(err "Player not found", ErrorCode::NotFound)
+        };
+        let Ok(unlocked) = get_player_achievements(&app.pool, &player.player_id).await else {
+            return response!(internal_server_error)
+        };
+        let available = ACHIEVEMENTS.iter()
+            .map(|a| AchievementInfo {
+                key: a.key.to_string(),
+                name: a.name.to_string(),
+                description: a.description.to_string(),
+            })
+            .collect();
+        response!(ok PlayerAchievements { unlocked, available })
+    }
     #[oai(path="/servers/:server_id/players/:player_id/sessions/:session_id/might_friends", method="get")]
     async fn get_player_approximate_friend(
         &self, Data(app): Data<&AppData>, extract: PlayerExtractor, Path(session_id): Path<String>,
-        OptionalAnonymousTokenBearer(_user_token): OptionalAnonymousTokenBearer,
+        OptionalAnonymousTokenBearer(anonymizer): OptionalAnonymousTokenBearer,
     ) -> Response<Vec<PlayerSeen>>{
         let ctx = PlayerContext::from(extract);
-        handle_worker_player_result(app.player_worker.get_player_approximate_friend(&ctx, &session_id).await)
+        let result = app.player_worker.get_player_approximate_friend(&ctx, &session_id).await;
+        handle_worker_player_result(result.map(|seen| anonymizer.apply_all(seen)))
     }
     #[oai(path="/servers/:server_id/players/:player_id/most_played_maps", method="get")]
     async fn get_player_most_played(
         &self, Data(app): Data<&AppData>, extract: PlayerExtractor,
-        OptionalAnonymousTokenBearer(_user_token): OptionalAnonymousTokenBearer,
+        OptionalAnonymousTokenBearer(_anonymizer): OptionalAnonymousTokenBearer,
     ) -> Response<Vec<PlayerMostPlayedMap>>{
         let ctx = PlayerContext::from(extract);
         handle_worker_player_result(app.player_worker.get_most_played_maps(&ctx).await)
@@ -909,7 +853,7 @@ impl PlayerApi{
     #[oai(path="/servers/:server_id/players/:player_id/regions", method="get")]
     async fn get_player_region(
         &self, Data(app): Data<&AppData>, extract: PlayerExtractor,
-        OptionalAnonymousTokenBearer(_user_token): OptionalAnonymousTokenBearer,
+        OptionalAnonymousTokenBearer(_anonymizer): OptionalAnonymousTokenBearer,
     ) -> Response<Vec<PlayerRegionTime>>{
         let ctx = PlayerContext::from(extract);
         handle_worker_player_result(app.player_worker.get_regions(&ctx).await)
@@ -919,6 +863,7 @@ impl UriPatternExt for PlayerApi{
     fn get_all_patterns(&self) -> Vec<RoutePattern<'_>> {
         vec![
             "/servers/{server_id}/players/{player_id}/playing",
+            "/servers/{server_id}/players/playing",
             "/servers/{server_id}/players/autocomplete",
             "/servers/{server_id}/players/stats",
             "/servers/{server_id}/players/countries",
@@ -932,6 +877,8 @@ impl UriPatternExt for PlayerApi{
             "/servers/{server_id}/players/{player_id}/infractions",
             "/servers/{server_id}/players/{player_id}/detail",
             "/players/{player_id}/pfp",
+            "/players/{player_id}/achievements",
+            "/players/{player_id}/calendar",
             "/servers/{server_id}/players/{player_id}/most_played_maps",
             "/servers/{server_id}/players/{player_id}/regions",
             "/servers/{server_id}/players/{player_id}/legacy_stats",
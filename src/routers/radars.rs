@@ -5,6 +5,7 @@ use poem::web::Data;
 use poem_openapi::{Enum, OpenApi};
 use poem_openapi::param::Query;
 use crate::{response, AppData};
+use crate::core::anonymization::Anonymizer;
 use crate::core::model::*;
 use crate::core::api_models::*;
 use crate::core::utils::*;
@@ -259,8 +260,12 @@ impl RadarApi {
         ServerExtractor(server): ServerExtractor,
         Query(latitude): Query<f64>,
         Query(longitude): Query<f64>,
-        Query(page): Query<usize>
+        Query(page): Query<usize>,
+        OptionalTokenBearer(user_token): OptionalTokenBearer,
     ) -> Response<CountryPlayers> {
+        let Ok(anonymizer) = Anonymizer::for_server(app, &server.server_id, user_token.as_ref()).await else {
+            return response!(internal_server_error)
+        };
         let pool = &*app.pool.clone();
         let offset = (page * 10) as i64;
         let server_id = server.server_id;
@@ -329,7 +334,7 @@ impl RadarApi {
             count: player.total_player_count.unwrap_or_default(),
             code: country_geometry.country_code.unwrap_or_default(),
             name: country_geometry.country_name.unwrap_or_default(),
-            players: result.result.iter_into(),
+            players: anonymizer.apply_all(result.result.iter_into()),
         })
     }
     #[oai(path="/radars/:server_id/query", method="get")]
@@ -340,8 +345,12 @@ impl RadarApi {
         Query(longitude): Query<f64>,
         Query(time): Query<DateTime<Utc>>,
         Query(interval): Query<TimeInterval>,
-        Query(page): Query<usize>
+        Query(page): Query<usize>,
+        OptionalTokenBearer(user_token): OptionalTokenBearer,
     ) -> Response<CountryPlayers> {
+        let Ok(anonymizer) = Anonymizer::for_server(app, &server.server_id, user_token.as_ref()).await else {
+            return response!(internal_server_error)
+        };
         let pool = &*app.pool.clone();
         let offset = (page * 10) as i64;
         let server_id = server.server_id;
@@ -419,7 +428,7 @@ impl RadarApi {
             count: player.total_player_count.unwrap_or_default(),
             code: country_geometry.country_code.unwrap_or_default(),
             name: country_geometry.country_name.unwrap_or_default(),
-            players: result.result.iter_into(),
+            players: anonymizer.apply_all(result.result.iter_into()),
         })
     }
 }
@@ -0,0 +1,205 @@
+use poem::web::Data;
+use poem_openapi::OpenApi;
+use poem_openapi::param::Query;
+
+use crate::core::anonymization::Anonymizer;
+use crate::core::api_models::*;
+use crate::core::model::DbSearchResult;
+use crate::core::utils::*;
+use crate::{response, AppData};
+
+const SEARCH_KINDS: &[&str] = &["guide", "comment", "map", "music", "player", "server"];
+const DEFAULT_LIMIT: i64 = 20;
+const MAX_LIMIT: i64 = 50;
+const MIN_QUERY_LEN: usize = 2;
+const MAX_QUERY_LEN: usize = 100;
+/// What `ts_headline` wraps matches in, turned into `<mark>` once the text around is escaped
+const MARK_START: &str = "[[mark]]";
+const MARK_END: &str = "[[/mark]]";
+
+pub struct SearchApi;
+
+#[OpenApi]
+impl SearchApi {
+    /// Searches guides, guide comments, maps and their creators, map music, players and servers
+    /// at once, best matches first. `q` takes web search syntax (`"exact words"`, `-excluded`,
+    /// `or`) and tolerates typos in names and titles. `types` narrows it down, comma separated.
+    #[oai(path = "/search", method = "get")]
+    async fn search(
+        &self, Data(app): Data<&AppData>,
+        Query(q): Query<String>,
+        Query(types): Query<Option<String>>,
+        Query(limit): Query<Option<i64>>,
+        OptionalTokenBearer(user_token): OptionalTokenBearer,
+    ) -> Response<SearchResults> {
+        let q = q.trim().to_string();
+        let q_len = q.chars().count();
+        if q_len < MIN_QUERY_LEN || q_len > MAX_QUERY_LEN {
+            return response!(err "Search must be between 2 and 100 characters", ErrorCode::BadRequest)
+        }
+        let kinds: Vec<String> = match types.as_deref() {
+            Some(types) => types.split(',').map(|t| t.trim().to_lowercase()).filter(|t| !t.is_empty()).collect(),
+            None => SEARCH_KINDS.iter().map(|k| k.to_string()).collect(),
+        };
+        if kinds.is_empty() || kinds.iter().any(|k| !SEARCH_KINDS.contains(&k.as_str())) {
+            return response!(err "Unknown search type", ErrorCode::BadRequest)
+        }
+        let limit = limit.unwrap_or(DEFAULT_LIMIT).clamp(1, MAX_LIMIT);
+
+        let Ok(anonymizer) = Anonymizer::everywhere(app, user_token.as_ref()).await else {
+            return response!(internal_server_error)
+        };
+
+        // Full-text hits score 0.5 to 1 so they rank with name similarity, which is already 0 to 1.
+        let pool = &*app.pool;
+        let func = || sqlx::query_as!(DbSearchResult, r#"
+            WITH q AS (
+                SELECT websearch_to_tsquery('english', $1) AS en,
+                       websearch_to_tsquery('simple', $1) AS simple,
+                       $1::text AS raw
+            )
+            (
+                SELECT 'guide' AS "kind!",
+                    g.id::text AS "id!",
+                    g.title AS "title!",
+                    ts_headline('english', g.content, q.en, 'StartSel="[[mark]]", StopSel="[[/mark]]", MaxFragments=2, MaxWords=25, MinWords=8') AS "snippet?",
+                    g.map_name AS "map_name?",
+                    g.slug::text AS "guide_slug?",
+                    g.server_id::text AS "server_id?",
+                    GREATEST(
+                        CASE WHEN g.search_vector @@ q.en THEN 0.5 + ts_rank_cd(g.search_vector, q.en, 32) / 2 ELSE 0 END,
+                        word_similarity(q.raw, g.title)
+                    )::float8 AS "score!"
+                FROM website.guides g CROSS JOIN q
+                WHERE 'guide' = ANY($2::text[]) AND (g.search_vector @@ q.en OR q.raw <% g.title)
+                ORDER BY 8 DESC
+                LIMIT $3
+            )
+            UNION ALL
+            (
+                SELECT 'comment', c.id::text, g.title,
+                    ts_headline('english', c.content, q.en, 'StartSel="[[mark]]", StopSel="[[/mark]]", MaxFragments=1, MaxWords=25, MinWords=8'),
+                    g.map_name, g.slug::text, g.server_id::text,
+                    (0.5 + ts_rank_cd(c.search_vector, q.en, 32) / 2)::float8
+                FROM website.guide_comments c
+                JOIN website.guides g ON g.id = c.guide_id
+                CROSS JOIN q
+                WHERE 'comment' = ANY($2::text[]) AND c.search_vector @@ q.en
+                ORDER BY 8 DESC
+                LIMIT $3
+            )
+            UNION ALL
+            (
+                SELECT 'map', m.name::text, m.name::text,
+                    ts_headline('simple', COALESCE(m.creators, ''), q.simple, 'StartSel="[[mark]]", StopSel="[[/mark]]", HighlightAll=true'),
+                    m.name::text, NULL, NULL,
+                    GREATEST(
+                        CASE WHEN m.search_vector @@ q.simple THEN 0.5 + ts_rank_cd(m.search_vector, q.simple, 32) / 2 ELSE 0 END,
+                        word_similarity(q.raw, m.name)
+                    )::float8
+                FROM map_metadata m CROSS JOIN q
+                WHERE 'map' = ANY($2::text[]) AND (m.search_vector @@ q.simple OR q.raw <% m.name)
+                ORDER BY 8 DESC
+                LIMIT $3
+            )
+            UNION ALL
+            (
+                SELECT 'music', mm.id::text, mm.music_name, NULL,
+                    (SELECT amm.map_name FROM associated_map_music amm
+                     WHERE amm.map_music_id = mm.id ORDER BY amm.map_name LIMIT 1),
+                    NULL, NULL,
+                    GREATEST(
+                        CASE WHEN mm.search_vector @@ q.simple THEN 0.5 + ts_rank_cd(mm.search_vector, q.simple, 32) / 2 ELSE 0 END,
+                        word_similarity(q.raw, mm.music_name)
+                    )::float8
+                FROM map_music mm CROSS JOIN q
+                WHERE 'music' = ANY($2::text[]) AND (mm.search_vector @@ q.simple OR q.raw <% mm.music_name)
+                ORDER BY 8 DESC
+                LIMIT $3
+            )
+            UNION ALL
+            (
+                SELECT 'player', p.player_id::text, p.player_name, NULL, NULL, NULL, NULL,
+                    (CASE WHEN p.player_id = q.raw THEN 1 ELSE similarity(p.player_name, q.raw) END)::float8
+                FROM player p CROSS JOIN q
+                WHERE 'player' = ANY($2::text[]) AND (p.player_id = q.raw OR p.player_name % q.raw)
+                ORDER BY 8 DESC
+                LIMIT $3
+            )
+            UNION ALL
+            (
+                SELECT 'server', s.server_id::text, COALESCE(s.server_fullname, s.server_name, s.server_id),
+                    NULL, NULL, NULL, s.server_id::text,
+                    (CASE WHEN s.readable_link = q.raw THEN 1 ELSE GREATEST(
+                        word_similarity(q.raw, COALESCE(s.server_fullname, '')),
+                        word_similarity(q.raw, COALESCE(s.server_name, ''))
+                    ) END)::float8
+                FROM server s CROSS JOIN q
+                WHERE 'server' = ANY($2::text[])
+                    AND (s.readable_link = q.raw OR q.raw <% s.server_fullname OR q.raw <% s.server_name)
+                ORDER BY 8 DESC
+                LIMIT $3
+            )
+            ORDER BY 8 DESC
+            LIMIT $3
+        "#, q, &kinds, limit).fetch_all(pool);
+
+        let key = format!("search:{}:{limit}:{q}", kinds.join(","));
+        let rows = match cached_response(&key, &app.cache, 60, func).await {
+            Ok(rows) => rows.result,
+            Err(e) => {
+                tracing::error!("Search failed: {e}");
+                return response!(internal_server_error)
+            }
+        };
+        let results = rows.into_iter()
+            .filter_map(DbSearchResult::into_result)
+            .map(|mut result| {
+                result.snippet = result.snippet.as_deref().map(highlight);
+                result
+            })
+            .collect();
+        response!(ok SearchResults {
+            query: q,
+            results: anonymizer.apply_all(results),
+        })
+    }
+}
+
+/// Snippets come from user text, so everything but the highlighting is escaped.
+fn highlight(snippet: &str) -> String {
+    let mut html = String::with_capacity(snippet.len());
+    for c in snippet.chars() {
+        match c {
+            '&' => html.push_str("&amp;"),
+            '<' => html.push_str("&lt;"),
+            '>' => html.push_str("&gt;"),
+            '"' => html.push_str("&quot;"),
+            '\'' => html.push_str("&#39;"),
+            c => html.push(c),
+        }
+    }
+    html.replace(MARK_START, "<mark>").replace(MARK_END, "</mark>")
+}
+
+impl UriPatternExt for SearchApi {
+    fn get_all_patterns(&self) -> Vec<RoutePattern<'_>> {
+        vec![
+            "/search",
+        ].iter_into()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn highlight_escapes_everything_but_marks() {
+        assert_eq!(
+            highlight("<img src=x onerror=\"alert(1)\"> [[mark]]boss[[/mark]] & co"),
+            "&lt;img src=x onerror=&quot;alert(1)&quot;&gt; <mark>boss</mark> &amp; co"
+        );
+        assert_eq!(highlight("it's [[mark]]<b>[[/mark]]"), "it&#39;s <mark>&lt;b&gt;</mark>");
+    }
+}
@@ -5,6 +5,7 @@ use poem_openapi::OpenApi;
 use crate::{response, AppData};
 use crate::core::model::*;
 use crate::core::api_models::*;
+use crate::core::permissions::{has_permission, Permission, Scope};
 use crate::core::utils::*;
 
 fn truncate_error(error: &str) -> String {
@@ -85,7 +86,7 @@ impl ServerApi {
 
     #[oai(path = "/fetch-status", method="get")]
     async fn get_fetch_status(&self, Data(data): Data<&AppData>, TokenBearer(user_token): TokenBearer) -> Response<Vec<FetchStatusEntry>> {
-        if !check_superuser(data, user_token.id).await {
+        if !has_permission(data, user_token.id, Permission::ServersManage, Scope::Global).await {
             return response!(err "Unauthorized", ErrorCode::Forbidden);
         }
         let pool = &*data.pool.clone();
@@ -0,0 +1,344 @@
+use poem::web::Data;
+use poem::{Body, Request};
+use poem_openapi::param::{Header, Path};
+use poem_openapi::payload::PlainText;
+use poem_openapi::{ApiResponse, OpenApi};
+
+use crate::AppData;
+use crate::core::uploads::*;
+use crate::core::permissions::{check_character_models, check_map_models};
+use crate::core::utils::*;
+use crate::routers::characters::CharacterApi;
+use crate::routers::maps::MapApi;
+
+/// Resumable uploads following the tus 1.0.0 protocol (core, creation, expiration,
+/// checksum and termination). The upload target is passed through `Upload-Metadata`:
+/// `kind` is `map_model` (with `map_name`, `res_type`) or `character_model`
+/// (with `server_id`, `model_id`, optional `name`), `credit` is optional for both.
+pub struct UploadsApi;
+
+#[derive(ApiResponse)]
+enum TusResponse {
+    #[oai(status = 204)]
+    Options(
+        #[oai(header = "Tus-Resumable")] String,
+        #[oai(header = "Tus-Version")] String,
+        #[oai(header = "Tus-Extension")] String,
+        #[oai(header = "Tus-Max-Size")] u64,
+        #[oai(header = "Tus-Checksum-Algorithm")] String,
+    ),
+    #[oai(status = 201)]
+    Created(
+        #[oai(header = "Location")] String,
+        #[oai(header = "Upload-Expires")] String,
+        #[oai(header = "Tus-Resumable")] String,
+    ),
+    #[oai(status = 200)]
+    Offset(
+        #[oai(header = "Upload-Offset")] u64,
+        #[oai(header = "Upload-Length")] u64,
+        #[oai(header = "Upload-Expires")] String,
+        #[oai(header = "Cache-Control")] String,
+        #[oai(header = "Tus-Resumable")] String,
+    ),
+    #[oai(status = 204)]
+    Patched(
+        #[oai(header = "Upload-Offset")] u64,
+        #[oai(header = "Upload-Expires")] String,
+        #[oai(header = "Tus-Resumable")] String,
+    ),
+    #[oai(status = 204)]
+    Terminated(#[oai(header = "Tus-Resumable")] String),
+    #[oai(status = 400)]
+    BadRequest(PlainText<String>),
+    #[oai(status = 403)]
+    Forbidden(PlainText<String>),
+    #[oai(status = 404)]
+    NotFound(PlainText<String>),
+    #[oai(status = 409)]
+    Conflict(PlainText<String>, #[oai(header = "Upload-Offset")] u64),
+    #[oai(status = 412)]
+    PreconditionFailed(PlainText<String>, #[oai(header = "Tus-Version")] String),
+    #[oai(status = 413)]
+    TooLarge(PlainText<String>),
+    #[oai(status = 415)]
+    UnsupportedMediaType(PlainText<String>),
+    #[oai(status = 423)]
+    Locked(PlainText<String>),
+    #[oai(status = 460)]
+    ChecksumMismatch(PlainText<String>),
+    #[oai(status = 500)]
+    InternalError(PlainText<String>),
+}
+
+impl From<UploadError> for TusResponse {
+    fn from(value: UploadError) -> Self {
+        match value {
+            UploadError::NotFound => TusResponse::NotFound(PlainText("Upload not found or expired".to_string())),
+            UploadError::OffsetMismatch(offset) => TusResponse::Conflict(
+                PlainText("Upload-Offset does not match the current offset".to_string()), offset
+            ),
+            UploadError::Locked => TusResponse::Locked(PlainText("Upload is being written to".to_string())),
+            UploadError::ChecksumMismatch => TusResponse::ChecksumMismatch(PlainText("Checksum mismatch".to_string())),
+            UploadError::TooLarge => TusResponse::TooLarge(PlainText("Upload exceeds the allowed size".to_string())),
+            UploadError::BadRequest(msg) => TusResponse::BadRequest(PlainText(msg)),
+            UploadError::Internal(msg) => {
+                tracing::error!("Upload failed: {msg}");
+                TusResponse::InternalError(PlainText("Something went wrong".to_string()))
+            }
+        }
+    }
+}
+
+fn http_date(date: chrono::DateTime<chrono::Utc>) -> String {
+    date.format("%a, %d %b %Y %H:%M:%S GMT").to_string()
+}
+
+fn resumable() -> String {
+    TUS_VERSION.to_string()
+}
+
+fn check_resumable(version: &Option<String>) -> Result<(), TusResponse> {
+    match version.as_deref() {
+        Some(TUS_VERSION) => Ok(()),
+        _ => Err(TusResponse::PreconditionFailed(
+            PlainText("Unsupported Tus-Resumable version".to_string()), TUS_VERSION.to_string()
+        )),
+    }
+}
+
+fn target_from_metadata(
+    metadata: &std::collections::HashMap<String, String>
+) -> Result<(UploadTarget, Option<String>), String> {
+    let field = |name: &str| metadata.get(name).map(|v| v.trim().to_string()).filter(|v| !v.is_empty());
+    let credit = field("credit");
+    let target = match field("kind").as_deref() {
+        Some("map_model") => {
+            let map_name = field("map_name").ok_or("map_name is required")?;
+            let res_type = field("res_type").ok_or("res_type is required")?;
+            if res_type != "low" && res_type != "high" {
+                return Err("Invalid res_type. Must be 'low' or 'high'".to_string())
+            }
+            UploadTarget::MapModel { map_name, res_type }
+        }
+        Some("character_model") => UploadTarget::CharacterModel {
+            server_id: field("server_id").ok_or("server_id is required")?,
+            model_id: field("model_id").ok_or("model_id is required")?,
+            name: field("name"),
+        },
+        _ => return Err("kind must be 'map_model' or 'character_model'".to_string()),
+    };
+    Ok((target, credit))
+}
+
+async fn finish_upload(app: &AppData, upload: &TusUpload, path: &std::path::Path) -> Result<(), FinishError> {
+    match &upload.target {
+        UploadTarget::MapModel { map_name, res_type } => {
+            MapApi::finish_model_upload(
+                app, map_name, res_type, upload.credit.clone(), upload.uploaded_by, path
+            ).await
+        }
+        UploadTarget::CharacterModel { server_id, model_id, name } => {
+            CharacterApi::finish_model_upload(
+                app, server_id, model_id, name.clone(), upload.credit.clone(), upload.uploaded_by, path
+            ).await
+        }
+    }
+}
+
+#[OpenApi]
+impl UploadsApi {
+    /// Advertise the supported tus version and extensions
+    #[oai(path = "/uploads", method = "options")]
+    async fn upload_options(&self) -> TusResponse {
+        TusResponse::Options(
+            resumable(),
+            TUS_VERSION.to_string(),
+            TUS_EXTENSIONS.to_string(),
+            MAX_UPLOAD_SIZE,
+            TUS_CHECKSUM_ALGORITHMS.to_string(),
+        )
+    }
+
+    /// Create a resumable upload (`models.upload` on the target's servers)
+    #[oai(path = "/uploads", method = "post")]
+    async fn create_upload(
+        &self,
+        Data(app): Data<&AppData>,
+        TokenBearer(user_token): TokenBearer,
+        #[oai(name = "Tus-Resumable")] tus_resumable: Header<Option<String>>,
+        #[oai(name = "Upload-Length")] upload_length: Header<u64>,
+        #[oai(name = "Upload-Metadata")] upload_metadata: Header<Option<String>>,
+    ) -> TusResponse {
+        if let Err(resp) = check_resumable(&tus_resumable.0) {
+            return resp
+        }
+        let metadata = parse_metadata(upload_metadata.0.as_deref().unwrap_or_default());
+        let (target, credit) = match target_from_metadata(&metadata) {
+            Ok(result) => result,
+            Err(e) => return TusResponse::BadRequest(PlainText(e)),
+        };
+        let allowed = match &target {
+            UploadTarget::MapModel { map_name, .. } => check_map_models(app, user_token.id, map_name).await,
+            UploadTarget::CharacterModel { server_id, model_id, .. } => {
+                check_character_models(app, user_token.id, server_id, model_id).await
+            }
+        };
+        if !allowed {
+            return TusResponse::Forbidden(PlainText("Forbidden".to_string()))
+        }
+
+        let store = UploadStore::from_env(app.cache.redis_pool.clone());
+        match store.create(target, credit, upload_length.0, user_token.id).await {
+            Ok(upload) => {
+                tracing::info!("Upload created: {}, size: {}", upload.id, upload.length);
+                TusResponse::Created(
+                    format!("/uploads/{}", upload.id),
+                    http_date(upload.expires_at),
+                    resumable(),
+                )
+            }
+            Err(e) => e.into(),
+        }
+    }
+
+    /// Query how many bytes of an upload the server has
+    #[oai(path = "/uploads/:upload_id", method = "head")]
+    async fn get_upload_offset(
+        &self,
+        Data(app): Data<&AppData>,
+        TokenBearer(user_token): TokenBearer,
+        Path(upload_id): Path<String>,
+        #[oai(name = "Tus-Resumable")] tus_resumable: Header<Option<String>>,
+    ) -> TusResponse {
+        if let Err(resp) = check_resumable(&tus_resumable.0) {
+            return resp
+        }
+        let store = UploadStore::from_env(app.cache.redis_pool.clone());
+        match store.get(&upload_id).await {
+            Ok((upload, _)) if upload.uploaded_by != user_token.id => {
+                TusResponse::Forbidden(PlainText("Forbidden".to_string()))
+            }
+            Ok((upload, offset)) => TusResponse::Offset(
+                offset,
+                upload.length,
+                http_date(upload.expires_at),
+                "no-store".to_string(),
+                resumable(),
+            ),
+            Err(e) => e.into(),
+        }
+    }
+
+    /// Append bytes at `Upload-Offset`, the last PATCH stores the model.
+    /// When storing fails with a 500 the upload is kept, an empty PATCH at the final offset retries it.
+    #[oai(path = "/uploads/:upload_id", method = "patch")]
+    async fn patch_upload(
+        &self,
+        req: &Request,
+        Data(app): Data<&AppData>,
+        TokenBearer(user_token): TokenBearer,
+        Path(upload_id): Path<String>,
+        #[oai(name = "Tus-Resumable")] tus_resumable: Header<Option<String>>,
+        #[oai(name = "Upload-Offset")] upload_offset: Header<u64>,
+        #[oai(name = "Upload-Checksum")] upload_checksum: Header<Option<String>>,
+        body: Body,
+    ) -> TusResponse {
+        if let Err(resp) = check_resumable(&tus_resumable.0) {
+            return resp
+        }
+        if req.content_type() != Some("application/offset+octet-stream") {
+            return TusResponse::UnsupportedMediaType(
+                PlainText("Content-Type must be application/offset+octet-stream".to_string())
+            )
+        }
+
+        let store = UploadStore::from_env(app.cache.redis_pool.clone());
+        match store.get(&upload_id).await {
+            Ok((upload, _)) if upload.uploaded_by != user_token.id => {
+                return TusResponse::Forbidden(PlainText("Forbidden".to_string()))
+            }
+            Ok(_) => {}
+            Err(e) => return e.into(),
+        }
+
+        let (upload, offset) = match store.append(
+            &upload_id, upload_offset.0, upload_checksum.0.as_deref(), body.into_async_read()
+        ).await {
+            Ok(result) => result,
+            Err(e) => return e.into(),
+        };
+
+        if offset < upload.length {
+            return TusResponse::Patched(offset, http_date(upload.expires_at), resumable())
+        }
+
+        // A retried final PATCH must not store the model twice, nor one that was just finished
+        let lock = match store.lock(&upload_id).await {
+            Ok(lock) => lock,
+            Err(e) => return e.into(),
+        };
+        if let Err(e) = store.get(&upload_id).await {
+            store.unlock(&upload_id, &lock).await;
+            return e.into()
+        }
+        let data_path = store.data_path(&upload_id);
+        let result = finish_upload(app, &upload, &data_path).await;
+        // Transient failures keep the upload, the client retries with an empty PATCH at the final offset
+        let retryable = matches!(result, Err(FinishError::Failed(_)))
+            && tokio::fs::try_exists(&data_path).await.unwrap_or(false);
+        if !retryable {
+            store.delete(&upload_id).await;
+        }
+        store.unlock(&upload_id, &lock).await;
+        match result {
+            Ok(()) => {
+                tracing::info!("Upload completed: {}, final size: {}", upload_id, upload.length);
+                TusResponse::Patched(offset, http_date(upload.expires_at), resumable())
+            }
+            Err(FinishError::Rejected(e)) => {
+                tracing::warn!("Rejected upload {}: {}", upload_id, e);
+                TusResponse::BadRequest(PlainText(e))
+            }
+            Err(FinishError::Failed(e)) => {
+                tracing::error!("Failed to store upload {}: {}", upload_id, e);
+                TusResponse::InternalError(PlainText("Failed to store file".to_string()))
+            }
+        }
+    }
+
+    /// Abort an upload and drop what was received
+    #[oai(path = "/uploads/:upload_id", method = "delete")]
+    async fn terminate_upload(
+        &self,
+        Data(app): Data<&AppData>,
+        TokenBearer(user_token): TokenBearer,
+        Path(upload_id): Path<String>,
+        #[oai(name = "Tus-Resumable")] tus_resumable: Header<Option<String>>,
+    ) -> TusResponse {
+        if let Err(resp) = check_resumable(&tus_resumable.0) {
+            return resp
+        }
+        let store = UploadStore::from_env(app.cache.redis_pool.clone());
+        match store.get(&upload_id).await {
+            Ok((upload, _)) if upload.uploaded_by != user_token.id => {
+                TusResponse::Forbidden(PlainText("Forbidden".to_string()))
+            }
+            Ok(_) => {
+                store.delete(&upload_id).await;
+                tracing::warn!("Upload terminated: {}", upload_id);
+                TusResponse::Terminated(resumable())
+            }
+            Err(e) => e.into(),
+        }
+    }
+}
+
+impl UriPatternExt for UploadsApi {
+    fn get_all_patterns(&self) -> Vec<RoutePattern<'_>> {
+        vec![
+            "/uploads",
+            "/uploads/{upload_id}",
+        ].iter_into()
+    }
+}